@@ -0,0 +1,175 @@
+//! Benchmarks sequence tree evaluation for growing numbers of fixtures, both on
+//! a single thread and in parallel. Run with `cargo bench --bench
+//! sequence_tree`.
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use lightshow::{
+    audio::processing::fft::RecentFftData,
+    fixtures::FixtureRequest,
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{
+        effects::{ColorEffectInfo, EffectUpdateCommonInfo, color},
+        keyframes::{InterpolationType, Keyframe, KeyframeValue, Keyframes},
+        positions::TempoMap,
+        sequence_tree::{SequenceTree, TreeEvaluator},
+        sequences::{Sequence, SequenceId},
+        tracks::{Clip, TimeSegment, Track, TrackContents, TrackInfo},
+    },
+    util::blending::BlendingMode,
+};
+
+const FIXTURE_COUNTS: [usize; 4] = [100, 1_000, 5_000, 10_000];
+const EFFECT_TRACKS: usize = 16;
+const ITERATIONS: u32 = 200;
+
+/// Tick length at 44 Hz, which evaluation has to fit into comfortably.
+const TICK: Duration = Duration::from_nanos(1_000_000_000 / 44);
+
+fn shockwave_track(index: usize) -> Track {
+    let effect_info = color::shockwave::ColorShockwaveEffect {
+        color: Color::hsv(index as f32 * 20., 1., 1.),
+        center: Vec3::new(index as f32, 0., 0.),
+        radius: 0.,
+        flat: 10.,
+        head: 30.,
+        tail: 30.,
+    };
+
+    let effect_keyframes = Keyframes::new(vec![
+        Keyframe {
+            time: 0.0.into(),
+            interpolation: InterpolationType::LINEAR,
+            key: "radius".to_string(),
+            value: KeyframeValue::FloatKeyframe(0.),
+        },
+        Keyframe {
+            time: 4.0.into(),
+            interpolation: InterpolationType::LINEAR,
+            key: "radius".to_string(),
+            value: KeyframeValue::FloatKeyframe(300.),
+        },
+    ]);
+
+    Track::new(
+        TrackInfo {
+            blending_mode: BlendingMode::Add,
+            factor: 0.5,
+            track_keyframes: Keyframes::default(),
+            modulators: Vec::new(),
+            audio_bindings: Vec::new(),
+        },
+        TrackContents::EffectTrack {
+            effect_init_info: ColorEffectInfo::ColorShockwaveEffect(effect_info).into(),
+            effect_keyframes,
+        },
+    )
+}
+
+/// Builds a primary sequence with a number of shockwave tracks, plus a
+/// sequence track playing a nested sequence of shockwave tracks, so every
+/// level of the tree is exercised.
+fn build_show(sequence_store: &mut SimpleStore<Sequence>) -> SimpleHandle<Sequence> {
+    let nested_handle = sequence_store.add(Sequence {
+        id: SequenceId::fresh(),
+        name: "Nested Sequence".into(),
+        length: 8.,
+        tempo_map: TempoMap::default(),
+        tracks: (0..EFFECT_TRACKS / 2).map(shockwave_track).collect(),
+        loop_region: None,
+        audio: None,
+    });
+
+    let mut tracks: Vec<Track> = (0..EFFECT_TRACKS).map(shockwave_track).collect();
+    tracks.push(Track::new(
+        TrackInfo {
+            blending_mode: BlendingMode::Add,
+            factor: 1.0,
+            track_keyframes: Keyframes::default(),
+            modulators: Vec::new(),
+            audio_bindings: Vec::new(),
+        },
+        TrackContents::SequenceTrack {
+            clips: vec![Clip::new(nested_handle, TimeSegment::new(0., 8., 0.))],
+        },
+    ));
+
+    sequence_store.add(Sequence {
+        id: SequenceId::fresh(),
+        name: "Main Sequence".into(),
+        length: 8.,
+        tempo_map: TempoMap::default(),
+        tracks,
+        loop_region: None,
+        audio: None,
+    })
+}
+
+fn fixture_requests(count: usize) -> Vec<FixtureRequest> {
+    (0..count)
+        .map(|i| FixtureRequest {
+            groups: vec![0],
+            position: Vec3::new((i % 100) as f32 * 2., (i / 100) as f32 * 2., 0.),
+            has_color: true,
+            has_pan_tilt: false,
+        })
+        .collect()
+}
+
+fn time_evaluation(
+    sequence_tree: &SequenceTree,
+    fixtures: &[FixtureRequest],
+    evaluator: &mut TreeEvaluator,
+) -> Duration {
+    // warm up the buffers so only steady state evaluation is measured
+    std::hint::black_box(sequence_tree.get_values_recursive(fixtures, evaluator));
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        std::hint::black_box(sequence_tree.get_values_recursive(fixtures, evaluator));
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let mut sequence_store = SimpleStore::<Sequence>::default();
+    let primary_handle = build_show(&mut sequence_store);
+
+    let recent_fft_data = RecentFftData::default();
+    let tempo_map = TempoMap::default();
+    let mut sequence_tree = SequenceTree::new();
+    sequence_tree.update_recursive(
+        &sequence_store,
+        Some(primary_handle),
+        1.0,
+        &[],
+        &EffectUpdateCommonInfo {
+            recent_fft_data: &recent_fft_data,
+            global_time: 1.0,
+            tempo_map: &tempo_map,
+            playback_rate: 1.0,
+        },
+    );
+
+    let mut single_threaded = TreeEvaluator::single_threaded();
+    let mut parallel = TreeEvaluator::new();
+
+    println!(
+        "{:>9} {:>16} {:>16} {:>8} {:>14}",
+        "fixtures", "single (µs)", "parallel (µs)", "speedup", "tick budget"
+    );
+    for count in FIXTURE_COUNTS {
+        let fixtures = fixture_requests(count);
+        let single_time = time_evaluation(&sequence_tree, &fixtures, &mut single_threaded);
+        let parallel_time = time_evaluation(&sequence_tree, &fixtures, &mut parallel);
+        println!(
+            "{:>9} {:>16.1} {:>16.1} {:>7.2}x {:>13.1}%",
+            count,
+            single_time.as_secs_f64() * 1e6,
+            parallel_time.as_secs_f64() * 1e6,
+            single_time.as_secs_f64() / parallel_time.as_secs_f64(),
+            parallel_time.as_secs_f64() / TICK.as_secs_f64() * 100.,
+        );
+    }
+}
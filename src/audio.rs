@@ -1,14 +1,18 @@
 use bevy::prelude::*;
 
+pub mod analysis;
 pub mod capture;
+pub mod file;
 pub mod processing;
+pub mod song;
 
 pub struct AudioPlugin;
 
-type HeapConsumer<T> = ringbuf::HeapCons<T>;
+pub type HeapConsumer<T> = ringbuf::HeapCons<T>;
+pub type HeapProducer<T> = ringbuf::HeapProd<T>;
 
 impl Plugin for AudioPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(processing::AudioProcessingPlugin);
+        app.add_plugins((processing::AudioProcessingPlugin, song::SongPlugin));
     }
 }
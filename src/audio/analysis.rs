@@ -0,0 +1,208 @@
+use ringbuf::{HeapRb, traits::*};
+
+use crate::audio::{
+    file::AudioFile,
+    processing::fft::{FftConfig, FftProcessor},
+};
+
+/// The number of frames in each block at the finest resolution of a
+/// `Waveform`.
+const WAVEFORM_BLOCK: usize = 64;
+
+/// The range of frequencies shown in a `Spectrogram`, in Hz.
+const SPECTROGRAM_MIN_FREQUENCY: f32 = 30.0;
+const SPECTROGRAM_MAX_FREQUENCY: f32 = 16000.0;
+
+/// How far below full scale the quietest level in a `Spectrogram` is, in dB.
+const SPECTROGRAM_RANGE_DB: f32 = 80.0;
+
+/// Everything worked out about a song ahead of time for drawing it on the
+/// timeline.
+#[derive(Debug)]
+pub struct SongAnalysis {
+    pub waveform: Waveform,
+    pub spectrogram: Spectrogram,
+}
+
+impl SongAnalysis {
+    /// Analyzes a song. This takes a while for long songs, so it is best done
+    /// off the main thread.
+    pub fn new(song: &AudioFile) -> Self {
+        Self {
+            waveform: Waveform::new(song),
+            spectrogram: Spectrogram::new(song, FftConfig::new(song.sample_rate, 1024, 1024), 64),
+        }
+    }
+}
+
+/// The lowest and highest sample within a stretch of audio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Peak {
+    fn merge(self, other: Peak) -> Peak {
+        Peak {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+}
+
+/// Overview of a song for drawing its waveform, mixed down to mono. Peaks are
+/// kept for blocks of frames at several resolutions, each with blocks twice
+/// as long as the one before, so any stretch of the song is covered by a few
+/// blocks no matter how long it is.
+#[derive(Debug)]
+pub struct Waveform {
+    sample_rate: u32,
+    frame_count: usize,
+    levels: Vec<Vec<Peak>>,
+}
+
+impl Waveform {
+    pub fn new(song: &AudioFile) -> Self {
+        let frame_count = song.frame_count();
+        let finest: Vec<Peak> = (0..frame_count)
+            .step_by(WAVEFORM_BLOCK)
+            .map(|start| {
+                (start..(start + WAVEFORM_BLOCK).min(frame_count))
+                    .map(|index| {
+                        let mono = song.mono(index);
+                        Peak {
+                            min: mono,
+                            max: mono,
+                        }
+                    })
+                    .reduce(Peak::merge)
+                    .expect("blocks are never empty")
+            })
+            .collect();
+
+        let mut levels = vec![finest];
+        while let Some(coarsest) = levels.last()
+            && coarsest.len() > 1
+        {
+            let coarser = coarsest
+                .chunks(2)
+                .map(|pair| pair.iter().copied().reduce(Peak::merge).unwrap())
+                .collect();
+            levels.push(coarser);
+        }
+
+        Self {
+            sample_rate: song.sample_rate,
+            frame_count,
+            levels,
+        }
+    }
+
+    /// Gets the peak between two points in time, in seconds. This is rounded
+    /// out to whole blocks, using the coarsest resolution that still has
+    /// blocks shorter than the stretch of time. `None` if the stretch lies
+    /// outside of the song.
+    pub fn peak(&self, start: f64, end: f64) -> Option<Peak> {
+        let first = (start * self.sample_rate as f64).max(0.0) as usize;
+        let last = ((end * self.sample_rate as f64).ceil().max(0.0) as usize).min(self.frame_count);
+        if first >= last {
+            return None;
+        }
+
+        let blocks_spanned = ((last - first) / WAVEFORM_BLOCK).max(1);
+        let level = (blocks_spanned.ilog2() as usize).min(self.levels.len() - 1);
+        let block = WAVEFORM_BLOCK << level;
+        let peaks = &self.levels[level];
+        let to = ((last - 1) / block).min(peaks.len() - 1);
+        peaks[first / block..=to]
+            .iter()
+            .copied()
+            .reduce(Peak::merge)
+    }
+}
+
+/// How loud each frequency band of a song is over time, mixed down to mono.
+/// Computed by running the whole song through an `FftProcessor`, with the
+/// frequency bins grouped into bands spaced evenly in pitch.
+#[derive(Debug)]
+pub struct Spectrogram {
+    bands: usize,
+    /// Level of every band of every column, column by column with the lowest
+    /// band first. Levels go from 0 (silence) to 1 (full scale).
+    levels: Vec<f32>,
+    column_duration: f64,
+}
+
+impl Spectrogram {
+    pub fn new(song: &AudioFile, config: FftConfig, bands: usize) -> Self {
+        let max_frequency = SPECTROGRAM_MAX_FREQUENCY.min(config.sample_rate as f32 / 2.0);
+        let band_edges: Vec<f32> = (0..=bands)
+            .map(|edge| {
+                SPECTROGRAM_MIN_FREQUENCY
+                    * (max_frequency / SPECTROGRAM_MIN_FREQUENCY).powf(edge as f32 / bands as f32)
+            })
+            .collect();
+        // the magnitude of a full scale sine, given the Hanning window
+        let full_scale = config.window_size as f32 / 4.0;
+
+        let ring = HeapRb::<f32>::new(config.window_size * 4);
+        let (mut producer, consumer) = ring.split();
+        let mut processor = FftProcessor::new(config, consumer);
+        let mut samples = (0..song.frame_count()).map(|index| song.mono(index));
+        let mut pushed = 0;
+        let mut levels = Vec::new();
+        loop {
+            let pushed_now = producer.push_iter(&mut samples);
+            pushed += pushed_now;
+            while let Some(spectrum) = processor.process_next_frame() {
+                levels.extend(band_edges.windows(2).map(|edges| {
+                    let magnitude = spectrum.average_magnitude_range(edges[0], edges[1]);
+                    let db = 20.0 * (magnitude / full_scale).max(1e-9).log10();
+                    (db / SPECTROGRAM_RANGE_DB + 1.0).clamp(0.0, 1.0)
+                }));
+            }
+            if pushed_now == 0 {
+                break;
+            }
+        }
+
+        // however far the processor moves on with each frame
+        let column_count = levels.len() / bands.max(1);
+        let consumed = pushed - processor.buffered_samples();
+        let column_duration = if column_count > 0 {
+            consumed as f64 / column_count as f64 / song.sample_rate as f64
+        } else {
+            0.0
+        };
+
+        Self {
+            bands,
+            levels,
+            column_duration,
+        }
+    }
+
+    pub fn bands(&self) -> usize {
+        self.bands
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.levels.len() / self.bands.max(1)
+    }
+
+    /// Gets the length of time each column covers, in seconds.
+    pub fn column_duration(&self) -> f64 {
+        self.column_duration
+    }
+
+    /// Gets the length of time all columns cover together, in seconds.
+    pub fn duration(&self) -> f64 {
+        self.column_count() as f64 * self.column_duration
+    }
+
+    /// Gets the levels of every band in a column, lowest band first.
+    pub fn column(&self, index: usize) -> &[f32] {
+        &self.levels[index * self.bands..(index + 1) * self.bands]
+    }
+}
@@ -0,0 +1,184 @@
+use std::{fmt, path::Path};
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CODEC_TYPE_NULL, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+/// Audio decoded from a file, held in memory as 32-bit float samples with the
+/// channels of each frame interleaved.
+///
+/// WAV, FLAC and MP3 files are decoded.
+#[derive(Debug)]
+pub struct AudioFile {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<f32>,
+}
+
+/// Errors that can occur while decoding an audio file.
+#[derive(Debug)]
+pub enum AudioFileError {
+    Io(std::io::Error),
+    UnknownFormat,
+    NoAudio,
+    Decode(String),
+}
+
+impl fmt::Display for AudioFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioFileError::Io(e) => write!(f, "could not read audio file: {}", e),
+            AudioFileError::UnknownFormat => write!(f, "not a recognized audio file"),
+            AudioFileError::NoAudio => write!(f, "the file contains no audio"),
+            AudioFileError::Decode(reason) => write!(f, "could not decode audio: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AudioFileError {}
+
+impl From<std::io::Error> for AudioFileError {
+    fn from(e: std::io::Error) -> Self {
+        AudioFileError::Io(e)
+    }
+}
+
+impl From<SymphoniaError> for AudioFileError {
+    fn from(e: SymphoniaError) -> Self {
+        match e {
+            SymphoniaError::IoError(e) => AudioFileError::Io(e),
+            SymphoniaError::Unsupported(_) => AudioFileError::UnknownFormat,
+            e => AudioFileError::Decode(e.to_string()),
+        }
+    }
+}
+
+impl AudioFile {
+    /// Reads and decodes an audio file. The format is recognized from the
+    /// contents of the file, with the extension only as a hint.
+    pub fn load(path: &Path) -> Result<Self, AudioFileError> {
+        let file = std::fs::File::open(path)?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+            hint.with_extension(extension);
+        }
+        let mut format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )?
+            .format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(AudioFileError::NoAudio)?;
+        let track_id = track.id;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+
+        let mut sample_rate = track.codec_params.sample_rate.unwrap_or(0);
+        let mut channels = track
+            .codec_params
+            .channels
+            .map_or(0, |channels| channels.count() as u16);
+        let mut samples = Vec::new();
+        let mut buffer: Option<SampleBuffer<f32>> = None;
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                // the end of the stream is reported as an unexpected EOF
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // a corrupt packet is skipped rather than losing the whole file
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let spec = *decoded.spec();
+            sample_rate = spec.rate;
+            channels = spec.channels.count() as u16;
+            let needed = decoded.capacity() * spec.channels.count();
+            let buffer = match &mut buffer {
+                Some(buffer) if buffer.capacity() >= needed => buffer,
+                _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+            };
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+
+        if channels == 0 || sample_rate == 0 {
+            return Err(AudioFileError::NoAudio);
+        }
+        // drop a partial frame at the end
+        let frame_count = samples.len() / channels as usize;
+        samples.truncate(frame_count * channels as usize);
+        Ok(Self {
+            sample_rate,
+            channels,
+            samples,
+        })
+    }
+
+    /// Gets the number of frames, i.e. samples per channel.
+    pub fn frame_count(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    /// Gets the length of the audio in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frame_count() as f64 / self.sample_rate as f64
+    }
+
+    /// Gets the samples of a single frame, one per channel.
+    pub fn frame(&self, index: usize) -> &[f32] {
+        let channels = self.channels as usize;
+        &self.samples[index * channels..(index + 1) * channels]
+    }
+
+    /// Gets a single frame mixed down to mono.
+    pub fn mono(&self, index: usize) -> f32 {
+        let frame = self.frame(index);
+        frame.iter().sum::<f32>() / frame.len() as f32
+    }
+
+    /// Gets the left and right channels at a point in time, in seconds,
+    /// interpolating between frames. Mono audio plays on both channels, and
+    /// any channels past the second are left out. Silent outside of the audio.
+    pub fn stereo_at(&self, seconds: f64) -> (f32, f32) {
+        let position = seconds * self.sample_rate as f64;
+        if !(position >= 0.0 && position + 1.0 < self.frame_count() as f64) {
+            return (0.0, 0.0);
+        }
+        let index = position as usize;
+        let t = (position - index as f64) as f32;
+        let (current, next) = (self.frame(index), self.frame(index + 1));
+        let channel = |channel: usize| current[channel] * (1.0 - t) + next[channel] * t;
+        if self.channels == 1 {
+            let mono = channel(0);
+            (mono, mono)
+        } else {
+            (channel(0), channel(1))
+        }
+    }
+}
@@ -123,6 +123,17 @@ impl RecentFftData {
         let from = total - self.new_from_fixed_update_count;
         self.past_second.range(from..total)
     }
+
+    /// Retrieves pairs of consecutive frames, one for each frame added in the
+    /// last FixedUpdate cycle (along with the frame before it), for measures
+    /// that compare frames. A new frame without a frame before it is skipped.
+    pub fn get_new_pairs_from_fixed_update(
+        &self,
+    ) -> impl Iterator<Item = (&SpectrumData, &SpectrumData)> {
+        let total = self.past_second.len();
+        let from = (total - self.new_from_fixed_update_count).max(1);
+        (from..total).map(move |i| (&self.past_second[i - 1], &self.past_second[i]))
+    }
 }
 
 /// Spectrum data from a single FFT frame
@@ -157,6 +168,41 @@ impl SpectrumData {
         sum / (bin_max - bin_min + 1) as f32
     }
 
+    /// Get the root mean square of all magnitudes, i.e. the overall loudness
+    pub fn rms(&self) -> f32 {
+        if self.magnitudes.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self
+            .magnitudes
+            .iter()
+            .map(|magnitude| magnitude * magnitude)
+            .sum();
+        (sum / self.magnitudes.len() as f32).sqrt()
+    }
+
+    /// Get the spectral flux in a frequency range (in Hz) since a previous
+    /// frame, i.e. the average increase in magnitude, ignoring decreases
+    pub fn flux_range(&self, previous: &SpectrumData, freq_min: f32, freq_max: f32) -> f32 {
+        let bin_count = self.magnitudes.len().min(previous.magnitudes.len());
+        let bin_min = self.config.frequency_to_bin(freq_min);
+        let bin_max = self
+            .config
+            .frequency_to_bin(freq_max)
+            .min(bin_count.saturating_sub(1));
+
+        if bin_count == 0 || bin_min > bin_max {
+            return 0.0;
+        }
+
+        let sum: f32 = self.magnitudes[bin_min..=bin_max]
+            .iter()
+            .zip(&previous.magnitudes[bin_min..=bin_max])
+            .map(|(magnitude, previous)| (magnitude - previous).max(0.0))
+            .sum();
+        sum / (bin_max - bin_min + 1) as f32
+    }
+
     /// Get the peak frequency (ignoring DC component)
     pub fn peak_frequency(&self) -> (f32, f32) {
         let (peak_idx, &peak_mag) = self
@@ -273,6 +319,18 @@ impl FftProcessor {
         })
     }
 
+    /// Swaps the ring buffer consumer that samples are read from, returning
+    /// the previous one. Anything already buffered in the new consumer is
+    /// dropped, so processing starts from fresh samples.
+    pub fn replace_input(&mut self, mut consumer: HeapConsumer<f32>) -> HeapConsumer<f32> {
+        consumer.clear();
+        let current = self
+            .consumer
+            .get_mut()
+            .expect("could not unlock audio consumer");
+        std::mem::replace(current, consumer)
+    }
+
     /// Get the current configuration
     pub fn config(&self) -> &FftConfig {
         &self.config
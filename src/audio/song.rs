@@ -0,0 +1,344 @@
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, futures::check_ready},
+};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::{HeapRb, traits::*};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    audio::{
+        HeapConsumer,
+        analysis::SongAnalysis,
+        file::AudioFile,
+        processing::fft::{FftConfig, FftProcessor},
+    },
+    simple_store::SimpleStore,
+    timeline::{playback::*, sequences::*},
+};
+
+/// Bevy plugin for playing the audio attached to the primary sequence.
+pub struct SongPlugin;
+
+impl Plugin for SongPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SongLibrary>().add_systems(
+            Update,
+            (manage_song_player, sync_song_playback)
+                .chain()
+                .after(increment_playback_time),
+        );
+    }
+}
+
+/// How far the song may drift from the playback head, in seconds, before it
+/// jumps back into place rather than catching up gradually.
+const RESYNC_THRESHOLD: f64 = 0.1;
+
+/// How much the playback rate changes per second of drift while the song
+/// catches up with the playback head.
+const RATE_CORRECTION: f64 = 0.5;
+
+/// The most the playback rate changes while catching up, small enough that
+/// the change in pitch can't be heard.
+const MAX_RATE_CORRECTION: f64 = 0.005;
+
+/// Bevy resource that keeps the decoded audio files in memory, so a song is
+/// only decoded once. Songs are decoded in the background, and files that
+/// fail to decode are remembered as well, so the error is only reported once.
+/// Songs are also analyzed for drawing on the timeline, in the background.
+#[derive(Resource, Debug, Default)]
+pub struct SongLibrary {
+    songs: HashMap<PathBuf, Song>,
+    analyses: HashMap<PathBuf, Analysis>,
+}
+
+#[derive(Debug)]
+enum Song {
+    Decoding(Task<Result<AudioFile, String>>),
+    Done(Result<Arc<AudioFile>, String>),
+}
+
+#[derive(Debug)]
+enum Analysis {
+    Running(Task<SongAnalysis>),
+    Done(Arc<SongAnalysis>),
+}
+
+impl SongLibrary {
+    /// Gets a song, starting to decode it in the background the first time
+    /// it is asked for. `Ok(None)` while the song is still being decoded.
+    pub fn get(&mut self, path: &Path) -> Result<Option<Arc<AudioFile>>, String> {
+        let song = self.songs.entry(path.to_path_buf()).or_insert_with(|| {
+            let path = path.to_path_buf();
+            Song::Decoding(AsyncComputeTaskPool::get().spawn(async move {
+                AudioFile::load(&path)
+                    .map_err(|e| format!("Failed to load \"{}\": {}", path.display(), e))
+            }))
+        });
+        if let Song::Decoding(task) = song
+            && let Some(done) = check_ready(task)
+        {
+            if let Err(e) = &done {
+                error!("{}", e);
+            }
+            *song = Song::Done(done.map(Arc::new));
+        }
+        match song {
+            Song::Decoding(_) => Ok(None),
+            Song::Done(done) => done.clone().map(Some),
+        }
+    }
+
+    /// Gets the analysis of a song, starting it in the background the first
+    /// time it is asked for. `Ok(None)` while the song is still being decoded
+    /// or the analysis is still running.
+    pub fn analysis(&mut self, path: &Path) -> Result<Option<Arc<SongAnalysis>>, String> {
+        let Some(song) = self.get(path)? else {
+            return Ok(None);
+        };
+        let analysis = self.analyses.entry(path.to_path_buf()).or_insert_with(|| {
+            Analysis::Running(
+                AsyncComputeTaskPool::get().spawn(async move { SongAnalysis::new(&song) }),
+            )
+        });
+        if let Analysis::Running(task) = analysis
+            && let Some(done) = check_ready(task)
+        {
+            *analysis = Analysis::Done(Arc::new(done));
+        }
+        match analysis {
+            Analysis::Running(_) => Ok(None),
+            Analysis::Done(done) => Ok(Some(done.clone())),
+        }
+    }
+
+    /// Forgets a song, so it is decoded again the next time it is asked for,
+    /// e.g. after the file has changed.
+    pub fn reload(&mut self, path: &Path) {
+        self.songs.remove(path);
+        self.analyses.remove(path);
+    }
+}
+
+/// The state shared between the `SongPlayer` and its audio stream.
+#[derive(Debug)]
+struct SongState {
+    song: Option<Arc<AudioFile>>,
+    /// Where in the song the stream is, in seconds.
+    position: f64,
+    playing: bool,
+    /// How fast the song plays, where 1 is its normal speed.
+    rate: f64,
+}
+
+/// Bevy resource that plays a song on the default audio output, and passes a
+/// mono mix of it on to the FFT processor.
+#[derive(Resource)]
+pub struct SongPlayer {
+    _stream: cpal::Stream,
+    state: Arc<Mutex<SongState>>,
+    /// The mono mix for the FFT processor, until it has been handed over.
+    fft_input: Mutex<Option<HeapConsumer<f32>>>,
+    /// The input the FFT processor had before the song was handed to it, to
+    /// give back once the song stops.
+    displaced_fft_input: Mutex<Option<HeapConsumer<f32>>>,
+    /// Whether the FFT processor was created for the song.
+    created_fft: bool,
+}
+
+impl SongPlayer {
+    /// Opens the default audio output for playing songs. The mono mix is
+    /// resampled to `fft_sample_rate` for the FFT processor.
+    pub fn open(fft_sample_rate: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .ok_or("No output device available")?;
+        let config: cpal::StreamConfig = device.default_output_config()?.into();
+        let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0 as f64;
+
+        let state = Arc::new(Mutex::new(SongState {
+            song: None,
+            position: 0.0,
+            playing: false,
+            rate: 1.0,
+        }));
+
+        // one second of buffer for the FFT processor
+        let ring = HeapRb::<f32>::new(fft_sample_rate as usize);
+        let (mut producer, consumer) = ring.split();
+        let fft_step = fft_sample_rate as f64 / sample_rate;
+        let mut fft_phase = 0.0;
+
+        let stream_state = state.clone();
+        let stream = device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let Ok(mut state) = stream_state.lock() else {
+                    data.fill(0.0);
+                    return;
+                };
+                for frame in data.chunks_mut(channels) {
+                    let (left, right) = match &state.song {
+                        Some(song) if state.playing => song.stereo_at(state.position),
+                        _ => (0.0, 0.0),
+                    };
+                    if state.playing {
+                        state.position += state.rate / sample_rate;
+                    }
+
+                    match frame {
+                        [mono] => *mono = (left + right) * 0.5,
+                        [first, second, rest @ ..] => {
+                            *first = left;
+                            *second = right;
+                            rest.fill(0.0);
+                        }
+                        [] => {}
+                    }
+
+                    fft_phase += fft_step;
+                    while fft_phase >= 1.0 {
+                        fft_phase -= 1.0;
+                        let _ = producer.try_push((left + right) * 0.5);
+                    }
+                }
+            },
+            |err| eprintln!("Audio stream error: {}", err),
+            None,
+        )?;
+        stream.play()?;
+
+        Ok(Self {
+            _stream: stream,
+            state,
+            fft_input: Mutex::new(Some(consumer)),
+            displaced_fft_input: Mutex::new(None),
+            created_fft: false,
+        })
+    }
+}
+
+/// Opens the audio output while the primary sequence has audio attached, and
+/// closes it again once it doesn't. While open, the song feeds the FFT
+/// processor in place of whatever fed it before, e.g. the microphone. The
+/// output isn't tried again after failing to open until the audio is
+/// attached again.
+pub fn manage_song_player(
+    mut commands: Commands,
+    primary_sequence: Res<PrimarySequence>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    player: Option<ResMut<SongPlayer>>,
+    fft: Option<ResMut<FftProcessor>>,
+    mut failed: Local<bool>,
+) {
+    let has_audio = primary_sequence
+        .0
+        .and_then(|handle| sequence_store.get(handle))
+        .is_some_and(|sequence| sequence.audio.is_some());
+
+    match (player, has_audio) {
+        (None, true) if !*failed => {
+            let fft_sample_rate = fft
+                .as_ref()
+                .map_or(FftConfig::default().sample_rate, |fft| {
+                    fft.config().sample_rate
+                });
+            match SongPlayer::open(fft_sample_rate) {
+                Ok(player) => commands.insert_resource(player),
+                Err(e) => {
+                    error!("Failed to open audio output for the song: {}", e);
+                    *failed = true;
+                }
+            }
+        }
+        (Some(mut player), true) => {
+            // hand the song over to the FFT processor
+            let player = &mut *player;
+            let Some(input) = player.fft_input.get_mut().ok().and_then(Option::take) else {
+                return;
+            };
+            match fft {
+                Some(mut fft) => {
+                    if let Ok(displaced) = player.displaced_fft_input.get_mut() {
+                        *displaced = Some(fft.replace_input(input));
+                    }
+                }
+                None => {
+                    commands.insert_resource(FftProcessor::new(FftConfig::default(), input));
+                    player.created_fft = true;
+                }
+            }
+        }
+        (Some(mut player), false) => {
+            // give the FFT processor back what it had before
+            if player.created_fft {
+                commands.remove_resource::<FftProcessor>();
+            } else if let (Some(mut fft), Ok(Some(input))) =
+                (fft, player.displaced_fft_input.get_mut().map(Option::take))
+            {
+                fft.replace_input(input);
+            }
+            commands.remove_resource::<SongPlayer>();
+        }
+        (None, has_audio) => {
+            if !has_audio {
+                *failed = false;
+            }
+        }
+    }
+}
+
+/// Keeps the song in step with the playback head. The song jumps along when
+/// the playback head jumps, and is silent while playback is paused, running
+/// backwards, or blacked out, and while the song is still being decoded. Small amounts of drift between the audio output
+/// and the playback head are caught up by playing slightly faster or slower.
+pub fn sync_song_playback(
+    playback: Res<PlaybackInformation>,
+    primary_sequence: Res<PrimarySequence>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    mut library: ResMut<SongLibrary>,
+    player: Option<Res<SongPlayer>>,
+) {
+    let Some(player) = player else {
+        return;
+    };
+    let Ok(mut state) = player.state.lock() else {
+        return;
+    };
+
+    let audio = primary_sequence
+        .0
+        .and_then(|handle| sequence_store.get(handle))
+        .and_then(|sequence| sequence.audio.as_ref());
+    let Some((song, offset)) =
+        audio.and_then(|audio| Some((library.get(&audio.path).ok().flatten()?, audio.offset)))
+    else {
+        state.song = None;
+        state.playing = false;
+        return;
+    };
+
+    let changed = !state
+        .song
+        .as_ref()
+        .is_some_and(|current| Arc::ptr_eq(current, &song));
+    state.song = Some(song);
+    state.playing = playback.is_playing && !playback.reversed && !playback.blacked_out;
+
+    let target = playback.current_time - offset;
+    let drift = target - state.position;
+    if changed || !state.playing || drift.is_nan() || drift.abs() >= RESYNC_THRESHOLD {
+        state.position = target;
+        state.rate = 1.0;
+    } else {
+        state.rate =
+            1.0 + (drift * RATE_CORRECTION).clamp(-MAX_RATE_CORRECTION, MAX_RATE_CORRECTION);
+    }
+}
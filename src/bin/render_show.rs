@@ -0,0 +1,146 @@
+//! Renders the primary sequence of a show file to a DMX recording, faster
+//! than real time and without a window or a network.
+//!
+//! Usage: `render_show <show file> <output file> [--fps <rate>]
+//! [--start <seconds>] [--end <seconds>] [--audio <file>]
+//! [--audio-offset <seconds>]`
+//!
+//! Audio-reactive effects are fed from the audio attached to the sequence,
+//! unless another file is given with `--audio`.
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Instant,
+};
+
+use lightshow::{
+    audio::file::AudioFile,
+    render::{OfflineRenderer, RenderSettings},
+    show_file::ShowFile,
+};
+
+const USAGE: &str = "usage: render_show <show file> <output file> [--fps <rate>] \
+    [--start <seconds>] [--end <seconds>] [--audio <file>] [--audio-offset <seconds>]";
+
+struct Arguments {
+    show_path: PathBuf,
+    output_path: PathBuf,
+    settings: RenderSettings,
+    frame_rate_given: bool,
+    audio_path: Option<PathBuf>,
+    audio_offset: Option<f64>,
+}
+
+fn parse_arguments() -> Result<Arguments, String> {
+    let mut positional = Vec::new();
+    let mut settings = RenderSettings::default();
+    let mut frame_rate_given = false;
+    let mut audio_path = None;
+    let mut audio_offset = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        let seconds = |name: &str, value: String| {
+            value
+                .parse::<f64>()
+                .map_err(|e| format!("invalid value for {}: {}", name, e))
+        };
+        match arg.as_str() {
+            "--fps" => {
+                settings.frame_rate = seconds("--fps", value("--fps")?)?;
+                frame_rate_given = true;
+            }
+            "--start" => settings.start = seconds("--start", value("--start")?)?,
+            "--end" => settings.end = Some(seconds("--end", value("--end")?)?),
+            "--audio" => audio_path = Some(PathBuf::from(value("--audio")?)),
+            "--audio-offset" => {
+                audio_offset = Some(seconds("--audio-offset", value("--audio-offset")?)?)
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => positional.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [show_path, output_path] = <[PathBuf; 2]>::try_from(positional)
+        .map_err(|_| "expected a show file and an output file".to_string())?;
+    Ok(Arguments {
+        show_path,
+        output_path,
+        settings,
+        frame_rate_given,
+        audio_path,
+        audio_offset,
+    })
+}
+
+fn load_audio(path: &Path) -> Result<Arc<AudioFile>, String> {
+    AudioFile::load(path)
+        .map(Arc::new)
+        .map_err(|e| format!("failed to load \"{}\": {}", path.display(), e))
+}
+
+fn run(arguments: Arguments) -> Result<(), String> {
+    let show_file = ShowFile::load(&arguments.show_path).map_err(|e| {
+        format!(
+            "failed to load show from {}: {}",
+            arguments.show_path.display(),
+            e
+        )
+    })?;
+
+    let mut settings = arguments.settings;
+    // render at the rate the show runs at, unless told otherwise
+    if !arguments.frame_rate_given {
+        settings.frame_rate = show_file.settings.update_rate_hz;
+    }
+    let mut renderer = OfflineRenderer::from_show_file(&show_file, settings)?;
+
+    let attached = renderer
+        .sequence()
+        .and_then(|sequence| sequence.audio.clone());
+    let audio = match (arguments.audio_path, attached) {
+        (Some(path), attached) => Some((
+            load_audio(&path)?,
+            arguments
+                .audio_offset
+                .or(attached.map(|audio| audio.offset))
+                .unwrap_or(0.0),
+        )),
+        (None, Some(attached)) => Some((
+            load_audio(&attached.path)?,
+            arguments.audio_offset.unwrap_or(attached.offset),
+        )),
+        (None, None) => None,
+    };
+    if let Some((song, offset)) = audio {
+        renderer = renderer.with_audio(song, offset);
+    }
+
+    let started = Instant::now();
+    let frame_count = renderer
+        .render_to_file(&arguments.output_path)
+        .map_err(|e| format!("failed to write {}: {}", arguments.output_path.display(), e))?;
+    println!(
+        "Rendered {} frames to {} in {:.2} s",
+        frame_count,
+        arguments.output_path.display(),
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let result = parse_arguments()
+        .map_err(|e| format!("{}\n{}", e, USAGE))
+        .and_then(run);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
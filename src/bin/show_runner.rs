@@ -0,0 +1,136 @@
+//! Runs a show without a window, for installations on machines without a
+//! display. Fixtures are sent out over Art-Net, the song attached to the
+//! sequence plays on the default audio output, and MIDI inputs can be
+//! followed for cues, timecode and beat clock.
+//!
+//! Usage: `show_runner <show file> [--play] [--listen <address>]
+//! [--artnet <address>] [--midi <name>]... [--capture]`
+//!
+//! The show is controlled with commands typed on standard input, or sent
+//! one per line to the socket given with `--listen`. Type `help` for the
+//! list of commands.
+
+use bevy::prelude::*;
+use std::{path::PathBuf, process::ExitCode};
+
+use lightshow::{
+    headless::{
+        HeadlessPlugin, RunnerCommand, RunnerControl, connect_midi_inputs, start_audio_capture,
+    },
+    network::ActiveSocket,
+    show_file::ShowFile,
+};
+
+const USAGE: &str = "usage: show_runner <show file> [--play] [--listen <address>] \
+    [--artnet <address>] [--midi <name>]... [--capture]";
+
+/// The address Art-Net is sent from unless told otherwise.
+const DEFAULT_ART_NET_ADDRESS: &str = "0.0.0.0:6454";
+
+struct Arguments {
+    show_path: PathBuf,
+    play: bool,
+    listen: Option<String>,
+    art_net: String,
+    midi: Vec<String>,
+    capture: bool,
+}
+
+fn parse_arguments() -> Result<Arguments, String> {
+    let mut show_path = None;
+    let mut play = false;
+    let mut listen = None;
+    let mut art_net = DEFAULT_ART_NET_ADDRESS.to_string();
+    let mut midi = Vec::new();
+    let mut capture = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--play" => play = true,
+            "--listen" => listen = Some(value("--listen")?),
+            "--artnet" => art_net = value("--artnet")?,
+            "--midi" => midi.push(value("--midi")?),
+            "--capture" => capture = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if show_path.is_none() => show_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    Ok(Arguments {
+        show_path: show_path.ok_or("expected a show file")?,
+        play,
+        listen,
+        art_net,
+        midi,
+        capture,
+    })
+}
+
+fn run(arguments: Arguments) -> Result<(), String> {
+    // fail early rather than running an empty show
+    ShowFile::load(&arguments.show_path).map_err(|e| {
+        format!(
+            "failed to load show from {}: {}",
+            arguments.show_path.display(),
+            e
+        )
+    })?;
+
+    let mut app = App::new();
+    app.add_plugins(HeadlessPlugin);
+    let world = app.world_mut();
+
+    let socket = ActiveSocket::open(&arguments.art_net).map_err(|e| {
+        format!(
+            "failed to open Art-Net socket on {}: {}",
+            arguments.art_net, e
+        )
+    })?;
+    world.insert_resource(socket);
+    if !arguments.midi.is_empty() {
+        let connected = connect_midi_inputs(world, &arguments.midi)?;
+        if connected.is_empty() {
+            warn!("No MIDI inputs matched {:?}", arguments.midi);
+        }
+        for name in connected {
+            info!("Connected MIDI input \"{}\"", name);
+        }
+    }
+    if arguments.capture {
+        start_audio_capture(world).map_err(|e| format!("failed to capture audio: {}", e))?;
+    }
+
+    let control = world.resource::<RunnerControl>();
+    control.queue(RunnerCommand::Load(arguments.show_path));
+    if arguments.play {
+        control.queue(RunnerCommand::Play);
+    }
+    if let Some(address) = &arguments.listen {
+        let local_address = control
+            .listen(address.as_str())
+            .map_err(|e| format!("failed to listen on {}: {}", address, e))?;
+        info!("Listening for commands on {}", local_address);
+    }
+    control.listen_stdin();
+
+    match app.run() {
+        AppExit::Success => Ok(()),
+        AppExit::Error(code) => Err(format!("exited with code {}", code)),
+    }
+}
+
+fn main() -> ExitCode {
+    let result = parse_arguments()
+        .map_err(|e| format!("{}\n{}", e, USAGE))
+        .and_then(run);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
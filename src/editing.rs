@@ -0,0 +1,637 @@
+use bevy::prelude::*;
+
+use crate::{
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{
+        audio_bindings::{AudioBinding, validate_audio_bindings},
+        effects::EffectInfo,
+        keyframes::Keyframes,
+        modulators::{Modulator, validate_modulators},
+        sequences::{LoopRegion, Sequence, SequenceAudio, check_nesting},
+        tracks::{Clip, ClipPlayback, TimeSegment, Track, TrackContents, TrackInfo},
+    },
+};
+
+/// Bevy plugin for the edit history.
+pub struct EditingPlugin;
+
+impl Plugin for EditingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditHistory>()
+            .add_observer(apply_edit)
+            .add_observer(undo_edit)
+            .add_observer(redo_edit);
+    }
+}
+
+/// Which set of keyframes on a track an edit refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyframesTarget {
+    /// The track-level keyframes within `TrackInfo`.
+    Track,
+    /// The effect keyframes of an effect track.
+    Effect,
+}
+
+/// A single, reversible edit to the sequences in the `SimpleStore`. Applying
+/// a command hands back its inverse, which is what gets stored on the undo
+/// (or redo) stack.
+#[derive(Debug)]
+pub enum EditCommand {
+    AddTrack {
+        sequence: SimpleHandle<Sequence>,
+        index: usize,
+        track: Track,
+    },
+    RemoveTrack {
+        sequence: SimpleHandle<Sequence>,
+        index: usize,
+    },
+    MoveTrack {
+        sequence: SimpleHandle<Sequence>,
+        from: usize,
+        to: usize,
+    },
+    MoveClip {
+        sequence: SimpleHandle<Sequence>,
+        track: usize,
+        clip: usize,
+        time_segment: TimeSegment,
+    },
+    AddClip {
+        sequence: SimpleHandle<Sequence>,
+        track: usize,
+        index: usize,
+        clip: Clip,
+    },
+    RemoveClip {
+        sequence: SimpleHandle<Sequence>,
+        track: usize,
+        index: usize,
+    },
+    SetKeyframes {
+        sequence: SimpleHandle<Sequence>,
+        track: usize,
+        target: KeyframesTarget,
+        keyframes: Keyframes,
+    },
+    SetEffect {
+        sequence: SimpleHandle<Sequence>,
+        track: usize,
+        effect_info: EffectInfo,
+    },
+    SetModulators {
+        sequence: SimpleHandle<Sequence>,
+        track: usize,
+        modulators: Vec<Modulator>,
+    },
+    SetAudioBindings {
+        sequence: SimpleHandle<Sequence>,
+        track: usize,
+        audio_bindings: Vec<AudioBinding>,
+    },
+    SetLoopRegion {
+        sequence: SimpleHandle<Sequence>,
+        loop_region: Option<LoopRegion>,
+    },
+    SetAudio {
+        sequence: SimpleHandle<Sequence>,
+        audio: Option<SequenceAudio>,
+    },
+    /// Several commands applied in order as a single step, e.g. removing a
+    /// clip and adding both halves of it back when splitting.
+    Batch(Vec<EditCommand>),
+}
+
+/// Identifies what an edit modifies, so that consecutive edits to the same
+/// thing (such as every frame of a drag) can be merged into a single step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditTarget {
+    Tracks(SimpleHandle<Sequence>),
+    Clip(SimpleHandle<Sequence>, usize, usize),
+    Clips(SimpleHandle<Sequence>, usize),
+    Keyframes(SimpleHandle<Sequence>, usize, KeyframesTarget),
+    Effect(SimpleHandle<Sequence>, usize),
+    Modulators(SimpleHandle<Sequence>, usize),
+    AudioBindings(SimpleHandle<Sequence>, usize),
+    LoopRegion(SimpleHandle<Sequence>),
+    Audio(SimpleHandle<Sequence>),
+}
+
+impl EditCommand {
+    /// Gets what the command modifies. Batches have no single target and are
+    /// never merged.
+    pub fn target(&self) -> Option<EditTarget> {
+        let target = match self {
+            EditCommand::AddTrack { sequence, .. }
+            | EditCommand::RemoveTrack { sequence, .. }
+            | EditCommand::MoveTrack { sequence, .. } => EditTarget::Tracks(*sequence),
+            EditCommand::MoveClip {
+                sequence,
+                track,
+                clip,
+                ..
+            } => EditTarget::Clip(*sequence, *track, *clip),
+            EditCommand::AddClip {
+                sequence, track, ..
+            }
+            | EditCommand::RemoveClip {
+                sequence, track, ..
+            } => EditTarget::Clips(*sequence, *track),
+            EditCommand::SetKeyframes {
+                sequence,
+                track,
+                target,
+                ..
+            } => EditTarget::Keyframes(*sequence, *track, *target),
+            EditCommand::SetEffect {
+                sequence, track, ..
+            } => EditTarget::Effect(*sequence, *track),
+            EditCommand::SetModulators {
+                sequence, track, ..
+            } => EditTarget::Modulators(*sequence, *track),
+            EditCommand::SetAudioBindings {
+                sequence, track, ..
+            } => EditTarget::AudioBindings(*sequence, *track),
+            EditCommand::SetLoopRegion { sequence, .. } => EditTarget::LoopRegion(*sequence),
+            EditCommand::SetAudio { sequence, .. } => EditTarget::Audio(*sequence),
+            EditCommand::Batch(_) => return None,
+        };
+        Some(target)
+    }
+
+    /// Applies the command to the sequence store, returning the command that
+    /// reverts it. Nothing is modified if the command fails. Commands that
+    /// would nest a sequence within itself are refused (see `check_nesting`).
+    pub fn apply(
+        mut self,
+        sequence_store: &mut SimpleStore<Sequence>,
+    ) -> Result<EditCommand, String> {
+        self.apply_in_place(sequence_store)?;
+        Ok(self)
+    }
+
+    /// Applies the command to the sequence store, turning it into the command
+    /// that reverts it. If it fails, neither the store nor the command are
+    /// modified, so the command can be kept and tried again. The only
+    /// exception is a batch that can't be rolled back completely, see the
+    /// error for which steps are affected.
+    pub fn apply_in_place(
+        &mut self,
+        sequence_store: &mut SimpleStore<Sequence>,
+    ) -> Result<(), String> {
+        match self {
+            EditCommand::AddTrack {
+                sequence,
+                index,
+                track,
+            } => {
+                let (sequence, index) = (*sequence, *index);
+                for nested in track.contents.referenced_sequences() {
+                    check_nesting(sequence_store, sequence, nested)
+                        .map_err(|cycle| cycle.to_string())?;
+                }
+                let tracks = &mut get_sequence_mut(sequence_store, sequence)?.tracks;
+                if index > tracks.len() {
+                    return Err(format!(
+                        "cannot add track at index {}, sequence only has {} tracks",
+                        index,
+                        tracks.len()
+                    ));
+                }
+                let EditCommand::AddTrack { track, .. } =
+                    std::mem::replace(self, EditCommand::RemoveTrack { sequence, index })
+                else {
+                    unreachable!("matched above");
+                };
+                tracks.insert(index, track);
+            }
+            EditCommand::RemoveTrack { sequence, index } => {
+                let (sequence, index) = (*sequence, *index);
+                let tracks = &mut get_sequence_mut(sequence_store, sequence)?.tracks;
+                if index >= tracks.len() {
+                    return Err(format!(
+                        "cannot remove track {}, sequence only has {} tracks",
+                        index,
+                        tracks.len()
+                    ));
+                }
+                let track = tracks.remove(index);
+                *self = EditCommand::AddTrack {
+                    sequence,
+                    index,
+                    track,
+                };
+            }
+            EditCommand::MoveTrack { sequence, from, to } => {
+                let tracks = &mut get_sequence_mut(sequence_store, *sequence)?.tracks;
+                if *from >= tracks.len() || *to >= tracks.len() {
+                    return Err(format!(
+                        "cannot move track {} to {}, sequence only has {} tracks",
+                        from,
+                        to,
+                        tracks.len()
+                    ));
+                }
+                let track = tracks.remove(*from);
+                tracks.insert(*to, track);
+                std::mem::swap(from, to);
+            }
+            EditCommand::MoveClip {
+                sequence,
+                track,
+                clip,
+                time_segment,
+            } => {
+                let clip_ref = get_clips_mut(sequence_store, *sequence, *track)?
+                    .get_mut(*clip)
+                    .ok_or_else(|| format!("clip {} does not exist on track {}", clip, track))?;
+                std::mem::swap(&mut clip_ref.time_segment, time_segment);
+            }
+            EditCommand::AddClip {
+                sequence,
+                track,
+                index,
+                clip,
+            } => {
+                let (sequence, track, index) = (*sequence, *track, *index);
+                check_nesting(sequence_store, sequence, clip.sequence_handle)
+                    .map_err(|cycle| cycle.to_string())?;
+                if let Some(time_remap) = &clip.playback.time_remap {
+                    ClipPlayback::validate_time_remap(time_remap)?;
+                }
+                let clips = get_clips_mut(sequence_store, sequence, track)?;
+                if index > clips.len() {
+                    return Err(format!(
+                        "cannot add clip at index {}, track {} only has {} clips",
+                        index,
+                        track,
+                        clips.len()
+                    ));
+                }
+                let EditCommand::AddClip { clip, .. } = std::mem::replace(
+                    self,
+                    EditCommand::RemoveClip {
+                        sequence,
+                        track,
+                        index,
+                    },
+                ) else {
+                    unreachable!("matched above");
+                };
+                clips.insert(index, clip);
+            }
+            EditCommand::RemoveClip {
+                sequence,
+                track,
+                index,
+            } => {
+                let (sequence, track, index) = (*sequence, *track, *index);
+                let clips = get_clips_mut(sequence_store, sequence, track)?;
+                if index >= clips.len() {
+                    return Err(format!(
+                        "cannot remove clip {}, track {} only has {} clips",
+                        index,
+                        track,
+                        clips.len()
+                    ));
+                }
+                let clip = clips.remove(index);
+                *self = EditCommand::AddClip {
+                    sequence,
+                    track,
+                    index,
+                    clip,
+                };
+            }
+            EditCommand::SetKeyframes {
+                sequence,
+                track,
+                target,
+                keyframes,
+            } => {
+                let track_ref = get_track_mut(sequence_store, *sequence, *track)?;
+                let keyframes_ref = match (*target, &mut track_ref.contents) {
+                    (KeyframesTarget::Track, _) => {
+                        TrackInfo::validate_keyframes(keyframes)?;
+                        &mut track_ref.info.track_keyframes
+                    }
+                    (
+                        KeyframesTarget::Effect,
+                        TrackContents::EffectTrack {
+                            effect_init_info,
+                            effect_keyframes,
+                        },
+                    ) => {
+                        effect_init_info.validate_keyframes(keyframes)?;
+                        effect_keyframes
+                    }
+                    (KeyframesTarget::Effect, _) => {
+                        return Err(format!("track {} is not an effect track", track));
+                    }
+                };
+                std::mem::swap(keyframes_ref, keyframes);
+            }
+            EditCommand::SetEffect {
+                sequence,
+                track,
+                effect_info,
+            } => {
+                let track_ref = get_track_mut(sequence_store, *sequence, *track)?;
+                let TrackContents::EffectTrack {
+                    effect_init_info,
+                    effect_keyframes,
+                } = &mut track_ref.contents
+                else {
+                    return Err(format!("track {} is not an effect track", track));
+                };
+                // the keyframes, modulators and audio bindings of the track
+                // have to keep fitting the effect
+                effect_info.validate_keyframes(effect_keyframes)?;
+                let schemas = effect_info.parameters();
+                validate_modulators(&track_ref.info.modulators, schemas)?;
+                validate_audio_bindings(&track_ref.info.audio_bindings, schemas)?;
+                std::mem::swap(effect_init_info, effect_info);
+                // active copies of the effect have to pick up the new info
+                track_ref.mark_changed();
+            }
+            EditCommand::SetModulators {
+                sequence,
+                track,
+                modulators,
+            } => {
+                let track_ref = get_track_mut(sequence_store, *sequence, *track)?;
+                let schemas = match &track_ref.contents {
+                    TrackContents::EffectTrack {
+                        effect_init_info, ..
+                    } => effect_init_info.parameters(),
+                    _ => &[],
+                };
+                validate_modulators(modulators, schemas)?;
+                std::mem::swap(&mut track_ref.info.modulators, modulators);
+            }
+            EditCommand::SetAudioBindings {
+                sequence,
+                track,
+                audio_bindings,
+            } => {
+                let track_ref = get_track_mut(sequence_store, *sequence, *track)?;
+                let schemas = match &track_ref.contents {
+                    TrackContents::EffectTrack {
+                        effect_init_info, ..
+                    } => effect_init_info.parameters(),
+                    _ => &[],
+                };
+                validate_audio_bindings(audio_bindings, schemas)?;
+                std::mem::swap(&mut track_ref.info.audio_bindings, audio_bindings);
+            }
+            EditCommand::SetLoopRegion {
+                sequence,
+                loop_region,
+            } => {
+                let sequence_ref = get_sequence_mut(sequence_store, *sequence)?;
+                std::mem::swap(&mut sequence_ref.loop_region, loop_region);
+            }
+            EditCommand::SetAudio { sequence, audio } => {
+                let sequence_ref = get_sequence_mut(sequence_store, *sequence)?;
+                std::mem::swap(&mut sequence_ref.audio, audio);
+            }
+            EditCommand::Batch(commands) => {
+                for applied in 0..commands.len() {
+                    let Err(e) = commands[applied].apply_in_place(sequence_store) else {
+                        continue;
+                    };
+                    // roll back whatever already went through, so the batch
+                    // applies either fully or not at all. Every step is rolled
+                    // back, even if an earlier one can't be.
+                    let rollback_errors: Vec<String> = commands[..applied]
+                        .iter_mut()
+                        .rev()
+                        .filter_map(|command| command.apply_in_place(sequence_store).err())
+                        .collect();
+                    if rollback_errors.is_empty() {
+                        return Err(e);
+                    }
+                    return Err(format!(
+                        "{} (rolling back the rest of the batch failed too: {})",
+                        e,
+                        rollback_errors.join("; ")
+                    ));
+                }
+                // the steps have to be reverted in reverse order
+                commands.reverse();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn get_sequence_mut(
+    sequence_store: &mut SimpleStore<Sequence>,
+    sequence: SimpleHandle<Sequence>,
+) -> Result<&mut Sequence, String> {
+    sequence_store
+        .get_mut(sequence)
+        .ok_or_else(|| "sequence does not exist".to_string())
+}
+
+fn get_track_mut(
+    sequence_store: &mut SimpleStore<Sequence>,
+    sequence: SimpleHandle<Sequence>,
+    track: usize,
+) -> Result<&mut Track, String> {
+    get_sequence_mut(sequence_store, sequence)?
+        .tracks
+        .get_mut(track)
+        .ok_or_else(|| format!("track {} does not exist", track))
+}
+
+fn get_clips_mut(
+    sequence_store: &mut SimpleStore<Sequence>,
+    sequence: SimpleHandle<Sequence>,
+    track: usize,
+) -> Result<&mut Vec<Clip>, String> {
+    match &mut get_track_mut(sequence_store, sequence, track)?.contents {
+        TrackContents::SequenceTrack { clips } => Ok(clips),
+        _ => Err(format!("track {} is not a sequence track", track)),
+    }
+}
+
+/// A single step in the edit history. Holds the command that moves the show
+/// to the other side of the step (the inverse on the undo stack, the redo on
+/// the redo stack), plus the revisions on either side of it.
+#[derive(Debug)]
+struct HistoryEntry {
+    command: EditCommand,
+    target: Option<EditTarget>,
+    revision_before: u64,
+    revision_after: u64,
+}
+
+/// Bevy resource that holds the undo and redo stacks for show editing. Every
+/// state of the show gets a revision number, which is used to tell whether
+/// there are changes since the show was last saved.
+#[derive(Resource, Debug, Default)]
+pub struct EditHistory {
+    undo_stack: Vec<HistoryEntry>,
+    redo_stack: Vec<HistoryEntry>,
+    current_revision: u64,
+    saved_revision: u64,
+    next_revision: u64,
+}
+
+impl EditHistory {
+    /// Applies a command and records it on the undo stack, clearing the redo
+    /// stack. If `merge` is set and the previous step edited the same target
+    /// (e.g. the previous frame of the same drag), the two are merged into a
+    /// single step, so that undoing reverts to before the first of them.
+    pub fn apply(
+        &mut self,
+        command: EditCommand,
+        sequence_store: &mut SimpleStore<Sequence>,
+        merge: bool,
+    ) -> Result<(), String> {
+        let target = command.target();
+        let inverse = command.apply(sequence_store)?;
+        self.redo_stack.clear();
+
+        self.next_revision += 1;
+        let revision = self.next_revision;
+
+        match self.undo_stack.last_mut() {
+            Some(previous) if merge && target.is_some() && previous.target == target => {
+                // the previous inverse already restores the state from before
+                // the merged edits, so the new one can be dropped
+                previous.revision_after = revision;
+            }
+            _ => self.undo_stack.push(HistoryEntry {
+                command: inverse,
+                target,
+                revision_before: self.current_revision,
+                revision_after: revision,
+            }),
+        }
+        self.current_revision = revision;
+        Ok(())
+    }
+
+    /// Reverts the most recent step. Returns whether there was anything to
+    /// undo.
+    pub fn undo(&mut self, sequence_store: &mut SimpleStore<Sequence>) -> Result<bool, String> {
+        let Some(mut entry) = self.undo_stack.pop() else {
+            return Ok(false);
+        };
+        if let Err(e) = entry.command.apply_in_place(sequence_store) {
+            // nothing changed, so the step stays where it was to be tried
+            // again
+            self.undo_stack.push(entry);
+            return Err(e);
+        }
+        self.current_revision = entry.revision_before;
+        self.redo_stack.push(entry);
+        Ok(true)
+    }
+
+    /// Re-applies the most recently undone step. Returns whether there was
+    /// anything to redo.
+    pub fn redo(&mut self, sequence_store: &mut SimpleStore<Sequence>) -> Result<bool, String> {
+        let Some(mut entry) = self.redo_stack.pop() else {
+            return Ok(false);
+        };
+        if let Err(e) = entry.command.apply_in_place(sequence_store) {
+            self.redo_stack.push(entry);
+            return Err(e);
+        }
+        self.current_revision = entry.revision_after;
+        self.undo_stack.push(entry);
+        Ok(true)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Whether the show has changed since it was last saved (or loaded).
+    pub fn is_dirty(&self) -> bool {
+        self.current_revision != self.saved_revision
+    }
+
+    /// Marks the current state of the show as saved.
+    pub fn mark_saved(&mut self) {
+        self.saved_revision = self.current_revision;
+    }
+
+    /// Drops all history, e.g. after a different show has been loaded. The
+    /// resulting state counts as saved.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Bevy event that applies an edit through the edit history. See
+/// `EditHistory::apply` for the meaning of `merge`.
+#[derive(Event)]
+pub struct ApplyEdit {
+    command: Option<EditCommand>,
+    merge: bool,
+}
+
+impl ApplyEdit {
+    /// Constructs a new `ApplyEdit` event.
+    pub fn new(command: EditCommand, merge: bool) -> Self {
+        Self {
+            command: Some(command),
+            merge,
+        }
+    }
+}
+
+/// Bevy event that undoes the most recent edit.
+#[derive(Event)]
+pub struct UndoEdit {}
+
+/// Bevy event that redoes the most recently undone edit.
+#[derive(Event)]
+pub struct RedoEdit {}
+
+/// Bevy observer that listens for `ApplyEdit` events and applies them to the
+/// sequence store. The sequence tree picks up the changes by itself.
+fn apply_edit(
+    mut edit: On<ApplyEdit>,
+    mut history: ResMut<EditHistory>,
+    mut sequence_store: ResMut<SimpleStore<Sequence>>,
+) {
+    let merge = edit.merge;
+    let Some(command) = edit.event_mut().command.take() else {
+        return;
+    };
+    if let Err(e) = history.apply(command, &mut sequence_store, merge) {
+        warn!("Failed to apply edit: {}", e);
+    }
+}
+
+/// Bevy observer that listens for `UndoEdit` events.
+fn undo_edit(
+    _undo: On<UndoEdit>,
+    mut history: ResMut<EditHistory>,
+    mut sequence_store: ResMut<SimpleStore<Sequence>>,
+) {
+    if let Err(e) = history.undo(&mut sequence_store) {
+        warn!("Failed to undo edit: {}", e);
+    }
+}
+
+/// Bevy observer that listens for `RedoEdit` events.
+fn redo_edit(
+    _redo: On<RedoEdit>,
+    mut history: ResMut<EditHistory>,
+    mut sequence_store: ResMut<SimpleStore<Sequence>>,
+) {
+    if let Err(e) = history.redo(&mut sequence_store) {
+        warn!("Failed to redo edit: {}", e);
+    }
+}
@@ -1,11 +1,13 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     network::{ArtNetBuffers, ArtNetDataPointer},
-    timeline::sequence_tree::SequenceTree,
+    timeline::sequence_tree::{SequenceTree, TreeEvaluator},
     util::blending::{BlendingMode, colors::blend_colors, pan_tilt::blend_pan_tilt},
 };
 
+#[cfg(feature = "gui")]
 pub mod color_light;
 
 /// Bevy plugin for fixtures.
@@ -15,8 +17,13 @@ impl Plugin for FixturesPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(FixedUpdate, update_fixtures)
             .add_systems(FixedUpdate, add_color_data_to_buffer)
-            .add_systems(FixedUpdate, add_pan_tilt_data_to_buffer)
-            .add_systems(Update, apply_color_fixture_material);
+            .add_systems(FixedUpdate, add_pan_tilt_data_to_buffer);
+        #[cfg(feature = "gui")]
+        app.add_systems(
+            Update,
+            // there is nothing to draw when running headless
+            apply_color_fixture_material.run_if(resource_exists::<Assets<ColorMaterial>>),
+        );
     }
 }
 
@@ -25,7 +32,8 @@ impl Plugin for FixturesPlugin {
 ///
 /// TODO: Add fixture series eventually.
 #[derive(Component, Debug, Default)]
-#[require(Transform, Mesh2d, MeshMaterial2d<ColorMaterial>)]
+#[require(Transform)]
+#[cfg_attr(feature = "gui", require(Mesh2d, MeshMaterial2d<ColorMaterial>))]
 pub struct Fixture {
     pub groups: Vec<u32>,
 }
@@ -61,8 +69,58 @@ impl Default for ColorFixture {
     }
 }
 
+impl ColorFixture {
+    /// Writes the current color of the fixture into the DMX buffers, at the
+    /// red, green, blue (and white) channels counted from the pointer. With a
+    /// white channel, the white part of the color is taken out of the others.
+    pub fn write_dmx(
+        &self,
+        pointer: ArtNetDataPointer,
+        buffers: &mut ArtNetBuffers,
+    ) -> Result<(), String> {
+        let (mut r, mut g, mut b) = match self.encoding {
+            RgbEncoding::Linear => {
+                let c = self.color.to_linear();
+                (c.red, c.green, c.blue)
+            }
+            RgbEncoding::Srgb => {
+                let c = self.color.to_srgba();
+                (c.red, c.green, c.blue)
+            }
+        };
+
+        let w = self.white_channel.map(|_| {
+            let w = r.min(g).min(b);
+            r -= w;
+            g -= w;
+            b -= w;
+            w
+        });
+
+        buffers.write(
+            pointer.offset_by(self.red_channel as u16)?,
+            (r * 255.0).clamp(0.0, 255.0) as u8,
+        )?;
+        buffers.write(
+            pointer.offset_by(self.green_channel as u16)?,
+            (g * 255.0).clamp(0.0, 255.0) as u8,
+        )?;
+        buffers.write(
+            pointer.offset_by(self.blue_channel as u16)?,
+            (b * 255.0).clamp(0.0, 255.0) as u8,
+        )?;
+        if let (Some(white_offset), Some(w)) = (self.white_channel, w) {
+            buffers.write(
+                pointer.offset_by(white_offset as u16)?,
+                (w * 255.0).clamp(0.0, 255.0) as u8,
+            )?;
+        }
+        Ok(())
+    }
+}
+
 /// Enum that represents the encoding of the color data.
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RgbEncoding {
     #[default]
     Linear,
@@ -102,9 +160,30 @@ pub struct PanTiltFixture {
     pub tilt_range: (f32, f32),
 }
 
+impl PanTiltFixture {
+    /// Writes the current pan and tilt of the fixture into the DMX buffers,
+    /// as the first two channels from the pointer. Each angle is scaled
+    /// across its range.
+    pub fn write_dmx(
+        &self,
+        pointer: ArtNetDataPointer,
+        buffers: &mut ArtNetBuffers,
+    ) -> Result<(), String> {
+        let pan = ((self.pan - self.pan_range.0) / (self.pan_range.1 - self.pan_range.0) * 255.0)
+            .clamp(0.0, 255.0) as u8;
+        let tilt = ((self.tilt - self.tilt_range.0) / (self.tilt_range.1 - self.tilt_range.0)
+            * 255.0)
+            .clamp(0.0, 255.0) as u8;
+
+        buffers.write(pointer.offset_by(0)?, pan)?;
+        buffers.write(pointer.offset_by(1)?, tilt)?;
+        Ok(())
+    }
+}
+
 /// Simple data struct used to group important request information together
 /// when pulling data from the scene tree.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct FixtureRequest {
     pub groups: Vec<u32>,
     pub position: Vec3,
@@ -172,9 +251,13 @@ impl FixtureResponse {
 }
 
 /// Bevy system that updates all fixture information, pulling from the sequence
-/// tree. Expects the sequence tree to be up to date.
+/// tree. Expects the sequence tree to be up to date. The requests and the
+/// evaluation buffers are kept between updates, so this doesn't allocate once
+/// the set of fixtures is stable.
 pub fn update_fixtures(
     sequence_tree: Res<SequenceTree>,
+    mut fixture_reqs: Local<Vec<FixtureRequest>>,
+    mut evaluator: Local<TreeEvaluator>,
     mut fixture_query: Query<(
         &mut Fixture,
         Option<&mut ColorFixture>,
@@ -182,18 +265,18 @@ pub fn update_fixtures(
         &GlobalTransform,
     )>,
 ) {
-    let mut fixture_reqs: Vec<FixtureRequest> = Vec::new();
-
-    for (fixture, color_fixture, pan_tilt_fixture, transform) in fixture_query.iter_mut() {
-        fixture_reqs.push(FixtureRequest {
-            groups: fixture.groups.clone(),
-            position: transform.translation(),
-            has_color: color_fixture.is_some(),
-            has_pan_tilt: pan_tilt_fixture.is_some(),
-        })
+    fixture_reqs.resize_with(fixture_query.iter().count(), FixtureRequest::default);
+
+    for (request, (fixture, color_fixture, pan_tilt_fixture, transform)) in
+        fixture_reqs.iter_mut().zip(fixture_query.iter())
+    {
+        request.groups.clone_from(&fixture.groups);
+        request.position = transform.translation();
+        request.has_color = color_fixture.is_some();
+        request.has_pan_tilt = pan_tilt_fixture.is_some();
     }
 
-    let values = sequence_tree.get_values_recursive(&fixture_reqs);
+    let values = sequence_tree.get_values_recursive(&fixture_reqs, &mut evaluator);
 
     assert_eq!(values.len(), fixture_reqs.len());
 
@@ -216,48 +299,7 @@ pub fn add_color_data_to_buffer(
     color_query: Query<(&ArtNetDataPointer, &ColorFixture)>,
 ) {
     for (pointer, fixture) in color_query.iter() {
-        let (mut r, mut g, mut b) = match fixture.encoding {
-            RgbEncoding::Linear => {
-                let c = fixture.color.to_linear();
-                (c.red, c.green, c.blue)
-            }
-            RgbEncoding::Srgb => {
-                let c = fixture.color.to_srgba();
-                (c.red, c.green, c.blue)
-            }
-        };
-
-        let w = fixture.white_channel.map(|_| {
-            let w = r.min(g).min(b);
-            r -= w;
-            g -= w;
-            b -= w;
-            w
-        });
-
-        let result: Result<(), String> = (|| {
-            buffers.write(
-                pointer.offset_by(fixture.red_channel as u16)?,
-                (r * 255.0).clamp(0.0, 255.0) as u8,
-            )?;
-            buffers.write(
-                pointer.offset_by(fixture.green_channel as u16)?,
-                (g * 255.0).clamp(0.0, 255.0) as u8,
-            )?;
-            buffers.write(
-                pointer.offset_by(fixture.blue_channel as u16)?,
-                (b * 255.0).clamp(0.0, 255.0) as u8,
-            )?;
-            if let (Some(white_offset), Some(w)) = (fixture.white_channel, w) {
-                buffers.write(
-                    pointer.offset_by(white_offset as u16)?,
-                    (w * 255.0).clamp(0.0, 255.0) as u8,
-                )?;
-            }
-            Ok(())
-        })();
-
-        if let Err(e) = result {
+        if let Err(e) = fixture.write_dmx(*pointer, &mut buffers) {
             warn!("Failed to write fixture DMX data: {}", e);
         }
     }
@@ -269,22 +311,7 @@ pub fn add_pan_tilt_data_to_buffer(
     pan_tilt_query: Query<(&ArtNetDataPointer, &PanTiltFixture)>,
 ) {
     for (pointer, fixture) in pan_tilt_query.iter() {
-        let pan = ((fixture.pan - fixture.pan_range.0)
-            / (fixture.pan_range.1 - fixture.pan_range.0)
-            * 255.0)
-            .clamp(0.0, 255.0) as u8;
-        let tilt = ((fixture.tilt - fixture.tilt_range.0)
-            / (fixture.tilt_range.1 - fixture.tilt_range.0)
-            * 255.0)
-            .clamp(0.0, 255.0) as u8;
-
-        let result: Result<(), String> = (|| {
-            buffers.write(pointer.offset_by(0)?, pan)?;
-            buffers.write(pointer.offset_by(1)?, tilt)?;
-            Ok(())
-        })();
-
-        if let Err(e) = result {
+        if let Err(e) = fixture.write_dmx(*pointer, &mut buffers) {
             warn!("Failed to write fixture DMX data: {}", e);
         }
     }
@@ -292,6 +319,7 @@ pub fn add_pan_tilt_data_to_buffer(
 
 /// Bevy system that updates the materials of fixtures that have color fixture
 /// components in the visual representation.
+#[cfg(feature = "gui")]
 pub fn apply_color_fixture_material(
     mut materials: ResMut<Assets<ColorMaterial>>,
     query: Query<(&ColorFixture, &MeshMaterial2d<ColorMaterial>)>,
@@ -0,0 +1,458 @@
+use bevy::{
+    app::{ScheduleRunnerPlugin, TerminalCtrlCHandlerPlugin},
+    log::LogPlugin,
+    prelude::*,
+};
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    LightshowCorePlugin,
+    audio::{
+        capture::AudioCapture,
+        processing::fft::{FftConfig, FftProcessor},
+    },
+    midi::{MidiInputDevice, MidiInputManager},
+    show_file::{LoadShow, ShowFile},
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{cues::*, playback::*, sequences::*},
+};
+
+/// How often the headless runner updates, in Hz. Fixtures and the network
+/// output still run at the update rate of the show, this only needs to be
+/// comfortably faster than that.
+const UPDATE_RATE_HZ: f64 = 240.0;
+
+/// Every command the headless runner understands, as listed by `help`.
+const HELP: &str = "commands: load <show file>, play, pause, stop, seek <seconds>, \
+    sequence <name>, go [cue list], back [cue list], release [cue list], status, help, quit";
+
+/// Bevy plugin for running a show without a display, e.g. on a small box in
+/// an installation. Runs everything `LightshowCorePlugin` does, controlled by
+/// `RunnerCommand`s sent through `RunnerControl` rather than a UI.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(
+            Duration::from_secs_f64(1.0 / UPDATE_RATE_HZ),
+        )))
+        .add_plugins((LogPlugin::default(), TerminalCtrlCHandlerPlugin))
+        .add_plugins(LightshowCorePlugin)
+        .init_resource::<RunnerControl>()
+        .add_systems(Update, run_runner_commands.before(increment_playback_time));
+    }
+}
+
+/// A command for the headless runner, written as a single line of text, e.g.
+/// `seek 12.5` or `go Main`. Cue list commands without a name apply to the
+/// first cue list of the show.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunnerCommand {
+    /// Replaces the current show with one loaded from disk.
+    Load(PathBuf),
+    Play,
+    Pause,
+    /// Pauses playback and moves the playback head back to the start.
+    Stop,
+    /// Moves the playback head to a time in seconds.
+    Seek(f64),
+    /// Opens the sequence with the given name and starts it from the top.
+    Sequence(String),
+    Go(Option<String>),
+    Back(Option<String>),
+    Release(Option<String>),
+    /// Answers with the state of playback and of every cue list.
+    Status,
+    Help,
+    Quit,
+}
+
+impl FromStr for RunnerCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (name, argument) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(name, argument)| (name, argument.trim()));
+        let argument = (!argument.is_empty()).then(|| argument.to_string());
+        let name = name.to_lowercase();
+        let required = |what: &str| {
+            argument
+                .clone()
+                .ok_or_else(|| format!("{} needs {}", name, what))
+        };
+        let none = |command: RunnerCommand| match &argument {
+            Some(_) => Err(format!("{} takes no argument", name)),
+            None => Ok(command),
+        };
+
+        match name.as_str() {
+            "load" => Ok(RunnerCommand::Load(required("a show file")?.into())),
+            "play" => none(RunnerCommand::Play),
+            "pause" => none(RunnerCommand::Pause),
+            "stop" => none(RunnerCommand::Stop),
+            "seek" => {
+                let time = required("a time in seconds")?;
+                match time.parse::<f64>() {
+                    Ok(time) if time.is_finite() => Ok(RunnerCommand::Seek(time)),
+                    _ => Err(format!("invalid time \"{}\"", time)),
+                }
+            }
+            "sequence" => Ok(RunnerCommand::Sequence(required("a sequence name")?)),
+            "go" => Ok(RunnerCommand::Go(argument)),
+            "back" => Ok(RunnerCommand::Back(argument)),
+            "release" => Ok(RunnerCommand::Release(argument)),
+            "status" => none(RunnerCommand::Status),
+            "help" => none(RunnerCommand::Help),
+            "quit" | "exit" => none(RunnerCommand::Quit),
+            "" => Err("empty command".to_string()),
+            _ => Err(format!("unknown command \"{}\", try \"help\"", name)),
+        }
+    }
+}
+
+impl fmt::Display for RunnerCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let with_cue_list =
+            |f: &mut fmt::Formatter<'_>, name, cue_list: &Option<String>| match cue_list {
+                Some(cue_list) => write!(f, "{} {}", name, cue_list),
+                None => write!(f, "{}", name),
+            };
+        match self {
+            RunnerCommand::Load(path) => write!(f, "load {}", path.display()),
+            RunnerCommand::Play => write!(f, "play"),
+            RunnerCommand::Pause => write!(f, "pause"),
+            RunnerCommand::Stop => write!(f, "stop"),
+            RunnerCommand::Seek(time) => write!(f, "seek {}", time),
+            RunnerCommand::Sequence(name) => write!(f, "sequence {}", name),
+            RunnerCommand::Go(cue_list) => with_cue_list(f, "go", cue_list),
+            RunnerCommand::Back(cue_list) => with_cue_list(f, "back", cue_list),
+            RunnerCommand::Release(cue_list) => with_cue_list(f, "release", cue_list),
+            RunnerCommand::Status => write!(f, "status"),
+            RunnerCommand::Help => write!(f, "help"),
+            RunnerCommand::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+/// A command waiting to be run, along with where to send the reply to. The
+/// reply is only logged if there is nowhere to send it.
+struct RunnerRequest {
+    command: RunnerCommand,
+    reply: Option<Sender<Result<String, String>>>,
+}
+
+/// Bevy resource that collects `RunnerCommand`s from wherever they come from,
+/// until they are run on the next update.
+#[derive(Resource)]
+pub struct RunnerControl {
+    sender: Sender<RunnerRequest>,
+    requests: Mutex<Receiver<RunnerRequest>>,
+}
+
+impl Default for RunnerControl {
+    fn default() -> Self {
+        let (sender, requests) = mpsc::channel();
+        Self {
+            sender,
+            requests: Mutex::new(requests),
+        }
+    }
+}
+
+impl RunnerControl {
+    /// Queues a command to run on the next update.
+    pub fn queue(&self, command: RunnerCommand) {
+        let _ = self.sender.send(RunnerRequest {
+            command,
+            reply: None,
+        });
+    }
+
+    /// Takes commands from standard input, one per line, and answers each on
+    /// standard output.
+    pub fn listen_stdin(&self) {
+        let sender = self.sender.clone();
+        thread::spawn(move || serve_lines(io::stdin().lock(), io::stdout(), &sender));
+    }
+
+    /// Accepts connections on a TCP socket, taking commands one per line and
+    /// answering each with a line of its own. Anyone who can connect has full
+    /// control over the show, so this is meant for local addresses such as
+    /// `127.0.0.1:7770`. Returns the address the socket was bound to.
+    pub fn listen(&self, address: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(address)?;
+        let local_address = listener.local_addr()?;
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        thread::spawn(move || serve_connection(stream, &sender));
+                    }
+                    Err(e) => warn!("Failed to accept control connection: {}", e),
+                }
+            }
+        });
+        Ok(local_address)
+    }
+}
+
+fn serve_connection(stream: TcpStream, sender: &Sender<RunnerRequest>) {
+    match stream.try_clone() {
+        Ok(input) => serve_lines(BufReader::new(input), stream, sender),
+        Err(e) => warn!("Failed to read from control connection: {}", e),
+    }
+}
+
+/// Runs every line of the input as a command, answering each with a line
+/// starting with `ok` or `error`, followed by a message. Stops once the input
+/// ends or the runner has shut down.
+fn serve_lines(input: impl BufRead, mut output: impl Write, sender: &Sender<RunnerRequest>) {
+    for line in input.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match line.parse::<RunnerCommand>() {
+            Ok(command) => {
+                let (reply_sender, reply_receiver) = mpsc::channel();
+                let request = RunnerRequest {
+                    command,
+                    reply: Some(reply_sender),
+                };
+                if sender.send(request).is_err() {
+                    break;
+                }
+                match reply_receiver.recv() {
+                    Ok(reply) => reply,
+                    Err(_) => break,
+                }
+            }
+            Err(e) => Err(e),
+        };
+        let answer = match reply {
+            Ok(message) => format!("ok {}", message),
+            Err(e) => format!("error {}", e),
+        };
+        if writeln!(output, "{}", answer.trim_end())
+            .and_then(|()| output.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Bevy system that runs the commands waiting in `RunnerControl`, in the
+/// order they arrived. Each command sees the effects of the ones before it,
+/// e.g. `play` right after `load` plays the new show.
+pub fn run_runner_commands(world: &mut World) {
+    let requests: Vec<RunnerRequest> = match world.resource::<RunnerControl>().requests.lock() {
+        Ok(requests) => requests.try_iter().collect(),
+        Err(_) => return,
+    };
+
+    for request in requests {
+        let reply = run_command(world, &request.command);
+        match request.reply {
+            Some(reply_sender) => {
+                let _ = reply_sender.send(reply);
+            }
+            None => match reply {
+                Ok(message) => info!("{}: {}", request.command, message),
+                Err(e) => error!("{}: {}", request.command, e),
+            },
+        }
+    }
+}
+
+fn run_command(world: &mut World, command: &RunnerCommand) -> Result<String, String> {
+    match command {
+        RunnerCommand::Load(path) => {
+            // loading the show only logs failures, so check first to answer
+            ShowFile::load(path)
+                .map_err(|e| format!("failed to load show from {}: {}", path.display(), e))?;
+            world.trigger(LoadShow { path: path.clone() });
+            Ok(format!("loaded {}", path.display()))
+        }
+        RunnerCommand::Play => {
+            controllable_playback(world)?.play();
+            Ok("playing".to_string())
+        }
+        RunnerCommand::Pause => {
+            controllable_playback(world)?.pause();
+            Ok("paused".to_string())
+        }
+        RunnerCommand::Stop => {
+            let mut playback = controllable_playback(world)?;
+            playback.pause();
+            playback.seek(0.0);
+            Ok("stopped".to_string())
+        }
+        RunnerCommand::Seek(time) => {
+            controllable_playback(world)?.seek(time.max(0.0));
+            Ok(format!("at {:.2} s", time.max(0.0)))
+        }
+        RunnerCommand::Sequence(name) => {
+            let handle = world
+                .resource::<SimpleStore<Sequence>>()
+                .iter()
+                .find(|(_, sequence)| sequence.name == *name)
+                .map(|(handle, _)| handle)
+                .ok_or_else(|| format!("no sequence named \"{}\"", name))?;
+            world.resource_mut::<PrimarySequence>().0 = Some(handle);
+            world.resource_mut::<PlaybackInformation>().seek(0.0);
+            Ok(format!("opened \"{}\"", name))
+        }
+        RunnerCommand::Go(name) => {
+            let (cue_list, name) = find_cue_list(world, name.as_deref())?;
+            world.trigger(CueGo { cue_list });
+            Ok(format!("go on \"{}\"", name))
+        }
+        RunnerCommand::Back(name) => {
+            let (cue_list, name) = find_cue_list(world, name.as_deref())?;
+            world.trigger(CueBack { cue_list });
+            Ok(format!("back on \"{}\"", name))
+        }
+        RunnerCommand::Release(name) => {
+            let (cue_list, name) = find_cue_list(world, name.as_deref())?;
+            world.trigger(CueRelease { cue_list });
+            Ok(format!("released \"{}\"", name))
+        }
+        RunnerCommand::Status => Ok(status(world)),
+        RunnerCommand::Help => Ok(HELP.to_string()),
+        RunnerCommand::Quit => {
+            world.write_message(AppExit::Success);
+            Ok("quitting".to_string())
+        }
+    }
+}
+
+/// Gets the playback information, unless an external clock is in control of
+/// playback.
+fn controllable_playback(world: &mut World) -> Result<Mut<'_, PlaybackInformation>, String> {
+    let playback = world.resource_mut::<PlaybackInformation>();
+    if playback.external_clock {
+        return Err("playback follows an external clock".to_string());
+    }
+    Ok(playback)
+}
+
+/// Finds a cue list by name, or the first cue list without one.
+fn find_cue_list(
+    world: &World,
+    name: Option<&str>,
+) -> Result<(SimpleHandle<CueList>, String), String> {
+    let mut cue_lists = world.resource::<SimpleStore<CueList>>().iter();
+    let found = match name {
+        Some(name) => cue_lists.find(|(_, cue_list)| cue_list.name == name),
+        None => cue_lists.next(),
+    };
+    match (found, name) {
+        (Some((handle, cue_list)), _) => Ok((handle, cue_list.name.clone())),
+        (None, Some(name)) => Err(format!("no cue list named \"{}\"", name)),
+        (None, None) => Err("the show has no cue lists".to_string()),
+    }
+}
+
+/// Describes the state of playback and of every running cue list, on a
+/// single line.
+fn status(world: &World) -> String {
+    let playback = world.resource::<PlaybackInformation>();
+    let sequence_store = world.resource::<SimpleStore<Sequence>>();
+    let mut status = match world
+        .resource::<PrimarySequence>()
+        .0
+        .and_then(|handle| sequence_store.get(handle))
+    {
+        Some(sequence) => {
+            let state = if playback.blacked_out {
+                "blacked out"
+            } else if playback.is_playing {
+                "playing"
+            } else {
+                "paused"
+            };
+            format!(
+                "{} \"{}\" at {:.2} of {:.2} s",
+                state, sequence.name, playback.current_time, sequence.length
+            )
+        }
+        None => "no sequence open".to_string(),
+    };
+
+    let cue_playback = world.resource::<CuePlayback>();
+    for (handle, cue_list) in world.resource::<SimpleStore<CueList>>().iter() {
+        if let Some(index) = cue_playback.current_cue(handle)
+            && let Some(cue) = cue_list.cues.get(index)
+        {
+            status += &format!(
+                ", \"{}\" on cue {} \"{}\"",
+                cue_list.name,
+                index + 1,
+                cue.name
+            );
+        }
+    }
+    status
+}
+
+/// Connects every MIDI input whose name contains any of the given names,
+/// returning the full names of the inputs connected.
+pub fn connect_midi_inputs(world: &mut World, names: &[String]) -> Result<Vec<String>, String> {
+    let mut manager = world.resource_mut::<MidiInputManager>();
+    manager.init_client().map_err(|e| e.to_string())?;
+    let mut matching = Vec::new();
+    for port in manager.get_available_ports().map_err(|e| e.to_string())? {
+        let port_name = manager.port_name(&port).map_err(|e| e.to_string())?;
+        if names.iter().any(|name| port_name.contains(name.as_str())) {
+            matching.push((port, port_name));
+        }
+    }
+
+    let mut devices = Vec::new();
+    let mut connected = Vec::new();
+    for (index, (port, port_name)) in matching.into_iter().enumerate() {
+        // every connection uses up the client it was opened with
+        if index > 0 {
+            manager.init_client().map_err(|e| e.to_string())?;
+        }
+        let (connection, message_queue) = manager
+            .open_new_midi_connection(&port)
+            .map_err(|e| format!("failed to connect to \"{}\": {}", port_name, e))?;
+        devices.push(MidiInputDevice {
+            connection: Mutex::new(connection),
+            message_queue,
+        });
+        connected.push(port_name);
+    }
+    world.spawn_batch(devices);
+    Ok(connected)
+}
+
+/// Starts capturing the default audio input for the FFT processor, which
+/// audio reactive effects follow while no song is playing.
+pub fn start_audio_capture(world: &mut World) -> Result<(), String> {
+    let (capture, consumer) = AudioCapture::default().map_err(|e| e.to_string())?;
+    world.insert_resource(capture);
+    world.insert_resource(FftProcessor::new(FftConfig::default(), consumer));
+    Ok(())
+}
@@ -1,40 +1,73 @@
 use bevy::prelude::*;
 
+#[cfg(feature = "gui")]
 use bevy_egui::EguiPlugin;
 
 use crate::audio::AudioPlugin;
+#[cfg(feature = "gui")]
 use crate::camera::CameraPlugin;
+use crate::editing::EditingPlugin;
 use crate::fixtures::FixturesPlugin;
 use crate::midi::MidiPlugin;
 use crate::network::NetworkPlugin;
+use crate::show_file::ShowFilePlugin;
+use crate::timecode::TimecodePlugin;
 use crate::timeline::TimelinePlugin;
+#[cfg(feature = "gui")]
 use crate::ui::UiPlugin;
 
 pub mod audio;
+#[cfg(feature = "gui")]
 pub mod camera;
+pub mod editing;
 pub mod fixtures;
+pub mod headless;
 pub mod midi;
 pub mod network;
+pub mod render;
+pub mod show_file;
 pub mod simple_store;
+#[cfg(feature = "gui")]
 pub mod tests;
+pub mod timecode;
 pub mod timeline;
+#[cfg(feature = "gui")]
 pub mod ui;
 pub mod util;
 
+/// Bevy plugin for the full program, with a window, the fixture preview and
+/// the editing UI.
+#[cfg(feature = "gui")]
 pub struct LightshowPlugin;
 
+#[cfg(feature = "gui")]
 impl Plugin for LightshowPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ClearColor(Color::srgb(0.1, 0.1, 0.1)))
-            .insert_resource(Time::<Fixed>::from_hz(44.0))
             .add_plugins(DefaultPlugins)
             .add_plugins(CameraPlugin)
-            .add_plugins(TimelinePlugin)
-            .add_plugins(FixturesPlugin)
             .add_plugins(EguiPlugin::default())
             .add_plugins(UiPlugin)
+            .add_plugins(LightshowCorePlugin)
+            .add_systems(Startup, tests::single_pulse::pulse_test_startup);
+    }
+}
+
+/// Bevy plugin for everything needed to run a show, without anything that
+/// needs a display. Shared by `LightshowPlugin` and the headless runner (see
+/// `headless::HeadlessPlugin`).
+pub struct LightshowCorePlugin;
+
+impl Plugin for LightshowCorePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(44.0))
+            .add_plugins(TimelinePlugin)
+            .add_plugins(FixturesPlugin)
             .add_plugins(AudioPlugin)
             .add_plugins(MidiPlugin)
-            .add_plugins(NetworkPlugin);
+            .add_plugins(NetworkPlugin)
+            .add_plugins(TimecodePlugin)
+            .add_plugins(ShowFilePlugin)
+            .add_plugins(EditingPlugin);
     }
 }
@@ -3,32 +3,107 @@ use midir::{self, MidiInput, MidiInputConnection, MidiInputPort};
 use std::{
     collections::VecDeque,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
+pub mod clock;
+
 pub struct MidiPlugin;
 
 impl Plugin for MidiPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<MidiInputManager>();
+        app.init_resource::<MidiInputManager>()
+            .add_plugins(clock::MidiClockPlugin)
+            .add_systems(Update, dispatch_midi_messages);
     }
 }
 
+/// Messages received on a MIDI input, along with when they arrived.
+pub type MidiMessageQueue = Arc<Mutex<VecDeque<(Instant, MidiMessage)>>>;
+
 #[derive(Component)]
 pub struct MidiInputDevice {
     pub connection: Mutex<MidiInputConnection<()>>,
-    pub message_queue: Arc<Mutex<VecDeque<MidiMessage>>>,
+    pub message_queue: MidiMessageQueue,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum MidiMessage {
-    NoteOn { channel: u8, note: u8, velocity: u8 },
-    NoteOff { channel: u8, note: u8, velocity: u8 },
-    ControlChange { channel: u8, control: u8, value: u8 },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        control: u8,
+        value: u8,
+    },
+    /// One of the eight MIDI Time Code quarter frames that together carry a
+    /// full timecode.
+    TimecodeQuarterFrame {
+        piece: u8,
+        value: u8,
+    },
+    /// A full MIDI Time Code message, which is sent when the source locates
+    /// to a new position. `rate` is the two bit frame rate code.
+    TimecodeFullFrame {
+        hours: u8,
+        minutes: u8,
+        seconds: u8,
+        frames: u8,
+        rate: u8,
+    },
+    /// MIDI beat clock, sent 24 times per beat.
+    TimingClock,
+    /// Starts playback from the beginning of the song.
+    Start,
+    /// Starts playback from the current song position.
+    Continue,
+    Stop,
+    /// Moves the song position, counted in sixteenth notes (6 clocks) from
+    /// the beginning of the song.
+    SongPosition {
+        position: u16,
+    },
 }
 
 impl MidiMessage {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
         let first_byte = *bytes.get(0).ok_or("bytes cannot be empty")?;
+        match first_byte {
+            0xF0 => return Self::from_sysex(bytes),
+            0xF2 => {
+                let lsb = *bytes
+                    .get(1)
+                    .ok_or("song position messages must include a position")?;
+                let msb = *bytes
+                    .get(2)
+                    .ok_or("song position messages must include a position")?;
+                return Ok(MidiMessage::SongPosition {
+                    position: (msb as u16 & 0x7F) << 7 | lsb as u16 & 0x7F,
+                });
+            }
+            0xF8 => return Ok(MidiMessage::TimingClock),
+            0xFA => return Ok(MidiMessage::Start),
+            0xFB => return Ok(MidiMessage::Continue),
+            0xFC => return Ok(MidiMessage::Stop),
+            0xF1 => {
+                let data = *bytes
+                    .get(1)
+                    .ok_or("quarter frame messages must include a value")?;
+                return Ok(MidiMessage::TimecodeQuarterFrame {
+                    piece: (data >> 4) & 0x07,
+                    value: data & 0x0F,
+                });
+            }
+            _ => {}
+        }
         let first_four_bits = (first_byte & 0xF0u8) >> 4;
         let channel = first_byte & 0x0Fu8;
         match first_four_bits {
@@ -60,6 +135,55 @@ impl MidiMessage {
             _ => Err("unknown MIDI message"),
         }
     }
+
+    /// Parses a system exclusive message. Only MIDI Time Code full frames
+    /// (`F0 7F <device> 01 01 hh mm ss ff F7`) are understood.
+    fn from_sysex(bytes: &[u8]) -> Result<Self, &'static str> {
+        match *bytes {
+            [0xF0, 0x7F, _, 0x01, 0x01, hr, mn, sc, fr, 0xF7] => {
+                Ok(MidiMessage::TimecodeFullFrame {
+                    hours: hr & 0x1F,
+                    minutes: mn,
+                    seconds: sc,
+                    frames: fr,
+                    rate: (hr >> 5) & 0x03,
+                })
+            }
+            _ => Err("unknown MIDI system exclusive message"),
+        }
+    }
+}
+
+/// Bevy event that is triggered for every message received on an open MIDI
+/// input.
+#[derive(Event, Debug)]
+pub struct MidiMessageReceived {
+    pub message: MidiMessage,
+    pub received_at: Instant,
+}
+
+impl MidiMessageReceived {
+    /// Finds the Bevy time (see `Time::elapsed_secs_f64`) at which the message
+    /// arrived, given the current Bevy time.
+    pub fn received_at(&self, now: f64) -> f64 {
+        now - self.received_at.elapsed().as_secs_f64()
+    }
+}
+
+/// Empties the message queue of every open MIDI input, triggering a
+/// `MidiMessageReceived` for each message in the order they arrived.
+pub fn dispatch_midi_messages(mut commands: Commands, devices: Query<&MidiInputDevice>) {
+    for device in devices.iter() {
+        let Ok(mut queue) = device.message_queue.lock() else {
+            continue;
+        };
+        for (received_at, message) in queue.drain(..) {
+            commands.trigger(MidiMessageReceived {
+                message,
+                received_at,
+            });
+        }
+    }
 }
 
 #[derive(Resource, Default)]
@@ -89,29 +213,42 @@ impl MidiInputManager {
         }
     }
 
+    pub fn port_name(
+        &self,
+        input_port: &MidiInputPort,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        match &self.input_client {
+            Some(client) => match client.lock() {
+                Ok(client) => Ok(client.port_name(input_port)?),
+                Err(err) => Err(format!("Could not acquire lock: {}", err).into()),
+            },
+            None => Err("MIDI input client has not been initialized".into()),
+        }
+    }
+
     pub fn open_new_midi_connection(
         &mut self,
         input_port: &MidiInputPort,
-    ) -> Result<
-        (MidiInputConnection<()>, Arc<Mutex<VecDeque<MidiMessage>>>),
-        Box<dyn std::error::Error>,
-    > {
+    ) -> Result<(MidiInputConnection<()>, MidiMessageQueue), Box<dyn std::error::Error>> {
         let Some(taken_input_client) = self.input_client.take() else {
             return Err("MIDI input client has not been initialized".into());
         };
         let Ok(owned_input_client) = taken_input_client.into_inner() else {
             return Err("Could not acquire lock for mutex inner.".into());
         };
-        let message_queue: Arc<Mutex<VecDeque<MidiMessage>>> =
-            Arc::new(Mutex::new(VecDeque::new()));
+        let message_queue: MidiMessageQueue = Arc::new(Mutex::new(VecDeque::new()));
         let message_queue_cloned = message_queue.clone();
         let connection = owned_input_client.connect(
             input_port,
             format!("lightshow-midi-in-{}", self.connection_counter).as_str(),
             move |_timestamp, message, _| {
+                let received_at = Instant::now();
                 let message = MidiMessage::from_bytes(message);
                 match message {
-                    Ok(message) => message_queue_cloned.lock().unwrap().push_back(message),
+                    Ok(message) => message_queue_cloned
+                        .lock()
+                        .unwrap()
+                        .push_back((received_at, message)),
                     Err(err) => println!("Warning: {}", err),
                 }
             },
@@ -0,0 +1,239 @@
+use bevy::prelude::*;
+
+use crate::{
+    midi::{MidiMessage, MidiMessageReceived, dispatch_midi_messages},
+    simple_store::SimpleStore,
+    timecode::TimecodeSettings,
+    timeline::{playback::*, sequences::*},
+};
+
+/// Bevy plugin for following MIDI beat clock.
+pub struct MidiClockPlugin;
+
+impl Plugin for MidiClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MidiClock>()
+            .add_systems(
+                Update,
+                follow_midi_clock
+                    .after(dispatch_midi_messages)
+                    .before(increment_playback_time),
+            )
+            .add_observer(receive_midi_clock);
+    }
+}
+
+/// The number of clock ticks in a beat.
+const TICKS_PER_BEAT: f64 = 24.0;
+
+/// The number of clock ticks in each step of the song position, i.e. a
+/// sixteenth note.
+const TICKS_PER_SONG_POSITION: u64 = 6;
+
+/// How much of each new tick interval goes into the tempo estimate. Lower
+/// values smooth out more jitter, but follow tempo changes more slowly.
+const TEMPO_SMOOTHING: f64 = 0.05;
+
+/// Tick intervals this many times longer than the tempo estimate are taken to
+/// be a dropout rather than a change in tempo.
+const DROPOUT_FACTOR: f64 = 4.0;
+
+/// The range of tempos that are followed.
+const MIN_BPM: f64 = 20.0;
+const MAX_BPM: f64 = 400.0;
+
+/// Bevy resource that follows MIDI beat clock from any open MIDI input, along
+/// with the transport (Start, Continue, and Stop) and Song Position Pointer
+/// messages.
+///
+/// The tempo is estimated from the time between ticks, smoothed to hide
+/// jitter. Song position is kept in ticks, and is extrapolated between ticks
+/// using the tempo, so the beat phase runs smoothly.
+///
+/// Not every source sends transport messages. Until the first one arrives,
+/// the clock counts as running for as long as ticks keep coming, and every
+/// tick moves the song position on.
+#[derive(Resource, Debug, Default)]
+pub struct MidiClock {
+    /// Whether playback follows the clock. Only takes effect while no
+    /// timecode is chased (see `TimecodeSettings::chase`), as timecode chase
+    /// takes precedence.
+    pub follow: bool,
+    /// Whether a transport message has been received, after which only the
+    /// transport starts and stops the clock.
+    has_transport: bool,
+    running: bool,
+    /// The song position of the next tick, counted in ticks from the start of
+    /// the song.
+    ticks: u64,
+    /// Whether a tick has arrived since playback started or the song position
+    /// moved, i.e. the song position is moving on.
+    ticked: bool,
+    last_tick_at: Option<f64>,
+    /// The smoothed time between ticks, in seconds.
+    tick_interval: Option<f64>,
+}
+
+impl MidiClock {
+    /// Whether the song position is moving on at the given Bevy time (see
+    /// `Time::elapsed_secs_f64`). Without transport, the clock stops once
+    /// ticks stop arriving for as long as a dropout lasts.
+    pub fn is_running(&self, now: f64) -> bool {
+        if self.has_transport {
+            return self.running;
+        }
+        match (self.last_tick_at, self.tick_interval) {
+            (Some(last_tick_at), Some(interval)) => now - last_tick_at < interval * DROPOUT_FACTOR,
+            _ => false,
+        }
+    }
+
+    /// Gets the tempo of the clock, once a few ticks have been received.
+    pub fn bpm(&self) -> Option<f64> {
+        self.tick_interval
+            .map(|interval| 60.0 / (interval * TICKS_PER_BEAT))
+    }
+
+    /// Gets the song position in beats at the given Bevy time (see
+    /// `Time::elapsed_secs_f64`). Between ticks, the song position moves on
+    /// at the current tempo, but never past the next tick.
+    pub fn beats_at(&self, now: f64) -> f64 {
+        if self.is_running(now)
+            && self.ticked
+            && let (Some(last_tick_at), Some(interval)) = (self.last_tick_at, self.tick_interval)
+        {
+            let since_tick = ((now - last_tick_at) / interval).clamp(0.0, 1.0);
+            (self.ticks as f64 - 1.0 + since_tick) / TICKS_PER_BEAT
+        } else {
+            self.ticks as f64 / TICKS_PER_BEAT
+        }
+    }
+
+    /// Gets how far into the current beat the song position is, from 0 to 1.
+    pub fn beat_phase(&self, now: f64) -> f64 {
+        self.beats_at(now).fract()
+    }
+
+    fn tick(&mut self, at: f64) {
+        if let Some(last_tick_at) = self.last_tick_at {
+            let interval = at - last_tick_at;
+            let in_range = interval > 60.0 / (MAX_BPM * TICKS_PER_BEAT)
+                && interval < 60.0 / (MIN_BPM * TICKS_PER_BEAT);
+            self.tick_interval = match self.tick_interval {
+                // long gaps are dropouts rather than changes in tempo
+                Some(estimate) if in_range && interval < estimate * DROPOUT_FACTOR => {
+                    Some(estimate + (interval - estimate) * TEMPO_SMOOTHING)
+                }
+                None if in_range => Some(interval),
+                estimate => estimate,
+            };
+        }
+        self.last_tick_at = Some(at);
+
+        // clock keeps ticking while stopped, so the tempo is known right away
+        if self.running || !self.has_transport {
+            self.ticks += 1;
+            self.ticked = true;
+        }
+    }
+
+    fn start(&mut self) {
+        self.has_transport = true;
+        self.running = true;
+        self.ticks = 0;
+        self.ticked = false;
+    }
+
+    fn resume(&mut self) {
+        self.has_transport = true;
+        self.running = true;
+        self.ticked = false;
+    }
+
+    fn stop(&mut self) {
+        self.has_transport = true;
+        self.running = false;
+    }
+
+    fn song_position(&mut self, position: u16) {
+        self.ticks = position as u64 * TICKS_PER_SONG_POSITION;
+        self.ticked = false;
+    }
+}
+
+/// Bevy observer that passes MIDI clock, transport, and song position
+/// messages on to the `MidiClock`.
+fn receive_midi_clock(
+    received: On<MidiMessageReceived>,
+    time: Res<Time>,
+    mut clock: ResMut<MidiClock>,
+) {
+    match received.message {
+        MidiMessage::TimingClock => clock.tick(received.received_at(time.elapsed_secs_f64())),
+        MidiMessage::Start => clock.start(),
+        MidiMessage::Continue => clock.resume(),
+        MidiMessage::Stop => clock.stop(),
+        MidiMessage::SongPosition { position } => clock.song_position(position),
+        _ => {}
+    }
+}
+
+/// Moves the playback head along with the MIDI clock while following it.
+/// The song position is counted in beats from the start of the primary
+/// sequence. Once the tempo of the clock is known, the sequence plays at that
+/// tempo instead of the one in its tempo map (see
+/// `PlaybackInformation::external_tempo`), so effects and modulators lock on
+/// to the clock too.
+pub fn follow_midi_clock(
+    time: Res<Time>,
+    clock: Res<MidiClock>,
+    timecode: Res<TimecodeSettings>,
+    mut playback: ResMut<PlaybackInformation>,
+    primary_sequence: Res<PrimarySequence>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    mut following: Local<bool>,
+) {
+    if !clock.follow || !timecode.is_master() {
+        // playback is left alone if timecode chase has taken over
+        if *following && timecode.is_master() {
+            playback.external_clock = false;
+            playback.pause();
+        }
+        if *following {
+            playback.external_tempo = None;
+        }
+        *following = false;
+        return;
+    }
+    *following = true;
+    playback.external_clock = true;
+
+    let Some(sequence) = primary_sequence
+        .0
+        .and_then(|handle| sequence_store.get(handle))
+    else {
+        playback.external_tempo = None;
+        return;
+    };
+    // only rebuilt when the tempo or the meter changes
+    let up_to_date = match (&playback.external_tempo, clock.bpm()) {
+        (Some(tempo_map), Some(bpm)) => {
+            tempo_map.tempo_points()[0].bpm == bpm
+                && tempo_map.meter_changes() == sequence.tempo_map.meter_changes()
+        }
+        (None, None) => true,
+        _ => false,
+    };
+    if !up_to_date {
+        playback.external_tempo = clock.bpm().map(|bpm| sequence.tempo_map.with_tempo(bpm));
+    }
+
+    let now = time.elapsed_secs_f64();
+    let tempo_map = playback
+        .external_tempo
+        .as_ref()
+        .unwrap_or(&sequence.tempo_map);
+    let current_time = tempo_map.beats_to_seconds(clock.beats_at(now));
+    playback.seek(current_time.clamp(0.0, sequence.length));
+    playback.is_playing = clock.is_running(now);
+}
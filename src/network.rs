@@ -17,6 +17,46 @@ pub struct ActiveSocket {
     pub socket: Option<std::net::UdpSocket>,
 }
 
+impl ActiveSocket {
+    /// Binds a socket for sending and receiving Art-Net, with broadcasting
+    /// enabled. The socket is non-blocking, so that `receive` can poll it.
+    pub fn open(address: impl std::net::ToSocketAddrs) -> std::io::Result<Self> {
+        let socket = std::net::UdpSocket::bind(address)?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: Some(socket),
+        })
+    }
+
+    /// Reads every Art-Net packet waiting on the socket. Packets that can't
+    /// be parsed are skipped. The socket has to be non-blocking (as opened by
+    /// `open`), otherwise this waits until a packet arrives.
+    pub fn receive(&self) -> Vec<ArtCommand> {
+        let Some(socket) = &self.socket else {
+            return Vec::new();
+        };
+
+        let mut commands = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            match socket.recv(&mut buffer) {
+                Ok(length) => {
+                    if let Ok(command) = ArtCommand::from_buffer(&buffer[..length]) {
+                        commands.push(command);
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Failed to receive Art-Net packet: {}", e);
+                    break;
+                }
+            }
+        }
+        commands
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ArtNetAddress {
     pub net: u8,
@@ -101,6 +141,11 @@ impl Dmx512Buffer {
         self.length = 0;
     }
 
+    /// Gets the channels written so far, up to the highest one.
+    pub fn data(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+
     pub fn write(&mut self, offset: usize, value: u8) -> Result<(), String> {
         if offset >= 512 {
             return Err(format!(
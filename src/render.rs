@@ -0,0 +1,552 @@
+use bevy::prelude::*;
+use ringbuf::{HeapRb, traits::*};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    audio::{
+        HeapProducer,
+        file::AudioFile,
+        processing::fft::{FftConfig, FftProcessor, RecentFftData},
+    },
+    fixtures::{ColorFixture, FixtureRequest, PanTiltFixture},
+    network::{ArtNetAddress, ArtNetBuffers, ArtNetDataPointer},
+    show_file::{FixtureData, ShowFile},
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{
+        effects::EffectUpdateCommonInfo,
+        sequence_tree::{SequenceTree, TreeEvaluator},
+        sequences::Sequence,
+    },
+};
+
+/// Marks the start of a DMX recording file.
+const DMX_RECORDING_MAGIC: &[u8; 5] = b"LSDMX";
+
+/// Current version of the DMX recording file format.
+const DMX_RECORDING_VERSION: u8 = 1;
+
+/// Settings for rendering a sequence offline (see `OfflineRenderer`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSettings {
+    /// How many frames are rendered per second of the sequence.
+    pub frame_rate: f64,
+    /// Where the render starts, in seconds.
+    pub start: f64,
+    /// Where the render ends, in seconds. Runs to the end of the sequence if
+    /// `None`.
+    pub end: Option<f64>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            frame_rate: 44.0,
+            start: 0.0,
+            end: None,
+        }
+    }
+}
+
+/// A fixture as seen by an offline render, standing in for the components
+/// of a fixture entity.
+#[derive(Debug)]
+pub struct RenderFixture {
+    pub groups: Vec<u32>,
+    pub position: Vec3,
+    pub color: Option<ColorFixture>,
+    pub pan_tilt: Option<PanTiltFixture>,
+    pub pointer: Option<ArtNetDataPointer>,
+}
+
+impl RenderFixture {
+    /// Builds a fixture from its saved form in a show file's patch.
+    pub fn from_data(fixture_data: &FixtureData) -> Result<Self, String> {
+        Ok(Self {
+            groups: fixture_data.groups.clone(),
+            position: Vec3::from_array(fixture_data.position),
+            color: fixture_data.color_fixture(),
+            pan_tilt: fixture_data.pan_tilt_fixture(),
+            pointer: fixture_data.artnet_data_pointer()?,
+        })
+    }
+}
+
+/// The DMX data of every universe written to during a single frame of a
+/// render, in order of address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DmxFrame {
+    /// When the frame happens within the sequence, in seconds.
+    pub time: f64,
+    pub universes: Vec<(ArtNetAddress, Vec<u8>)>,
+}
+
+/// Audio fed into the FFT processor during a render, so audio-reactive
+/// effects respond to it as if it were playing.
+struct RenderAudio {
+    song: Arc<AudioFile>,
+    offset: f64,
+    processor: FftProcessor,
+    producer: HeapProducer<f32>,
+    /// The next frame of the song to be fed in. Negative before the song
+    /// starts.
+    next_frame: i64,
+}
+
+impl RenderAudio {
+    /// Feeds the song in up to a point in time within the sequence, and
+    /// processes whatever can be.
+    fn feed_until(&mut self, time: f64, recent_fft_data: &mut RecentFftData) {
+        let sample_rate = self.song.sample_rate as f64;
+        let until = ((time - self.offset) * sample_rate).floor() as i64;
+        let frame_count = self.song.frame_count() as i64;
+        let mut frames = Vec::new();
+        while self.next_frame < until {
+            let song = &self.song;
+            let from = self.next_frame;
+            // silence outside of the song
+            let samples = (from..until).map(|index| {
+                if (0..frame_count).contains(&index) {
+                    song.mono(index as usize)
+                } else {
+                    0.0
+                }
+            });
+            self.next_frame += self.producer.push_iter(samples) as i64;
+            while let Some(frame) = self.processor.process_next_frame() {
+                frames.push(frame);
+            }
+        }
+        recent_fft_data.add(frames.into());
+    }
+}
+
+/// Renders a sequence to DMX frames faster than real time, without a window,
+/// audio devices, or a network. Time moves on in fixed steps, and the
+/// sequence tree and fixtures are driven the same way as during playback.
+/// Audio-reactive effects can be fed from an audio file (see
+/// `OfflineRenderer::with_audio`).
+///
+/// Every render starts from a fresh sequence tree and uses the time within
+/// the sequence as global time, so rendering the same sequence twice gives
+/// the same frames. Cues and trigger tracks are left out, as they are fired
+/// live.
+pub struct OfflineRenderer {
+    sequence_store: SimpleStore<Sequence>,
+    sequence: SimpleHandle<Sequence>,
+    fixtures: Vec<RenderFixture>,
+    requests: Vec<FixtureRequest>,
+    settings: RenderSettings,
+    end: f64,
+    next_frame: u64,
+    sequence_tree: SequenceTree,
+    evaluator: TreeEvaluator,
+    recent_fft_data: RecentFftData,
+    audio: Option<RenderAudio>,
+    buffers: ArtNetBuffers,
+}
+
+impl OfflineRenderer {
+    /// Prepares a render of a sequence within the store, lighting the given
+    /// fixtures.
+    pub fn new(
+        sequence_store: SimpleStore<Sequence>,
+        sequence: SimpleHandle<Sequence>,
+        fixtures: Vec<RenderFixture>,
+        settings: RenderSettings,
+    ) -> Result<Self, String> {
+        let Some(sequence_ref) = sequence_store.get(sequence) else {
+            return Err("the sequence to render does not exist".to_string());
+        };
+        if !(settings.frame_rate > 0.0 && settings.frame_rate.is_finite()) {
+            return Err(format!(
+                "frame rate must be above zero, got {}",
+                settings.frame_rate
+            ));
+        }
+        let end = settings.end.unwrap_or(sequence_ref.length);
+
+        let requests = fixtures
+            .iter()
+            .map(|fixture| FixtureRequest {
+                groups: fixture.groups.clone(),
+                position: fixture.position,
+                has_color: fixture.color.is_some(),
+                has_pan_tilt: fixture.pan_tilt.is_some(),
+            })
+            .collect();
+
+        Ok(Self {
+            sequence_store,
+            sequence,
+            fixtures,
+            requests,
+            settings,
+            end,
+            next_frame: 0,
+            sequence_tree: SequenceTree::new(),
+            evaluator: TreeEvaluator::new(),
+            recent_fft_data: RecentFftData::new(),
+            audio: None,
+            buffers: ArtNetBuffers::default(),
+        })
+    }
+
+    /// Prepares a render of the primary sequence of a show file, lighting its
+    /// patch.
+    pub fn from_show_file(show_file: &ShowFile, settings: RenderSettings) -> Result<Self, String> {
+        let loaded = show_file.to_sequences().map_err(|e| e.to_string())?;
+        let sequence = loaded
+            .primary_sequence
+            .ok_or("the show has no primary sequence")?;
+        let fixtures = show_file
+            .patch
+            .iter()
+            .enumerate()
+            .map(|(index, fixture_data)| {
+                RenderFixture::from_data(fixture_data).map_err(|reason| {
+                    format!("fixture {} in the patch is invalid: {}", index, reason)
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Self::new(loaded.sequence_store, sequence, fixtures, settings)
+    }
+
+    /// Feeds a song to audio-reactive effects during the render, starting
+    /// `offset` seconds into the sequence (see `SequenceAudio`).
+    pub fn with_audio(mut self, song: Arc<AudioFile>, offset: f64) -> Self {
+        let config = FftConfig {
+            sample_rate: song.sample_rate,
+            ..FftConfig::default()
+        };
+        let ring = HeapRb::<f32>::new(config.window_size * 4);
+        let (producer, consumer) = ring.split();
+        // the audio just before the first frame is fed in along with it
+        let step = 1.0 / self.settings.frame_rate;
+        let next_frame = ((self.settings.start - step - offset) * song.sample_rate as f64).floor();
+        self.audio = Some(RenderAudio {
+            song,
+            offset,
+            processor: FftProcessor::new(config, consumer),
+            producer,
+            next_frame: next_frame as i64,
+        });
+        self
+    }
+
+    /// Gets the sequence being rendered.
+    pub fn sequence(&self) -> Option<&Sequence> {
+        self.sequence_store.get(self.sequence)
+    }
+
+    /// Gets the number of frames in the whole render.
+    pub fn frame_count(&self) -> u64 {
+        if self.end < self.settings.start {
+            return 0;
+        }
+        ((self.end - self.settings.start) * self.settings.frame_rate).floor() as u64 + 1
+    }
+
+    /// Renders the next frame, or returns `None` once the end is reached.
+    pub fn next_frame(&mut self) -> Option<DmxFrame> {
+        if self.next_frame >= self.frame_count() {
+            return None;
+        }
+        let time = self.settings.start + self.next_frame as f64 / self.settings.frame_rate;
+        self.next_frame += 1;
+
+        if let Some(audio) = &mut self.audio {
+            audio.feed_until(time, &mut self.recent_fft_data);
+        }
+
+        let sequence = self
+            .sequence_store
+            .get(self.sequence)
+            .expect("sequence was checked when the render was prepared");
+        let common_info = EffectUpdateCommonInfo {
+            recent_fft_data: &self.recent_fft_data,
+            global_time: time,
+            tempo_map: &sequence.tempo_map,
+            playback_rate: 1.0,
+        };
+        self.sequence_tree.update_recursive(
+            &self.sequence_store,
+            Some(self.sequence),
+            time,
+            &[],
+            &common_info,
+        );
+
+        let values = self
+            .sequence_tree
+            .get_values_recursive(&self.requests, &mut self.evaluator);
+        for (fixture, value) in self.fixtures.iter_mut().zip(values) {
+            if let (Some(color_fixture), Some(color)) = (&mut fixture.color, value.color) {
+                color_fixture.color = color;
+            }
+            if let (Some(pan_tilt_fixture), Some(pan_tilt)) =
+                (&mut fixture.pan_tilt, value.pan_tilt)
+            {
+                pan_tilt_fixture.pan = pan_tilt.pan;
+                pan_tilt_fixture.tilt = pan_tilt.tilt;
+            }
+
+            let Some(pointer) = fixture.pointer else {
+                continue;
+            };
+            let result = fixture
+                .color
+                .as_ref()
+                .map_or(Ok(()), |color_fixture| {
+                    color_fixture.write_dmx(pointer, &mut self.buffers)
+                })
+                .and_then(|()| {
+                    fixture
+                        .pan_tilt
+                        .as_ref()
+                        .map_or(Ok(()), |pan_tilt_fixture| {
+                            pan_tilt_fixture.write_dmx(pointer, &mut self.buffers)
+                        })
+                });
+            if let Err(e) = result {
+                warn!("Failed to write fixture DMX data: {}", e);
+            }
+        }
+
+        let mut universes: Vec<(ArtNetAddress, Vec<u8>)> = self
+            .buffers
+            .iter_dirty()
+            .map(|(address, buffer)| (address, buffer.data().to_vec()))
+            .collect();
+        universes.sort_by_key(|(address, _)| (address.net, address.subnet, address.universe));
+        self.buffers.clear_all();
+
+        Some(DmxFrame { time, universes })
+    }
+
+    /// Renders every frame into memory.
+    pub fn render(mut self) -> DmxRecording {
+        let frame_rate = self.settings.frame_rate;
+        let frames = std::iter::from_fn(|| self.next_frame()).collect();
+        DmxRecording { frame_rate, frames }
+    }
+
+    /// Renders every frame straight into a DMX recording file, without
+    /// keeping them in memory. Returns the number of frames written.
+    pub fn render_to_file(mut self, path: &Path) -> io::Result<u64> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        DmxRecording::write_header(&mut writer, self.settings.frame_rate)?;
+        let mut frame_count = 0;
+        while let Some(frame) = self.next_frame() {
+            DmxRecording::write_frame(&mut writer, &frame)?;
+            frame_count += 1;
+        }
+        writer.flush()?;
+        Ok(frame_count)
+    }
+}
+
+/// Frames of DMX data recorded at a fixed rate, e.g. by an
+/// `OfflineRenderer`.
+///
+/// Saved as a small binary format: a header of `LSDMX`, the format version
+/// as a byte, and the frame rate as an `f64`, followed by the frames until
+/// the end of the file. Each frame is its time as an `f64`, the number of
+/// universes as a `u16`, and for each universe its net, subnet and universe
+/// as bytes, the length of its data as a `u16`, and the data itself. Numbers
+/// are little endian.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DmxRecording {
+    pub frame_rate: f64,
+    pub frames: Vec<DmxFrame>,
+}
+
+impl DmxRecording {
+    /// Writes the recording to disk.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        Self::write_header(&mut writer, self.frame_rate)?;
+        for frame in &self.frames {
+            Self::write_frame(&mut writer, frame)?;
+        }
+        writer.flush()
+    }
+
+    /// Reads a recording from disk.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 5];
+        reader.read_exact(&mut magic)?;
+        if &magic != DMX_RECORDING_MAGIC {
+            return Err(invalid_data("not a DMX recording".to_string()));
+        }
+        let version = read_bytes::<1>(&mut reader)?[0];
+        if version > DMX_RECORDING_VERSION {
+            return Err(invalid_data(format!(
+                "DMX recording version {} is newer than the supported version {}",
+                version, DMX_RECORDING_VERSION
+            )));
+        }
+        let frame_rate = f64::from_le_bytes(read_bytes(&mut reader)?);
+
+        let mut frames = Vec::new();
+        loop {
+            // the file ends after the last whole frame
+            let time = match read_bytes(&mut reader) {
+                Ok(time) => f64::from_le_bytes(time),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            };
+            let universe_count = u16::from_le_bytes(read_bytes(&mut reader)?);
+            let mut universes = Vec::with_capacity(universe_count as usize);
+            for _ in 0..universe_count {
+                let [net, subnet, universe] = read_bytes(&mut reader)?;
+                let address = ArtNetAddress::new(net, subnet, universe).map_err(invalid_data)?;
+                let length = u16::from_le_bytes(read_bytes(&mut reader)?) as usize;
+                if length > 512 {
+                    return Err(invalid_data(format!(
+                        "universe data is {} channels long, more than 512",
+                        length
+                    )));
+                }
+                let mut data = vec![0; length];
+                reader.read_exact(&mut data)?;
+                universes.push((address, data));
+            }
+            frames.push(DmxFrame { time, universes });
+        }
+
+        Ok(Self { frame_rate, frames })
+    }
+
+    fn write_header(writer: &mut impl Write, frame_rate: f64) -> io::Result<()> {
+        writer.write_all(DMX_RECORDING_MAGIC)?;
+        writer.write_all(&[DMX_RECORDING_VERSION])?;
+        writer.write_all(&frame_rate.to_le_bytes())
+    }
+
+    fn write_frame(writer: &mut impl Write, frame: &DmxFrame) -> io::Result<()> {
+        writer.write_all(&frame.time.to_le_bytes())?;
+        writer.write_all(&(frame.universes.len() as u16).to_le_bytes())?;
+        for (address, data) in &frame.universes {
+            writer.write_all(&[address.net, address.subnet, address.universe])?;
+            writer.write_all(&(data.len() as u16).to_le_bytes())?;
+            writer.write_all(data)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid_data(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        timeline::{
+            effects::{ColorEffectInfo, EffectInfo, color::shockwave::ColorShockwaveEffect},
+            keyframes::{InterpolationType, Keyframe, KeyframeValue, Keyframes},
+            positions::TempoMap,
+            sequences::SequenceId,
+            tracks::{Track, TrackContents, TrackInfo},
+        },
+        util::blending::BlendingMode,
+    };
+
+    /// A shockwave that grows across a row of four fixtures over two
+    /// seconds, each fixture taking up three channels of universe 0:0:0.
+    fn shockwave_renderer() -> OfflineRenderer {
+        let mut sequence_store = SimpleStore::default();
+        let sequence = sequence_store.add(Sequence {
+            id: SequenceId::fresh(),
+            name: "Shockwave".into(),
+            length: 2.0,
+            tempo_map: TempoMap::default(),
+            tracks: vec![Track::new(
+                TrackInfo {
+                    blending_mode: BlendingMode::default(),
+                    factor: 1.0,
+                    track_keyframes: Keyframes::default(),
+                    modulators: Vec::new(),
+                    audio_bindings: Vec::new(),
+                },
+                TrackContents::EffectTrack {
+                    effect_init_info: EffectInfo::ColorEffectInfo(
+                        ColorEffectInfo::ColorShockwaveEffect(ColorShockwaveEffect {
+                            color: Color::srgb(1.0, 0.5, 0.0),
+                            center: Vec3::ZERO,
+                            radius: 0.0,
+                            flat: 2.0,
+                            head: 3.0,
+                            tail: 3.0,
+                        }),
+                    ),
+                    effect_keyframes: Keyframes::new(vec![Keyframe {
+                        time: 2.0.into(),
+                        interpolation: InterpolationType::LINEAR,
+                        key: "radius".into(),
+                        value: KeyframeValue::FloatKeyframe(20.0),
+                    }]),
+                },
+            )],
+            loop_region: None,
+            audio: None,
+        });
+        let address = ArtNetAddress::new(0, 0, 0).unwrap();
+        let fixtures = (0..4)
+            .map(|index| RenderFixture {
+                groups: Vec::new(),
+                position: Vec3::new(index as f32 * 5.0, 0.0, 0.0),
+                color: Some(ColorFixture {
+                    red_channel: 0,
+                    green_channel: 1,
+                    blue_channel: 2,
+                    ..default()
+                }),
+                pan_tilt: None,
+                pointer: Some(ArtNetDataPointer::new(address, index * 3).unwrap()),
+            })
+            .collect();
+        let settings = RenderSettings {
+            frame_rate: 10.0,
+            ..default()
+        };
+        OfflineRenderer::new(sequence_store, sequence, fixtures, settings).unwrap()
+    }
+
+    #[test]
+    fn rendering_twice_gives_the_same_frames() {
+        let first = shockwave_renderer().render();
+        let second = shockwave_renderer().render();
+        assert_eq!(first.frames.len(), 21);
+        // the shockwave actually moves across the fixtures
+        assert_ne!(first.frames[5], first.frames[15]);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn recordings_round_trip_through_a_file() {
+        let recording = shockwave_renderer().render();
+        let path = std::env::temp_dir().join(format!(
+            "lightshow-dmx-recording-{}.lsdmx",
+            std::process::id()
+        ));
+        recording.save(&path).unwrap();
+        let loaded = DmxRecording::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), recording);
+    }
+}
@@ -0,0 +1,1399 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+};
+
+use crate::{
+    editing::EditHistory,
+    fixtures::{ColorFixture, Fixture, PanTiltFixture, RgbEncoding},
+    midi::clock::MidiClock,
+    network::{ArtNetAddress, ArtNetDataPointer},
+    simple_store::{SimpleHandle, SimpleStore},
+    timecode::{TimecodeChase, TimecodeSettings},
+    timeline::{
+        audio_bindings::{AudioBinding, AudioFeature, validate_audio_bindings},
+        cues::{Cue, CueContent, CueFollow, CueList, CuePlayback},
+        effects::EffectInfo,
+        keyframes::{InterpolationType, Keyframe, KeyframeValue, Keyframes},
+        modulators::{
+            Modulator, ModulatorRate, ModulatorShape, ModulatorTarget, validate_modulators,
+        },
+        playback::{PlaybackEndMode, PlaybackInformation, Playlist},
+        positions::{MeterChange, TempoMap, TempoPoint, TimelinePosition},
+        sequence_tree::ClearSequenceTree,
+        sequences::{
+            LoopRegion, PrimarySequence, Sequence, SequenceAudio, SequenceCycle, SequenceId,
+            find_sequence_cycle,
+        },
+        tracks::{Clip, ClipLoopMode, ClipPlayback, TimeSegment, Track, TrackContents, TrackInfo},
+    },
+    util::blending::BlendingMode,
+};
+
+/// Current version of the show file format. Bumped whenever the format
+/// changes in a way that older versions of the program cannot read.
+pub const SHOW_FILE_VERSION: u32 = 2;
+
+/// Oldest version of the show file format that can still be read. Effects
+/// were saved in a form of their own before version 2, rather than through
+/// reflection.
+pub const OLDEST_SHOW_FILE_VERSION: u32 = 2;
+
+/// Bevy plugin for saving and loading show files.
+pub struct ShowFilePlugin;
+
+impl Plugin for ShowFilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(save_show).add_observer(load_show);
+    }
+}
+
+/// Everything that can go wrong while saving or loading a show file.
+#[derive(Debug)]
+pub enum ShowFileError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    UnsupportedVersion { found: u32 },
+    DuplicateSequenceId(SequenceId),
+    UnknownSequence { id: SequenceId, context: String },
+    InvalidTempoMap { sequence: String, reason: String },
+    InvalidSettings(String),
+    InvalidFixture { index: usize, reason: String },
+    InvalidKeyframes { context: String, reason: String },
+    InvalidModulators { context: String, reason: String },
+    InvalidAudioBindings { context: String, reason: String },
+    SequenceCycle(SequenceCycle),
+}
+
+impl fmt::Display for ShowFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShowFileError::Io(error) => write!(f, "could not access show file: {}", error),
+            ShowFileError::Parse(error) => write!(f, "could not parse show file: {}", error),
+            ShowFileError::Serialize(error) => {
+                write!(f, "could not serialize show file: {}", error)
+            }
+            ShowFileError::UnsupportedVersion { found } => write!(
+                f,
+                "show file has version {}, but only versions {} to {} are supported",
+                found, OLDEST_SHOW_FILE_VERSION, SHOW_FILE_VERSION
+            ),
+            ShowFileError::DuplicateSequenceId(id) => {
+                write!(f, "sequence id {} is used more than once", id)
+            }
+            ShowFileError::UnknownSequence { id, context } => {
+                write!(
+                    f,
+                    "{} refers to sequence {}, which does not exist",
+                    context, id
+                )
+            }
+            ShowFileError::InvalidTempoMap { sequence, reason } => {
+                write!(
+                    f,
+                    "sequence \"{}\" has an invalid tempo map: {}",
+                    sequence, reason
+                )
+            }
+            ShowFileError::InvalidSettings(reason) => {
+                write!(f, "show settings are invalid: {}", reason)
+            }
+            ShowFileError::InvalidFixture { index, reason } => {
+                write!(f, "fixture {} in the patch is invalid: {}", index, reason)
+            }
+            ShowFileError::InvalidKeyframes { context, reason } => {
+                write!(f, "keyframes of {} are invalid: {}", context, reason)
+            }
+            ShowFileError::InvalidModulators { context, reason } => {
+                write!(f, "modulators of {} are invalid: {}", context, reason)
+            }
+            ShowFileError::InvalidAudioBindings { context, reason } => {
+                write!(f, "audio bindings of {} are invalid: {}", context, reason)
+            }
+            ShowFileError::SequenceCycle(cycle) => write!(f, "{}", cycle),
+        }
+    }
+}
+
+impl std::error::Error for ShowFileError {}
+
+impl From<std::io::Error> for ShowFileError {
+    fn from(value: std::io::Error) -> Self {
+        ShowFileError::Io(value)
+    }
+}
+
+impl From<ron::error::SpannedError> for ShowFileError {
+    fn from(value: ron::error::SpannedError) -> Self {
+        ShowFileError::Parse(value)
+    }
+}
+
+impl From<ron::Error> for ShowFileError {
+    fn from(value: ron::Error) -> Self {
+        ShowFileError::Serialize(value)
+    }
+}
+
+/// Root of a show file. Holds every sequence in the show, which one is open,
+/// the playlist, the cue lists, the fixture patch, and general settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShowFile {
+    pub version: u32,
+    pub primary_sequence: Option<SequenceId>,
+    #[serde(default)]
+    pub playlist: Vec<SequenceId>,
+    pub sequences: Vec<SequenceData>,
+    #[serde(default)]
+    pub cue_lists: Vec<CueListData>,
+    pub patch: Vec<FixtureData>,
+    pub settings: ShowSettings,
+}
+
+/// General settings saved along with a show.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShowSettings {
+    pub update_rate_hz: f64,
+    #[serde(default)]
+    pub end_mode: PlaybackEndMode,
+    #[serde(default)]
+    pub timecode: TimecodeSettings,
+    #[serde(default)]
+    pub follow_midi_clock: bool,
+}
+
+impl ShowSettings {
+    /// Checks that the settings can be applied as they are.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.update_rate_hz.is_finite() && self.update_rate_hz > 0.0) {
+            return Err(format!(
+                "the update rate has to be a positive number of Hz, but is {}",
+                self.update_rate_hz
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ShowSettings {
+    fn default() -> Self {
+        Self {
+            update_rate_hz: 44.0,
+            end_mode: PlaybackEndMode::default(),
+            timecode: TimecodeSettings::default(),
+            follow_midi_clock: false,
+        }
+    }
+}
+
+/// Saved form of a `Sequence`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceData {
+    pub id: SequenceId,
+    pub name: String,
+    pub length: f64,
+    pub tempo_points: Vec<TempoPoint>,
+    pub meter_changes: Vec<MeterChange>,
+    pub tracks: Vec<TrackData>,
+    #[serde(default)]
+    pub loop_region: Option<LoopRegion>,
+    #[serde(default)]
+    pub audio: Option<SequenceAudio>,
+}
+
+/// Saved form of a `Track`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackData {
+    pub blending_mode: BlendingMode,
+    pub factor: f32,
+    pub track_keyframes: Vec<KeyframeData>,
+    #[serde(default)]
+    pub modulators: Vec<ModulatorData>,
+    #[serde(default)]
+    pub audio_bindings: Vec<AudioBindingData>,
+    pub contents: TrackContentsData,
+}
+
+/// Saved form of `TrackContents`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TrackContentsData {
+    Effect {
+        #[serde(with = "reflected_effect")]
+        effect: EffectInfo,
+        keyframes: Vec<KeyframeData>,
+    },
+    Sequence {
+        clips: Vec<ClipData>,
+    },
+    Trigger {
+        sequence: SequenceId,
+    },
+}
+
+/// Saved form of a `Clip`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipData {
+    pub sequence: SequenceId,
+    pub time_segment: TimeSegment,
+    pub speed: f64,
+    pub reverse: bool,
+    pub loop_mode: ClipLoopMode,
+    pub loop_length: Option<TimelinePosition>,
+    pub time_remap: Option<Vec<KeyframeData>>,
+    pub fade_in: TimelinePosition,
+    pub fade_out: TimelinePosition,
+}
+
+/// Saved form of a `CueList`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CueListData {
+    pub name: String,
+    pub cues: Vec<CueData>,
+}
+
+/// Saved form of a `Cue`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CueData {
+    pub name: String,
+    pub content: CueContentData,
+    pub delay: f64,
+    pub fade_in: f64,
+    pub fade_out: f64,
+    pub follow: CueFollow,
+}
+
+/// Saved form of `CueContent`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CueContentData {
+    Sequence(SequenceId),
+    Look { sequence: SequenceId, time: f64 },
+}
+
+/// Saved form of a `Keyframe`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyframeData {
+    pub time: TimelinePosition,
+    pub interpolation: InterpolationType,
+    pub key: String,
+    pub value: KeyframeValueData,
+}
+
+/// Saved form of a `KeyframeValue`. Colors are stored as linear RGBA and
+/// vectors as plain arrays.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum KeyframeValueData {
+    Float(f32),
+    Color([f32; 4]),
+    Vec3([f32; 3]),
+    BlendingMode(BlendingMode),
+}
+
+/// Saved form of a `Modulator`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModulatorData {
+    pub target: ModulatorTarget,
+    pub shape: ModulatorShape,
+    pub rate: ModulatorRate,
+    pub phase: f64,
+    pub spread: [f32; 3],
+    pub depth: [f32; 4],
+    pub offset: [f32; 4],
+    #[serde(default)]
+    pub seed: u32,
+}
+
+/// Saved form of an `AudioBinding`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioBindingData {
+    pub target: ModulatorTarget,
+    pub feature: AudioFeature,
+    pub gain: f32,
+    pub threshold: f32,
+    pub curve: f32,
+    pub attack: f32,
+    pub release: f32,
+    pub depth: [f32; 4],
+    pub offset: [f32; 4],
+}
+
+/// Just the version of a show file, read before anything else.
+#[derive(Deserialize)]
+struct ShowFileVersion {
+    version: u32,
+}
+
+/// Serializes the `EffectInfo` of an effect track through reflection, so
+/// effects are saved without any code of their own. Only initialization
+/// parameters are stored; fields marked `#[reflect(skip_serializing)]`, i.e.
+/// any runtime state of an effect, are rebuilt on load.
+mod reflected_effect {
+    use std::sync::OnceLock;
+
+    use bevy::reflect::{
+        FromReflect, TypeRegistry,
+        serde::{TypedReflectDeserializer, TypedReflectSerializer},
+    };
+    use serde::{Deserializer, Serialize, Serializer, de::DeserializeSeed, de::Error};
+
+    use crate::timeline::effects::EffectInfo;
+
+    /// Gets a type registry with every effect and the types of its fields.
+    fn registry() -> &'static TypeRegistry {
+        static REGISTRY: OnceLock<TypeRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(|| {
+            let mut registry = TypeRegistry::new();
+            registry.register::<EffectInfo>();
+            registry
+        })
+    }
+
+    pub fn serialize<S: Serializer>(effect: &EffectInfo, serializer: S) -> Result<S::Ok, S::Error> {
+        TypedReflectSerializer::new(effect, registry()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<EffectInfo, D::Error> {
+        let effect =
+            TypedReflectDeserializer::of::<EffectInfo>(registry()).deserialize(deserializer)?;
+        EffectInfo::from_reflect(&*effect)
+            .ok_or_else(|| D::Error::custom("effect is missing some of its fields"))
+    }
+}
+
+/// Saved form of a single patched fixture.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureData {
+    pub groups: Vec<u32>,
+    pub position: [f32; 3],
+    pub color: Option<ColorFixtureData>,
+    pub pan_tilt: Option<PanTiltFixtureData>,
+    pub artnet: Option<ArtNetPointerData>,
+}
+
+/// Saved form of a `ColorFixture`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColorFixtureData {
+    pub encoding: RgbEncoding,
+    pub red_channel: u8,
+    pub green_channel: u8,
+    pub blue_channel: u8,
+    pub white_channel: Option<u8>,
+}
+
+/// Saved form of a `PanTiltFixture`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PanTiltFixtureData {
+    pub pan_range: (f32, f32),
+    pub tilt_range: (f32, f32),
+}
+
+/// Saved form of an `ArtNetDataPointer`. Validated again on load.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtNetPointerData {
+    pub net: u8,
+    pub subnet: u8,
+    pub universe: u8,
+    pub offset: u16,
+}
+
+fn color_to_data(color: &Color) -> [f32; 4] {
+    color.to_linear().to_f32_array()
+}
+
+fn color_from_data(data: &[f32; 4]) -> Color {
+    LinearRgba::from_f32_array(*data).into()
+}
+
+/// Everything rebuilt from the sequences of a show file, along with the cue
+/// lists that refer to them (see `ShowFile::to_sequences`).
+#[derive(Debug)]
+pub struct LoadedSequences {
+    pub sequence_store: SimpleStore<Sequence>,
+    pub primary_sequence: Option<SimpleHandle<Sequence>>,
+    pub playlist: Vec<SimpleHandle<Sequence>>,
+    pub cue_lists: SimpleStore<CueList>,
+}
+
+impl ShowFile {
+    /// Builds a show file from the in-memory sequence store, the primary
+    /// sequence, the playlist, the cue lists, and the current fixture patch.
+    /// Sequence handles are remapped to the ids of their sequences, which
+    /// stay the same across saves. Should two sequences share an id, the later
+    /// one is given a new id.
+    pub fn from_sequences(
+        sequence_store: &SimpleStore<Sequence>,
+        primary_sequence: Option<SimpleHandle<Sequence>>,
+        playlist: &[SimpleHandle<Sequence>],
+        cue_lists: &SimpleStore<CueList>,
+        patch: Vec<FixtureData>,
+        settings: ShowSettings,
+    ) -> Self {
+        let mut used_ids = HashSet::new();
+        let ids: HashMap<SimpleHandle<Sequence>, SequenceId> = sequence_store
+            .iter()
+            .map(|(handle, sequence)| {
+                let mut id = sequence.id;
+                while !used_ids.insert(id) {
+                    id = SequenceId::fresh();
+                }
+                (handle, id)
+            })
+            .collect();
+        // handles that point nowhere are saved as an id that will fail to load
+        // rather than silently retargeting them
+        let id_of = |handle: &SimpleHandle<Sequence>| {
+            ids.get(handle).copied().unwrap_or(SequenceId(u32::MAX))
+        };
+
+        let sequences = sequence_store
+            .iter()
+            .map(|(handle, sequence)| SequenceData {
+                id: id_of(&handle),
+                name: sequence.name.clone(),
+                length: sequence.length,
+                tempo_points: sequence.tempo_map.tempo_points().to_vec(),
+                meter_changes: sequence.tempo_map.meter_changes().to_vec(),
+                tracks: sequence
+                    .tracks
+                    .iter()
+                    .map(|track| TrackData::from_track(track, &id_of))
+                    .collect(),
+                loop_region: sequence.loop_region,
+                audio: sequence.audio.clone(),
+            })
+            .collect();
+
+        Self {
+            version: SHOW_FILE_VERSION,
+            primary_sequence: primary_sequence.map(|handle| id_of(&handle)),
+            playlist: playlist.iter().map(id_of).collect(),
+            sequences,
+            cue_lists: cue_lists
+                .iter()
+                .map(|(_, cue_list)| CueListData::from_cue_list(cue_list, &id_of))
+                .collect(),
+            patch,
+            settings,
+        }
+    }
+
+    /// Rebuilds an in-memory sequence store from the show file, along with the
+    /// handles of the primary sequence and the playlist. Ids are remapped to
+    /// fresh handles, and any reference to a sequence that does not exist is
+    /// reported as an error.
+    pub fn to_sequences(&self) -> Result<LoadedSequences, ShowFileError> {
+        let mut sequence_store = SimpleStore::default();
+        let mut handles: HashMap<SequenceId, SimpleHandle<Sequence>> = HashMap::new();
+
+        // first pass: reserve a handle for every sequence so that references
+        // can be resolved regardless of order
+        for sequence_data in &self.sequences {
+            let handle = sequence_store.add(Sequence {
+                id: sequence_data.id,
+                name: sequence_data.name.clone(),
+                length: sequence_data.length,
+                tempo_map: TempoMap::default(),
+                tracks: Vec::new(),
+                loop_region: sequence_data.loop_region,
+                audio: sequence_data.audio.clone(),
+            });
+            if handles.insert(sequence_data.id, handle).is_some() {
+                return Err(ShowFileError::DuplicateSequenceId(sequence_data.id));
+            }
+        }
+
+        // second pass: fill in the contents of every sequence
+        for sequence_data in &self.sequences {
+            let tempo_map = TempoMap::new(
+                sequence_data.tempo_points.clone(),
+                sequence_data.meter_changes.clone(),
+            )
+            .map_err(|reason| ShowFileError::InvalidTempoMap {
+                sequence: sequence_data.name.clone(),
+                reason,
+            })?;
+            let tracks = sequence_data
+                .tracks
+                .iter()
+                .enumerate()
+                .map(|(track_i, track_data)| {
+                    track_data.to_track(&handles, &sequence_data.name, track_i)
+                })
+                .collect::<Result<Vec<Track>, ShowFileError>>()?;
+
+            let sequence = sequence_store
+                .get_mut(handles[&sequence_data.id])
+                .expect("sequence was added during the first pass");
+            sequence.tempo_map = tempo_map;
+            sequence.tracks = tracks;
+        }
+
+        // a cycle would make the sequence tree recurse forever during playback
+        find_sequence_cycle(&sequence_store).map_err(ShowFileError::SequenceCycle)?;
+
+        let primary_sequence = self
+            .primary_sequence
+            .map(|id| {
+                handles
+                    .get(&id)
+                    .copied()
+                    .ok_or_else(|| ShowFileError::UnknownSequence {
+                        id,
+                        context: "the primary sequence".into(),
+                    })
+            })
+            .transpose()?;
+
+        let playlist = self
+            .playlist
+            .iter()
+            .enumerate()
+            .map(|(entry_i, id)| {
+                handles
+                    .get(id)
+                    .copied()
+                    .ok_or_else(|| ShowFileError::UnknownSequence {
+                        id: *id,
+                        context: format!("entry {} of the playlist", entry_i),
+                    })
+            })
+            .collect::<Result<Vec<_>, ShowFileError>>()?;
+
+        let mut cue_lists = SimpleStore::default();
+        for cue_list_data in &self.cue_lists {
+            cue_lists.add(cue_list_data.to_cue_list(&handles)?);
+        }
+
+        // only now that the whole file is known to be valid, so that a file
+        // that fails to load doesn't use up ids
+        for sequence_data in &self.sequences {
+            sequence_data.id.reserve();
+        }
+
+        Ok(LoadedSequences {
+            sequence_store,
+            primary_sequence,
+            playlist,
+            cue_lists,
+        })
+    }
+
+    /// Writes the show file to disk in RON format. Songs in the show file's
+    /// directory, or below it, are written relative to it, so the show can be
+    /// moved together with its songs.
+    pub fn save(&self, path: &Path) -> Result<(), ShowFileError> {
+        let mut show_file = self.clone();
+        let directory = std::path::absolute(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        for audio in show_file
+            .sequences
+            .iter_mut()
+            .filter_map(|sequence| sequence.audio.as_mut())
+        {
+            if let Ok(song) = std::path::absolute(&audio.path)
+                && let Ok(relative) = song.strip_prefix(&directory)
+            {
+                audio.path = relative.to_path_buf();
+            }
+        }
+        let contents = ron::ser::to_string_pretty(&show_file, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Reads a show file from disk, rejecting files written by a version of
+    /// the format that can't be read or with settings that can't be applied.
+    /// Relative song paths are resolved against the show file's directory.
+    pub fn load(path: &Path) -> Result<Self, ShowFileError> {
+        let contents = std::fs::read_to_string(path)?;
+        // the version is checked on its own first, as the rest of the file
+        // may not parse in a different version of the format
+        let ShowFileVersion { version } = ron::from_str(&contents)?;
+        if !(OLDEST_SHOW_FILE_VERSION..=SHOW_FILE_VERSION).contains(&version) {
+            return Err(ShowFileError::UnsupportedVersion { found: version });
+        }
+        let mut show_file: ShowFile = ron::from_str(&contents)?;
+        show_file
+            .settings
+            .validate()
+            .map_err(ShowFileError::InvalidSettings)?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        for audio in show_file
+            .sequences
+            .iter_mut()
+            .filter_map(|sequence| sequence.audio.as_mut())
+        {
+            if audio.path.is_relative() {
+                audio.path = directory.join(&audio.path);
+            }
+        }
+        Ok(show_file)
+    }
+}
+
+impl CueListData {
+    fn from_cue_list(
+        cue_list: &CueList,
+        id_of: &impl Fn(&SimpleHandle<Sequence>) -> SequenceId,
+    ) -> Self {
+        Self {
+            name: cue_list.name.clone(),
+            cues: cue_list
+                .cues
+                .iter()
+                .map(|cue| CueData {
+                    name: cue.name.clone(),
+                    content: match cue.content {
+                        CueContent::Sequence(sequence) => {
+                            CueContentData::Sequence(id_of(&sequence))
+                        }
+                        CueContent::Look { sequence, time } => CueContentData::Look {
+                            sequence: id_of(&sequence),
+                            time,
+                        },
+                    },
+                    delay: cue.delay,
+                    fade_in: cue.fade_in,
+                    fade_out: cue.fade_out,
+                    follow: cue.follow,
+                })
+                .collect(),
+        }
+    }
+
+    fn to_cue_list(
+        &self,
+        handles: &HashMap<SequenceId, SimpleHandle<Sequence>>,
+    ) -> Result<CueList, ShowFileError> {
+        let cues = self
+            .cues
+            .iter()
+            .enumerate()
+            .map(|(cue_i, cue_data)| {
+                let resolve = |id: SequenceId| {
+                    handles
+                        .get(&id)
+                        .copied()
+                        .ok_or_else(|| ShowFileError::UnknownSequence {
+                            id,
+                            context: format!("cue {} of cue list \"{}\"", cue_i + 1, self.name),
+                        })
+                };
+                let content = match &cue_data.content {
+                    CueContentData::Sequence(id) => CueContent::Sequence(resolve(*id)?),
+                    CueContentData::Look { sequence, time } => CueContent::Look {
+                        sequence: resolve(*sequence)?,
+                        time: *time,
+                    },
+                };
+                Ok(Cue {
+                    name: cue_data.name.clone(),
+                    content,
+                    delay: cue_data.delay,
+                    fade_in: cue_data.fade_in,
+                    fade_out: cue_data.fade_out,
+                    follow: cue_data.follow,
+                })
+            })
+            .collect::<Result<Vec<Cue>, ShowFileError>>()?;
+
+        Ok(CueList {
+            name: self.name.clone(),
+            cues,
+        })
+    }
+}
+
+impl TrackData {
+    fn from_track(track: &Track, id_of: &impl Fn(&SimpleHandle<Sequence>) -> SequenceId) -> Self {
+        let contents = match &track.contents {
+            TrackContents::EffectTrack {
+                effect_init_info,
+                effect_keyframes,
+            } => TrackContentsData::Effect {
+                effect: effect_init_info.clone(),
+                keyframes: KeyframeData::from_keyframes(effect_keyframes),
+            },
+            TrackContents::SequenceTrack { clips } => TrackContentsData::Sequence {
+                clips: clips
+                    .iter()
+                    .map(|clip| ClipData {
+                        sequence: id_of(&clip.sequence_handle),
+                        time_segment: clip.time_segment,
+                        speed: clip.playback.speed,
+                        reverse: clip.playback.reverse,
+                        loop_mode: clip.playback.loop_mode,
+                        loop_length: clip.playback.loop_length,
+                        time_remap: clip
+                            .playback
+                            .time_remap
+                            .as_ref()
+                            .map(KeyframeData::from_keyframes),
+                        fade_in: clip.fade_in,
+                        fade_out: clip.fade_out,
+                    })
+                    .collect(),
+            },
+            TrackContents::TriggerTrack { sequence_handle } => TrackContentsData::Trigger {
+                sequence: id_of(sequence_handle),
+            },
+        };
+        Self {
+            blending_mode: track.info.blending_mode,
+            factor: track.info.factor,
+            track_keyframes: KeyframeData::from_keyframes(&track.info.track_keyframes),
+            modulators: track
+                .info
+                .modulators
+                .iter()
+                .map(ModulatorData::from_modulator)
+                .collect(),
+            audio_bindings: track
+                .info
+                .audio_bindings
+                .iter()
+                .map(AudioBindingData::from_audio_binding)
+                .collect(),
+            contents,
+        }
+    }
+
+    fn to_track(
+        &self,
+        handles: &HashMap<SequenceId, SimpleHandle<Sequence>>,
+        sequence_name: &str,
+        track_i: usize,
+    ) -> Result<Track, ShowFileError> {
+        let resolve = |id: SequenceId| {
+            handles
+                .get(&id)
+                .copied()
+                .ok_or_else(|| ShowFileError::UnknownSequence {
+                    id,
+                    context: format!("track {} of sequence \"{}\"", track_i, sequence_name),
+                })
+        };
+
+        let contents = match &self.contents {
+            TrackContentsData::Effect { effect, keyframes } => {
+                let effect_init_info = effect.clone();
+                let effect_keyframes = KeyframeData::to_keyframes(keyframes);
+                effect_init_info
+                    .validate_keyframes(&effect_keyframes)
+                    .map_err(|reason| ShowFileError::InvalidKeyframes {
+                        context: format!("track {} of sequence \"{}\"", track_i, sequence_name),
+                        reason,
+                    })?;
+                TrackContents::EffectTrack {
+                    effect_init_info,
+                    effect_keyframes,
+                }
+            }
+            TrackContentsData::Sequence { clips } => TrackContents::SequenceTrack {
+                clips: clips
+                    .iter()
+                    .enumerate()
+                    .map(|(clip_i, clip_data)| {
+                        let mut clip =
+                            Clip::new(resolve(clip_data.sequence)?, clip_data.time_segment);
+                        let time_remap = clip_data
+                            .time_remap
+                            .as_deref()
+                            .map(KeyframeData::to_keyframes);
+                        if let Some(time_remap) = &time_remap {
+                            ClipPlayback::validate_time_remap(time_remap).map_err(|reason| {
+                                ShowFileError::InvalidKeyframes {
+                                    context: format!(
+                                        "time remap of clip {} on track {} of sequence \"{}\"",
+                                        clip_i, track_i, sequence_name
+                                    ),
+                                    reason,
+                                }
+                            })?;
+                        }
+                        clip.playback = ClipPlayback {
+                            speed: clip_data.speed,
+                            reverse: clip_data.reverse,
+                            loop_mode: clip_data.loop_mode,
+                            loop_length: clip_data.loop_length,
+                            time_remap,
+                        };
+                        clip.fade_in = clip_data.fade_in;
+                        clip.fade_out = clip_data.fade_out;
+                        Ok(clip)
+                    })
+                    .collect::<Result<Vec<Clip>, ShowFileError>>()?,
+            },
+            TrackContentsData::Trigger { sequence } => TrackContents::TriggerTrack {
+                sequence_handle: resolve(*sequence)?,
+            },
+        };
+
+        let modulators: Vec<Modulator> = self
+            .modulators
+            .iter()
+            .map(ModulatorData::to_modulator)
+            .collect();
+        let schemas = match &contents {
+            TrackContents::EffectTrack {
+                effect_init_info, ..
+            } => effect_init_info.parameters(),
+            _ => &[],
+        };
+        validate_modulators(&modulators, schemas).map_err(|reason| {
+            ShowFileError::InvalidModulators {
+                context: format!("track {} of sequence \"{}\"", track_i, sequence_name),
+                reason,
+            }
+        })?;
+        let audio_bindings: Vec<AudioBinding> = self
+            .audio_bindings
+            .iter()
+            .map(AudioBindingData::to_audio_binding)
+            .collect();
+        validate_audio_bindings(&audio_bindings, schemas).map_err(|reason| {
+            ShowFileError::InvalidAudioBindings {
+                context: format!("track {} of sequence \"{}\"", track_i, sequence_name),
+                reason,
+            }
+        })?;
+        let track_keyframes = KeyframeData::to_keyframes(&self.track_keyframes);
+        TrackInfo::validate_keyframes(&track_keyframes).map_err(|reason| {
+            ShowFileError::InvalidKeyframes {
+                context: format!("track {} of sequence \"{}\"", track_i, sequence_name),
+                reason,
+            }
+        })?;
+
+        Ok(Track::new(
+            TrackInfo {
+                blending_mode: self.blending_mode,
+                factor: self.factor,
+                track_keyframes,
+                modulators,
+                audio_bindings,
+            },
+            contents,
+        ))
+    }
+}
+
+impl ModulatorData {
+    fn from_modulator(modulator: &Modulator) -> Self {
+        Self {
+            target: modulator.target.clone(),
+            shape: modulator.shape,
+            rate: modulator.rate,
+            phase: modulator.phase,
+            spread: modulator.spread.to_array(),
+            depth: modulator.depth.to_array(),
+            offset: modulator.offset.to_array(),
+            seed: modulator.seed,
+        }
+    }
+
+    fn to_modulator(&self) -> Modulator {
+        Modulator {
+            target: self.target.clone(),
+            shape: self.shape,
+            rate: self.rate,
+            phase: self.phase,
+            spread: Vec3::from_array(self.spread),
+            depth: Vec4::from_array(self.depth),
+            offset: Vec4::from_array(self.offset),
+            seed: self.seed,
+        }
+    }
+}
+
+impl AudioBindingData {
+    fn from_audio_binding(audio_binding: &AudioBinding) -> Self {
+        Self {
+            target: audio_binding.target.clone(),
+            feature: audio_binding.feature,
+            gain: audio_binding.gain,
+            threshold: audio_binding.threshold,
+            curve: audio_binding.curve,
+            attack: audio_binding.attack,
+            release: audio_binding.release,
+            depth: audio_binding.depth.to_array(),
+            offset: audio_binding.offset.to_array(),
+        }
+    }
+
+    fn to_audio_binding(&self) -> AudioBinding {
+        AudioBinding {
+            target: self.target.clone(),
+            feature: self.feature,
+            gain: self.gain,
+            threshold: self.threshold,
+            curve: self.curve,
+            attack: self.attack,
+            release: self.release,
+            depth: Vec4::from_array(self.depth),
+            offset: Vec4::from_array(self.offset),
+        }
+    }
+}
+
+impl KeyframeData {
+    fn from_keyframes(keyframes: &Keyframes) -> Vec<Self> {
+        keyframes
+            .inner()
+            .iter()
+            .map(|keyframe| KeyframeData {
+                time: keyframe.time,
+                interpolation: keyframe.interpolation,
+                key: keyframe.key.clone(),
+                value: match &keyframe.value {
+                    KeyframeValue::FloatKeyframe(value) => KeyframeValueData::Float(*value),
+                    KeyframeValue::ColorKeyframe(value) => {
+                        KeyframeValueData::Color(color_to_data(value))
+                    }
+                    KeyframeValue::Vec3Keyframe(value) => KeyframeValueData::Vec3(value.to_array()),
+                    KeyframeValue::BlendingModeKeyframe(value) => {
+                        KeyframeValueData::BlendingMode(*value)
+                    }
+                },
+            })
+            .collect()
+    }
+
+    fn to_keyframes(keyframes: &[Self]) -> Keyframes {
+        Keyframes::new(
+            keyframes
+                .iter()
+                .map(|keyframe_data| Keyframe {
+                    time: keyframe_data.time,
+                    interpolation: keyframe_data.interpolation,
+                    key: keyframe_data.key.clone(),
+                    value: match &keyframe_data.value {
+                        KeyframeValueData::Float(value) => KeyframeValue::FloatKeyframe(*value),
+                        KeyframeValueData::Color(value) => {
+                            KeyframeValue::ColorKeyframe(color_from_data(value))
+                        }
+                        KeyframeValueData::Vec3(value) => {
+                            KeyframeValue::Vec3Keyframe(Vec3::from_array(*value))
+                        }
+                        KeyframeValueData::BlendingMode(value) => {
+                            KeyframeValue::BlendingModeKeyframe(*value)
+                        }
+                    },
+                })
+                .collect(),
+        )
+    }
+}
+
+impl FixtureData {
+    /// Builds the saved form of a fixture from its components.
+    pub fn from_components(
+        fixture: &Fixture,
+        transform: &Transform,
+        color_fixture: Option<&ColorFixture>,
+        pan_tilt_fixture: Option<&PanTiltFixture>,
+        artnet_data_pointer: Option<&ArtNetDataPointer>,
+    ) -> Self {
+        Self {
+            groups: fixture.groups.clone(),
+            position: transform.translation.to_array(),
+            color: color_fixture.map(|color_fixture| ColorFixtureData {
+                encoding: color_fixture.encoding,
+                red_channel: color_fixture.red_channel,
+                green_channel: color_fixture.green_channel,
+                blue_channel: color_fixture.blue_channel,
+                white_channel: color_fixture.white_channel,
+            }),
+            pan_tilt: pan_tilt_fixture.map(|pan_tilt_fixture| PanTiltFixtureData {
+                pan_range: pan_tilt_fixture.pan_range,
+                tilt_range: pan_tilt_fixture.tilt_range,
+            }),
+            artnet: artnet_data_pointer.map(|pointer| ArtNetPointerData {
+                net: pointer.address.net,
+                subnet: pointer.address.subnet,
+                universe: pointer.address.universe,
+                offset: pointer.offset,
+            }),
+        }
+    }
+
+    /// Rebuilds the color part of the fixture, if it has one. The fixture
+    /// starts out dark.
+    pub fn color_fixture(&self) -> Option<ColorFixture> {
+        self.color.as_ref().map(|color_data| ColorFixture {
+            encoding: color_data.encoding,
+            red_channel: color_data.red_channel,
+            green_channel: color_data.green_channel,
+            blue_channel: color_data.blue_channel,
+            white_channel: color_data.white_channel,
+            ..default()
+        })
+    }
+
+    /// Rebuilds the pan/tilt part of the fixture, if it has one. The fixture
+    /// starts out at its default position.
+    pub fn pan_tilt_fixture(&self) -> Option<PanTiltFixture> {
+        self.pan_tilt.as_ref().map(|pan_tilt_data| PanTiltFixture {
+            pan_range: pan_tilt_data.pan_range,
+            tilt_range: pan_tilt_data.tilt_range,
+            ..default()
+        })
+    }
+
+    /// Validates the saved Art-Net pointer of the fixture, if it has one.
+    pub fn artnet_data_pointer(&self) -> Result<Option<ArtNetDataPointer>, String> {
+        self.artnet
+            .as_ref()
+            .map(|pointer| {
+                ArtNetDataPointer::new(
+                    ArtNetAddress::new(pointer.net, pointer.subnet, pointer.universe)?,
+                    pointer.offset,
+                )
+            })
+            .transpose()
+    }
+}
+
+/// Bevy event that saves the current show to the specified path.
+#[derive(Event)]
+pub struct SaveShow {
+    pub path: std::path::PathBuf,
+}
+
+/// Bevy event that replaces the current show with one loaded from the
+/// specified path. Any existing fixtures are despawned and the sequence tree
+/// is cleared.
+#[derive(Event)]
+pub struct LoadShow {
+    pub path: std::path::PathBuf,
+}
+
+/// Components read from every fixture when saving the patch.
+type FixturePatchData<'a> = (
+    &'a Fixture,
+    &'a Transform,
+    Option<&'a ColorFixture>,
+    Option<&'a PanTiltFixture>,
+    Option<&'a ArtNetDataPointer>,
+);
+
+/// Bevy observer that listens for `SaveShow` events and writes the current
+/// sequences, patch, and settings to disk.
+fn save_show(
+    save: On<SaveShow>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    primary_sequence: Res<PrimarySequence>,
+    (playback, playlist, cue_lists, timecode, midi_clock): (
+        Res<PlaybackInformation>,
+        Res<Playlist>,
+        Res<SimpleStore<CueList>>,
+        Res<TimecodeSettings>,
+        Res<MidiClock>,
+    ),
+    fixed_time: Res<Time<Fixed>>,
+    mut history: ResMut<EditHistory>,
+    fixture_query: Query<FixturePatchData>,
+) {
+    let patch = fixture_query
+        .iter()
+        .map(
+            |(fixture, transform, color_fixture, pan_tilt_fixture, pointer)| {
+                FixtureData::from_components(
+                    fixture,
+                    transform,
+                    color_fixture,
+                    pan_tilt_fixture,
+                    pointer,
+                )
+            },
+        )
+        .collect();
+    let settings = ShowSettings {
+        update_rate_hz: 1.0 / fixed_time.timestep().as_secs_f64(),
+        end_mode: playback.end_mode,
+        timecode: timecode.clone(),
+        follow_midi_clock: midi_clock.follow,
+    };
+
+    let show_file = ShowFile::from_sequences(
+        &sequence_store,
+        primary_sequence.0,
+        &playlist.0,
+        &cue_lists,
+        patch,
+        settings,
+    );
+    match show_file.save(&save.path) {
+        Ok(()) => {
+            history.mark_saved();
+            info!("Saved show to {}", save.path.display());
+        }
+        Err(e) => error!("Failed to save show to {}: {}", save.path.display(), e),
+    }
+}
+
+/// Bevy observer that listens for `LoadShow` events and replaces the current
+/// show with the one on disk. Nothing is changed if the file fails to load.
+/// Fixtures only get meshes to draw them with when there is something to
+/// draw them on, i.e. not when running headless or built without the GUI.
+fn load_show(
+    load: On<LoadShow>,
+    mut commands: Commands,
+    #[cfg(feature = "gui")] mut meshes: Option<ResMut<Assets<Mesh>>>,
+    #[cfg(feature = "gui")] mut materials: Option<ResMut<Assets<ColorMaterial>>>,
+    (mut sequence_store, mut primary_sequence, mut midi_clock): (
+        ResMut<SimpleStore<Sequence>>,
+        ResMut<PrimarySequence>,
+        ResMut<MidiClock>,
+    ),
+    fixture_query: Query<Entity, With<Fixture>>,
+) {
+    let loaded = ShowFile::load(&load.path).and_then(|show_file| {
+        let sequences = show_file.to_sequences()?;
+        let pointers = show_file
+            .patch
+            .iter()
+            .enumerate()
+            .map(|(index, fixture_data)| {
+                fixture_data
+                    .artnet_data_pointer()
+                    .map_err(|reason| ShowFileError::InvalidFixture { index, reason })
+            })
+            .collect::<Result<Vec<_>, ShowFileError>>()?;
+        Ok((show_file, sequences, pointers))
+    });
+    let (show_file, sequences, pointers) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("Failed to load show from {}: {}", load.path.display(), e);
+            return;
+        }
+    };
+
+    *sequence_store = sequences.sequence_store;
+    primary_sequence.0 = sequences.primary_sequence;
+    commands.insert_resource(Playlist(sequences.playlist));
+    commands.insert_resource(sequences.cue_lists);
+    commands.insert_resource(CuePlayback::default());
+    // playback starts over at the beginning of the new show
+    commands.insert_resource(PlaybackInformation {
+        end_mode: show_file.settings.end_mode,
+        ..default()
+    });
+    commands.insert_resource(Time::<Fixed>::from_hz(show_file.settings.update_rate_hz));
+    commands.insert_resource(show_file.settings.timecode.clone());
+    commands.insert_resource(TimecodeChase::default());
+    midi_clock.follow = show_file.settings.follow_midi_clock;
+    // edits from the previous show can't be undone on top of the new one
+    commands.insert_resource(EditHistory::default());
+
+    for entity in fixture_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    for (fixture_data, pointer) in show_file.patch.iter().zip(pointers) {
+        let mut entity_commands = commands.spawn((
+            Transform::from_translation(Vec3::from_array(fixture_data.position)),
+            Fixture::new(fixture_data.groups.clone()),
+        ));
+        #[cfg(feature = "gui")]
+        if let (Some(meshes), Some(materials)) = (&mut meshes, &mut materials) {
+            entity_commands.insert((
+                Mesh2d(meshes.add(Circle::new(1.0))),
+                MeshMaterial2d(materials.add(Color::BLACK)),
+            ));
+        }
+        if let Some(color_fixture) = fixture_data.color_fixture() {
+            entity_commands.insert(color_fixture);
+        }
+        if let Some(pan_tilt_fixture) = fixture_data.pan_tilt_fixture() {
+            entity_commands.insert(pan_tilt_fixture);
+        }
+        if let Some(pointer) = pointer {
+            entity_commands.insert(pointer);
+        }
+    }
+
+    commands.trigger(ClearSequenceTree {});
+    info!("Loaded show from {}", load.path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::timeline::{
+        effects::{ColorEffectInfo, color::fill::ColorFillEffect},
+        positions::{MusicalTime, TempoRamp},
+    };
+
+    /// Writes a file into a directory of its own within the temporary
+    /// directory, so tests running at the same time don't collide.
+    fn write_temporary(test: &str, name: &str, contents: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("lightshow-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn rejects_versions_it_cannot_read_before_parsing_the_rest() {
+        // effects were saved in a form of their own in version 1
+        let old = write_temporary(
+            "old-version",
+            "show.ron",
+            "(version: 1, primary_sequence: None, sequences: [(id: 1, name: \"Main\", \
+             tracks: [(contents: Effect(effect: ColorFill(color: (1.0, 1.0, 1.0, 1.0))))])])",
+        );
+        assert!(matches!(
+            ShowFile::load(&old),
+            Err(ShowFileError::UnsupportedVersion { found: 1 })
+        ));
+
+        let new = write_temporary("new-version", "show.ron", "(version: 3, something_new: ())");
+        assert!(matches!(
+            ShowFile::load(&new),
+            Err(ShowFileError::UnsupportedVersion { found: 3 })
+        ));
+    }
+
+    #[test]
+    fn round_trips_through_a_saved_file() {
+        let directory =
+            std::env::temp_dir().join(format!("lightshow-round-trip-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("show.ron");
+
+        let mut sequence_store = SimpleStore::default();
+        let nested = sequence_store.add(Sequence {
+            id: SequenceId::fresh(),
+            name: "Nested".into(),
+            length: 8.0,
+            tempo_map: TempoMap::new(
+                vec![
+                    TempoPoint::new(0.0, 120.0, TempoRamp::Linear),
+                    TempoPoint::new(16.0, 90.0, TempoRamp::Constant),
+                ],
+                vec![MeterChange::new(0, 4), MeterChange::new(2, 3)],
+            )
+            .unwrap(),
+            tracks: vec![Track::new(
+                TrackInfo {
+                    blending_mode: BlendingMode::Add,
+                    factor: 0.5,
+                    track_keyframes: Keyframes::new(vec![Keyframe {
+                        time: MusicalTime::new(1, 2, 0).into(),
+                        interpolation: InterpolationType::CONSTANT,
+                        key: "blending_mode".into(),
+                        value: KeyframeValue::BlendingModeKeyframe(BlendingMode::Multiply),
+                    }]),
+                    modulators: Vec::new(),
+                    audio_bindings: Vec::new(),
+                },
+                TrackContents::EffectTrack {
+                    effect_init_info: EffectInfo::ColorEffectInfo(
+                        ColorEffectInfo::ColorFillEffect(ColorFillEffect {
+                            color: Color::srgb(1.0, 0.5, 0.0),
+                        }),
+                    ),
+                    effect_keyframes: Keyframes::new(vec![Keyframe {
+                        time: 2.0.into(),
+                        interpolation: InterpolationType::LINEAR,
+                        key: "color".into(),
+                        value: KeyframeValue::ColorKeyframe(Color::srgb(0.0, 0.0, 1.0)),
+                    }]),
+                },
+            )],
+            loop_region: None,
+            audio: None,
+        });
+        let mut clip = Clip::new(
+            nested,
+            TimeSegment::new(1.0, MusicalTime::new(2, 0, 0), 0.0),
+        );
+        clip.playback.time_remap = Some(Keyframes::new(vec![Keyframe {
+            time: 4.0.into(),
+            interpolation: InterpolationType::LINEAR,
+            key: "time".into(),
+            value: KeyframeValue::FloatKeyframe(2.0),
+        }]));
+        clip.fade_in = 0.5.into();
+        let main = sequence_store.add(Sequence {
+            id: SequenceId::fresh(),
+            name: "Main".into(),
+            length: 30.0,
+            tempo_map: TempoMap::default(),
+            tracks: vec![Track::new(
+                TrackInfo {
+                    blending_mode: BlendingMode::default(),
+                    factor: 1.0,
+                    track_keyframes: Keyframes::default(),
+                    modulators: Vec::new(),
+                    audio_bindings: Vec::new(),
+                },
+                TrackContents::SequenceTrack { clips: vec![clip] },
+            )],
+            loop_region: Some(LoopRegion::new(0.0, 16.0)),
+            audio: Some(SequenceAudio::new(directory.join("song.wav"))),
+        });
+        let patch = vec![FixtureData {
+            groups: vec![1, 2],
+            position: [1.0, 2.0, 3.0],
+            color: Some(ColorFixtureData {
+                encoding: RgbEncoding::Srgb,
+                red_channel: 0,
+                green_channel: 1,
+                blue_channel: 2,
+                white_channel: None,
+            }),
+            pan_tilt: None,
+            artnet: Some(ArtNetPointerData {
+                net: 0,
+                subnet: 0,
+                universe: 1,
+                offset: 10,
+            }),
+        }];
+        let settings = ShowSettings {
+            update_rate_hz: 60.0,
+            end_mode: PlaybackEndMode::PingPong,
+            follow_midi_clock: true,
+            ..default()
+        };
+        let saved = ShowFile::from_sequences(
+            &sequence_store,
+            Some(main),
+            &[main, nested],
+            &SimpleStore::default(),
+            patch,
+            settings,
+        );
+        saved.save(&path).unwrap();
+
+        // songs next to the show file are stored relative to it
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("path: \"song.wav\""));
+
+        let show_file = ShowFile::load(&path).unwrap();
+        let loaded = show_file.to_sequences().unwrap();
+        let reloaded = ShowFile::from_sequences(
+            &loaded.sequence_store,
+            loaded.primary_sequence,
+            &loaded.playlist,
+            &loaded.cue_lists,
+            show_file.patch,
+            show_file.settings,
+        );
+        assert_eq!(
+            ron::to_string(&reloaded).unwrap(),
+            ron::to_string(&saved).unwrap()
+        );
+    }
+}
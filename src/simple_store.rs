@@ -20,6 +20,21 @@ impl<T> Clone for SimpleHandle<T> {
 
 impl<T> Copy for SimpleHandle<T> {}
 
+impl<T> PartialEq for SimpleHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for SimpleHandle<T> {}
+
+impl<T> std::hash::Hash for SimpleHandle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
 impl<T> SimpleHandle<T> {
     fn new(index: u32, generation: u32) -> Self {
         SimpleHandle {
@@ -110,6 +125,22 @@ impl<T> SimpleStore<T> {
         }
     }
 
+    /// Iterates over every item in the store along with its handle, in order
+    /// of index.
+    pub fn iter(&self) -> impl Iterator<Item = (SimpleHandle<T>, &T)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                entry.item.as_ref().map(|item| {
+                    (
+                        SimpleHandle::new(index as u32, entry.current_generation),
+                        item,
+                    )
+                })
+            })
+    }
+
     pub fn remove(&mut self, handle: SimpleHandle<T>) -> Result<(), &'static str> {
         let entry = self
             .entries
@@ -10,7 +10,7 @@ use crate::{
     fixtures::*,
     network::*,
     simple_store::*,
-    timeline::{effects::*, keyframes::*, sequences::*, tracks::*},
+    timeline::{effects::*, keyframes::*, positions::*, sequences::*, tracks::*},
     util::blending::BlendingMode,
 };
 
@@ -96,6 +96,8 @@ pub fn frequency_cascade_test_startup(
         blending_mode: BlendingMode::Add,
         factor: 1.0,
         track_keyframes: Keyframes::default(),
+        modulators: Vec::new(),
+        audio_bindings: Vec::new(),
     };
 
     let track_contents = TrackContents::EffectTrack {
@@ -103,15 +105,16 @@ pub fn frequency_cascade_test_startup(
         effect_keyframes: Keyframes::default(),
     };
 
-    let track = Track {
-        info: track_info,
-        contents: track_contents,
-    };
+    let track = Track::new(track_info, track_contents);
 
     let sequence = Sequence {
+        id: SequenceId::fresh(),
         name: "Main Sequence".into(),
         length: 4.,
+        tempo_map: TempoMap::default(),
         tracks: vec![track],
+        loop_region: None,
+        audio: None,
     };
 
     let sequence_handle = sequence_store.add(sequence);
@@ -6,7 +6,7 @@ use crate::{
     fixtures::*,
     network::*,
     simple_store::*,
-    timeline::{effects::*, keyframes::*, sequences::*, tracks::*},
+    timeline::{effects::*, keyframes::*, positions::*, sequences::*, tracks::*},
     util::blending::BlendingMode,
 };
 
@@ -66,13 +66,13 @@ pub fn pulse_test_startup(
 
     let effect_keyframes = Keyframes::new(vec![
         Keyframe {
-            time: 0.,
+            time: 0.0.into(),
             interpolation: InterpolationType::LINEAR,
             key: "radius".to_string(),
             value: KeyframeValue::FloatKeyframe(0.),
         },
         Keyframe {
-            time: 3.,
+            time: 3.0.into(),
             interpolation: InterpolationType::LINEAR,
             key: "radius".to_string(),
             value: KeyframeValue::FloatKeyframe(300.),
@@ -92,6 +92,8 @@ pub fn pulse_test_startup(
         blending_mode: BlendingMode::Add,
         factor: 1.0,
         track_keyframes: Keyframes::default(),
+        modulators: Vec::new(),
+        audio_bindings: Vec::new(),
     };
 
     let track_contents = TrackContents::EffectTrack {
@@ -99,15 +101,16 @@ pub fn pulse_test_startup(
         effect_keyframes,
     };
 
-    let track = Track {
-        info: track_info,
-        contents: track_contents,
-    };
+    let track = Track::new(track_info, track_contents);
 
     let sequence = Sequence {
+        id: SequenceId::fresh(),
         name: "Main Sequence".into(),
         length: 4.,
+        tempo_map: TempoMap::default(),
         tracks: vec![track],
+        loop_region: None,
+        audio: None,
     };
 
     let sequence_handle = sequence_store.add(sequence);
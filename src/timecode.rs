@@ -0,0 +1,630 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+use crate::{
+    midi::dispatch_midi_messages,
+    simple_store::SimpleStore,
+    timeline::{playback::*, sequences::*},
+};
+
+pub mod art_net;
+pub mod ltc;
+pub mod mtc;
+
+/// Bevy plugin for SMPTE timecode. Playback can either chase an external
+/// clock (LTC from the audio input, MIDI Time Code, or ArtTimeCode packets),
+/// or act as the master and generate timecode of its own.
+pub struct TimecodePlugin;
+
+impl Plugin for TimecodePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimecodeSettings>()
+            .init_resource::<TimecodeChase>()
+            .init_resource::<mtc::MtcDecoder>()
+            .add_systems(
+                Update,
+                (
+                    ltc::manage_ltc_devices,
+                    ltc::decode_ltc_input,
+                    art_net::receive_art_net_timecode,
+                    chase_timecode,
+                )
+                    .chain()
+                    .after(dispatch_midi_messages)
+                    .before(increment_playback_time),
+            )
+            .add_systems(
+                Update,
+                (art_net::send_art_net_timecode, ltc::feed_ltc_output)
+                    .after(increment_playback_time),
+            )
+            .add_observer(mtc::receive_mtc);
+    }
+}
+
+/// How long it takes to run through every timecode of a day, after which
+/// timecode wraps around to midnight.
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+/// The number of frames after which a source that keeps repeating the same
+/// timecode is considered stopped.
+const STOPPED_FRAMES: f64 = 4.0;
+
+/// The number of frames without a new timecode after which chasing is
+/// considered freewheeling.
+const DROPOUT_FRAMES: f64 = 2.0;
+
+/// How much of the difference between the received timecode and the running
+/// chase clock is corrected with each frame. Smaller differences than the
+/// jump threshold are smoothed out this way to hide jitter.
+const CORRECTION: f64 = 0.1;
+
+/// A SMPTE timecode address, i.e. a frame within a day.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+}
+
+impl Timecode {
+    /// Constructs a new `Timecode`, checking that each field is in range. The
+    /// frames are only checked against the fastest frame rate.
+    pub fn new(hours: u8, minutes: u8, seconds: u8, frames: u8) -> Result<Self, String> {
+        if hours >= 24 {
+            Err(format!(
+                "timecode hours must be between 0 and 23 inclusive, got {}",
+                hours
+            ))
+        } else if minutes >= 60 {
+            Err(format!(
+                "timecode minutes must be between 0 and 59 inclusive, got {}",
+                minutes
+            ))
+        } else if seconds >= 60 {
+            Err(format!(
+                "timecode seconds must be between 0 and 59 inclusive, got {}",
+                seconds
+            ))
+        } else if frames >= 30 {
+            Err(format!(
+                "timecode frames must be between 0 and 29 inclusive, got {}",
+                frames
+            ))
+        } else {
+            Ok(Self {
+                hours,
+                minutes,
+                seconds,
+                frames,
+            })
+        }
+    }
+
+    /// Counts the frames since midnight at the given frame rate, leaving out
+    /// the frame numbers dropped by drop frame timecode.
+    pub fn frame_count(&self, frame_rate: FrameRate) -> i64 {
+        let hours = self.hours as i64;
+        let minutes = self.minutes as i64;
+        let seconds = self.seconds as i64;
+        let frames = self.frames as i64;
+        let count = ((hours * 60 + minutes) * 60 + seconds) * frame_rate.nominal() + frames;
+        if frame_rate.is_drop_frame() {
+            // frames 0 and 1 are dropped at the start of every minute, except
+            // every tenth minute
+            let total_minutes = hours * 60 + minutes;
+            count - 2 * (total_minutes - total_minutes / 10)
+        } else {
+            count
+        }
+    }
+
+    /// Finds the timecode of a frame counted from midnight at the given frame
+    /// rate, wrapping around at the end of the day.
+    pub fn from_frame_count(count: i64, frame_rate: FrameRate) -> Self {
+        let mut count = count.rem_euclid(frame_rate.frames_per_day());
+        if frame_rate.is_drop_frame() {
+            // 17982 frames in every ten minutes, 1798 in each minute after the
+            // first
+            let tens = count / 17982;
+            let remainder = count % 17982;
+            count += 18 * tens;
+            if remainder >= 2 {
+                count += 2 * ((remainder - 2) / 1798);
+            }
+        }
+        let nominal = frame_rate.nominal();
+        Self {
+            hours: (count / (nominal * 3600)) as u8,
+            minutes: (count / (nominal * 60) % 60) as u8,
+            seconds: (count / nominal % 60) as u8,
+            frames: (count % nominal) as u8,
+        }
+    }
+
+    /// Converts the timecode into seconds since midnight.
+    pub fn to_seconds(&self, frame_rate: FrameRate) -> f64 {
+        self.frame_count(frame_rate) as f64 / frame_rate.fps()
+    }
+
+    /// Finds the timecode of the frame playing at the given number of seconds
+    /// since midnight.
+    pub fn from_seconds(seconds: f64, frame_rate: FrameRate) -> Self {
+        // the small bias keeps exact frame starts from rounding down
+        Self::from_frame_count(
+            (seconds * frame_rate.fps() + 1e-6).floor() as i64,
+            frame_rate,
+        )
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}:{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
+impl FromStr for Timecode {
+    type Err = String;
+
+    /// Parses a timecode written as `HH:MM:SS:FF`. Drop frame timecode is
+    /// often written with a `;` or `.` before the frames, which is accepted
+    /// as well.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s
+            .trim()
+            .split([':', ';', '.'])
+            .map(|field| {
+                field
+                    .parse::<u8>()
+                    .map_err(|_| format!("\"{}\" is not a valid timecode field", field))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        match fields[..] {
+            [hours, minutes, seconds, frames] => Timecode::new(hours, minutes, seconds, frames),
+            _ => Err(format!(
+                "timecode must be written as HH:MM:SS:FF, got \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// The frame rates used by SMPTE timecode.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FrameRate {
+    Fps24,
+    #[default]
+    Fps25,
+    /// 29.97 frames per second, which drops frame numbers to keep timecode in
+    /// line with the clock on the wall.
+    Fps2997Drop,
+    Fps30,
+}
+
+impl FrameRate {
+    /// Every frame rate, in the order they are listed in the UI.
+    pub const ALL: [FrameRate; 4] = [
+        FrameRate::Fps24,
+        FrameRate::Fps25,
+        FrameRate::Fps2997Drop,
+        FrameRate::Fps30,
+    ];
+
+    /// Gets the number of frames per second.
+    pub fn fps(&self) -> f64 {
+        match self {
+            FrameRate::Fps24 => 24.0,
+            FrameRate::Fps25 => 25.0,
+            FrameRate::Fps2997Drop => 30000.0 / 1001.0,
+            FrameRate::Fps30 => 30.0,
+        }
+    }
+
+    /// Gets the number of frame numbers in each second of timecode.
+    pub fn nominal(&self) -> i64 {
+        match self {
+            FrameRate::Fps24 => 24,
+            FrameRate::Fps25 => 25,
+            FrameRate::Fps2997Drop | FrameRate::Fps30 => 30,
+        }
+    }
+
+    pub fn is_drop_frame(&self) -> bool {
+        *self == FrameRate::Fps2997Drop
+    }
+
+    /// Gets the number of frames in a day of timecode.
+    pub fn frames_per_day(&self) -> i64 {
+        if self.is_drop_frame() {
+            24 * 6 * 17982
+        } else {
+            24 * 3600 * self.nominal()
+        }
+    }
+
+    /// Gets the frame rate for the two bit code used by both MIDI Time Code
+    /// and ArtTimeCode.
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(FrameRate::Fps24),
+            1 => Some(FrameRate::Fps25),
+            2 => Some(FrameRate::Fps2997Drop),
+            3 => Some(FrameRate::Fps30),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FrameRate::Fps24 => "24 fps",
+            FrameRate::Fps25 => "25 fps",
+            FrameRate::Fps2997Drop => "29.97 fps drop frame",
+            FrameRate::Fps30 => "30 fps",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The external clocks that playback can chase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimecodeSource {
+    /// Linear timecode, decoded from the default audio input.
+    Ltc,
+    /// MIDI Time Code, from any open MIDI input.
+    Mtc,
+    /// ArtTimeCode packets, received on the Art-Net socket.
+    ArtNet,
+}
+
+impl TimecodeSource {
+    /// Every source, in the order they are listed in the UI.
+    pub const ALL: [TimecodeSource; 3] = [
+        TimecodeSource::Ltc,
+        TimecodeSource::Mtc,
+        TimecodeSource::ArtNet,
+    ];
+}
+
+impl fmt::Display for TimecodeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TimecodeSource::Ltc => "LTC",
+            TimecodeSource::Mtc => "MTC",
+            TimecodeSource::ArtNet => "Art-Net",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Bevy resource that holds the timecode settings, which are saved along
+/// with a show.
+///
+/// The playback head sits at the start of the primary sequence at `offset`.
+/// While chasing, dropouts shorter than `freewheel` seconds are bridged by
+/// running on, and differences of more than `jump_threshold` seconds between
+/// the received timecode and the playback head are treated as a jump rather
+/// than smoothed out.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimecodeSettings {
+    /// The external clock to chase, if any. Without one, lightshow is the
+    /// master and can generate timecode.
+    pub chase: Option<TimecodeSource>,
+    /// The frame rate of generated timecode. LTC is decoded at this rate too,
+    /// as it doesn't carry its frame rate, while MTC and ArtTimeCode do.
+    pub frame_rate: FrameRate,
+    pub offset: Timecode,
+    pub freewheel: f64,
+    pub jump_threshold: f64,
+    /// Whether to broadcast ArtTimeCode packets while lightshow is the
+    /// master.
+    pub generate_art_net: bool,
+    /// Whether to play LTC on the default audio output while lightshow is
+    /// the master.
+    pub generate_ltc: bool,
+}
+
+impl Default for TimecodeSettings {
+    fn default() -> Self {
+        Self {
+            chase: None,
+            frame_rate: FrameRate::default(),
+            offset: Timecode::default(),
+            freewheel: 1.0,
+            jump_threshold: 0.2,
+            generate_art_net: false,
+            generate_ltc: false,
+        }
+    }
+}
+
+impl TimecodeSettings {
+    /// Gets the offset in seconds since midnight.
+    pub fn offset_seconds(&self) -> f64 {
+        self.offset.to_seconds(self.frame_rate)
+    }
+
+    /// Whether timecode should be generated, i.e. lightshow is the master.
+    pub fn is_master(&self) -> bool {
+        self.chase.is_none()
+    }
+}
+
+/// A timecode that has been received from an external clock.
+#[derive(Debug, Clone, Copy)]
+pub struct ReceivedTimecode {
+    pub source: TimecodeSource,
+    pub timecode: Timecode,
+    pub frame_rate: FrameRate,
+    /// The Bevy time (see `Time::elapsed_secs_f64`) at which the frame
+    /// started at the source, with any latency of the source taken away.
+    pub at: f64,
+    /// Whether the source is parked at this timecode rather than running, as
+    /// after an MTC full frame message.
+    pub parked: bool,
+}
+
+/// How chasing an external clock is going.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChaseStatus {
+    /// Nothing has been received, or the signal was lost for longer than the
+    /// freewheel time.
+    #[default]
+    NoSignal,
+    /// Timecode is coming in and running.
+    Locked,
+    /// The signal has dropped out, and playback is running on by itself.
+    Freewheeling,
+    /// The source has stopped on a single frame.
+    Parked,
+}
+
+impl fmt::Display for ChaseStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChaseStatus::NoSignal => "No signal",
+            ChaseStatus::Locked => "Locked",
+            ChaseStatus::Freewheeling => "Freewheeling",
+            ChaseStatus::Parked => "Parked",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Timecode position of the chase clock at a point in time.
+#[derive(Debug, Clone, Copy)]
+struct ChaseAnchor {
+    seconds: f64,
+    at: f64,
+    running: bool,
+}
+
+impl ChaseAnchor {
+    fn seconds_at(&self, time: f64) -> f64 {
+        if self.running {
+            self.seconds + (time - self.at)
+        } else {
+            self.seconds
+        }
+    }
+}
+
+/// Bevy resource that follows an external clock. Decoders hand it every
+/// timecode they receive (see `TimecodeChase::receive`), and `chase_timecode`
+/// moves the playback head along with it.
+#[derive(Resource, Debug, Default)]
+pub struct TimecodeChase {
+    received: Vec<ReceivedTimecode>,
+    anchor: Option<ChaseAnchor>,
+    last_received: Option<ReceivedTimecode>,
+    /// The time at which the timecode last moved on to a new frame.
+    last_advance: f64,
+    status: ChaseStatus,
+}
+
+impl TimecodeChase {
+    /// Queues a received timecode, to be chased on the next update.
+    pub fn receive(&mut self, timecode: ReceivedTimecode) {
+        self.received.push(timecode);
+    }
+
+    pub fn status(&self) -> ChaseStatus {
+        self.status
+    }
+
+    /// Gets the last timecode received from the chased source.
+    pub fn last_received(&self) -> Option<ReceivedTimecode> {
+        self.last_received
+    }
+
+    /// Forgets everything that has been received.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Chases every timecode received from `source` since the last update,
+    /// and returns the position of the chase clock in seconds since midnight
+    /// along with whether it is running.
+    fn update(
+        &mut self,
+        source: TimecodeSource,
+        now: f64,
+        settings: &TimecodeSettings,
+    ) -> Option<(f64, bool)> {
+        for timecode in std::mem::take(&mut self.received) {
+            if timecode.source == source {
+                self.chase(timecode, settings.jump_threshold);
+            }
+        }
+
+        let anchor = self.anchor.as_mut()?;
+        if anchor.running {
+            let frame_duration = self
+                .last_received
+                .map_or(1.0 / 30.0, |last| 1.0 / last.frame_rate.fps());
+            let silence = now - self.last_advance;
+            if silence > settings.freewheel {
+                *anchor = ChaseAnchor {
+                    seconds: anchor.seconds_at(now),
+                    at: now,
+                    running: false,
+                };
+                self.status = ChaseStatus::NoSignal;
+            } else if silence > DROPOUT_FRAMES * frame_duration {
+                self.status = ChaseStatus::Freewheeling;
+            } else {
+                self.status = ChaseStatus::Locked;
+            }
+        }
+        Some((anchor.seconds_at(now), anchor.running))
+    }
+
+    fn chase(&mut self, timecode: ReceivedTimecode, jump_threshold: f64) {
+        let seconds = timecode.timecode.to_seconds(timecode.frame_rate);
+        let repeated = self
+            .last_received
+            .is_some_and(|last| last.timecode == timecode.timecode);
+        self.last_received = Some(timecode);
+
+        let parked = ChaseAnchor {
+            seconds,
+            at: timecode.at,
+            running: false,
+        };
+        if timecode.parked {
+            self.anchor = Some(parked);
+            self.last_advance = timecode.at;
+            self.status = ChaseStatus::Parked;
+            return;
+        }
+        if repeated {
+            // a source that keeps sending the same frame has stopped
+            let frame_duration = 1.0 / timecode.frame_rate.fps();
+            if timecode.at - self.last_advance > STOPPED_FRAMES * frame_duration {
+                self.anchor = Some(parked);
+                self.status = ChaseStatus::Parked;
+            }
+            return;
+        }
+
+        self.last_advance = timecode.at;
+        let seconds = match self.anchor {
+            Some(anchor) if anchor.running => {
+                let predicted = anchor.seconds_at(timecode.at);
+                let error = seconds - predicted;
+                if error.abs() > jump_threshold {
+                    info!("Timecode jumped by {:+.3} seconds", error);
+                    seconds
+                } else {
+                    predicted + error * CORRECTION
+                }
+            }
+            _ => seconds,
+        };
+        self.anchor = Some(ChaseAnchor {
+            seconds,
+            at: timecode.at,
+            running: true,
+        });
+        self.status = ChaseStatus::Locked;
+    }
+}
+
+/// Moves the playback head along with the chased external clock, if there
+/// is one. Timecode before the offset holds the playback head at the start
+/// of the primary sequence, and timecode past its end holds it at the end.
+pub fn chase_timecode(
+    time: Res<Time>,
+    settings: Res<TimecodeSettings>,
+    mut chase: ResMut<TimecodeChase>,
+    mut playback: ResMut<PlaybackInformation>,
+    primary_sequence: Res<PrimarySequence>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    mut chasing: Local<bool>,
+) {
+    let Some(source) = settings.chase else {
+        if *chasing {
+            *chasing = false;
+            playback.external_clock = false;
+            playback.pause();
+            chase.reset();
+        }
+        return;
+    };
+    *chasing = true;
+    playback.external_clock = true;
+
+    let Some((seconds, running)) = chase.update(source, time.elapsed_secs_f64(), &settings) else {
+        return;
+    };
+    let length = primary_sequence
+        .0
+        .and_then(|handle| sequence_store.get(handle))
+        .map_or(0.0, |sequence| sequence.length);
+    // timecode wraps around at midnight, so the offset may lie on the day
+    // before
+    let mut current_time = (seconds - settings.offset_seconds()).rem_euclid(SECONDS_PER_DAY);
+    if current_time >= SECONDS_PER_DAY / 2.0 {
+        current_time -= SECONDS_PER_DAY;
+    }
+    playback.seek(current_time.clamp(0.0, length));
+    playback.is_playing = running;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_frame_skips_the_first_two_frames_of_most_minutes() {
+        let frame_rate = FrameRate::Fps2997Drop;
+        let at = |minutes, seconds, frames| {
+            Timecode::new(0, minutes, seconds, frames)
+                .unwrap()
+                .frame_count(frame_rate)
+        };
+        assert_eq!(at(0, 59, 29) + 1, at(1, 0, 2));
+        assert_eq!(at(9, 59, 29) + 1, at(10, 0, 0));
+        assert_eq!(at(10, 0, 0), 17982);
+        assert_eq!(
+            Timecode::from_frame_count(1800, frame_rate),
+            Timecode::new(0, 1, 0, 2).unwrap()
+        );
+        assert_eq!(
+            Timecode::from_frame_count(17982, frame_rate),
+            Timecode::new(0, 10, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn frame_counts_round_trip() {
+        for frame_rate in FrameRate::ALL {
+            for count in (0..frame_rate.frames_per_day()).step_by(997) {
+                let timecode = Timecode::from_frame_count(count, frame_rate);
+                assert_eq!(timecode.frame_count(frame_rate), count);
+            }
+        }
+    }
+
+    #[test]
+    fn frame_counts_wrap_around_midnight() {
+        for frame_rate in FrameRate::ALL {
+            let last = Timecode::from_frame_count(-1, frame_rate);
+            assert_eq!(
+                last.frame_count(frame_rate),
+                frame_rate.frames_per_day() - 1
+            );
+            assert_eq!((last.hours, last.minutes, last.seconds), (23, 59, 59));
+            assert_eq!(last.frames as i64, frame_rate.nominal() - 1);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use artnet_protocol::{ARTNET_PROTOCOL_VERSION, ArtCommand, FrameType};
+use bevy::prelude::*;
+
+use crate::{network::ActiveSocket, timecode::*};
+
+impl From<FrameType> for FrameRate {
+    fn from(frame_type: FrameType) -> Self {
+        match frame_type {
+            FrameType::Film => FrameRate::Fps24,
+            FrameType::EBU => FrameRate::Fps25,
+            FrameType::DF => FrameRate::Fps2997Drop,
+            FrameType::SMPTE => FrameRate::Fps30,
+        }
+    }
+}
+
+impl From<FrameRate> for FrameType {
+    fn from(frame_rate: FrameRate) -> Self {
+        match frame_rate {
+            FrameRate::Fps24 => FrameType::Film,
+            FrameRate::Fps25 => FrameType::EBU,
+            FrameRate::Fps2997Drop => FrameType::DF,
+            FrameRate::Fps30 => FrameType::SMPTE,
+        }
+    }
+}
+
+/// Hands ArtTimeCode packets waiting on the Art-Net socket to the chase while
+/// Art-Net is the chased source.
+pub fn receive_art_net_timecode(
+    time: Res<Time>,
+    settings: Res<TimecodeSettings>,
+    socket: Res<ActiveSocket>,
+    mut chase: ResMut<TimecodeChase>,
+) {
+    if settings.chase != Some(TimecodeSource::ArtNet) {
+        return;
+    }
+    let now = time.elapsed_secs_f64();
+    for command in socket.receive() {
+        let ArtCommand::OpTimeCode(packet) = command else {
+            continue;
+        };
+        match decode_art_net_timecode(&packet) {
+            Ok((timecode, frame_rate)) => chase.receive(ReceivedTimecode {
+                source: TimecodeSource::ArtNet,
+                timecode,
+                frame_rate,
+                at: now,
+                parked: false,
+            }),
+            Err(e) => warn!("Ignoring ArtTimeCode packet: {}", e),
+        }
+    }
+}
+
+/// Reads the timecode and frame rate of an ArtTimeCode packet.
+fn decode_art_net_timecode(
+    packet: &artnet_protocol::Timecode,
+) -> Result<(Timecode, FrameRate), String> {
+    let timecode = Timecode::new(packet.hours, packet.minutes, packet.seconds, packet.frames)?;
+    Ok((timecode, packet.frame_type.into()))
+}
+
+/// Broadcasts an ArtTimeCode packet whenever the playback head moves on to a
+/// new frame, while lightshow is the master and Art-Net generation is on.
+pub fn send_art_net_timecode(
+    settings: Res<TimecodeSettings>,
+    playback: Res<PlaybackInformation>,
+    socket: Res<ActiveSocket>,
+    mut last_sent: Local<Option<Timecode>>,
+) {
+    if !settings.is_master() || !settings.generate_art_net {
+        *last_sent = None;
+        return;
+    }
+    let Some(socket) = &socket.socket else {
+        return;
+    };
+
+    let timecode = Timecode::from_seconds(
+        playback.current_time + settings.offset_seconds(),
+        settings.frame_rate,
+    );
+    if *last_sent == Some(timecode) {
+        return;
+    }
+    *last_sent = Some(timecode);
+
+    let command = ArtCommand::OpTimeCode(artnet_protocol::Timecode {
+        version: ARTNET_PROTOCOL_VERSION,
+        filler1: 0,
+        stream_id: 0,
+        frames: timecode.frames,
+        seconds: timecode.seconds,
+        minutes: timecode.minutes,
+        hours: timecode.hours,
+        frame_type: settings.frame_rate.into(),
+    });
+    match command.write_to_buffer() {
+        Ok(packet) => {
+            if let Err(e) = socket.send_to(&packet, "255.255.255.255:6454") {
+                warn!("Failed to send ArtTimeCode packet: {}", e);
+            }
+        }
+        Err(e) => warn!("Failed to serialize ArtTimeCode packet: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(timecode: Timecode, frame_type: FrameType) -> artnet_protocol::Timecode {
+        artnet_protocol::Timecode {
+            version: ARTNET_PROTOCOL_VERSION,
+            filler1: 0,
+            stream_id: 0,
+            frames: timecode.frames,
+            seconds: timecode.seconds,
+            minutes: timecode.minutes,
+            hours: timecode.hours,
+            frame_type,
+        }
+    }
+
+    #[test]
+    fn decodes_every_frame_type() {
+        let timecode = Timecode::new(1, 2, 3, 4).unwrap();
+        for frame_rate in FrameRate::ALL {
+            assert_eq!(
+                decode_art_net_timecode(&packet(timecode, frame_rate.into())),
+                Ok((timecode, frame_rate))
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_drop_frame_timecode() {
+        // 00:01:00;00 and ;01 don't exist, so the minute starts at ;02
+        let timecode = Timecode::new(0, 1, 0, 2).unwrap();
+        let (decoded, frame_rate) =
+            decode_art_net_timecode(&packet(timecode, FrameType::DF)).unwrap();
+        assert_eq!(frame_rate, FrameRate::Fps2997Drop);
+        assert_eq!(decoded.frame_count(frame_rate), 1800);
+    }
+
+    #[test]
+    fn survives_a_round_trip_through_a_buffer() {
+        let timecode = Timecode::new(23, 59, 59, 29).unwrap();
+        let buffer = ArtCommand::OpTimeCode(packet(timecode, FrameType::SMPTE))
+            .write_to_buffer()
+            .unwrap();
+        let Ok(ArtCommand::OpTimeCode(packet)) = ArtCommand::from_buffer(&buffer) else {
+            panic!("expected an ArtTimeCode packet");
+        };
+        assert_eq!(
+            decode_art_net_timecode(&packet),
+            Ok((timecode, FrameRate::Fps30))
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_fields() {
+        let mut packet = packet(Timecode::default(), FrameType::EBU);
+        packet.minutes = 60;
+        assert!(decode_art_net_timecode(&packet).is_err());
+    }
+}
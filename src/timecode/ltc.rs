@@ -0,0 +1,544 @@
+use bevy::prelude::*;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::{
+    HeapRb,
+    traits::{Observer as _, *},
+};
+use std::sync::Mutex;
+
+use crate::{
+    audio::{HeapConsumer, HeapProducer, capture::AudioCapture},
+    timecode::*,
+};
+
+/// The last 16 bits of every LTC frame, with bit 64 of the frame in bit 0.
+const SYNC_WORD: u128 = 0xBFFC;
+
+/// The number of bits in an LTC frame.
+const FRAME_BITS: u32 = 80;
+
+/// How far the signal has to cross zero to count as a transition, which
+/// keeps noise on a silent input from being read as bits.
+const HYSTERESIS: f32 = 0.02;
+
+/// Level of generated LTC.
+const OUTPUT_LEVEL: f32 = 0.5;
+
+/// How far ahead of the playback head generated LTC is queued, in seconds.
+const OUTPUT_LOOKAHEAD: f64 = 0.1;
+
+/// How far generated LTC may drift from the playback head before it starts
+/// over at the playback head, in seconds.
+const OUTPUT_RESYNC: f64 = 0.2;
+
+/// A frame decoded by `LtcDecoder`.
+#[derive(Debug, Clone, Copy)]
+pub struct LtcFrame {
+    pub timecode: Timecode,
+    pub drop_frame: bool,
+    /// The index of the sample just after the end of the frame, within the
+    /// samples it was decoded from.
+    pub end_sample: usize,
+}
+
+/// Decodes linear timecode from audio samples. LTC is a biphase mark code:
+/// the signal flips at the start of every bit, and flips once more halfway
+/// through a one. The decoder follows the length of a bit as it goes, so any
+/// of the common frame rates, and some variation in speed, is understood.
+#[derive(Debug)]
+pub struct LtcDecoder {
+    min_bit_length: f64,
+    max_bit_length: f64,
+    /// The current estimate of the length of a bit, in samples.
+    bit_length: f64,
+    /// Whether the signal is currently above zero.
+    high: bool,
+    /// The number of samples since the last transition.
+    interval: usize,
+    /// The length of the first half of a one, once it has been read.
+    half_bit: Option<f64>,
+    /// The last 80 bits read, with the oldest in bit 0.
+    bits: u128,
+}
+
+impl LtcDecoder {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f64;
+        let bits_per_second = |fps: f64| fps * FRAME_BITS as f64;
+        Self {
+            // leave some room around 24 and 30 fps for varispeed
+            min_bit_length: sample_rate / bits_per_second(32.0),
+            max_bit_length: sample_rate / bits_per_second(22.0),
+            bit_length: sample_rate / bits_per_second(27.0),
+            high: false,
+            interval: 0,
+            half_bit: None,
+            bits: 0,
+        }
+    }
+
+    /// Decodes the next run of samples, returning every frame that ended
+    /// within them.
+    pub fn decode(&mut self, samples: &[f32]) -> Vec<LtcFrame> {
+        let mut frames = Vec::new();
+        for (index, &sample) in samples.iter().enumerate() {
+            self.interval += 1;
+            let high = if self.high {
+                sample > -HYSTERESIS
+            } else {
+                sample > HYSTERESIS
+            };
+            if high == self.high {
+                continue;
+            }
+            self.high = high;
+            let interval = std::mem::take(&mut self.interval) as f64;
+
+            let Some(bit) = self.read_interval(interval) else {
+                continue;
+            };
+            self.bits = (self.bits >> 1) | (bit as u128) << (FRAME_BITS - 1);
+            if self.bits >> 64 == SYNC_WORD
+                && let Some((timecode, drop_frame)) = parse_frame(self.bits)
+            {
+                frames.push(LtcFrame {
+                    timecode,
+                    drop_frame,
+                    end_sample: index + 1,
+                });
+            }
+        }
+        frames
+    }
+
+    /// Reads the time between two transitions, returning a bit once one is
+    /// complete.
+    fn read_interval(&mut self, interval: f64) -> Option<bool> {
+        if interval < self.min_bit_length * 0.3 || interval > self.max_bit_length * 1.5 {
+            // not LTC, so wait for the next sync word
+            self.half_bit = None;
+            self.bits = 0;
+            return None;
+        }
+        if interval > self.bit_length * 0.75 {
+            // a stray half bit means the halves were paired up wrong, which
+            // the zero sorts out
+            self.half_bit = None;
+            self.track_bit_length(interval);
+            Some(false)
+        } else if let Some(first_half) = self.half_bit.take() {
+            self.track_bit_length(first_half + interval);
+            Some(true)
+        } else {
+            self.half_bit = Some(interval);
+            None
+        }
+    }
+
+    fn track_bit_length(&mut self, measured: f64) {
+        self.bit_length = (self.bit_length * 0.9 + measured * 0.1)
+            .clamp(self.min_bit_length, self.max_bit_length);
+    }
+}
+
+/// Reads the `length` bits starting at bit `start` of an LTC frame.
+fn field(bits: u128, start: u32, length: u32) -> u8 {
+    ((bits >> start) & ((1 << length) - 1)) as u8
+}
+
+/// Reads the timecode and drop frame flag from an LTC frame.
+fn parse_frame(bits: u128) -> Option<(Timecode, bool)> {
+    let timecode = Timecode::new(
+        field(bits, 48, 4) + 10 * field(bits, 56, 2),
+        field(bits, 32, 4) + 10 * field(bits, 40, 3),
+        field(bits, 16, 4) + 10 * field(bits, 24, 3),
+        field(bits, 0, 4) + 10 * field(bits, 8, 2),
+    )
+    .ok()?;
+    Some((timecode, field(bits, 10, 1) == 1))
+}
+
+/// Builds an LTC frame, with bit 0 of the frame in bit 0. User bits are left
+/// empty.
+fn frame_bits(timecode: Timecode, frame_rate: FrameRate) -> u128 {
+    let mut bits = SYNC_WORD << 64;
+    let mut put = |start: u32, value: u8| bits |= (value as u128) << start;
+    put(0, timecode.frames % 10);
+    put(8, timecode.frames / 10);
+    put(10, frame_rate.is_drop_frame() as u8);
+    put(16, timecode.seconds % 10);
+    put(24, timecode.seconds / 10);
+    put(32, timecode.minutes % 10);
+    put(40, timecode.minutes / 10);
+    put(48, timecode.hours % 10);
+    put(56, timecode.hours / 10);
+
+    // the polarity correction bit keeps the number of zeros even, so every
+    // frame starts on the same edge
+    let polarity_bit = if frame_rate == FrameRate::Fps25 {
+        59
+    } else {
+        27
+    };
+    if (FRAME_BITS - bits.count_ones()) % 2 == 1 {
+        bits |= 1 << polarity_bit;
+    }
+    bits
+}
+
+/// Encodes linear timecode into audio samples (see `LtcDecoder`).
+#[derive(Debug)]
+pub struct LtcEncoder {
+    sample_rate: f64,
+    high: bool,
+    /// The part of a sample left over from the last half bit, which keeps
+    /// frame rates that don't divide the sample rate on time.
+    remainder: f64,
+}
+
+impl LtcEncoder {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f64,
+            high: false,
+            remainder: 0.0,
+        }
+    }
+
+    /// Appends the samples of a single frame to `output`.
+    pub fn encode(&mut self, timecode: Timecode, frame_rate: FrameRate, output: &mut Vec<f32>) {
+        let bits = frame_bits(timecode, frame_rate);
+        let half_bit_length = self.sample_rate / (frame_rate.fps() * FRAME_BITS as f64) / 2.0;
+        for index in 0..FRAME_BITS {
+            self.high = !self.high;
+            self.write(half_bit_length, output);
+            if (bits >> index) & 1 == 1 {
+                self.high = !self.high;
+            }
+            self.write(half_bit_length, output);
+        }
+    }
+
+    fn write(&mut self, length: f64, output: &mut Vec<f32>) {
+        self.remainder += length;
+        let count = self.remainder.floor();
+        self.remainder -= count;
+        let level = if self.high {
+            OUTPUT_LEVEL
+        } else {
+            -OUTPUT_LEVEL
+        };
+        output.extend(std::iter::repeat_n(level, count as usize));
+    }
+}
+
+/// Bevy resource that reads LTC from the default audio input. Only exists
+/// while LTC is the chased source.
+#[derive(Resource)]
+pub struct LtcInput {
+    _capture: AudioCapture,
+    consumer: Mutex<HeapConsumer<f32>>,
+    sample_rate: u32,
+    decoder: LtcDecoder,
+    samples: Vec<f32>,
+}
+
+impl LtcInput {
+    /// Opens the default audio input for reading LTC.
+    pub fn open() -> Result<Self, Box<dyn std::error::Error>> {
+        let sample_rate = 44100;
+        let (capture, consumer) = AudioCapture::new(sample_rate, sample_rate as usize)?;
+        Ok(Self {
+            _capture: capture,
+            consumer: Mutex::new(consumer),
+            sample_rate,
+            decoder: LtcDecoder::new(sample_rate),
+            samples: Vec::new(),
+        })
+    }
+}
+
+/// Bevy resource that plays LTC on the default audio output. Only exists
+/// while lightshow is the master and LTC generation is on.
+#[derive(Resource)]
+pub struct LtcOutput {
+    _stream: cpal::Stream,
+    producer: Mutex<HeapProducer<f32>>,
+    sample_rate: u32,
+    encoder: LtcEncoder,
+    /// The frame to encode next, counted from midnight, while LTC is being
+    /// played.
+    next_frame: Option<i64>,
+    samples: Vec<f32>,
+}
+
+impl LtcOutput {
+    /// Opens the default audio output for playing LTC. Every channel plays
+    /// the same signal.
+    pub fn open() -> Result<Self, Box<dyn std::error::Error>> {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .ok_or("No output device available")?;
+        let config: cpal::StreamConfig = device.default_output_config()?.into();
+        let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0;
+
+        // one second of buffer, far more than is ever queued
+        let ring = HeapRb::<f32>::new(sample_rate as usize);
+        let (producer, mut consumer) = ring.split();
+
+        // silence whenever nothing is queued, e.g. while paused
+        let stream = device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                for frame in data.chunks_mut(channels) {
+                    frame.fill(consumer.try_pop().unwrap_or(0.0));
+                }
+            },
+            |err| eprintln!("Audio stream error: {}", err),
+            None,
+        )?;
+        stream.play()?;
+
+        Ok(Self {
+            _stream: stream,
+            producer: Mutex::new(producer),
+            sample_rate,
+            encoder: LtcEncoder::new(sample_rate),
+            next_frame: None,
+            samples: Vec::new(),
+        })
+    }
+}
+
+/// Opens the audio input while LTC is chased, and the audio output while LTC
+/// is generated, closing them again when they are no longer needed. A device
+/// that fails to open isn't tried again until the setting is turned off and
+/// on.
+pub fn manage_ltc_devices(
+    mut commands: Commands,
+    settings: Res<TimecodeSettings>,
+    input: Option<Res<LtcInput>>,
+    output: Option<Res<LtcOutput>>,
+    mut failed: Local<(bool, bool)>,
+) {
+    let (input_failed, output_failed) = &mut *failed;
+
+    if settings.chase != Some(TimecodeSource::Ltc) {
+        *input_failed = false;
+        if input.is_some() {
+            commands.remove_resource::<LtcInput>();
+        }
+    } else if input.is_none() && !*input_failed {
+        match LtcInput::open() {
+            Ok(input) => commands.insert_resource(input),
+            Err(e) => {
+                error!("Failed to open audio input for LTC: {}", e);
+                *input_failed = true;
+            }
+        }
+    }
+
+    if !settings.is_master() || !settings.generate_ltc {
+        *output_failed = false;
+        if output.is_some() {
+            commands.remove_resource::<LtcOutput>();
+        }
+    } else if output.is_none() && !*output_failed {
+        match LtcOutput::open() {
+            Ok(output) => commands.insert_resource(output),
+            Err(e) => {
+                error!("Failed to open audio output for LTC: {}", e);
+                *output_failed = true;
+            }
+        }
+    }
+}
+
+/// Hands LTC decoded from the audio input to the chase.
+pub fn decode_ltc_input(
+    time: Res<Time>,
+    settings: Res<TimecodeSettings>,
+    input: Option<ResMut<LtcInput>>,
+    mut chase: ResMut<TimecodeChase>,
+) {
+    let Some(mut input) = input else {
+        return;
+    };
+    let input = &mut *input;
+    let Ok(consumer) = input.consumer.get_mut() else {
+        return;
+    };
+    input.samples.clear();
+    input.samples.extend(consumer.pop_iter());
+
+    let now = time.elapsed_secs_f64();
+    let sample_count = input.samples.len();
+    for frame in input.decoder.decode(&input.samples) {
+        // LTC only says whether it is drop frame
+        let frame_rate = match (frame.drop_frame, settings.frame_rate) {
+            (true, _) => FrameRate::Fps2997Drop,
+            (false, FrameRate::Fps2997Drop) => FrameRate::Fps30,
+            (false, frame_rate) => frame_rate,
+        };
+        // the frame is only complete once it has ended
+        let ended_at = now - (sample_count - frame.end_sample) as f64 / input.sample_rate as f64;
+        chase.receive(ReceivedTimecode {
+            source: TimecodeSource::Ltc,
+            timecode: frame.timecode,
+            frame_rate,
+            at: ended_at - 1.0 / frame_rate.fps(),
+            parked: false,
+        });
+    }
+}
+
+/// Keeps the audio output queued with LTC for the frames just ahead of the
+/// playback head while playback is in progress.
+pub fn feed_ltc_output(
+    settings: Res<TimecodeSettings>,
+    playback: Res<PlaybackInformation>,
+    output: Option<ResMut<LtcOutput>>,
+) {
+    let Some(mut output) = output else {
+        return;
+    };
+    let output = &mut *output;
+    if !playback.is_playing {
+        output.next_frame = None;
+        return;
+    }
+    let Ok(producer) = output.producer.get_mut() else {
+        return;
+    };
+
+    let frame_rate = settings.frame_rate;
+    let sample_rate = output.sample_rate as f64;
+    let mut queued = producer.occupied_len() as f64 / sample_rate;
+    // where the playback head will be once everything queued has played
+    let expected = playback.current_time + settings.offset_seconds() + queued;
+    let mut next_frame = match output.next_frame {
+        Some(next_frame)
+            if (next_frame as f64 / frame_rate.fps() - expected).abs() <= OUTPUT_RESYNC =>
+        {
+            next_frame
+        }
+        // playback was started or the playback head moved
+        _ => (expected * frame_rate.fps()).ceil() as i64,
+    };
+
+    while queued < OUTPUT_LOOKAHEAD {
+        output.samples.clear();
+        output.encoder.encode(
+            Timecode::from_frame_count(next_frame, frame_rate),
+            frame_rate,
+            &mut output.samples,
+        );
+        producer.push_slice(&output.samples);
+        queued += output.samples.len() as f64 / sample_rate;
+        next_frame += 1;
+    }
+    output.next_frame = Some(next_frame);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_bits_parse_back_into_the_same_timecode() {
+        let timecode = Timecode::new(23, 59, 59, 24).unwrap();
+        for frame_rate in FrameRate::ALL {
+            let bits = frame_bits(timecode, frame_rate);
+            assert_eq!(bits >> 64, SYNC_WORD);
+            assert_eq!(
+                parse_frame(bits),
+                Some((timecode, frame_rate.is_drop_frame()))
+            );
+        }
+    }
+
+    #[test]
+    fn frames_have_an_even_number_of_zeros() {
+        for frame_rate in FrameRate::ALL {
+            for count in [0, 1, 1799, 1800, 17981, 17982, 123456] {
+                let bits = frame_bits(Timecode::from_frame_count(count, frame_rate), frame_rate);
+                assert_eq!((FRAME_BITS - bits.count_ones()) % 2, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_frames_with_fields_out_of_range() {
+        let bits = frame_bits(Timecode::default(), FrameRate::Fps25);
+        // 70 seconds
+        assert_eq!(parse_frame(bits | 7 << 24), None);
+    }
+
+    /// Encodes five consecutive frames from the given frame count, and
+    /// decodes them again. The last frame is never decoded, since its final
+    /// bit only ends once another frame starts.
+    fn round_trip(sample_rate: u32, frame_rate: FrameRate, start: i64) -> Vec<Timecode> {
+        let mut encoder = LtcEncoder::new(sample_rate);
+        let mut samples = Vec::new();
+        for count in start..start + 5 {
+            encoder.encode(
+                Timecode::from_frame_count(count, frame_rate),
+                frame_rate,
+                &mut samples,
+            );
+        }
+        LtcDecoder::new(sample_rate)
+            .decode(&samples)
+            .into_iter()
+            .map(|frame| {
+                assert_eq!(frame.drop_frame, frame_rate.is_drop_frame());
+                frame.timecode
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_encoded_frames() {
+        for sample_rate in [44100, 48000] {
+            for frame_rate in FrameRate::ALL {
+                let start = 90000;
+                let counts: Vec<i64> = round_trip(sample_rate, frame_rate, start)
+                    .iter()
+                    .map(|timecode| timecode.frame_count(frame_rate))
+                    .collect();
+                assert_eq!(
+                    counts,
+                    (start..start + 4).collect::<Vec<_>>(),
+                    "{} at {} Hz",
+                    frame_rate,
+                    sample_rate
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_drop_frame_timecode_across_a_minute() {
+        // 00:00:59;29 is followed by 00:01:00;02, skipping ;00 and ;01
+        let timecodes: Vec<String> = round_trip(48000, FrameRate::Fps2997Drop, 1798)
+            .iter()
+            .map(Timecode::to_string)
+            .collect();
+        assert_eq!(
+            timecodes,
+            ["00:00:59:28", "00:00:59:29", "00:01:00:02", "00:01:00:03"]
+        );
+    }
+
+    #[test]
+    fn ignores_silence_and_noise() {
+        let mut decoder = LtcDecoder::new(48000);
+        let noise: Vec<f32> = (0..48000)
+            .map(|i| ((i * 7919) % 13) as f32 / 1000.0 - 0.006)
+            .collect();
+        assert!(decoder.decode(&vec![0.0; 48000]).is_empty());
+        assert!(decoder.decode(&noise).is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+use bevy::prelude::*;
+
+use crate::{
+    midi::{MidiMessage, MidiMessageReceived},
+    timecode::*,
+};
+
+/// How far the source has moved on by the time the last of the eight quarter
+/// frames of a timecode arrives, in frames.
+const QUARTER_FRAME_LATENCY: f64 = 1.75;
+
+/// Bevy resource that puts MIDI Time Code quarter frame messages back
+/// together. A full timecode is spread over eight quarter frames, sent in
+/// order over the course of two frames.
+#[derive(Resource, Debug, Default)]
+pub struct MtcDecoder {
+    pieces: [u8; 8],
+    next_piece: u8,
+}
+
+impl MtcDecoder {
+    /// Reads a quarter frame, returning the full timecode and its frame rate
+    /// once the last piece has arrived. Pieces that arrive out of order are
+    /// dropped until the next timecode starts.
+    pub fn quarter_frame(&mut self, piece: u8, value: u8) -> Option<(Timecode, FrameRate)> {
+        if piece == 0 {
+            self.next_piece = 0;
+        }
+        if piece != self.next_piece {
+            self.next_piece = 0;
+            return None;
+        }
+        self.pieces[piece as usize] = value & 0x0F;
+        self.next_piece = (piece + 1) % 8;
+        if piece < 7 {
+            return None;
+        }
+
+        let pieces = self.pieces;
+        let timecode = Timecode::new(
+            pieces[6] | (pieces[7] & 0x1) << 4,
+            pieces[4] | (pieces[5] & 0x3) << 4,
+            pieces[2] | (pieces[3] & 0x3) << 4,
+            pieces[0] | (pieces[1] & 0x1) << 4,
+        )
+        .ok()?;
+        let frame_rate = FrameRate::from_code((pieces[7] >> 1) & 0x3)?;
+        Some((timecode, frame_rate))
+    }
+
+    /// Drops any partly received timecode.
+    pub fn reset(&mut self) {
+        self.next_piece = 0;
+    }
+}
+
+/// Bevy observer that hands MIDI Time Code to the chase while MTC is the
+/// chased source.
+pub fn receive_mtc(
+    received: On<MidiMessageReceived>,
+    time: Res<Time>,
+    settings: Res<TimecodeSettings>,
+    mut decoder: ResMut<MtcDecoder>,
+    mut chase: ResMut<TimecodeChase>,
+) {
+    if settings.chase != Some(TimecodeSource::Mtc) {
+        return;
+    }
+    let received_at = received.received_at(time.elapsed_secs_f64());
+    match received.message {
+        MidiMessage::TimecodeQuarterFrame { piece, value } => {
+            if let Some((timecode, frame_rate)) = decoder.quarter_frame(piece, value) {
+                chase.receive(ReceivedTimecode {
+                    source: TimecodeSource::Mtc,
+                    timecode,
+                    frame_rate,
+                    at: received_at - QUARTER_FRAME_LATENCY / frame_rate.fps(),
+                    parked: false,
+                });
+            }
+        }
+        MidiMessage::TimecodeFullFrame {
+            hours,
+            minutes,
+            seconds,
+            frames,
+            rate,
+        } => {
+            // a full frame is sent after locating, with quarter frames
+            // following once the source runs again
+            decoder.reset();
+            match (
+                Timecode::new(hours, minutes, seconds, frames),
+                FrameRate::from_code(rate),
+            ) {
+                (Ok(timecode), Some(frame_rate)) => chase.receive(ReceivedTimecode {
+                    source: TimecodeSource::Mtc,
+                    timecode,
+                    frame_rate,
+                    at: received_at,
+                    parked: true,
+                }),
+                (Err(e), _) => warn!("Ignoring MTC full frame: {}", e),
+                (_, None) => warn!("Ignoring MTC full frame with frame rate code {}", rate),
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a timecode into the values of its eight quarter frames.
+    fn quarter_frames(timecode: Timecode, rate_code: u8) -> [u8; 8] {
+        [
+            timecode.frames & 0x0F,
+            timecode.frames >> 4,
+            timecode.seconds & 0x0F,
+            timecode.seconds >> 4,
+            timecode.minutes & 0x0F,
+            timecode.minutes >> 4,
+            timecode.hours & 0x0F,
+            timecode.hours >> 4 | rate_code << 1,
+        ]
+    }
+
+    fn feed(decoder: &mut MtcDecoder, values: [u8; 8]) -> Vec<Option<(Timecode, FrameRate)>> {
+        (0..8)
+            .map(|piece| decoder.quarter_frame(piece, values[piece as usize]))
+            .collect()
+    }
+
+    #[test]
+    fn assembles_a_timecode_from_eight_quarter_frames() {
+        let timecode = Timecode::new(23, 59, 58, 24).unwrap();
+        let mut decoder = MtcDecoder::default();
+        let results = feed(&mut decoder, quarter_frames(timecode, 1));
+        assert!(results[..7].iter().all(Option::is_none));
+        assert_eq!(results[7], Some((timecode, FrameRate::Fps25)));
+    }
+
+    #[test]
+    fn reads_the_frame_rate_from_the_last_quarter_frame() {
+        let timecode = Timecode::new(17, 1, 0, 2).unwrap();
+        for (code, frame_rate) in FrameRate::ALL.into_iter().enumerate() {
+            let mut decoder = MtcDecoder::default();
+            let results = feed(&mut decoder, quarter_frames(timecode, code as u8));
+            assert_eq!(results[7], Some((timecode, frame_rate)));
+        }
+    }
+
+    #[test]
+    fn decodes_drop_frame_timecode() {
+        // the first frame number left in a minute that isn't a tenth minute
+        let timecode = Timecode::new(0, 1, 0, 2).unwrap();
+        let mut decoder = MtcDecoder::default();
+        let (decoded, frame_rate) = feed(&mut decoder, quarter_frames(timecode, 2))[7].unwrap();
+        assert_eq!(frame_rate, FrameRate::Fps2997Drop);
+        assert_eq!(decoded.frame_count(frame_rate), 1800);
+        // eight quarter frames take two frames, so that is how far apart
+        // consecutive timecodes are
+        let next = Timecode::from_frame_count(1802, frame_rate);
+        assert_eq!(next, Timecode::new(0, 1, 0, 4).unwrap());
+        assert_eq!(
+            feed(&mut decoder, quarter_frames(next, 2))[7],
+            Some((next, frame_rate))
+        );
+    }
+
+    #[test]
+    fn ignores_the_upper_nibble_of_values() {
+        let timecode = Timecode::new(12, 34, 56, 7).unwrap();
+        let values = quarter_frames(timecode, 3).map(|value| value | 0x70);
+        let mut decoder = MtcDecoder::default();
+        assert_eq!(
+            feed(&mut decoder, values)[7],
+            Some((timecode, FrameRate::Fps30))
+        );
+    }
+
+    #[test]
+    fn drops_pieces_until_the_next_timecode_after_a_gap() {
+        let timecode = Timecode::new(1, 2, 3, 4).unwrap();
+        let values = quarter_frames(timecode, 0);
+        let mut decoder = MtcDecoder::default();
+        for piece in [0, 1, 2, 4, 5, 6, 7] {
+            assert_eq!(decoder.quarter_frame(piece, values[piece as usize]), None);
+        }
+        assert_eq!(
+            feed(&mut decoder, values)[7],
+            Some((timecode, FrameRate::Fps24))
+        );
+    }
+
+    #[test]
+    fn starts_over_after_a_reset() {
+        let timecode = Timecode::new(1, 2, 3, 4).unwrap();
+        let values = quarter_frames(timecode, 1);
+        let mut decoder = MtcDecoder::default();
+        for piece in 0..4 {
+            decoder.quarter_frame(piece, values[piece as usize]);
+        }
+        decoder.reset();
+        for piece in 4..8 {
+            assert_eq!(decoder.quarter_frame(piece, values[piece as usize]), None);
+        }
+    }
+}
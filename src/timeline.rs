@@ -1,8 +1,12 @@
 use bevy::prelude::*;
 
+pub mod audio_bindings;
+pub mod cues;
 pub mod effects;
 pub mod keyframes;
+pub mod modulators;
 pub mod playback;
+pub mod positions;
 pub mod sequence_tree;
 pub mod sequences;
 pub mod tracks;
@@ -11,7 +15,8 @@ pub struct TimelinePlugin;
 
 impl Plugin for TimelinePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(playback::PlaybackPlugin)
+        app.add_plugins(cues::CuesPlugin)
+            .add_plugins(playback::PlaybackPlugin)
             .add_plugins(sequences::SequencesPlugin)
             .add_plugins(sequence_tree::SequenceTreePlugin);
     }
@@ -0,0 +1,216 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::processing::fft::RecentFftData,
+    timeline::{
+        effects::parameters::ParameterSchema,
+        modulators::{ModulatorTarget, validate_target},
+    },
+};
+
+/// How long the running average of the flux that onsets are measured against
+/// takes to settle, in seconds.
+const ONSET_AVERAGE_TIME: f32 = 1.0;
+
+/// Makes a parameter react to the audio coming in. Like a `Modulator`, an
+/// audio binding belongs to a track and drives either the track factor or one
+/// of the parameters of the track's effect.
+///
+/// A feature of the audio is measured on every update and shaped into a level
+/// between 0 and 1: it is multiplied by `gain`, anything below `threshold` is
+/// cut off (with the rest stretched back out to fill 0 to 1), and the result
+/// is raised to the power of `curve`. The level then rises and falls no faster
+/// than `attack` and `release` allow, both given in seconds it takes to get
+/// most of the way there. Finally, the level is scaled by `depth`, shifted by
+/// `offset`, and added to the target, with the components used the same way
+/// as for modulators.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBinding {
+    pub target: ModulatorTarget,
+    pub feature: AudioFeature,
+    pub gain: f32,
+    pub threshold: f32,
+    pub curve: f32,
+    pub attack: f32,
+    pub release: f32,
+    pub depth: Vec4,
+    pub offset: Vec4,
+}
+
+impl AudioBinding {
+    /// Constructs a new binding of the bass energy to the given target, with
+    /// no depth and a short release.
+    pub fn new(target: ModulatorTarget) -> Self {
+        Self {
+            target,
+            feature: AudioFeature::BandEnergy {
+                low: 20.0,
+                high: 150.0,
+            },
+            gain: 0.1,
+            threshold: 0.0,
+            curve: 1.0,
+            attack: 0.0,
+            release: 0.2,
+            depth: Vec4::ZERO,
+            offset: Vec4::ZERO,
+        }
+    }
+
+    /// Cuts off, stretches and curves a measured feature into a level between
+    /// 0 and 1, before smoothing.
+    pub fn shape(&self, value: f32) -> f32 {
+        let value = (value * self.gain).clamp(0.0, 1.0);
+        if self.threshold >= 1.0 {
+            return 0.0;
+        }
+        let gated = ((value - self.threshold) / (1.0 - self.threshold)).max(0.0);
+        gated.powf(self.curve)
+    }
+}
+
+/// A feature of the incoming audio that can be measured on every update.
+/// Bands are given in Hz.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AudioFeature {
+    /// The average magnitude within a band.
+    BandEnergy { low: f32, high: f32 },
+    /// The overall loudness.
+    Rms,
+    /// How much louder a band got since the previous frame (spectral flux).
+    Flux { low: f32, high: f32 },
+    /// How far the flux of a band exceeds its recent average, relative to that
+    /// average, so only sudden hits register.
+    Onset { low: f32, high: f32 },
+}
+
+impl AudioFeature {
+    /// Measures the feature over the frames added since the previous update,
+    /// or `None` if there were none.
+    fn measure(&self, recent_fft_data: &RecentFftData) -> Option<f32> {
+        match *self {
+            AudioFeature::BandEnergy { low, high } => mean(
+                recent_fft_data
+                    .get_new_from_fixed_update()
+                    .map(|frame| frame.average_magnitude_range(low, high)),
+            ),
+            AudioFeature::Rms => mean(
+                recent_fft_data
+                    .get_new_from_fixed_update()
+                    .map(|frame| frame.rms()),
+            ),
+            AudioFeature::Flux { low, high } | AudioFeature::Onset { low, high } => mean(
+                recent_fft_data
+                    .get_new_pairs_from_fixed_update()
+                    .map(|(previous, frame)| frame.flux_range(previous, low, high)),
+            ),
+        }
+    }
+}
+
+/// Averages a series of values as they come, or `None` if there were none.
+fn mean(values: impl Iterator<Item = f32>) -> Option<f32> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    (count > 0).then(|| sum / count as f32)
+}
+
+/// Measures the average magnitude within a band, in Hz, over the frames added
+/// since the previous update. Gives 0 if there were none.
+pub fn band_energy(recent_fft_data: &RecentFftData, low: f32, high: f32) -> f32 {
+    AudioFeature::BandEnergy { low, high }
+        .measure(recent_fft_data)
+        .unwrap_or(0.0)
+}
+
+/// Runtime state of a single audio binding, carried from one update to the
+/// next by the active track it belongs to.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AudioEnvelope {
+    level: f32,
+    average_flux: f32,
+}
+
+impl AudioEnvelope {
+    /// Measures the feature of a binding and moves the level towards it, given
+    /// the time since the previous update in seconds. Returns the new level.
+    pub fn update(
+        &mut self,
+        binding: &AudioBinding,
+        recent_fft_data: &RecentFftData,
+        elapsed: f32,
+    ) -> f32 {
+        let measured = binding.feature.measure(recent_fft_data);
+        let value = match (binding.feature, measured) {
+            (AudioFeature::Onset { .. }, Some(flux)) => {
+                let onset = if self.average_flux > 0.0 {
+                    (flux / self.average_flux - 1.0).max(0.0)
+                } else {
+                    0.0
+                };
+                self.average_flux +=
+                    (flux - self.average_flux) * smoothing(elapsed, ONSET_AVERAGE_TIME);
+                onset
+            }
+            (_, Some(value)) => value,
+            // silence while nothing comes in
+            (_, None) => 0.0,
+        };
+
+        let target = binding.shape(value);
+        let time = if target > self.level {
+            binding.attack
+        } else {
+            binding.release
+        };
+        self.level += (target - self.level) * smoothing(elapsed, time);
+        self.level
+    }
+}
+
+/// Gets how far a value should move towards its target within the elapsed
+/// time, for a one-pole filter that gets most of the way there (1 - 1/e)
+/// within the given time. Moves all the way immediately if the time is zero.
+fn smoothing(elapsed: f32, time: f32) -> f32 {
+    if time <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-elapsed / time).exp()
+    }
+}
+
+/// Checks that every audio binding drives something that exists and is shaped
+/// sensibly. `schemas` are the parameters of the track's effect, if it has
+/// one.
+pub fn validate_audio_bindings(
+    audio_bindings: &[AudioBinding],
+    schemas: &[ParameterSchema],
+) -> Result<(), String> {
+    for (binding_i, binding) in audio_bindings.iter().enumerate() {
+        let context = format!("audio binding {}", binding_i);
+        validate_target(&binding.target, schemas, &context)?;
+        if let AudioFeature::BandEnergy { low, high }
+        | AudioFeature::Flux { low, high }
+        | AudioFeature::Onset { low, high } = binding.feature
+            && !(0.0 <= low && low < high)
+        {
+            return Err(format!(
+                "{} listens to an invalid band from {} Hz to {} Hz",
+                context, low, high
+            ));
+        }
+        if !(0.0..1.0).contains(&binding.threshold) {
+            return Err(format!(
+                "{} has a threshold of {}, which should be at least 0 and below 1",
+                context, binding.threshold
+            ));
+        }
+        if !(binding.curve > 0.0 && binding.attack >= 0.0 && binding.release >= 0.0) {
+            return Err(format!(
+                "{} needs a positive curve and attack and release times of at least 0",
+                context
+            ));
+        }
+    }
+    Ok(())
+}
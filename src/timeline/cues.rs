@@ -0,0 +1,604 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::sequences::Sequence,
+};
+
+/// Bevy plugin for cue lists.
+pub struct CuesPlugin;
+
+impl Plugin for CuesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimpleStore<CueList>>()
+            .init_resource::<CuePlayback>()
+            .add_systems(Update, update_cue_playback)
+            .add_observer(cue_go)
+            .add_observer(cue_back)
+            .add_observer(cue_jump)
+            .add_observer(cue_release);
+    }
+}
+
+/// A stack of cues for theatre-style playback. Rather than running along a
+/// timeline, playback steps from one cue to the next whenever GO is pressed
+/// (see `CueGo`), fading the new cue in while the previous one fades out.
+/// Stored in a global `SimpleStore`, while the state of playback lives in
+/// `CuePlayback`.
+#[derive(Debug)]
+pub struct CueList {
+    pub name: String,
+    pub cues: Vec<Cue>,
+}
+
+/// A single step within a cue list. Once the cue is run, it waits for `delay`
+/// seconds, then fades in over `fade_in` seconds. When the next cue takes
+/// over, it fades out over `fade_out` seconds, starting at the same time the
+/// next cue starts fading in. `follow` can run the next cue automatically.
+#[derive(Debug, Clone)]
+pub struct Cue {
+    pub name: String,
+    pub content: CueContent,
+    pub delay: f64,
+    pub fade_in: f64,
+    pub fade_out: f64,
+    pub follow: CueFollow,
+}
+
+impl Cue {
+    /// Constructs a new `Cue` with no delay, three second fades, and no
+    /// follow.
+    pub fn new(name: impl Into<String>, content: CueContent) -> Self {
+        Self {
+            name: name.into(),
+            content,
+            delay: 0.0,
+            fade_in: 3.0,
+            fade_out: 3.0,
+            follow: CueFollow::default(),
+        }
+    }
+
+    /// Gets the sequence played by the cue.
+    pub fn sequence(&self) -> SimpleHandle<Sequence> {
+        match self.content {
+            CueContent::Sequence(sequence) | CueContent::Look { sequence, .. } => sequence,
+        }
+    }
+}
+
+/// What a cue puts on stage while it is live.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CueContent {
+    /// Plays a sequence from its start once the cue has been run, looping for
+    /// as long as the cue is live.
+    Sequence(SimpleHandle<Sequence>),
+    /// A static look, i.e. a single frame of a sequence at a fixed time that
+    /// is held for as long as the cue is live.
+    Look {
+        sequence: SimpleHandle<Sequence>,
+        time: f64,
+    },
+}
+
+/// Whether a cue runs the next cue in its list by itself.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CueFollow {
+    /// Waits for the next GO.
+    #[default]
+    Manual,
+    /// Runs the next cue as soon as this one has finished fading in.
+    AfterFade,
+    /// Runs the next cue a number of seconds after this one was run.
+    Wait(f64),
+}
+
+/// Bevy resource that holds the playback state of every cue list: which cue
+/// each one is on, and every cue that is still fading in, holding, or fading
+/// out. Cues are timed against global time. Every update, the cues that are
+/// currently visible are collected as `LiveCue`s for the sequence tree.
+#[derive(Resource, Default, Debug)]
+pub struct CuePlayback {
+    states: HashMap<SimpleHandle<CueList>, CueListState>,
+    live_cues: Vec<LiveCue>,
+    next_id: u64,
+}
+
+/// Playback state of a single cue list.
+#[derive(Debug, Default)]
+struct CueListState {
+    current: Option<usize>,
+    running: Vec<RunningCue>,
+}
+
+/// A single run of a cue, from the moment it was run until it has faded out.
+#[derive(Debug)]
+struct RunningCue {
+    id: u64,
+    cue: usize,
+    go_time: f64,
+    /// Global time at which the cue starts fading out, if it has been taken
+    /// over by another cue or released.
+    release_time: Option<f64>,
+    /// Whether the follow of the cue has already run the next cue.
+    followed: bool,
+}
+
+/// A cue that currently contributes to the output, as handed to the sequence
+/// tree. `time` is the time within the cue's sequence, and `weight` is the
+/// current level of the cue's fades.
+#[derive(Debug, Clone, Copy)]
+pub struct LiveCue {
+    pub id: u64,
+    pub cue_list: SimpleHandle<CueList>,
+    pub cue: usize,
+    pub sequence: SimpleHandle<Sequence>,
+    pub time: f64,
+    pub weight: f32,
+}
+
+impl RunningCue {
+    /// Gets the level of the cue at a given global time, following its delay,
+    /// its fade in and, once released, its fade out.
+    fn weight(&self, cue: &Cue, time: f64) -> f32 {
+        let faded_in = |time: f64| {
+            let since_start = time - self.go_time - cue.delay;
+            if since_start < 0.0 {
+                0.0
+            } else if cue.fade_in <= 0.0 {
+                1.0
+            } else {
+                (since_start / cue.fade_in).min(1.0)
+            }
+        };
+        match self.release_time {
+            Some(release_time) if time >= release_time => {
+                let faded_out = if cue.fade_out <= 0.0 {
+                    0.0
+                } else {
+                    (1.0 - (time - release_time) / cue.fade_out).max(0.0)
+                };
+                (faded_in(release_time) * faded_out) as f32
+            }
+            _ => faded_in(time) as f32,
+        }
+    }
+
+    /// Whether the cue has completely faded out.
+    fn is_finished(&self, cue: &Cue, time: f64) -> bool {
+        self.release_time
+            .is_some_and(|release_time| time >= release_time + cue.fade_out.max(0.0))
+    }
+
+    /// Gets the global time at which the cue's follow runs the next cue, if
+    /// it has one.
+    fn follow_time(&self, cue: &Cue) -> Option<f64> {
+        match cue.follow {
+            CueFollow::Manual => None,
+            CueFollow::AfterFade => Some(self.go_time + cue.delay + cue.fade_in.max(0.0)),
+            CueFollow::Wait(wait) => Some(self.go_time + wait.max(0.0)),
+        }
+    }
+}
+
+impl CueListState {
+    /// Runs a cue, releasing every cue that is still live so that they fade
+    /// out as the new one fades in.
+    fn run(&mut self, cue_list: &CueList, cue: usize, time: f64, next_id: &mut u64) {
+        let release_time = time + cue_list.cues[cue].delay;
+        for running in &mut self.running {
+            if running.release_time.is_none() {
+                running.release_time = Some(release_time);
+            }
+        }
+        self.running.push(RunningCue {
+            id: *next_id,
+            cue,
+            go_time: time,
+            release_time: None,
+            followed: false,
+        });
+        *next_id += 1;
+        self.current = Some(cue);
+    }
+
+    /// Fades out every cue that is still live, leaving the cue list before its
+    /// first cue.
+    fn release(&mut self, time: f64) {
+        for running in &mut self.running {
+            if running.release_time.is_none() {
+                running.release_time = Some(time);
+            }
+        }
+        self.current = None;
+    }
+}
+
+impl CuePlayback {
+    /// Gets the cue a cue list is currently on, if it has been run.
+    pub fn current_cue(&self, cue_list: SimpleHandle<CueList>) -> Option<usize> {
+        self.states.get(&cue_list)?.current
+    }
+
+    /// Gets every cue that currently contributes to the output, as of the
+    /// last update.
+    pub fn live_cues(&self) -> &[LiveCue] {
+        &self.live_cues
+    }
+
+    /// Gets the current level of a cue, as of the last update. Zero if the cue
+    /// isn't live.
+    pub fn cue_level(&self, cue_list: SimpleHandle<CueList>, cue: usize) -> f32 {
+        self.live_cues
+            .iter()
+            .filter(|live_cue| live_cue.cue_list == cue_list && live_cue.cue == cue)
+            .map(|live_cue| live_cue.weight)
+            .sum()
+    }
+
+    /// Runs the next cue of a cue list, or the first one if the list hasn't
+    /// been run yet.
+    pub fn go(
+        &mut self,
+        cue_lists: &SimpleStore<CueList>,
+        cue_list: SimpleHandle<CueList>,
+        time: f64,
+    ) -> Result<(), String> {
+        let next = self.current_cue(cue_list).map_or(0, |current| current + 1);
+        self.jump(cue_lists, cue_list, next, time)
+    }
+
+    /// Runs the previous cue of a cue list. Going back from the first cue
+    /// releases it.
+    pub fn back(
+        &mut self,
+        cue_lists: &SimpleStore<CueList>,
+        cue_list: SimpleHandle<CueList>,
+        time: f64,
+    ) -> Result<(), String> {
+        match self.current_cue(cue_list) {
+            Some(0) => {
+                self.release(cue_list, time);
+                Ok(())
+            }
+            Some(current) => self.jump(cue_lists, cue_list, current - 1, time),
+            None => Err("cue list has not been run".to_string()),
+        }
+    }
+
+    /// Runs a specific cue of a cue list, fading out whatever was live.
+    pub fn jump(
+        &mut self,
+        cue_lists: &SimpleStore<CueList>,
+        cue_list: SimpleHandle<CueList>,
+        cue: usize,
+        time: f64,
+    ) -> Result<(), String> {
+        let list = cue_lists
+            .get(cue_list)
+            .ok_or_else(|| "cue list does not exist".to_string())?;
+        if cue >= list.cues.len() {
+            return Err(format!(
+                "cannot run cue {}, cue list \"{}\" only has {} cues",
+                cue + 1,
+                list.name,
+                list.cues.len()
+            ));
+        }
+        self.states
+            .entry(cue_list)
+            .or_default()
+            .run(list, cue, time, &mut self.next_id);
+        Ok(())
+    }
+
+    /// Fades out every live cue of a cue list.
+    pub fn release(&mut self, cue_list: SimpleHandle<CueList>, time: f64) {
+        if let Some(state) = self.states.get_mut(&cue_list) {
+            state.release(time);
+        }
+    }
+
+    /// Stops every cue list immediately, without fading.
+    pub fn reset(&mut self) {
+        self.states.clear();
+        self.live_cues.clear();
+    }
+
+    /// Runs any follows that are due, drops cues that have faded out, and
+    /// collects the cues that are currently live. Cues and cue lists that no
+    /// longer exist are dropped as well.
+    fn update(
+        &mut self,
+        cue_lists: &SimpleStore<CueList>,
+        sequence_store: &SimpleStore<Sequence>,
+        time: f64,
+    ) {
+        self.live_cues.clear();
+        let next_id = &mut self.next_id;
+        self.states
+            .retain(|handle, _| cue_lists.get(*handle).is_some());
+
+        for (handle, state) in &mut self.states {
+            let cue_list = cue_lists.get(*handle).expect("cue list was checked above");
+            state.running.retain(|running| {
+                cue_list
+                    .cues
+                    .get(running.cue)
+                    .is_some_and(|cue| !running.is_finished(cue, time))
+            });
+
+            // follows are run at the exact time they were due, so chains of
+            // follows stay in time even if several are due at once
+            while let Some(current) = state
+                .running
+                .iter_mut()
+                .rev()
+                .find(|running| running.release_time.is_none())
+                && !current.followed
+                && let Some(follow_time) = current.follow_time(&cue_list.cues[current.cue])
+                && follow_time <= time
+                && current.cue + 1 < cue_list.cues.len()
+            {
+                current.followed = true;
+                let next = current.cue + 1;
+                state.run(cue_list, next, follow_time, next_id);
+            }
+
+            for running in &state.running {
+                let cue = &cue_list.cues[running.cue];
+                let weight = running.weight(cue, time);
+                let Some(sequence) = sequence_store.get(cue.sequence()) else {
+                    continue;
+                };
+                if weight <= 0.0 {
+                    continue;
+                }
+                let since_start = (time - running.go_time - cue.delay).max(0.0);
+                let sequence_time = match cue.content {
+                    CueContent::Sequence(_) if sequence.length > 0.0 => {
+                        since_start % sequence.length
+                    }
+                    CueContent::Sequence(_) => 0.0,
+                    CueContent::Look { time, .. } => time,
+                };
+                self.live_cues.push(LiveCue {
+                    id: running.id,
+                    cue_list: *handle,
+                    cue: running.cue,
+                    sequence: cue.sequence(),
+                    time: sequence_time,
+                    weight,
+                });
+            }
+        }
+    }
+}
+
+/// Bevy event that runs the next cue of a cue list.
+#[derive(Event)]
+pub struct CueGo {
+    pub cue_list: SimpleHandle<CueList>,
+}
+
+/// Bevy event that runs the previous cue of a cue list.
+#[derive(Event)]
+pub struct CueBack {
+    pub cue_list: SimpleHandle<CueList>,
+}
+
+/// Bevy event that runs a specific cue of a cue list.
+#[derive(Event)]
+pub struct CueJump {
+    pub cue_list: SimpleHandle<CueList>,
+    pub cue: usize,
+}
+
+/// Bevy event that fades out every live cue of a cue list.
+#[derive(Event)]
+pub struct CueRelease {
+    pub cue_list: SimpleHandle<CueList>,
+}
+
+/// Bevy observer that listens for `CueGo` events.
+fn cue_go(
+    go: On<CueGo>,
+    time: Res<Time>,
+    cue_lists: Res<SimpleStore<CueList>>,
+    mut cue_playback: ResMut<CuePlayback>,
+) {
+    if let Err(e) = cue_playback.go(&cue_lists, go.cue_list, time.elapsed_secs_f64()) {
+        warn!("Could not run next cue: {}", e);
+    }
+}
+
+/// Bevy observer that listens for `CueBack` events.
+fn cue_back(
+    back: On<CueBack>,
+    time: Res<Time>,
+    cue_lists: Res<SimpleStore<CueList>>,
+    mut cue_playback: ResMut<CuePlayback>,
+) {
+    if let Err(e) = cue_playback.back(&cue_lists, back.cue_list, time.elapsed_secs_f64()) {
+        warn!("Could not run previous cue: {}", e);
+    }
+}
+
+/// Bevy observer that listens for `CueJump` events.
+fn cue_jump(
+    jump: On<CueJump>,
+    time: Res<Time>,
+    cue_lists: Res<SimpleStore<CueList>>,
+    mut cue_playback: ResMut<CuePlayback>,
+) {
+    if let Err(e) = cue_playback.jump(&cue_lists, jump.cue_list, jump.cue, time.elapsed_secs_f64())
+    {
+        warn!("Could not jump to cue: {}", e);
+    }
+}
+
+/// Bevy observer that listens for `CueRelease` events.
+fn cue_release(release: On<CueRelease>, time: Res<Time>, mut cue_playback: ResMut<CuePlayback>) {
+    cue_playback.release(release.cue_list, time.elapsed_secs_f64());
+}
+
+/// Bevy system that advances cue playback (see `CuePlayback::update`).
+fn update_cue_playback(
+    time: Res<Time>,
+    cue_lists: Res<SimpleStore<CueList>>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    mut cue_playback: ResMut<CuePlayback>,
+) {
+    cue_playback.update(&cue_lists, &sequence_store, time.elapsed_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeline::{positions::TempoMap, sequences::SequenceId};
+
+    /// A cue list with one cue per follow, all playing the same one second
+    /// sequence with two second fades and no delay.
+    fn cue_list(
+        follows: &[CueFollow],
+    ) -> (
+        SimpleStore<CueList>,
+        SimpleStore<Sequence>,
+        SimpleHandle<CueList>,
+    ) {
+        let mut sequence_store = SimpleStore::default();
+        let sequence = sequence_store.add(Sequence {
+            id: SequenceId::fresh(),
+            name: "Sequence".into(),
+            length: 1.0,
+            tempo_map: TempoMap::default(),
+            tracks: Vec::new(),
+            loop_region: None,
+            audio: None,
+        });
+        let cues = follows
+            .iter()
+            .enumerate()
+            .map(|(i, follow)| Cue {
+                fade_in: 2.0,
+                fade_out: 2.0,
+                follow: *follow,
+                ..Cue::new(format!("Cue {}", i + 1), CueContent::Sequence(sequence))
+            })
+            .collect();
+        let mut cue_lists = SimpleStore::default();
+        let handle = cue_lists.add(CueList {
+            name: "Cue list".into(),
+            cues,
+        });
+        (cue_lists, sequence_store, handle)
+    }
+
+    fn assert_level(
+        cue_playback: &CuePlayback,
+        cue_list: SimpleHandle<CueList>,
+        cue: usize,
+        expected: f32,
+    ) {
+        let level = cue_playback.cue_level(cue_list, cue);
+        assert!(
+            (level - expected).abs() < 1e-6,
+            "expected cue {} at {}, got {}",
+            cue + 1,
+            expected,
+            level
+        );
+    }
+
+    #[test]
+    fn after_fade_follows_run_the_next_cue_once_faded_in() {
+        let (cue_lists, sequence_store, handle) =
+            cue_list(&[CueFollow::AfterFade, CueFollow::Manual]);
+        let mut cue_playback = CuePlayback::default();
+        cue_playback.go(&cue_lists, handle, 0.0).unwrap();
+
+        cue_playback.update(&cue_lists, &sequence_store, 1.5);
+        assert_eq!(cue_playback.current_cue(handle), Some(0));
+        assert_level(&cue_playback, handle, 1, 0.0);
+
+        // the follow was due at 2s, so the next cue is half a second in
+        cue_playback.update(&cue_lists, &sequence_store, 2.5);
+        assert_eq!(cue_playback.current_cue(handle), Some(1));
+        assert_level(&cue_playback, handle, 0, 0.75);
+        assert_level(&cue_playback, handle, 1, 0.25);
+    }
+
+    #[test]
+    fn wait_follows_chain_in_time_when_several_are_due_at_once() {
+        let (cue_lists, sequence_store, handle) = cue_list(&[
+            CueFollow::Wait(1.0),
+            CueFollow::Wait(1.0),
+            CueFollow::Manual,
+        ]);
+        let mut cue_playback = CuePlayback::default();
+        cue_playback.go(&cue_lists, handle, 0.0).unwrap();
+
+        cue_playback.update(&cue_lists, &sequence_store, 3.0);
+        assert_eq!(cue_playback.current_cue(handle), Some(2));
+        // run at 1s at half level, released at 2s, so a quarter left at 3s
+        assert_level(&cue_playback, handle, 1, 0.25);
+        assert_level(&cue_playback, handle, 2, 0.5);
+
+        // the last cue has no follow, so playback stays on it
+        cue_playback.update(&cue_lists, &sequence_store, 10.0);
+        assert_eq!(cue_playback.current_cue(handle), Some(2));
+        assert_eq!(cue_playback.live_cues().len(), 1);
+    }
+
+    #[test]
+    fn going_back_during_a_fade_fades_out_from_the_current_level() {
+        let (cue_lists, sequence_store, handle) = cue_list(&[CueFollow::Manual, CueFollow::Manual]);
+        let mut cue_playback = CuePlayback::default();
+        cue_playback.go(&cue_lists, handle, 0.0).unwrap();
+        cue_playback.go(&cue_lists, handle, 2.0).unwrap();
+        cue_playback.back(&cue_lists, handle, 3.0).unwrap();
+        assert_eq!(cue_playback.current_cue(handle), Some(0));
+
+        cue_playback.update(&cue_lists, &sequence_store, 3.0);
+        assert_level(&cue_playback, handle, 0, 0.5);
+        assert_level(&cue_playback, handle, 1, 0.5);
+
+        // the first run of the first cue has faded out by now, leaving the
+        // second run fading in over the second cue fading out
+        cue_playback.update(&cue_lists, &sequence_store, 4.0);
+        assert_level(&cue_playback, handle, 0, 0.5);
+        assert_level(&cue_playback, handle, 1, 0.25);
+        assert_eq!(cue_playback.live_cues().len(), 2);
+
+        // going back from the first cue releases the list
+        cue_playback.back(&cue_lists, handle, 5.0).unwrap();
+        assert_eq!(cue_playback.current_cue(handle), None);
+        assert!(cue_playback.back(&cue_lists, handle, 5.0).is_err());
+    }
+
+    #[test]
+    fn releasing_during_a_fade_fades_out_from_the_current_level() {
+        let (cue_lists, sequence_store, handle) =
+            cue_list(&[CueFollow::AfterFade, CueFollow::Manual]);
+        let mut cue_playback = CuePlayback::default();
+        cue_playback.go(&cue_lists, handle, 0.0).unwrap();
+        cue_playback.release(handle, 1.0);
+        assert_eq!(cue_playback.current_cue(handle), None);
+
+        cue_playback.update(&cue_lists, &sequence_store, 1.0);
+        assert_level(&cue_playback, handle, 0, 0.5);
+        cue_playback.update(&cue_lists, &sequence_store, 2.0);
+        assert_level(&cue_playback, handle, 0, 0.25);
+
+        // a released cue no longer follows
+        assert_level(&cue_playback, handle, 1, 0.0);
+        cue_playback.update(&cue_lists, &sequence_store, 3.0);
+        assert!(cue_playback.live_cues().is_empty());
+
+        // the next GO starts the list over
+        cue_playback.go(&cue_lists, handle, 3.0).unwrap();
+        assert_eq!(cue_playback.current_cue(handle), Some(0));
+    }
+}
@@ -1,26 +1,107 @@
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    reflect::{ReflectMut, ReflectRef, Struct},
+};
 
 use crate::{
-    audio::processing::fft::RecentFftData, fixtures::PanTilt, timeline::keyframes::Keyframes,
+    audio::processing::fft::RecentFftData,
+    fixtures::PanTilt,
+    timeline::{
+        keyframes::{KeyframeValue, Keyframes},
+        modulators::{ActiveModulation, ModulatorTarget, TargetBase},
+        positions::TempoMap,
+        tracks::TrackInfo,
+    },
 };
 use derive_more::From;
 use enum_dispatch::enum_dispatch;
+use parameters::ParameterSchema;
 
 pub mod color;
 pub mod pan_tilt;
+pub mod parameters;
 
 /// Global information shared between all effects. Includes playback time, FFT
 /// data, and more in the future. Constructed with Bevy resources before
 /// sequence tree traversal and passed to all effect update functions.
-#[derive(Debug)]
+///
+/// The one exception is `tempo_map`, which is swapped out for the tempo map of
+/// each sequence as the tree is traversed, so it always belongs to the
+/// sequence the effect sits directly in. Effects can use it to resolve musical
+/// keyframe times and to lock onto the beat (see `TempoMap::beat_phase`).
+/// While an external clock sets the tempo, the primary sequence's tempo map
+/// runs at the tempo of the clock (see `PlaybackInformation::external_tempo`).
+///
+/// Likewise, `playback_rate` is swapped out for how fast time moves within
+/// each sequence, per second of global time. It is 1 at the root of the tree
+/// and multiplied by the rate of every clip a sequence is nested in (see
+/// `Clip::playback_rate`).
+#[derive(Debug, Clone, Copy)]
 pub struct EffectUpdateCommonInfo<'a> {
     pub recent_fft_data: &'a RecentFftData,
     pub global_time: f64,
+    pub tempo_map: &'a TempoMap,
+    pub playback_rate: f64,
+}
+
+/// How many times faster than its playback rate the local time of an effect
+/// may move between two updates for the step to still count as moving on
+/// rather than jumping. Leaves room for the rate changing between updates,
+/// e.g. along a time remap curve.
+const CONTINUITY_MARGIN: f64 = 4.0;
+
+/// How far the local time of an effect may move between two updates, in
+/// seconds, on top of `CONTINUITY_MARGIN`, for the step to still count as
+/// moving on. Covers small corrections, e.g. while chasing timecode.
+const CONTINUITY_TOLERANCE: f64 = 0.05;
+
+/// How the local time of an effect moved since its previous update. Worked
+/// out by the sequence tree, so that effects which build up state over time
+/// can tell playback moving on from the playback head jumping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeStep {
+    /// The first update since the effect was instantiated.
+    Start,
+    /// Time moved on by the given number of seconds. Negative while playing
+    /// backwards, and zero while paused.
+    Advance(f64),
+    /// Time jumped, e.g. because the user scrubbed or a loop wrapped around.
+    Jump,
+}
+
+impl TimeStep {
+    /// Works out the step from the previous update to the current one, given
+    /// the local and global time of each and the rate local time currently
+    /// moves at (see `EffectUpdateCommonInfo::playback_rate`). Without a
+    /// previous update, this is the start.
+    pub fn between(
+        previous: Option<(f64, f64)>,
+        local_time: f64,
+        global_time: f64,
+        playback_rate: f64,
+    ) -> Self {
+        let Some((previous_local_time, previous_global_time)) = previous else {
+            return TimeStep::Start;
+        };
+        let delta = local_time - previous_local_time;
+        let elapsed = (global_time - previous_global_time).abs();
+        if delta.abs() <= elapsed * playback_rate * CONTINUITY_MARGIN + CONTINUITY_TOLERANCE {
+            TimeStep::Advance(delta)
+        } else {
+            TimeStep::Jump
+        }
+    }
+
+    /// Whether time didn't simply move on, so any state built up before no
+    /// longer applies.
+    pub fn is_discontinuous(self) -> bool {
+        !matches!(self, TimeStep::Advance(_))
+    }
 }
 
 /// Contains the information used for any particular effect. Wrapper around
 /// both `ColorEffectInfo` and `PanTiltEffectInfo`.
-#[derive(Debug, Clone, From)]
+#[derive(Debug, Clone, From, Reflect)]
 pub enum EffectInfo {
     ColorEffectInfo(ColorEffectInfo),
     PanTiltEffectInfo(PanTiltEffectInfo),
@@ -30,28 +111,204 @@ impl EffectInfo {
     /// Calls into the effect info to update it in accordance to the current
     /// time (within the effect's direct sequence, i.e. not global) and any
     /// global information specified as common info. This is also where
-    /// keyframes are applied to effects; each individual implementation is
-    /// responsible for providing its own update mechanisms.
+    /// keyframes are applied to the parameters of effects (see
+    /// `parameters::Parameter`), followed by the modulators and audio bindings
+    /// in the info of the track that drive parameters, before the effect gets
+    /// to update itself. These are evaluated into `modulation`, so they can be
+    /// evaluated again for each fixture (see `EffectInfo::apply_modulation`).
+    /// Until then, parameters hold their value at the origin.
+    ///
+    /// Parameters without keyframes take their value from `init_info`, the
+    /// effect as it was instantiated, and so do the values modulators start
+    /// from. The effect's own parameters still hold the modulated values of
+    /// the previous update, which would otherwise add up.
+    ///
+    /// The effect is reset first if time didn't simply move on since the
+    /// previous update (see `TimeStep::is_discontinuous`).
     pub fn update(
         &mut self,
+        init_info: &EffectInfo,
         keyframes: &Keyframes,
+        track_info: &TrackInfo,
+        modulation: &mut ActiveModulation,
         current_time: f64,
+        step: TimeStep,
         common_info: &EffectUpdateCommonInfo,
     ) {
+        let schemas = self.parameters();
+        let init_effect = init_info.effect();
+        parameters::apply_keyframes(
+            self.effect_mut(),
+            init_effect,
+            schemas,
+            keyframes,
+            current_time,
+            common_info.tempo_map,
+        );
+        modulation.update(
+            &track_info.modulators,
+            &track_info.audio_bindings,
+            current_time,
+            common_info,
+            |target| {
+                let ModulatorTarget::Parameter(name) = target else {
+                    return None;
+                };
+                let schema = schemas.iter().find(|schema| schema.name == *name)?;
+                let base = parameters::keyframed_value(
+                    init_effect,
+                    schema,
+                    keyframes,
+                    current_time,
+                    common_info.tempo_map,
+                )?;
+                Some(TargetBase {
+                    value: base,
+                    range: schema.range,
+                    field: Some(schema.field),
+                })
+            },
+        );
+        self.apply_modulation(modulation, Vec3::ZERO);
         match self {
             EffectInfo::ColorEffectInfo(color_effect_info) => {
-                color_effect_info.update(keyframes, current_time, common_info)
+                if step.is_discontinuous() {
+                    color_effect_info.reset(current_time);
+                }
+                color_effect_info.update(keyframes, current_time, step, common_info)
             }
             EffectInfo::PanTiltEffectInfo(pan_tilt_effect_info) => {
-                pan_tilt_effect_info.update(keyframes, current_time, common_info)
+                if step.is_discontinuous() {
+                    pan_tilt_effect_info.reset(current_time);
+                }
+                pan_tilt_effect_info.update(keyframes, current_time, step, common_info)
             }
         }
     }
+
+    /// Carries runtime state (such as history buffers) over from a previous
+    /// instance of the same effect, after it has been re-instantiated from
+    /// changed init info. Does nothing if the effects are of different types
+    /// or the effect has no such state.
+    pub fn inherit_state(&mut self, previous: &EffectInfo) {
+        if let (
+            EffectInfo::ColorEffectInfo(ColorEffectInfo::ColorFrequencyCascadeEffect(effect)),
+            EffectInfo::ColorEffectInfo(ColorEffectInfo::ColorFrequencyCascadeEffect(previous)),
+        ) = (self, previous)
+        {
+            effect.inherit_state(previous);
+        }
+    }
+
+    /// Makes the effect info a copy of `source`, reusing the buffers it
+    /// already holds if both are the same effect. Used for effect infos that
+    /// are overwritten over and over, such as the scratch copies effects are
+    /// modulated in for every fixture.
+    pub fn copy_from(&mut self, source: &EffectInfo) {
+        match (self, source) {
+            (
+                EffectInfo::ColorEffectInfo(ColorEffectInfo::ColorFrequencyCascadeEffect(effect)),
+                EffectInfo::ColorEffectInfo(ColorEffectInfo::ColorFrequencyCascadeEffect(source)),
+            ) => effect.clone_from(source),
+            (effect_info, source) => *effect_info = source.clone(),
+        }
+    }
+
+    /// Gets the name of the effect type, e.g. `ColorShockwaveEffect`.
+    pub fn name(&self) -> &str {
+        let effect_info: &dyn PartialReflect = match self {
+            EffectInfo::ColorEffectInfo(color_effect_info) => color_effect_info,
+            EffectInfo::PanTiltEffectInfo(pan_tilt_effect_info) => pan_tilt_effect_info,
+        };
+        let ReflectRef::Enum(effect_info) = effect_info.reflect_ref() else {
+            unreachable!("effect infos are enums");
+        };
+        effect_info.variant_name()
+    }
+
+    /// Gets the effect wrapped by the effect info, for generic access to its
+    /// parameters through reflection.
+    fn effect(&self) -> &dyn Struct {
+        let effect_info: &dyn PartialReflect = match self {
+            EffectInfo::ColorEffectInfo(color_effect_info) => color_effect_info,
+            EffectInfo::PanTiltEffectInfo(pan_tilt_effect_info) => pan_tilt_effect_info,
+        };
+        let ReflectRef::Enum(effect_info) = effect_info.reflect_ref() else {
+            unreachable!("effect infos are enums");
+        };
+        let effect = effect_info
+            .field_at(0)
+            .expect("effect info variants should hold an effect");
+        let ReflectRef::Struct(effect) = effect.reflect_ref() else {
+            panic!("effects should be structs");
+        };
+        effect
+    }
+
+    /// Mutable version of `effect`.
+    fn effect_mut(&mut self) -> &mut dyn Struct {
+        let effect_info: &mut dyn PartialReflect = match self {
+            EffectInfo::ColorEffectInfo(color_effect_info) => color_effect_info,
+            EffectInfo::PanTiltEffectInfo(pan_tilt_effect_info) => pan_tilt_effect_info,
+        };
+        let ReflectMut::Enum(effect_info) = effect_info.reflect_mut() else {
+            unreachable!("effect infos are enums");
+        };
+        let effect = effect_info
+            .field_at_mut(0)
+            .expect("effect info variants should hold an effect");
+        let ReflectMut::Struct(effect) = effect.reflect_mut() else {
+            panic!("effects should be structs");
+        };
+        effect
+    }
+
+    /// Gets the schemas of all parameters the effect declares, in the order
+    /// they are declared in (see `parameters::parameter_schemas`).
+    pub fn parameters(&self) -> &'static [ParameterSchema] {
+        self.effect()
+            .get_represented_struct_info()
+            .map(parameters::parameter_schemas)
+            .unwrap_or(&[])
+    }
+
+    /// Gets the current value of a parameter, or `None` if the effect has no
+    /// such parameter.
+    pub fn get_parameter(&self, name: &str) -> Option<KeyframeValue> {
+        self.parameters()
+            .iter()
+            .any(|schema| schema.name == name)
+            .then(|| parameters::get_parameter(self.effect(), name))
+            .flatten()
+    }
+
+    /// Sets a parameter of the effect, failing if it has no such parameter or
+    /// the value doesn't fit it.
+    pub fn set_parameter(&mut self, name: &str, value: KeyframeValue) -> Result<(), String> {
+        let schemas = self.parameters();
+        parameters::set_parameter(self.effect_mut(), schemas, name, value)
+            .map_err(|e| format!("{}: {}", self.name(), e))
+    }
+
+    /// Sets every parameter driven by modulators to its modulated value for a
+    /// fixture at the given position.
+    pub fn apply_modulation(&mut self, modulation: &ActiveModulation, position: Vec3) {
+        let effect = self.effect_mut();
+        for (field, value) in modulation.field_values_at(position) {
+            parameters::set_field_at(effect, field, &value);
+        }
+    }
+
+    /// Checks that the keyframes fit the parameters of the effect (see
+    /// `parameters::validate_keyframes`).
+    pub fn validate_keyframes(&self, keyframes: &Keyframes) -> Result<(), String> {
+        parameters::validate_keyframes(self.parameters(), keyframes, self.name())
+    }
 }
 
 /// Contains all color effect implementations in an enum that requires all
 /// variants to implement `ColorEffectLike`.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Reflect)]
 #[enum_dispatch(ColorEffectLike)]
 pub enum ColorEffectInfo {
     ColorFillEffect(color::fill::ColorFillEffect),
@@ -61,7 +318,7 @@ pub enum ColorEffectInfo {
 
 /// Contains all pan/tilt effect implementations in an enum that requires all
 /// variants to implement `PanTiltEffectLike`.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Reflect)]
 #[enum_dispatch(PanTiltEffectLike)]
 pub enum PanTiltEffectInfo {
     PanTiltAllEffect(pan_tilt::all::PanTiltAllEffect),
@@ -75,15 +332,25 @@ pub trait ColorEffectLike: Send + Sync + std::fmt::Debug {
 
     /// Calls into the color effect to update it in accordance to the current
     /// time (within the effect's direct sequence, i.e. not global) and any
-    /// global information specified as common info. This is also where
-    /// keyframes are applied to effects; each individual implementation is
-    /// responsible for providing its own update mechanisms.
+    /// global information specified as common info. Keyframes have already
+    /// been applied to the effect's parameters by then, so this only needs to
+    /// be implemented by effects that do more than follow their parameters.
+    /// `step` tells how time moved since the previous update.
     fn update(
         &mut self,
-        keyframes: &Keyframes,
-        current_time: f64,
-        common_info: &EffectUpdateCommonInfo,
-    );
+        _keyframes: &Keyframes,
+        _current_time: f64,
+        _step: TimeStep,
+        _common_info: &EffectUpdateCommonInfo,
+    ) {
+    }
+
+    /// Forgets any state the effect has built up over time, such as history
+    /// buffers, as if it had just been instantiated at the given time. Called
+    /// before the first update and whenever time jumps, so the effect looks
+    /// the same no matter how playback got to where it is. Does nothing by
+    /// default, as most effects only depend on the current time.
+    fn reset(&mut self, _current_time: f64) {}
 
     /// Inserts the effect info as a component within the world. This is to be
     /// used for debug/informational graphics within the preview window.
@@ -98,15 +365,25 @@ pub trait PanTiltEffectLike: Send + Sync + std::fmt::Debug {
 
     /// Calls into the pan/tilt effect to update it in accordance to the current
     /// time (within the effect's direct sequence, i.e. not global) and any
-    /// global information specified as common info. This is also where
-    /// keyframes are applied to effects; each individual implementation is
-    /// responsible for providing its own update mechanisms.
+    /// global information specified as common info. Keyframes have already
+    /// been applied to the effect's parameters by then, so this only needs to
+    /// be implemented by effects that do more than follow their parameters.
+    /// `step` tells how time moved since the previous update.
     fn update(
         &mut self,
-        keyframes: &Keyframes,
-        current_time: f64,
-        common_info: &EffectUpdateCommonInfo,
-    );
+        _keyframes: &Keyframes,
+        _current_time: f64,
+        _step: TimeStep,
+        _common_info: &EffectUpdateCommonInfo,
+    ) {
+    }
+
+    /// Forgets any state the effect has built up over time, such as history
+    /// buffers, as if it had just been instantiated at the given time. Called
+    /// before the first update and whenever time jumps, so the effect looks
+    /// the same no matter how playback got to where it is. Does nothing by
+    /// default, as most effects only depend on the current time.
+    fn reset(&mut self, _current_time: f64) {}
 
     /// Inserts the effect info as a component within the world. This is to be
     /// used for debug/informational graphics within the preview window.
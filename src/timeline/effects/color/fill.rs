@@ -1,7 +1,8 @@
-use crate::timeline::{effects::*, keyframes::*};
+use crate::timeline::effects::{parameters::*, *};
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, Debug, Clone, Reflect)]
 pub struct ColorFillEffect {
+    #[reflect(@Parameter::color(Color::WHITE))]
     pub color: Color,
 }
 
@@ -10,15 +11,6 @@ impl ColorEffectLike for ColorFillEffect {
         self.color
     }
 
-    fn update(
-        &mut self,
-        keyframes: &Keyframes,
-        current_time: f64,
-        _common_info: &EffectUpdateCommonInfo,
-    ) {
-        self.color = keyframes.get_color_value("color", current_time, &self.color);
-    }
-
     fn insert_component(&self, entity_commands: &mut EntityCommands) {
         entity_commands.insert(self.clone());
     }
@@ -1,19 +1,45 @@
 use std::collections::VecDeque;
 
 use crate::{
-    timeline::{effects::*, keyframes::*},
+    timeline::{
+        audio_bindings::band_energy,
+        effects::{parameters::*, *},
+        keyframes::*,
+    },
     util::blending::{colors::interpolate_color_bands, sample_windowed},
 };
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, Debug, Reflect)]
 pub struct ColorFrequencyCascadeEffect {
+    #[reflect(skip_serializing)]
     pub past_values: VecDeque<(f32, f32)>, // freq, strength
     pub color_bands: Vec<(f32, Color)>,
+    #[reflect(@Parameter::vec3(Vec3::new(100.0, 0.0, 0.0)).unit(ParameterUnit::Distance))]
     pub scaled_direction: Vec3,
     pub buffer_size: usize,
+    #[reflect(@Parameter::float(0.1).range(0.0, f32::INFINITY))]
     pub window_size: f32,
+    /// Compensates the volume of the lows (20 to 150 Hz), which are usually
+    /// louder than the rest.
+    #[reflect(@Parameter::float(DEFAULT_LOW_GAIN).range(0.0, f32::INFINITY))]
+    pub low_gain: f32,
+    /// Compensates the volume of the mids (150 to 2000 Hz).
+    #[reflect(@Parameter::float(DEFAULT_MID_GAIN).range(0.0, f32::INFINITY))]
+    pub mid_gain: f32,
+    /// Compensates the volume of the highs (2000 to 20000 Hz), which are
+    /// usually quieter than the rest.
+    #[reflect(@Parameter::float(DEFAULT_HIGH_GAIN).range(0.0, f32::INFINITY))]
+    pub high_gain: f32,
+    /// Scales the overall volume into the strength of the cascade.
+    #[reflect(@Parameter::float(DEFAULT_LEVEL_GAIN).range(0.0, f32::INFINITY))]
+    pub level_gain: f32,
 }
 
+pub const DEFAULT_LOW_GAIN: f32 = 0.4;
+pub const DEFAULT_MID_GAIN: f32 = 1.0;
+pub const DEFAULT_HIGH_GAIN: f32 = 3.0;
+pub const DEFAULT_LEVEL_GAIN: f32 = 1.0 / 15.0;
+
 impl ColorFrequencyCascadeEffect {
     pub fn new(
         color_bands: Vec<(f32, Color)>,
@@ -27,8 +53,50 @@ impl ColorFrequencyCascadeEffect {
             scaled_direction,
             buffer_size,
             window_size,
+            low_gain: DEFAULT_LOW_GAIN,
+            mid_gain: DEFAULT_MID_GAIN,
+            high_gain: DEFAULT_HIGH_GAIN,
+            level_gain: DEFAULT_LEVEL_GAIN,
+        }
+    }
+
+    /// Takes over the recent history of a previous instance of the effect, so
+    /// that editing its parameters doesn't wipe the cascade. The history is
+    /// trimmed or padded at the oldest end to fit the buffer size.
+    pub fn inherit_state(&mut self, previous: &Self) {
+        let mut past_values = previous.past_values.clone();
+        while past_values.len() > self.buffer_size {
+            past_values.pop_front();
+        }
+        while past_values.len() < self.buffer_size {
+            past_values.push_front((0.0, 0.0));
+        }
+        self.past_values = past_values;
+    }
+}
+
+// written out so that `clone_from` reuses the buffers (see
+// `EffectInfo::copy_from`)
+impl Clone for ColorFrequencyCascadeEffect {
+    fn clone(&self) -> Self {
+        Self {
+            past_values: self.past_values.clone(),
+            color_bands: self.color_bands.clone(),
+            ..*self
         }
     }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.past_values.clone_from(&source.past_values);
+        self.color_bands.clone_from(&source.color_bands);
+        self.scaled_direction = source.scaled_direction;
+        self.buffer_size = source.buffer_size;
+        self.window_size = source.window_size;
+        self.low_gain = source.low_gain;
+        self.mid_gain = source.mid_gain;
+        self.high_gain = source.high_gain;
+        self.level_gain = source.level_gain;
+    }
 }
 
 impl ColorEffectLike for ColorFrequencyCascadeEffect {
@@ -54,31 +122,25 @@ impl ColorEffectLike for ColorFrequencyCascadeEffect {
         &mut self,
         _keyframes: &Keyframes,
         _current_time: f64,
+        _step: TimeStep,
         common_info: &EffectUpdateCommonInfo,
     ) {
-        let mut frame_count = 0;
-        let mut lows_volume = 0.0f32;
-        let mut mids_volume = 0.0f32;
-        let mut highs_volume = 0.0f32;
-
-        for frame in common_info.recent_fft_data.get_new_from_fixed_update() {
-            frame_count += 1;
-            lows_volume += frame.bass();
-            mids_volume += (frame.low_mid() + frame.mid()) / 2.0;
-            highs_volume += (frame.high_mid() + frame.treble()) / 2.0;
-        }
-
-        lows_volume /= frame_count as f32;
-        mids_volume /= frame_count as f32;
-        highs_volume /= frame_count as f32;
+        let recent_fft_data = common_info.recent_fft_data;
+        let lows_volume = band_energy(recent_fft_data, 20.0, 150.0);
+        let mids_volume = (band_energy(recent_fft_data, 150.0, 500.0)
+            + band_energy(recent_fft_data, 500.0, 2000.0))
+            / 2.0;
+        let highs_volume = (band_energy(recent_fft_data, 2000.0, 4000.0)
+            + band_energy(recent_fft_data, 4000.0, 20000.0))
+            / 2.0;
 
         let overall_volume = (lows_volume + mids_volume + highs_volume) / 3.0;
-        let intensity = (overall_volume / 15.0).clamp(0.0, 1.0);
+        let intensity = (overall_volume * self.level_gain).clamp(0.0, 1.0);
 
         // with compensation for usual volumes
-        let lows_comp = lows_volume * 0.4;
-        let mids_comp = mids_volume;
-        let highs_comp = highs_volume * 3.0;
+        let lows_comp = lows_volume * self.low_gain;
+        let mids_comp = mids_volume * self.mid_gain;
+        let highs_comp = highs_volume * self.high_gain;
 
         let total_weight = lows_comp + mids_comp + highs_comp;
         let average = if total_weight == 0.0 {
@@ -93,6 +155,12 @@ impl ColorEffectLike for ColorFrequencyCascadeEffect {
         self.past_values.push_back((average, intensity));
     }
 
+    /// Empties the cascade, so it builds up again from the new time rather
+    /// than showing what was heard before the jump.
+    fn reset(&mut self, _current_time: f64) {
+        self.past_values = VecDeque::from(vec![(0.0, 0.0); self.buffer_size]);
+    }
+
     fn insert_component(&self, entity_commands: &mut EntityCommands) {
         entity_commands.insert(self.clone());
     }
@@ -1,12 +1,18 @@
-use crate::timeline::{effects::*, keyframes::*};
+use crate::timeline::effects::{parameters::*, *};
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, Debug, Clone, Reflect)]
 pub struct ColorShockwaveEffect {
+    #[reflect(@Parameter::color(Color::WHITE))]
     pub color: Color,
+    #[reflect(@Parameter::vec3(Vec3::ZERO).unit(ParameterUnit::Distance))]
     pub center: Vec3,
+    #[reflect(@Parameter::float(0.0).range(0.0, f32::INFINITY).unit(ParameterUnit::Distance))]
     pub radius: f32,
+    #[reflect(@Parameter::float(10.0).range(0.0, f32::INFINITY).unit(ParameterUnit::Distance))]
     pub flat: f32,
+    #[reflect(@Parameter::float(30.0).range(0.0, f32::INFINITY).unit(ParameterUnit::Distance))]
     pub head: f32,
+    #[reflect(@Parameter::float(30.0).range(0.0, f32::INFINITY).unit(ParameterUnit::Distance))]
     pub tail: f32,
 }
 
@@ -23,20 +29,6 @@ impl ColorEffectLike for ColorShockwaveEffect {
         )
     }
 
-    fn update(
-        &mut self,
-        keyframes: &Keyframes,
-        current_time: f64,
-        _common_info: &EffectUpdateCommonInfo,
-    ) {
-        self.color = keyframes.get_color_value("color", current_time, &self.color);
-        self.center = keyframes.get_vec3_value("center", current_time, &self.center);
-        self.radius = keyframes.get_float_value("radius", current_time, &self.radius);
-        self.flat = keyframes.get_float_value("flat", current_time, &self.flat);
-        self.head = keyframes.get_float_value("head", current_time, &self.head);
-        self.tail = keyframes.get_float_value("tail", current_time, &self.tail);
-    }
-
     fn insert_component(&self, entity_commands: &mut EntityCommands) {
         entity_commands.insert(self.clone());
     }
@@ -1,11 +1,13 @@
 use crate::{
     fixtures::PanTilt,
-    timeline::{effects::*, keyframes::*},
+    timeline::effects::{parameters::*, *},
 };
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, Debug, Clone, Reflect)]
 pub struct PanTiltAllEffect {
+    #[reflect(@Parameter::float(0.0).unit(ParameterUnit::Degrees))]
     pub pan: f32,
+    #[reflect(@Parameter::float(0.0).unit(ParameterUnit::Degrees))]
     pub tilt: f32,
 }
 
@@ -14,16 +16,6 @@ impl PanTiltEffectLike for PanTiltAllEffect {
         PanTilt::new(self.pan, self.tilt)
     }
 
-    fn update(
-        &mut self,
-        keyframes: &Keyframes,
-        current_time: f64,
-        _common_info: &EffectUpdateCommonInfo,
-    ) {
-        self.pan = keyframes.get_float_value("pan", current_time, &self.pan);
-        self.tilt = keyframes.get_float_value("tilt", current_time, &self.tilt);
-    }
-
     fn insert_component(&self, entity_commands: &mut EntityCommands) {
         entity_commands.insert(self.clone());
     }
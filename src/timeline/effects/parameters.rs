@@ -0,0 +1,380 @@
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    fmt,
+    sync::{OnceLock, PoisonError, RwLock},
+};
+
+use bevy::{
+    prelude::*,
+    reflect::{Struct, StructInfo},
+};
+
+use crate::timeline::{
+    keyframes::{KeyframeValue, Keyframes},
+    positions::TempoMap,
+};
+
+/// Custom reflection attribute that declares a field of an effect to be a
+/// parameter, which can be animated with keyframes under the field's name and
+/// edited in the inspector. Fields without it (such as runtime state or
+/// structural settings) are left alone.
+///
+/// ```ignore
+/// #[derive(Component, Debug, Clone, Reflect)]
+/// pub struct MyEffect {
+///     #[reflect(@Parameter::float(10.0).range(0.0, f32::INFINITY).unit(ParameterUnit::Distance))]
+///     pub radius: f32,
+/// }
+/// ```
+///
+/// The kind of the default value has to match the type of the field.
+#[derive(Debug, Clone, Reflect)]
+pub struct Parameter {
+    pub default: KeyframeValue,
+    pub range: Option<(f32, f32)>,
+    pub unit: ParameterUnit,
+}
+
+impl Parameter {
+    pub fn float(default: f32) -> Self {
+        Self::new(KeyframeValue::FloatKeyframe(default))
+    }
+
+    pub fn color(default: Color) -> Self {
+        Self::new(KeyframeValue::ColorKeyframe(default))
+    }
+
+    pub fn vec3(default: Vec3) -> Self {
+        Self::new(KeyframeValue::Vec3Keyframe(default))
+    }
+
+    fn new(default: KeyframeValue) -> Self {
+        Self {
+            default,
+            range: None,
+            unit: ParameterUnit::None,
+        }
+    }
+
+    /// Limits the values of a float parameter, both ends inclusive. Either end
+    /// can be infinite. Ignored for other kinds of parameters.
+    pub fn range(mut self, min: f32, max: f32) -> Self {
+        self.range = Some((min, max));
+        self
+    }
+
+    pub fn unit(mut self, unit: ParameterUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+}
+
+/// The kinds of values a parameter can hold, matching the interpolatable
+/// variants of `KeyframeValue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterKind {
+    Float,
+    Color,
+    Vec3,
+}
+
+impl ParameterKind {
+    /// Gets the kind of a keyframe value, or `None` for blending modes, which
+    /// no effect parameter can hold.
+    pub fn of(value: &KeyframeValue) -> Option<Self> {
+        match value {
+            KeyframeValue::FloatKeyframe(_) => Some(ParameterKind::Float),
+            KeyframeValue::ColorKeyframe(_) => Some(ParameterKind::Color),
+            KeyframeValue::Vec3Keyframe(_) => Some(ParameterKind::Vec3),
+            KeyframeValue::BlendingModeKeyframe(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ParameterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterKind::Float => write!(f, "float"),
+            ParameterKind::Color => write!(f, "color"),
+            ParameterKind::Vec3 => write!(f, "vector"),
+        }
+    }
+}
+
+/// The unit a parameter is given in. Only used for display.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum ParameterUnit {
+    #[default]
+    None,
+    /// Angles in degrees.
+    Degrees,
+    /// Distances in world space, the same space fixtures are positioned in.
+    Distance,
+    /// Values between 0 and 1.
+    Fraction,
+}
+
+impl ParameterUnit {
+    /// Gets the suffix values in this unit are displayed with.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            ParameterUnit::None | ParameterUnit::Distance | ParameterUnit::Fraction => "",
+            ParameterUnit::Degrees => "°",
+        }
+    }
+}
+
+/// Everything known about a single parameter of an effect, gathered from the
+/// effect's reflection info (see `Parameter`).
+#[derive(Debug, Clone)]
+pub struct ParameterSchema {
+    pub name: &'static str,
+    /// Index of the parameter's field within the effect, for setting it
+    /// without looking it up by name (see `set_field_at`).
+    pub field: usize,
+    pub kind: ParameterKind,
+    pub default: KeyframeValue,
+    pub range: Option<(f32, f32)>,
+    pub unit: ParameterUnit,
+}
+
+impl ParameterSchema {
+    /// Checks whether a value fits the parameter, i.e. is of the right kind and
+    /// within range.
+    pub fn check(&self, value: &KeyframeValue) -> Result<(), String> {
+        match ParameterKind::of(value) {
+            Some(kind) if kind == self.kind => {}
+            Some(kind) => {
+                return Err(format!(
+                    "parameter \"{}\" is a {}, but was given a {}",
+                    self.name, self.kind, kind
+                ));
+            }
+            None => {
+                return Err(format!(
+                    "parameter \"{}\" is a {}, but was given a blending mode",
+                    self.name, self.kind
+                ));
+            }
+        }
+        if let (KeyframeValue::FloatKeyframe(value), Some((min, max))) = (value, self.range)
+            && !(min..=max).contains(value)
+        {
+            return Err(format!(
+                "parameter \"{}\" must be between {} and {}, but was given {}",
+                self.name, min, max, value
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Gets the schemas of all parameters declared on an effect type, in field
+/// order. They are gathered the first time they are asked for and kept from
+/// then on, as effects need them on every update.
+///
+/// Panics if a declared default doesn't match the type of its field, as that is
+/// a mistake in the effect's declaration.
+pub fn parameter_schemas(struct_info: &StructInfo) -> &'static [ParameterSchema] {
+    static SCHEMAS: OnceLock<RwLock<HashMap<TypeId, &'static [ParameterSchema]>>> = OnceLock::new();
+    let schemas = SCHEMAS.get_or_init(default);
+    if let Some(cached) = schemas
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&struct_info.type_id())
+    {
+        return cached;
+    }
+    schemas
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(struct_info.type_id())
+        // one per effect type, so leaking them is bounded
+        .or_insert_with(|| gather_parameter_schemas(struct_info).leak())
+}
+
+fn gather_parameter_schemas(struct_info: &StructInfo) -> Vec<ParameterSchema> {
+    struct_info
+        .iter()
+        .enumerate()
+        .filter_map(|(field_i, field)| {
+            let parameter = field.get_attribute::<Parameter>()?;
+            let kind = ParameterKind::of(&parameter.default)
+                .expect("parameter defaults should not be blending modes");
+            let field_kind = if field.is::<f32>() {
+                ParameterKind::Float
+            } else if field.is::<Color>() {
+                ParameterKind::Color
+            } else if field.is::<Vec3>() {
+                ParameterKind::Vec3
+            } else {
+                panic!(
+                    "field \"{}\" of {} can't be a parameter, as it is a {}",
+                    field.name(),
+                    struct_info.type_path(),
+                    field.type_path()
+                );
+            };
+            assert_eq!(
+                kind,
+                field_kind,
+                "default of parameter \"{}\" of {} should match its type",
+                field.name(),
+                struct_info.type_path()
+            );
+            Some(ParameterSchema {
+                name: field.name(),
+                field: field_i,
+                kind,
+                default: parameter.default.clone(),
+                range: parameter.range,
+                unit: parameter.unit,
+            })
+        })
+        .collect()
+}
+
+/// Gets the current value of a parameter of an effect.
+pub fn get_parameter(effect: &dyn Struct, name: &str) -> Option<KeyframeValue> {
+    let field = effect.field(name)?;
+    if let Some(value) = field.try_downcast_ref::<f32>() {
+        Some(KeyframeValue::FloatKeyframe(*value))
+    } else if let Some(value) = field.try_downcast_ref::<Color>() {
+        Some(KeyframeValue::ColorKeyframe(*value))
+    } else {
+        field
+            .try_downcast_ref::<Vec3>()
+            .map(|value| KeyframeValue::Vec3Keyframe(*value))
+    }
+}
+
+/// Sets a parameter of an effect, without checking it against the schema.
+/// Does nothing if the effect has no such field or the value is of a
+/// different type.
+pub fn set_field(effect: &mut dyn Struct, name: &str, value: &KeyframeValue) {
+    if let Some(field) = effect.field_mut(name) {
+        set_value(field, value);
+    }
+}
+
+/// Same as `set_field`, but with the field given by its index (see
+/// `ParameterSchema::field`).
+pub fn set_field_at(effect: &mut dyn Struct, index: usize, value: &KeyframeValue) {
+    if let Some(field) = effect.field_at_mut(index) {
+        set_value(field, value);
+    }
+}
+
+fn set_value(field: &mut dyn PartialReflect, value: &KeyframeValue) {
+    match value {
+        KeyframeValue::FloatKeyframe(value) => {
+            if let Some(field) = field.try_downcast_mut::<f32>() {
+                *field = *value;
+            }
+        }
+        KeyframeValue::ColorKeyframe(value) => {
+            if let Some(field) = field.try_downcast_mut::<Color>() {
+                *field = *value;
+            }
+        }
+        KeyframeValue::Vec3Keyframe(value) => {
+            if let Some(field) = field.try_downcast_mut::<Vec3>() {
+                *field = *value;
+            }
+        }
+        KeyframeValue::BlendingModeKeyframe(_) => {}
+    }
+}
+
+/// Sets a parameter of an effect, after checking the value against its
+/// schema.
+pub fn set_parameter(
+    effect: &mut dyn Struct,
+    schemas: &[ParameterSchema],
+    name: &str,
+    value: KeyframeValue,
+) -> Result<(), String> {
+    let schema = schemas
+        .iter()
+        .find(|schema| schema.name == name)
+        .ok_or_else(|| format!("there is no parameter \"{}\"", name))?;
+    schema.check(&value)?;
+    set_field(effect, name, &value);
+    Ok(())
+}
+
+/// Gets the value of a parameter at the given time from its keyframes. Without
+/// keyframes, the parameter has its value in `init`, the effect as it was
+/// instantiated.
+pub fn keyframed_value(
+    init: &dyn Struct,
+    schema: &ParameterSchema,
+    keyframes: &Keyframes,
+    current_time: f64,
+    tempo_map: &TempoMap,
+) -> Option<KeyframeValue> {
+    match get_parameter(init, schema.name)? {
+        KeyframeValue::FloatKeyframe(value) => Some(KeyframeValue::FloatKeyframe(
+            keyframes.get_float_value(schema.name, current_time, tempo_map, &value),
+        )),
+        KeyframeValue::ColorKeyframe(value) => Some(KeyframeValue::ColorKeyframe(
+            keyframes.get_color_value(schema.name, current_time, tempo_map, &value),
+        )),
+        KeyframeValue::Vec3Keyframe(value) => Some(KeyframeValue::Vec3Keyframe(
+            keyframes.get_vec3_value(schema.name, current_time, tempo_map, &value),
+        )),
+        KeyframeValue::BlendingModeKeyframe(_) => None,
+    }
+}
+
+/// Applies keyframes to every parameter of an effect. Parameters without
+/// keyframes are set back to their value in `init`, the effect as it was
+/// instantiated, so nothing written to them since (such as a modulated value)
+/// carries over.
+pub fn apply_keyframes(
+    effect: &mut dyn Struct,
+    init: &dyn Struct,
+    schemas: &[ParameterSchema],
+    keyframes: &Keyframes,
+    current_time: f64,
+    tempo_map: &TempoMap,
+) {
+    for schema in schemas {
+        if let Some(value) = keyframed_value(init, schema, keyframes, current_time, tempo_map) {
+            set_field(effect, schema.name, &value);
+        }
+    }
+}
+
+/// Checks that every keyframe refers to a parameter of the effect and holds a
+/// value that fits it. Keyframes that don't fit would otherwise be silently
+/// ignored, or fail during playback.
+pub fn validate_keyframes(
+    schemas: &[ParameterSchema],
+    keyframes: &Keyframes,
+    effect_name: &str,
+) -> Result<(), String> {
+    for (keyframe_i, keyframe) in keyframes.inner().iter().enumerate() {
+        let schema = schemas
+            .iter()
+            .find(|schema| schema.name == keyframe.key)
+            .ok_or_else(|| {
+                format!(
+                    "keyframe {} refers to \"{}\", which is not a parameter of {} (expected one of: {})",
+                    keyframe_i,
+                    keyframe.key,
+                    effect_name,
+                    schemas
+                        .iter()
+                        .map(|schema| schema.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+        schema
+            .check(&keyframe.value)
+            .map_err(|e| format!("keyframe {}: {}", keyframe_i, e))?;
+    }
+    Ok(())
+}
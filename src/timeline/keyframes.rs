@@ -1,4 +1,10 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    timeline::positions::{TempoMap, TimelinePosition},
+    util::blending::BlendingMode,
+};
 
 /// Wrapper around a list of common-target keyframes
 #[derive(Component, Debug, Clone, Default)]
@@ -9,43 +15,110 @@ pub struct Keyframes {
 /// Represents the value a parameter (`key`) should be at at a certain moment
 /// in time, plus the interpolation type that should be used to bring the value
 /// to this point. Used on tracks to automate either generic track parameters
-/// or effect track data.
+/// or effect track data. The time can be either absolute or on the beat grid,
+/// in which case it is resolved through the tempo whenever it is evaluated.
 #[derive(Debug, Clone)]
 pub struct Keyframe {
-    pub time: f64,
+    pub time: TimelinePosition,
     pub interpolation: InterpolationType,
     pub key: String,
     pub value: KeyframeValue,
 }
 
 /// Represents the value of a parameter, as declared by a `Keyframe`. Can be an
-/// `f32`, `Color`, or `Vec3` for now.
-#[derive(Debug, Clone)]
+/// `f32`, `Color`, or `Vec3` for now, plus a `BlendingMode` for track-level
+/// automation. Blending modes are discrete and therefore never interpolated.
+#[derive(Debug, Clone, Reflect)]
 pub enum KeyframeValue {
     FloatKeyframe(f32),
     ColorKeyframe(Color),
     Vec3Keyframe(Vec3),
+    BlendingModeKeyframe(BlendingMode),
+}
+
+impl KeyframeValue {
+    /// Gets the name of the kind of this value, as used in error messages.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            KeyframeValue::FloatKeyframe(_) => "float",
+            KeyframeValue::ColorKeyframe(_) => "color",
+            KeyframeValue::Vec3Keyframe(_) => "vector",
+            KeyframeValue::BlendingModeKeyframe(_) => "blending mode",
+        }
+    }
 }
 
 /// The type of interpolation used to bring a parameter to a keyframe's value.
 /// `InterpolationType::CONSTANT` represents an immediate snap to that value at
 /// and past the keyframe, and `InterpolationType::LINEAR` represents a
-/// gradual, linear sweep to that value. TODO: More interpolation types!
-#[derive(Default, Debug, Clone, Copy)]
+/// gradual, linear sweep to that value.
+///
+/// `InterpolationType::BEZIER` eases into the value along a cubic Bézier
+/// curve, given by its two control points (the outgoing tangent of the
+/// previous keyframe and the incoming tangent of this one) in normalized
+/// space, where (0, 0) is the previous keyframe and (1, 1) is this one. This
+/// works the same way as CSS `cubic-bezier()`, and is applied to every
+/// component of the value alike. The x coordinates are kept within 0 to 1,
+/// while y coordinates outside of it overshoot.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum InterpolationType {
     #[default]
     LINEAR,
     CONSTANT,
+    BEZIER {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+    },
+}
+
+impl InterpolationType {
+    /// A gentle ease in and out, used when switching a keyframe to a curve.
+    pub const EASE_IN_OUT: Self = InterpolationType::BEZIER {
+        x1: 0.42,
+        y1: 0.0,
+        x2: 0.58,
+        y2: 1.0,
+    };
+
+    /// Maps normalized time between two keyframes (from 0 to 1) onto how far
+    /// along the value should be. Constant interpolation stays at the start
+    /// until the next keyframe is reached.
+    pub fn ease(&self, time: f64) -> f64 {
+        match *self {
+            InterpolationType::CONSTANT => 0.0,
+            InterpolationType::LINEAR => time,
+            InterpolationType::BEZIER { x1, y1, x2, y2 } => {
+                let (x1, x2) = (x1.clamp(0.0, 1.0) as f64, x2.clamp(0.0, 1.0) as f64);
+                let (y1, y2) = (y1 as f64, y2 as f64);
+                let bezier = |p1: f64, p2: f64, t: f64| {
+                    let u = 1.0 - t;
+                    3.0 * u * u * t * p1 + 3.0 * u * t * t * p2 + t * t * t
+                };
+                // x(t) is monotonic with the control points clamped, so the
+                // parameter for the requested time can be found by bisection
+                let time = time.clamp(0.0, 1.0);
+                let (mut low, mut high) = (0.0, 1.0);
+                for _ in 0..32 {
+                    let mid = (low + high) / 2.0;
+                    if bezier(x1, x2, mid) < time {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                bezier(y1, y2, (low + high) / 2.0)
+            }
+        }
+    }
 }
 
 /// Interpolates a float between two values, using the specified time
 /// (normalized from 0 to 1) and interpolation type, which defines how the
 /// value moves from one point to the other.
 fn interpolate_float(start: f32, end: f32, time: f64, interpolation: InterpolationType) -> f32 {
-    match interpolation {
-        InterpolationType::CONSTANT => start,
-        InterpolationType::LINEAR => start + (end - start) * time as f32,
-    }
+    start + (end - start) * interpolation.ease(time) as f32
 }
 
 /// Interpolates between two colors, using the specified time (normalized from
@@ -61,10 +134,9 @@ fn interpolate_color(
     // Mixes should be done in Oklab perceptual color space!
     let start_oklab = Oklaba::from(*start);
     let end_oklab = Oklaba::from(*end);
-    match interpolation {
-        InterpolationType::CONSTANT => start_oklab.into(),
-        InterpolationType::LINEAR => start_oklab.mix(&end_oklab, time as f32).into(),
-    }
+    start_oklab
+        .mix(&end_oklab, interpolation.ease(time) as f32)
+        .into()
 }
 /// Interpolates between two `vec3`s, using the specified time (normalized from
 /// 0 to 1) and interpolation type, which defines how the value moves from one
@@ -77,6 +149,10 @@ fn interpolate_vec3(start: &Vec3, end: &Vec3, time: f64, interpolation: Interpol
     )
 }
 
+/// The keyframes directly before and after a point in time, along with their
+/// times resolved into seconds.
+type SurroundingKeyframes<'a> = (Option<(f64, &'a Keyframe)>, Option<(f64, &'a Keyframe)>);
+
 impl Keyframes {
     pub fn new(keyframes: Vec<Keyframe>) -> Self {
         Self { keyframes }
@@ -86,30 +162,103 @@ impl Keyframes {
         &self.keyframes
     }
 
+    /// Checks that every keyframe refers to one of `keys`, given as pairs of
+    /// a key and the kind of value it holds (see `KeyframeValue::kind_name`).
+    /// Used for keyframes with a fixed set of keys, unlike those of effects,
+    /// which are checked against the effect's parameters.
+    pub fn validate_keys(&self, keys: &[(&str, &str)]) -> Result<(), String> {
+        for (keyframe_i, keyframe) in self.keyframes.iter().enumerate() {
+            let (key, kind) = keys
+                .iter()
+                .find(|(key, _)| *key == keyframe.key)
+                .ok_or_else(|| {
+                    format!(
+                        "keyframe {} refers to \"{}\" (expected one of: {})",
+                        keyframe_i,
+                        keyframe.key,
+                        keys.iter()
+                            .map(|(key, _)| *key)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })?;
+            if keyframe.value.kind_name() != *kind {
+                return Err(format!(
+                    "keyframe {}: \"{}\" is a {}, but was given a {}",
+                    keyframe_i,
+                    key,
+                    kind,
+                    keyframe.value.kind_name()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts the keyframes back in chronological order after they have been
+    /// moved. Editors rely on this order (lookups don't, see
+    /// `Keyframes::get_surrounding_keyframes`). Keyframes at the same time
+    /// keep their relative order.
+    ///
+    /// Returns the new index of each keyframe, by its index before sorting,
+    /// so that anything referring to keyframes by index can follow along.
+    pub fn sort_by_time(&mut self, tempo_map: &TempoMap) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.keyframes.len()).collect();
+        order.sort_by(|&a, &b| {
+            self.keyframes[a]
+                .time
+                .to_seconds(tempo_map)
+                .total_cmp(&self.keyframes[b].time.to_seconds(tempo_map))
+        });
+
+        let mut new_indices = vec![0; order.len()];
+        for (new_index, &old_index) in order.iter().enumerate() {
+            new_indices[old_index] = new_index;
+        }
+        let mut old_keyframes: Vec<Option<Keyframe>> = self.keyframes.drain(..).map(Some).collect();
+        self.keyframes = order
+            .into_iter()
+            .filter_map(|old_index| old_keyframes[old_index].take())
+            .collect();
+        new_indices
+    }
+
     /// Retrieves the two keyframes around a specific point in time. Used as a
     /// helper function to then retrieve the interpolated value at that point in
     /// time.
     ///
-    /// Returns a tuple with the first keyframes before and after that point. If no
-    /// such keyframe exists, the associated value will be `None`.
+    /// Returns a tuple with the first keyframes before and after that point,
+    /// along with their times resolved into seconds. If no such keyframe
+    /// exists, the associated value will be `None`.
+    ///
+    /// Doesn't rely on the keyframes being in order: musical and absolute
+    /// keyframes can swap places when the tempo map changes, so every
+    /// keyframe is resolved under the given tempo map and compared. Of several
+    /// keyframes at the same time, the last one before and the first one after
+    /// the point are picked, as if they were sorted.
     fn get_surrounding_keyframes(
         &self,
         key: &str,
         time: f64,
-    ) -> (Option<&Keyframe>, Option<&Keyframe>) {
-        let mut start_keyframe: Option<&Keyframe> = None {};
-        let mut end_keyframe: Option<&Keyframe> = None {};
+        tempo_map: &TempoMap,
+    ) -> SurroundingKeyframes<'_> {
+        let mut start_keyframe: Option<(f64, &Keyframe)> = None {};
+        let mut end_keyframe: Option<(f64, &Keyframe)> = None {};
 
         for keyframe in self.inner().iter() {
             if keyframe.key != *key {
                 continue;
             }
-            if keyframe.time > time {
-                end_keyframe = Some(keyframe);
-                break;
+            let keyframe_time = keyframe.time.to_seconds(tempo_map);
+            if keyframe_time > time
+                && end_keyframe.is_none_or(|(end_time, _)| keyframe_time < end_time)
+            {
+                end_keyframe = Some((keyframe_time, keyframe));
             }
-            if keyframe.time < time {
-                start_keyframe = Some(keyframe);
+            if keyframe_time < time
+                && start_keyframe.is_none_or(|(start_time, _)| keyframe_time >= start_time)
+            {
+                start_keyframe = Some((keyframe_time, keyframe));
             }
         }
 
@@ -120,18 +269,24 @@ impl Keyframes {
     /// assuming surrounding keyframes are `f32`. Properly considers
     /// interpolation type. High-level function for use when requesting any
     /// `f32` value from keyframes.
-    pub fn get_float_value(&self, key: &str, time: f64, default: &f32) -> f32 {
+    pub fn get_float_value(
+        &self,
+        key: &str,
+        time: f64,
+        tempo_map: &TempoMap,
+        default: &f32,
+    ) -> f32 {
         // TODO: Maybe clean this tragedy up
-        let (start_opt, end_opt) = self.get_surrounding_keyframes(key, time);
+        let (start_opt, end_opt) = self.get_surrounding_keyframes(key, time, tempo_map);
 
-        if let Some(start_keyframe) = start_opt {
+        if let Some((start_time, start_keyframe)) = start_opt {
             let KeyframeValue::FloatKeyframe(start_value) = start_keyframe.value else {
                 panic!(
                     "tried to interpolate float from non-float start keyframe with key {}",
                     key.to_string()
                 );
             };
-            if let Some(end_keyframe) = end_opt {
+            if let Some((end_time, end_keyframe)) = end_opt {
                 let KeyframeValue::FloatKeyframe(end_value) = end_keyframe.value else {
                     panic!(
                         "tried to interpolate float from non-float end keyframe with key {}",
@@ -142,14 +297,14 @@ impl Keyframes {
                 return interpolate_float(
                     start_value,
                     end_value,
-                    (time - start_keyframe.time) / (end_keyframe.time - start_keyframe.time),
+                    (time - start_time) / (end_time - start_time),
                     end_keyframe.interpolation,
                 );
             }
             // start not end
             return start_value;
         }
-        if let Some(end_keyframe) = end_opt {
+        if let Some((_, end_keyframe)) = end_opt {
             let KeyframeValue::FloatKeyframe(end_value) = end_keyframe.value else {
                 panic!(
                     "tried to interpolate float from non-float end keyframe with key {}",
@@ -167,18 +322,24 @@ impl Keyframes {
     /// assuming surrounding keyframes are `Color`. Properly considers
     /// interpolation type. High-level function for use when requesting any
     /// `Color` value from keyframes.
-    pub fn get_color_value(&self, key: &str, time: f64, default: &Color) -> Color {
+    pub fn get_color_value(
+        &self,
+        key: &str,
+        time: f64,
+        tempo_map: &TempoMap,
+        default: &Color,
+    ) -> Color {
         // TODO: Maybe clean this tragedy up (also rethink copy pasted code idiot)
-        let (start_opt, end_opt) = self.get_surrounding_keyframes(key, time);
+        let (start_opt, end_opt) = self.get_surrounding_keyframes(key, time, tempo_map);
 
-        if let Some(start_keyframe) = start_opt {
+        if let Some((start_time, start_keyframe)) = start_opt {
             let KeyframeValue::ColorKeyframe(start_value) = start_keyframe.value else {
                 panic!(
                     "tried to interpolate color from non-float start keyframe with key {}",
                     key.to_string()
                 );
             };
-            if let Some(end_keyframe) = end_opt {
+            if let Some((end_time, end_keyframe)) = end_opt {
                 let KeyframeValue::ColorKeyframe(end_value) = end_keyframe.value else {
                     panic!(
                         "tried to interpolate color from non-float end keyframe with key {}",
@@ -189,14 +350,14 @@ impl Keyframes {
                 return interpolate_color(
                     &start_value,
                     &end_value,
-                    (time - start_keyframe.time) / (end_keyframe.time - start_keyframe.time),
+                    (time - start_time) / (end_time - start_time),
                     end_keyframe.interpolation,
                 );
             }
             // start not end
             return start_value;
         }
-        if let Some(end_keyframe) = end_opt {
+        if let Some((_, end_keyframe)) = end_opt {
             let KeyframeValue::ColorKeyframe(end_value) = end_keyframe.value else {
                 panic!(
                     "tried to interpolate color from non-float end keyframe with key {}",
@@ -214,18 +375,24 @@ impl Keyframes {
     /// assuming surrounding keyframes are `Vec3`. Properly considers
     /// interpolation type. High-level function for use when requesting any
     /// `Vec3` value from keyframes.
-    pub fn get_vec3_value(&self, key: &str, time: f64, default: &Vec3) -> Vec3 {
+    pub fn get_vec3_value(
+        &self,
+        key: &str,
+        time: f64,
+        tempo_map: &TempoMap,
+        default: &Vec3,
+    ) -> Vec3 {
         // TODO: Maybe clean this tragedy up (also rethink copy pasted code idiot)
-        let (start_opt, end_opt) = self.get_surrounding_keyframes(key, time);
+        let (start_opt, end_opt) = self.get_surrounding_keyframes(key, time, tempo_map);
 
-        if let Some(start_keyframe) = start_opt {
+        if let Some((start_time, start_keyframe)) = start_opt {
             let KeyframeValue::Vec3Keyframe(start_value) = start_keyframe.value else {
                 panic!(
                     "tried to interpolate vec3 from non-float start keyframe with key {}",
                     key.to_string()
                 );
             };
-            if let Some(end_keyframe) = end_opt {
+            if let Some((end_time, end_keyframe)) = end_opt {
                 let KeyframeValue::Vec3Keyframe(end_value) = end_keyframe.value else {
                     panic!(
                         "tried to interpolate vec3 from non-float end keyframe with key {}",
@@ -236,14 +403,14 @@ impl Keyframes {
                 return interpolate_vec3(
                     &start_value,
                     &end_value,
-                    (time - start_keyframe.time) / (end_keyframe.time - start_keyframe.time),
+                    (time - start_time) / (end_time - start_time),
                     end_keyframe.interpolation,
                 );
             }
             // start not end
             return start_value;
         }
-        if let Some(end_keyframe) = end_opt {
+        if let Some((_, end_keyframe)) = end_opt {
             let KeyframeValue::Vec3Keyframe(end_value) = end_keyframe.value else {
                 panic!(
                     "tried to interpolate vec3 from non-float end keyframe with key {}",
@@ -256,4 +423,31 @@ impl Keyframes {
         // neither
         *default
     }
+
+    /// Retrieves the value of a particular parameter at the specified time,
+    /// assuming surrounding keyframes are `BlendingMode`. Since blending modes
+    /// cannot be interpolated, the most recent keyframe always wins regardless
+    /// of interpolation type. Before the first keyframe, its value is used.
+    pub fn get_blending_mode_value(
+        &self,
+        key: &str,
+        time: f64,
+        tempo_map: &TempoMap,
+        default: &BlendingMode,
+    ) -> BlendingMode {
+        let (start_opt, end_opt) = self.get_surrounding_keyframes(key, time, tempo_map);
+
+        match start_opt.or(end_opt) {
+            Some((_, keyframe)) => {
+                let KeyframeValue::BlendingModeKeyframe(value) = keyframe.value else {
+                    panic!(
+                        "tried to read blending mode from non-blending mode keyframe with key {}",
+                        key
+                    );
+                };
+                value
+            }
+            None => *default,
+        }
+    }
 }
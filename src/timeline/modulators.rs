@@ -0,0 +1,423 @@
+use std::f64::consts::TAU;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::timeline::{
+    audio_bindings::{AudioBinding, AudioEnvelope, AudioFeature},
+    effects::{EffectUpdateCommonInfo, parameters::ParameterSchema},
+    keyframes::KeyframeValue,
+    positions::TempoMap,
+};
+
+/// A low-frequency oscillator that moves a parameter back and forth on top of
+/// its keyframes. Modulators belong to a track (see `TrackInfo`) and drive
+/// either the track factor or one of the parameters of the track's effect.
+///
+/// The modulator outputs a wave between -1 and 1, which is scaled by `depth`,
+/// shifted by `offset`, and added to the value the target would otherwise
+/// have. Several modulators on the same target add up. `depth` and `offset`
+/// are given per component: floats use `x`, vectors use `x`, `y` and `z`, and
+/// colors use Oklab lightness, a, b and alpha (`x`, `y`, `z` and `w`), the
+/// same space colors are interpolated in.
+///
+/// `phase` shifts the wave, in cycles. `spread` shifts it further for every
+/// fixture, by the number of cycles per unit of distance along it, so that a
+/// wave can travel across fixtures (a spread of zero moves them in unison).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Modulator {
+    pub target: ModulatorTarget,
+    pub shape: ModulatorShape,
+    pub rate: ModulatorRate,
+    pub phase: f64,
+    pub spread: Vec3,
+    pub depth: Vec4,
+    pub offset: Vec4,
+    /// Seeds `ModulatorShape::SmoothRandom`, so that several random
+    /// modulators don't move in lockstep.
+    pub seed: u32,
+}
+
+impl Modulator {
+    /// Constructs a new sine modulator on the given target, running at one
+    /// cycle per beat with no depth.
+    pub fn new(target: ModulatorTarget) -> Self {
+        Self {
+            target,
+            shape: ModulatorShape::Sine,
+            rate: ModulatorRate::Beats(1.0),
+            phase: 0.0,
+            spread: Vec3::ZERO,
+            depth: Vec4::ZERO,
+            offset: Vec4::ZERO,
+            seed: 0,
+        }
+    }
+
+    /// Gets the position of the modulator within its wave, in cycles, at the
+    /// given time within the sequence the track sits in (ignoring spread).
+    /// Tempo-synced modulators follow the tempo map of that sequence.
+    pub fn phase_at(&self, time: f64, tempo_map: &TempoMap) -> f64 {
+        let cycles = match self.rate {
+            ModulatorRate::Hertz(hertz) => time * hertz,
+            ModulatorRate::Beats(beats) if beats > 0.0 => tempo_map.seconds_to_beats(time) / beats,
+            ModulatorRate::Beats(_) => 0.0,
+        };
+        cycles + self.phase
+    }
+}
+
+/// The shape of the wave a modulator follows. All shapes run between -1 and 1.
+/// Sines and triangles start in the middle and rise first, saws rise from the
+/// bottom, and squares start at the top.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModulatorShape {
+    #[default]
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    /// Moves smoothly between random values, one per cycle.
+    SmoothRandom,
+}
+
+impl ModulatorShape {
+    pub const ALL: [ModulatorShape; 5] = [
+        ModulatorShape::Sine,
+        ModulatorShape::Triangle,
+        ModulatorShape::Saw,
+        ModulatorShape::Square,
+        ModulatorShape::SmoothRandom,
+    ];
+
+    /// Samples the wave at the given phase, in cycles.
+    pub fn sample(self, phase: f64, seed: u32) -> f32 {
+        let fraction = phase.rem_euclid(1.0);
+        let value = match self {
+            ModulatorShape::Sine => (phase * TAU).sin(),
+            ModulatorShape::Triangle => 4.0 * ((fraction - 0.25).rem_euclid(1.0) - 0.5).abs() - 1.0,
+            ModulatorShape::Saw => 2.0 * fraction - 1.0,
+            ModulatorShape::Square => {
+                if fraction < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            ModulatorShape::SmoothRandom => {
+                let cycle = phase.floor() as i64;
+                let eased = fraction * fraction * (3.0 - 2.0 * fraction);
+                let start = random_value(seed, cycle);
+                start + (random_value(seed, cycle + 1) - start) * eased
+            }
+        };
+        value as f32
+    }
+}
+
+/// Gets a random value between -1 and 1 that only depends on the seed and
+/// cycle, so random modulators look the same every time they are played.
+fn random_value(seed: u32, cycle: i64) -> f64 {
+    // SplitMix64 finalizer
+    let mut x = (cycle as u64) ^ ((seed as u64) << 32);
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+/// How fast a modulator runs, either free-running in cycles per second or
+/// synced to the tempo in beats per cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ModulatorRate {
+    Hertz(f64),
+    Beats(f64),
+}
+
+/// What a modulator (or an audio binding) drives.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModulatorTarget {
+    /// The factor of the track, which it is blended into its sequence with.
+    Factor,
+    /// A parameter of the effect on an effect track, by name.
+    Parameter(String),
+}
+
+/// Checks that every modulator drives something that exists and runs at a
+/// sensible rate. `schemas` are the parameters of the track's effect, if it
+/// has one.
+pub fn validate_modulators(
+    modulators: &[Modulator],
+    schemas: &[ParameterSchema],
+) -> Result<(), String> {
+    for (modulator_i, modulator) in modulators.iter().enumerate() {
+        validate_target(
+            &modulator.target,
+            schemas,
+            &format!("modulator {}", modulator_i),
+        )?;
+        let valid_rate = match modulator.rate {
+            ModulatorRate::Hertz(hertz) => hertz.is_finite(),
+            ModulatorRate::Beats(beats) => beats.is_finite() && beats > 0.0,
+        };
+        if !valid_rate {
+            return Err(format!(
+                "modulator {} has an invalid rate of {:?}",
+                modulator_i, modulator.rate
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a target exists, i.e. is the factor or one of the given
+/// parameters. `context` names whatever drives the target, for the error.
+pub(crate) fn validate_target(
+    target: &ModulatorTarget,
+    schemas: &[ParameterSchema],
+    context: &str,
+) -> Result<(), String> {
+    match target {
+        ModulatorTarget::Parameter(name) if !schemas.iter().any(|schema| schema.name == *name) => {
+            Err(format!(
+                "{} drives \"{}\", which is not a parameter of the track's effect",
+                context, name
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The modulators and audio bindings of a track as evaluated at the current
+/// time, grouped by what they drive. Held by active tracks, so that targets can
+/// be evaluated again for every fixture when modulators are spread across
+/// them, and so that audio bindings can smooth their level from one update to
+/// the next.
+#[derive(Debug, Default)]
+pub struct ActiveModulation {
+    targets: Vec<ModulatedTarget>,
+    /// The state of every audio binding of the track, in the same order, along
+    /// with the target and feature that tell which binding it belongs to.
+    envelopes: Vec<(ModulatorTarget, AudioFeature, AudioEnvelope)>,
+    /// The global time of the previous update.
+    last_update: Option<f64>,
+}
+
+/// A single target of `ActiveModulation`, with the value it would have without
+/// modulation and the modulators that drive it.
+#[derive(Debug)]
+struct ModulatedTarget {
+    target: ModulatorTarget,
+    base: TargetBase,
+    sources: Vec<ModulationSource>,
+}
+
+/// What a target of `ActiveModulation` is without modulation, as given to
+/// `ActiveModulation::update`.
+#[derive(Debug)]
+pub struct TargetBase {
+    /// The unmodulated value of the target.
+    pub value: KeyframeValue,
+    /// The range the modulated value is kept within, if any.
+    pub range: Option<(f32, f32)>,
+    /// The index of the effect field the target sets, if it is an effect
+    /// parameter (see `ParameterSchema::field`). Resolved once per update, so
+    /// that fixtures with modulators spread across them don't look it up by
+    /// name every time.
+    pub field: Option<usize>,
+}
+
+/// A single modulator or audio binding of a `ModulatedTarget`.
+#[derive(Debug)]
+struct ModulationSource {
+    wave: ModulationWave,
+    depth: Vec4,
+    offset: Vec4,
+}
+
+/// What a `ModulationSource` scales by its depth: the wave of a modulator with
+/// its phase at the current time, or the current level of an audio binding.
+#[derive(Debug)]
+enum ModulationWave {
+    Lfo {
+        shape: ModulatorShape,
+        seed: u32,
+        phase: f64,
+        spread: Vec3,
+    },
+    Level(f32),
+}
+
+impl ModulationSource {
+    fn value_at(&self, position: Vec3) -> Vec4 {
+        let value = match self.wave {
+            ModulationWave::Lfo {
+                shape,
+                seed,
+                phase,
+                spread,
+            } => shape.sample(phase + position.dot(spread) as f64, seed),
+            ModulationWave::Level(level) => level,
+        };
+        self.offset + self.depth * value
+    }
+}
+
+impl ActiveModulation {
+    /// Evaluates the given modulators at the current time (within the
+    /// sequence the track sits in), and measures the audio for the given audio
+    /// bindings. `base` gets the current unmodulated state of a target, or
+    /// `None` to leave the target alone.
+    pub fn update(
+        &mut self,
+        modulators: &[Modulator],
+        audio_bindings: &[AudioBinding],
+        current_time: f64,
+        common_info: &EffectUpdateCommonInfo,
+        base: impl Fn(&ModulatorTarget) -> Option<TargetBase>,
+    ) {
+        self.targets.clear();
+        for modulator in modulators {
+            self.add_source(&modulator.target, &base, || ModulationSource {
+                wave: ModulationWave::Lfo {
+                    shape: modulator.shape,
+                    seed: modulator.seed,
+                    phase: modulator.phase_at(current_time, common_info.tempo_map),
+                    spread: modulator.spread,
+                },
+                depth: modulator.depth,
+                offset: modulator.offset,
+            });
+        }
+
+        let elapsed = self.last_update.map_or(0.0, |last_update| {
+            (common_info.global_time - last_update).max(0.0)
+        }) as f32;
+        self.last_update = Some(common_info.global_time);
+        let mut envelopes = std::mem::take(&mut self.envelopes);
+        // the state stays with its binding when others are added, removed or
+        // moved, and starts over for a binding that listens to something else
+        for (binding_i, binding) in audio_bindings.iter().enumerate() {
+            let found = envelopes[binding_i..]
+                .iter()
+                .position(|(target, feature, _)| {
+                    *target == binding.target && *feature == binding.feature
+                });
+            match found {
+                Some(found) => envelopes.swap(binding_i, binding_i + found),
+                None => envelopes.insert(
+                    binding_i,
+                    (
+                        binding.target.clone(),
+                        binding.feature,
+                        AudioEnvelope::default(),
+                    ),
+                ),
+            }
+        }
+        envelopes.truncate(audio_bindings.len());
+        for (binding, (_, _, envelope)) in audio_bindings.iter().zip(envelopes.iter_mut()) {
+            self.add_source(&binding.target, &base, || ModulationSource {
+                wave: ModulationWave::Level(envelope.update(
+                    binding,
+                    common_info.recent_fft_data,
+                    elapsed,
+                )),
+                depth: binding.depth,
+                offset: binding.offset,
+            });
+        }
+        self.envelopes = envelopes;
+    }
+
+    /// Adds a source to the given target, unless `base` leaves the target
+    /// alone, in which case the source isn't evaluated at all.
+    fn add_source(
+        &mut self,
+        target: &ModulatorTarget,
+        base: impl Fn(&ModulatorTarget) -> Option<TargetBase>,
+        source: impl FnOnce() -> ModulationSource,
+    ) {
+        match self
+            .targets
+            .iter_mut()
+            .find(|modulated| modulated.target == *target)
+        {
+            Some(modulated) => modulated.sources.push(source()),
+            None => {
+                let Some(base) = base(target) else {
+                    return;
+                };
+                self.targets.push(ModulatedTarget {
+                    target: target.clone(),
+                    base,
+                    sources: vec![source()],
+                });
+            }
+        }
+    }
+
+    /// Whether any modulator is spread across fixtures, in which case targets
+    /// have to be evaluated for every fixture on its own.
+    pub fn has_spread(&self) -> bool {
+        self.targets.iter().any(|target| {
+            target.sources.iter().any(|source| {
+                matches!(source.wave, ModulationWave::Lfo { spread, .. } if spread != Vec3::ZERO)
+            })
+        })
+    }
+
+    /// Gets the modulated value of every target that sets an effect field for
+    /// a fixture at the given position, along with the index of that field.
+    pub fn field_values_at(&self, position: Vec3) -> impl Iterator<Item = (usize, KeyframeValue)> {
+        self.targets.iter().filter_map(move |target| {
+            target
+                .base
+                .field
+                .map(|field| (field, target.value_at(position)))
+        })
+    }
+
+    /// Gets the modulated value of a single target for a fixture at the given
+    /// position, or `None` if nothing modulates it.
+    pub fn value_at(&self, target: &ModulatorTarget, position: Vec3) -> Option<KeyframeValue> {
+        self.targets
+            .iter()
+            .find(|modulated| modulated.target == *target)
+            .map(|modulated| modulated.value_at(position))
+    }
+}
+
+impl ModulatedTarget {
+    fn value_at(&self, position: Vec3) -> KeyframeValue {
+        let delta: Vec4 = self
+            .sources
+            .iter()
+            .map(|source| source.value_at(position))
+            .sum();
+        match &self.base.value {
+            KeyframeValue::FloatKeyframe(value) => {
+                let value = value + delta.x;
+                KeyframeValue::FloatKeyframe(match self.base.range {
+                    Some((min, max)) => value.clamp(min, max),
+                    None => value,
+                })
+            }
+            KeyframeValue::Vec3Keyframe(value) => KeyframeValue::Vec3Keyframe(value + delta.xyz()),
+            KeyframeValue::ColorKeyframe(value) => {
+                let oklab = Oklaba::from(*value);
+                KeyframeValue::ColorKeyframe(
+                    Oklaba::new(
+                        (oklab.lightness + delta.x).clamp(0.0, 1.0),
+                        oklab.a + delta.y,
+                        oklab.b + delta.z,
+                        (oklab.alpha + delta.w).clamp(0.0, 1.0),
+                    )
+                    .into(),
+                )
+            }
+            KeyframeValue::BlendingModeKeyframe(_) => self.base.value.clone(),
+        }
+    }
+}
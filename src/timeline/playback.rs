@@ -1,6 +1,11 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
-use crate::{simple_store::SimpleStore, tests, timeline::sequences::*};
+use crate::{
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{positions::TempoMap, sequences::*},
+};
 
 /// Bevy plugin for playback.
 pub struct PlaybackPlugin;
@@ -8,24 +13,39 @@ pub struct PlaybackPlugin;
 impl Plugin for PlaybackPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<PlaybackInformation>()
-            .add_systems(Update, increment_playback_time)
-            .add_systems(Startup, tests::single_pulse::pulse_test_startup);
+            .init_resource::<Playlist>()
+            .add_systems(Update, increment_playback_time);
     }
 }
 
 /// Bevy resource that holds information about the current state of playback on
-/// the primary sequence, including current playback head time, whether or not
-/// playback is currently in progress, and the beats per minute and beats per bar
-/// to define gridlines.
+/// the primary sequence, including current playback head time and whether or
+/// not playback is currently in progress. Tempo lives on each sequence's
+/// `TempoMap`.
 ///
-/// TODO: Separate BPM into some separate measure. Maybe make sequences either
-/// time-bound or BPM bound? Really not sure how to best do this.
+/// `end_mode` decides what happens when the playback head reaches the end of
+/// the primary sequence. While `loop_region_enabled` is set and the primary
+/// sequence has a `LoopRegion`, the playback head repeats that region instead
+/// once it has entered it.
 #[derive(Resource, Debug)]
 pub struct PlaybackInformation {
     pub current_time: f64,
     pub is_playing: bool,
-    pub bpm: f64,
-    pub beats_per_bar: usize,
+    pub end_mode: PlaybackEndMode,
+    pub loop_region_enabled: bool,
+    /// Whether the playback head is currently running backwards, which only
+    /// happens in `PlaybackEndMode::PingPong`.
+    pub reversed: bool,
+    /// Whether the primary sequence is currently blacked out, after playback
+    /// has stopped in `PlaybackEndMode::Blackout`. Live cues still show.
+    pub blacked_out: bool,
+    /// Whether an external clock (see `TimecodeSettings::chase`) moves the
+    /// playback head, in which case it doesn't move on by itself.
+    pub external_clock: bool,
+    /// The tempo map the primary sequence plays at while an external clock
+    /// sets the tempo (see `MidiClock`). Takes the place of the sequence's own
+    /// tempo map, keeping its meter but running at the tempo of the clock.
+    pub external_tempo: Option<TempoMap>,
 }
 
 impl Default for PlaybackInformation {
@@ -33,32 +53,296 @@ impl Default for PlaybackInformation {
         Self {
             current_time: 0.0,
             is_playing: false,
-            bpm: 120.0,
-            beats_per_bar: 4,
+            end_mode: PlaybackEndMode::default(),
+            loop_region_enabled: false,
+            reversed: false,
+            blacked_out: false,
+            external_clock: false,
+            external_tempo: None,
         }
     }
 }
 
+impl PlaybackInformation {
+    /// Starts playback, lifting any blackout.
+    pub fn play(&mut self) {
+        self.is_playing = true;
+        self.blacked_out = false;
+    }
+
+    /// Pauses playback, leaving the playback head where it is.
+    pub fn pause(&mut self) {
+        self.is_playing = false;
+    }
+
+    /// Moves the playback head to a new time, lifting any blackout.
+    pub fn seek(&mut self, time: f64) {
+        self.current_time = time;
+        self.blacked_out = false;
+    }
+}
+
+/// What playback does once the playback head reaches the end of the primary
+/// sequence.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaybackEndMode {
+    /// Jumps back to the start and keeps playing.
+    #[default]
+    Loop,
+    /// Stops at the end, holding the last frame.
+    Hold,
+    /// Stops at the end and blacks out the primary sequence until playback is
+    /// started again.
+    Blackout,
+    /// Turns around and plays backwards to the start, then forwards again.
+    PingPong,
+    /// Moves on to the next sequence in the `Playlist`, holding the last frame
+    /// at the end of the last one.
+    FollowOn,
+}
+
+impl PlaybackEndMode {
+    /// Every end mode, in the order they are listed in the UI.
+    pub const ALL: [PlaybackEndMode; 5] = [
+        PlaybackEndMode::Loop,
+        PlaybackEndMode::Hold,
+        PlaybackEndMode::Blackout,
+        PlaybackEndMode::PingPong,
+        PlaybackEndMode::FollowOn,
+    ];
+}
+
+impl fmt::Display for PlaybackEndMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PlaybackEndMode::Loop => "Loop",
+            PlaybackEndMode::Hold => "Stop and hold",
+            PlaybackEndMode::Blackout => "Stop to black",
+            PlaybackEndMode::PingPong => "Ping-pong",
+            PlaybackEndMode::FollowOn => "Follow on",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Bevy resource that holds the order in which sequences are played when the
+/// end mode is `PlaybackEndMode::FollowOn`.
+#[derive(Resource, Default, Debug)]
+pub struct Playlist(pub Vec<SimpleHandle<Sequence>>);
+
+impl Playlist {
+    /// Finds the sequence that follows the given one in the playlist, if
+    /// there is any.
+    pub fn next_after(&self, handle: SimpleHandle<Sequence>) -> Option<SimpleHandle<Sequence>> {
+        let index = self.0.iter().position(|entry| *entry == handle)?;
+        self.0.get(index + 1).copied()
+    }
+}
+
 /// Increments current playback time when playback is currently in progress.
-/// Loops back to `0` when playback reaches the end of the primary sequence.
+/// What happens at the end of the primary sequence depends on the end mode
+/// (see `PlaybackEndMode`), unless the playback head is caught in an enabled
+/// loop region.
 pub fn increment_playback_time(
     time: Res<Time>,
     mut playback: ResMut<PlaybackInformation>,
-    primary_sequence: Res<PrimarySequence>,
+    mut primary_sequence: ResMut<PrimarySequence>,
+    playlist: Res<Playlist>,
     sequence_store: Res<SimpleStore<Sequence>>,
 ) {
-    let Some(sequence) = primary_sequence
+    let Some((handle, sequence)) = primary_sequence
         .0
-        .and_then(|handle| sequence_store.get(handle))
+        .and_then(|handle| Some((handle, sequence_store.get(handle)?)))
     else {
         playback.current_time = 0.0;
         playback.is_playing = false;
         return;
     };
-    if playback.is_playing {
-        playback.current_time += time.delta_secs_f64();
-        if playback.current_time > sequence.length {
-            playback.current_time = 0.0;
+    if !playback.is_playing || playback.external_clock {
+        return;
+    }
+
+    let delta = time.delta_secs_f64();
+    let length = sequence.length;
+    let ping_pong = playback.end_mode == PlaybackEndMode::PingPong;
+    if !ping_pong {
+        playback.reversed = false;
+    }
+    let loop_region = sequence
+        .loop_region
+        .filter(|_| playback.loop_region_enabled)
+        .and_then(|loop_region| loop_region.seconds(sequence));
+
+    if playback.reversed {
+        // the loop region only catches the playback head once it is inside
+        let start = loop_region
+            .map(|(start, _)| start)
+            .filter(|start| playback.current_time > *start)
+            .unwrap_or(0.0);
+        playback.current_time -= delta;
+        if playback.current_time <= start {
+            playback.current_time = (2.0 * start - playback.current_time).min(length);
+            playback.reversed = false;
+        }
+        return;
+    }
+
+    // playback was started again after stopping at the end
+    if playback.current_time >= length {
+        playback.current_time = 0.0;
+    }
+
+    // the loop region only catches the playback head once it has entered it,
+    // so playing on from past its end is still possible
+    let loop_region = loop_region.filter(|(_, end)| playback.current_time < *end);
+    playback.current_time += delta;
+
+    if let Some((start, end)) = loop_region {
+        if playback.current_time >= end {
+            let overshoot = playback.current_time - end;
+            if ping_pong {
+                playback.current_time = (end - overshoot).max(start);
+                playback.reversed = true;
+            } else {
+                playback.current_time = start + overshoot % (end - start);
+            }
+        }
+        return;
+    }
+
+    if playback.current_time < length {
+        return;
+    }
+    let overshoot = playback.current_time - length;
+    match playback.end_mode {
+        PlaybackEndMode::Loop => {
+            playback.current_time = if length > 0.0 {
+                overshoot % length
+            } else {
+                0.0
+            };
+        }
+        PlaybackEndMode::Hold => {
+            playback.current_time = length;
+            playback.is_playing = false;
         }
+        PlaybackEndMode::Blackout => {
+            playback.current_time = length;
+            playback.is_playing = false;
+            playback.blacked_out = true;
+        }
+        PlaybackEndMode::PingPong => {
+            playback.current_time = (length - overshoot).max(0.0);
+            playback.reversed = true;
+        }
+        PlaybackEndMode::FollowOn => {
+            match playlist
+                .next_after(handle)
+                .and_then(|next| Some((next, sequence_store.get(next)?)))
+            {
+                Some((next, next_sequence)) => {
+                    primary_sequence.0 = Some(next);
+                    playback.current_time = overshoot.min(next_sequence.length);
+                }
+                None => {
+                    playback.current_time = length;
+                    playback.is_playing = false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    /// A world playing the first of the given sequences, by their lengths,
+    /// which are also the playlist.
+    fn playing(end_mode: PlaybackEndMode, lengths: &[f64]) -> World {
+        let mut sequence_store = SimpleStore::default();
+        let playlist: Vec<_> = lengths
+            .iter()
+            .map(|length| {
+                sequence_store.add(Sequence {
+                    id: SequenceId::fresh(),
+                    name: "Sequence".into(),
+                    length: *length,
+                    tempo_map: TempoMap::default(),
+                    tracks: Vec::new(),
+                    loop_region: None,
+                    audio: None,
+                })
+            })
+            .collect();
+
+        let mut world = World::new();
+        world.insert_resource(PrimarySequence(playlist.first().copied()));
+        world.insert_resource(Playlist(playlist));
+        world.insert_resource(sequence_store);
+        world.insert_resource(PlaybackInformation {
+            is_playing: true,
+            end_mode,
+            ..default()
+        });
+        world.init_resource::<Time>();
+        world
+    }
+
+    /// Moves playback on by the given number of seconds.
+    fn advance(world: &mut World, seconds: f64) -> &PlaybackInformation {
+        world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f64(seconds));
+        world.run_system_once(increment_playback_time).unwrap();
+        world.resource::<PlaybackInformation>()
+    }
+
+    #[test]
+    fn ping_pong_turns_around_at_both_ends() {
+        let mut world = playing(PlaybackEndMode::PingPong, &[2.0]);
+        assert_eq!(advance(&mut world, 1.5).current_time, 1.5);
+
+        let playback = advance(&mut world, 1.0);
+        assert_eq!(playback.current_time, 1.5);
+        assert!(playback.reversed);
+        assert_eq!(advance(&mut world, 1.0).current_time, 0.5);
+
+        let playback = advance(&mut world, 1.0);
+        assert_eq!(playback.current_time, 0.5);
+        assert!(!playback.reversed);
+        assert!(playback.is_playing);
+    }
+
+    #[test]
+    fn hold_stops_on_the_last_frame() {
+        let mut world = playing(PlaybackEndMode::Hold, &[2.0]);
+        let playback = advance(&mut world, 2.5);
+        assert_eq!(playback.current_time, 2.0);
+        assert!(!playback.is_playing);
+        assert!(!playback.blacked_out);
+    }
+
+    #[test]
+    fn follow_on_moves_through_the_playlist_and_holds_at_its_end() {
+        let mut world = playing(PlaybackEndMode::FollowOn, &[2.0, 3.0]);
+        let playlist = world.resource::<Playlist>();
+        let (first, second) = (playlist.0[0], playlist.0[1]);
+        assert_eq!(playlist.next_after(first), Some(second));
+        assert_eq!(playlist.next_after(second), None);
+
+        let playback = advance(&mut world, 2.5);
+        assert_eq!(playback.current_time, 0.5);
+        assert!(playback.is_playing);
+        assert_eq!(world.resource::<PrimarySequence>().0, Some(second));
+
+        let playback = advance(&mut world, 3.0);
+        assert_eq!(playback.current_time, 3.0);
+        assert!(!playback.is_playing);
+        assert_eq!(world.resource::<PrimarySequence>().0, Some(second));
     }
 }
@@ -0,0 +1,649 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Number of ticks that make up a single beat. Matches the usual resolution of
+/// MIDI sequencers, which is fine enough for any practical lighting use.
+pub const TICKS_PER_BEAT: u32 = 960;
+
+/// How the tempo moves from one `TempoPoint` to the next. `Constant` holds the
+/// tempo until the next point and then jumps, while `Linear` ramps the tempo
+/// linearly (over beats) until it reaches the tempo of the next point. A ramp
+/// on the last point has nothing to ramp towards and behaves as `Constant`.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TempoRamp {
+    #[default]
+    Constant,
+    Linear,
+}
+
+/// A tempo change within a `TempoMap`, placed on the beat grid.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TempoPoint {
+    pub beat: f64,
+    pub bpm: f64,
+    pub ramp: TempoRamp,
+}
+
+impl TempoPoint {
+    /// Constructs a new `TempoPoint`.
+    pub fn new(beat: f64, bpm: f64, ramp: TempoRamp) -> Self {
+        Self { beat, bpm, ramp }
+    }
+}
+
+/// A time signature change within a `TempoMap`. Takes effect at the start of
+/// `bar` and lasts until the next change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MeterChange {
+    pub bar: u32,
+    pub beats_per_bar: usize,
+}
+
+impl MeterChange {
+    /// Constructs a new `MeterChange`.
+    pub fn new(bar: u32, beats_per_bar: usize) -> Self {
+        Self { bar, beats_per_bar }
+    }
+}
+
+/// The tempo and time signature of a sequence over time. Tempo points are
+/// placed in beats, so the beat grid stays fixed when a tempo is corrected,
+/// and conversions between seconds and beats are exact, including through
+/// tempo ramps. Meter changes define how beats are grouped into bars.
+///
+/// Always contains at least one tempo point at beat 0 and one meter change at
+/// bar 0, both sorted. Use `TempoMap::new` to construct a validated map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    tempo_points: Vec<TempoPoint>,
+    meter_changes: Vec<MeterChange>,
+    // cached start time of each tempo point in seconds
+    tempo_point_seconds: Vec<f64>,
+    // cached start beat of each meter change
+    meter_change_beats: Vec<f64>,
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        Self::constant(120.0, 4)
+    }
+}
+
+impl TempoMap {
+    /// Constructs a new `TempoMap` from a list of tempo points and meter
+    /// changes. Both lists must be sorted, start at beat/bar 0, and contain
+    /// only positive tempos and bar lengths.
+    pub fn new(
+        tempo_points: Vec<TempoPoint>,
+        meter_changes: Vec<MeterChange>,
+    ) -> Result<Self, String> {
+        match tempo_points.first() {
+            Some(first) if first.beat == 0.0 => {}
+            _ => return Err("tempo map must start with a tempo point at beat 0".into()),
+        }
+        match meter_changes.first() {
+            Some(first) if first.bar == 0 => {}
+            _ => return Err("tempo map must start with a meter change at bar 0".into()),
+        }
+        for window in tempo_points.windows(2) {
+            if window[1].beat <= window[0].beat {
+                return Err(format!(
+                    "tempo points must be in increasing order, got beat {} after beat {}",
+                    window[1].beat, window[0].beat
+                ));
+            }
+        }
+        for point in &tempo_points {
+            if !(point.bpm > 0.0 && point.bpm.is_finite()) {
+                return Err(format!(
+                    "tempo must be positive, got {} at beat {}",
+                    point.bpm, point.beat
+                ));
+            }
+        }
+        for window in meter_changes.windows(2) {
+            if window[1].bar <= window[0].bar {
+                return Err(format!(
+                    "meter changes must be in increasing order, got bar {} after bar {}",
+                    window[1].bar, window[0].bar
+                ));
+            }
+        }
+        for meter_change in &meter_changes {
+            if meter_change.beats_per_bar == 0 {
+                return Err(format!(
+                    "bars must contain at least one beat, got 0 at bar {}",
+                    meter_change.bar
+                ));
+            }
+        }
+
+        let mut tempo_map = Self {
+            tempo_points,
+            meter_changes,
+            tempo_point_seconds: Vec::new(),
+            meter_change_beats: Vec::new(),
+        };
+        tempo_map.rebuild_caches();
+        Ok(tempo_map)
+    }
+
+    /// Constructs a `TempoMap` with a single tempo and time signature.
+    pub fn constant(bpm: f64, beats_per_bar: usize) -> Self {
+        let mut tempo_map = Self {
+            tempo_points: vec![TempoPoint::new(0.0, bpm, TempoRamp::Constant)],
+            meter_changes: vec![MeterChange::new(0, beats_per_bar)],
+            tempo_point_seconds: Vec::new(),
+            meter_change_beats: Vec::new(),
+        };
+        tempo_map.rebuild_caches();
+        tempo_map
+    }
+
+    /// Constructs a copy of the tempo map that keeps its meter changes, but
+    /// plays at a single tempo throughout, e.g. the tempo of an external
+    /// clock.
+    pub fn with_tempo(&self, bpm: f64) -> Self {
+        let mut tempo_map = Self {
+            tempo_points: vec![TempoPoint::new(0.0, bpm, TempoRamp::Constant)],
+            meter_changes: self.meter_changes.clone(),
+            tempo_point_seconds: Vec::new(),
+            meter_change_beats: Vec::new(),
+        };
+        tempo_map.rebuild_caches();
+        tempo_map
+    }
+
+    pub fn tempo_points(&self) -> &[TempoPoint] {
+        &self.tempo_points
+    }
+
+    pub fn meter_changes(&self) -> &[MeterChange] {
+        &self.meter_changes
+    }
+
+    /// Recomputes the start times of all tempo points and the start beats of
+    /// all meter changes. Must be called whenever either list changes.
+    fn rebuild_caches(&mut self) {
+        self.tempo_point_seconds.clear();
+        let mut seconds = 0.0;
+        for i in 0..self.tempo_points.len() {
+            self.tempo_point_seconds.push(seconds);
+            if let Some(next) = self.tempo_points.get(i + 1) {
+                seconds += self.segment_seconds(i, next.beat - self.tempo_points[i].beat);
+            }
+        }
+
+        self.meter_change_beats.clear();
+        let mut beats = 0.0;
+        for (i, meter_change) in self.meter_changes.iter().enumerate() {
+            if i > 0 {
+                let previous = &self.meter_changes[i - 1];
+                beats += (meter_change.bar - previous.bar) as f64 * previous.beats_per_bar as f64;
+            }
+            self.meter_change_beats.push(beats);
+        }
+    }
+
+    /// Returns the tempo at the start of a tempo segment and how much it
+    /// changes per beat within that segment.
+    fn segment_slope(&self, index: usize) -> (f64, f64) {
+        let point = &self.tempo_points[index];
+        match (point.ramp, self.tempo_points.get(index + 1)) {
+            (TempoRamp::Linear, Some(next)) => {
+                (point.bpm, (next.bpm - point.bpm) / (next.beat - point.beat))
+            }
+            _ => (point.bpm, 0.0),
+        }
+    }
+
+    /// Number of seconds it takes to play `beats` beats from the start of a
+    /// tempo segment. Ramps are integrated exactly.
+    fn segment_seconds(&self, index: usize, beats: f64) -> f64 {
+        let (bpm, slope) = self.segment_slope(index);
+        if slope.abs() < 1e-12 {
+            beats * 60.0 / bpm
+        } else {
+            60.0 / slope * ((bpm + slope * beats) / bpm).ln()
+        }
+    }
+
+    /// Number of beats played within `seconds` seconds from the start of a
+    /// tempo segment. Exact inverse of `TempoMap::segment_seconds`.
+    fn segment_beats(&self, index: usize, seconds: f64) -> f64 {
+        let (bpm, slope) = self.segment_slope(index);
+        if slope.abs() < 1e-12 {
+            seconds * bpm / 60.0
+        } else {
+            bpm * ((slope * seconds / 60.0).exp() - 1.0) / slope
+        }
+    }
+
+    /// Converts a (fractional) number of beats into seconds. Negative beat
+    /// counts are extrapolated using the initial tempo.
+    pub fn beats_to_seconds(&self, beats: f64) -> f64 {
+        if beats < 0.0 {
+            return beats * 60.0 / self.tempo_points[0].bpm;
+        }
+        let index = self
+            .tempo_points
+            .partition_point(|point| point.beat <= beats)
+            .saturating_sub(1);
+        self.tempo_point_seconds[index]
+            + self.segment_seconds(index, beats - self.tempo_points[index].beat)
+    }
+
+    /// Converts a number of seconds into a (fractional) number of beats.
+    /// Negative times are extrapolated using the initial tempo.
+    pub fn seconds_to_beats(&self, seconds: f64) -> f64 {
+        if seconds < 0.0 {
+            return seconds * self.tempo_points[0].bpm / 60.0;
+        }
+        let index = self
+            .tempo_point_seconds
+            .partition_point(|point_seconds| *point_seconds <= seconds)
+            .saturating_sub(1);
+        self.tempo_points[index].beat
+            + self.segment_beats(index, seconds - self.tempo_point_seconds[index])
+    }
+
+    /// Gets the tempo, in beats per minute, at a (fractional) beat.
+    pub fn bpm_at_beat(&self, beats: f64) -> f64 {
+        let index = self
+            .tempo_points
+            .partition_point(|point| point.beat <= beats)
+            .saturating_sub(1);
+        let (bpm, slope) = self.segment_slope(index);
+        bpm + slope * (beats - self.tempo_points[index].beat).max(0.0)
+    }
+
+    /// Gets the number of beats in a particular bar.
+    pub fn beats_per_bar_at(&self, bar: u32) -> usize {
+        let index = self
+            .meter_changes
+            .partition_point(|meter_change| meter_change.bar <= bar)
+            .saturating_sub(1);
+        self.meter_changes[index].beats_per_bar
+    }
+
+    /// Converts a bar number into the beat at which that bar starts.
+    pub fn bar_to_beats(&self, bar: u32) -> f64 {
+        let index = self
+            .meter_changes
+            .partition_point(|meter_change| meter_change.bar <= bar)
+            .saturating_sub(1);
+        let meter_change = &self.meter_changes[index];
+        self.meter_change_beats[index]
+            + (bar - meter_change.bar) as f64 * meter_change.beats_per_bar as f64
+    }
+
+    /// Converts a (fractional) number of beats into the bar it falls in and
+    /// the (fractional) beat within that bar. Negative beat counts are
+    /// clamped to the start.
+    pub fn beats_to_bar(&self, beats: f64) -> (u32, f64) {
+        let beats = beats.max(0.0);
+        let index = self
+            .meter_change_beats
+            .partition_point(|change_beats| *change_beats <= beats)
+            .saturating_sub(1);
+        let meter_change = &self.meter_changes[index];
+        let beats_into_meter = beats - self.meter_change_beats[index];
+        let bars_into_meter = (beats_into_meter / meter_change.beats_per_bar as f64).floor();
+        (
+            meter_change.bar + bars_into_meter as u32,
+            beats_into_meter - bars_into_meter * meter_change.beats_per_bar as f64,
+        )
+    }
+
+    /// Gets how far through the current beat the specified time is, from 0
+    /// (on the beat) up to, but not including, 1.
+    pub fn beat_phase(&self, seconds: f64) -> f64 {
+        self.seconds_to_beats(seconds).rem_euclid(1.0)
+    }
+
+    /// Gets how far through the current bar the specified time is, from 0
+    /// (on the downbeat) up to, but not including, 1.
+    pub fn bar_phase(&self, seconds: f64) -> f64 {
+        let (bar, beat_in_bar) = self.beats_to_bar(self.seconds_to_beats(seconds));
+        beat_in_bar / self.beats_per_bar_at(bar) as f64
+    }
+}
+
+/// A position on the beat grid, expressed as bars, beats, and ticks. All three
+/// are zero-based, so the very start of a sequence is `0:0:0`; the `Display`
+/// implementation adds one to bars and beats to match how musicians count.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct MusicalTime {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+impl MusicalTime {
+    /// Constructs a new `MusicalTime`.
+    pub fn new(bar: u32, beat: u32, tick: u32) -> Self {
+        Self { bar, beat, tick }
+    }
+
+    /// Converts the position into a (fractional) number of beats from the
+    /// start of the sequence, using the bar lengths of the specified tempo map.
+    pub fn to_beats(&self, tempo_map: &TempoMap) -> f64 {
+        tempo_map.bar_to_beats(self.bar)
+            + self.beat as f64
+            + self.tick as f64 / TICKS_PER_BEAT as f64
+    }
+
+    /// Constructs the position closest to a (fractional) number of beats from
+    /// the start of the sequence, rounded to the nearest tick. Negative beat
+    /// counts are clamped to the start.
+    pub fn from_beats(beats: f64, tempo_map: &TempoMap) -> Self {
+        // round first so that a position a hair before a bar line snaps onto it
+        let beats = (beats.max(0.0) * TICKS_PER_BEAT as f64).round() / TICKS_PER_BEAT as f64;
+        let (bar, beat_in_bar) = tempo_map.beats_to_bar(beats);
+        let ticks_in_bar = (beat_in_bar * TICKS_PER_BEAT as f64).round() as u32;
+        Self {
+            bar,
+            beat: ticks_in_bar / TICKS_PER_BEAT,
+            tick: ticks_in_bar % TICKS_PER_BEAT,
+        }
+    }
+
+    /// Treats the position as a length of time starting at a (fractional)
+    /// beat, and gets the beat it ends at. Bars are counted from the bar the
+    /// start falls in, landing on the same beat within a later bar, so a
+    /// length of one bar lasts exactly the bar it starts in, whatever the
+    /// meter at the start of the sequence.
+    pub fn end_beats_from(&self, start_beats: f64, tempo_map: &TempoMap) -> f64 {
+        let (bar, beat_in_bar) = tempo_map.beats_to_bar(start_beats);
+        tempo_map.bar_to_beats(bar + self.bar)
+            + beat_in_bar
+            + self.beat as f64
+            + self.tick as f64 / TICKS_PER_BEAT as f64
+    }
+
+    /// Counterpart of `MusicalTime::end_beats_from` for a length of time
+    /// ending at a (fractional) beat, which gets the beat it starts at.
+    pub fn start_beats_before(&self, end_beats: f64, tempo_map: &TempoMap) -> f64 {
+        let beats = end_beats - self.beat as f64 - self.tick as f64 / TICKS_PER_BEAT as f64;
+        let (bar, beat_in_bar) = tempo_map.beats_to_bar(beats);
+        tempo_map.bar_to_beats(bar.saturating_sub(self.bar)) + beat_in_bar
+    }
+
+    /// Constructs the length of time between two (fractional) beats, counted
+    /// the same way as `MusicalTime::end_beats_from` and rounded to the
+    /// nearest tick.
+    pub fn length_between(start_beats: f64, end_beats: f64, tempo_map: &TempoMap) -> Self {
+        let (start_bar, start_beat_in_bar) = tempo_map.beats_to_bar(start_beats);
+        let (end_bar, end_beat_in_bar) = tempo_map.beats_to_bar(end_beats);
+        // whole bars, up to the same beat within a bar as the start (allowing
+        // for half a tick of rounding error, so whole bars stay whole)
+        let mut bars = end_bar.saturating_sub(start_bar);
+        if bars > 0 && end_beat_in_bar + 0.5 / (TICKS_PER_BEAT as f64) < start_beat_in_bar {
+            bars -= 1;
+        }
+        let remaining = end_beats - tempo_map.bar_to_beats(start_bar + bars) - start_beat_in_bar;
+        let ticks = (remaining.max(0.0) * TICKS_PER_BEAT as f64).round() as u32;
+        Self {
+            bar: bars,
+            beat: ticks / TICKS_PER_BEAT,
+            tick: ticks % TICKS_PER_BEAT,
+        }
+    }
+}
+
+impl fmt::Display for MusicalTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{:03}", self.bar + 1, self.beat + 1, self.tick)
+    }
+}
+
+/// A point in time within a sequence, either pinned to the clock in seconds
+/// or pinned to the beat grid. Musical positions are converted through the
+/// tempo whenever they are evaluated, so correcting the tempo of a song moves
+/// everything placed on the grid along with it instead of letting it drift.
+///
+/// Also used for lengths of time (such as clip durations). Musical lengths are
+/// counted on the beat grid from where they start, rather than from the start
+/// of the sequence (see `TimelinePosition::length_seconds_from`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TimelinePosition {
+    Seconds(f64),
+    Musical(MusicalTime),
+}
+
+impl Default for TimelinePosition {
+    fn default() -> Self {
+        TimelinePosition::Seconds(0.0)
+    }
+}
+
+impl From<f64> for TimelinePosition {
+    fn from(value: f64) -> Self {
+        TimelinePosition::Seconds(value)
+    }
+}
+
+impl From<MusicalTime> for TimelinePosition {
+    fn from(value: MusicalTime) -> Self {
+        TimelinePosition::Musical(value)
+    }
+}
+
+impl TimelinePosition {
+    /// Resolves the position into seconds from the start of the sequence.
+    pub fn to_seconds(&self, tempo_map: &TempoMap) -> f64 {
+        match self {
+            TimelinePosition::Seconds(seconds) => *seconds,
+            TimelinePosition::Musical(musical_time) => {
+                tempo_map.beats_to_seconds(musical_time.to_beats(tempo_map))
+            }
+        }
+    }
+
+    /// Resolves the position into a (fractional) number of beats from the
+    /// start of the sequence.
+    pub fn to_beats(&self, tempo_map: &TempoMap) -> f64 {
+        match self {
+            TimelinePosition::Seconds(seconds) => tempo_map.seconds_to_beats(*seconds),
+            TimelinePosition::Musical(musical_time) => musical_time.to_beats(tempo_map),
+        }
+    }
+
+    /// Resolves the position, as a length of time starting at `start` (in
+    /// seconds), into seconds. Musical lengths are counted on the beat grid
+    /// from the bar `start` falls in (see `MusicalTime::end_beats_from`), so a
+    /// bar lasts as long as the bar it starts in.
+    pub fn length_seconds_from(&self, start: f64, tempo_map: &TempoMap) -> f64 {
+        match self {
+            TimelinePosition::Seconds(length) => *length,
+            TimelinePosition::Musical(musical_time) => {
+                let start_beats = tempo_map.seconds_to_beats(start);
+                tempo_map.beats_to_seconds(musical_time.end_beats_from(start_beats, tempo_map))
+                    - start
+            }
+        }
+    }
+
+    /// Same as `TimelinePosition::length_seconds_from`, but for a length of
+    /// time ending at `end` (in seconds), such as a fade-out.
+    pub fn length_seconds_before(&self, end: f64, tempo_map: &TempoMap) -> f64 {
+        match self {
+            TimelinePosition::Seconds(length) => *length,
+            TimelinePosition::Musical(musical_time) => {
+                let end_beats = tempo_map.seconds_to_beats(end);
+                end - tempo_map
+                    .beats_to_seconds(musical_time.start_beats_before(end_beats, tempo_map))
+            }
+        }
+    }
+
+    /// Converts the position into a musical one, snapping to the nearest tick.
+    /// Musical positions are returned unchanged.
+    pub fn to_musical(&self, tempo_map: &TempoMap) -> Self {
+        match self {
+            TimelinePosition::Seconds(_) => TimelinePosition::Musical(MusicalTime::from_beats(
+                self.to_beats(tempo_map),
+                tempo_map,
+            )),
+            TimelinePosition::Musical(_) => *self,
+        }
+    }
+
+    /// Converts the position into one pinned to the clock at its current
+    /// position. Positions in seconds are returned unchanged.
+    pub fn to_absolute(&self, tempo_map: &TempoMap) -> Self {
+        TimelinePosition::Seconds(self.to_seconds(tempo_map))
+    }
+
+    /// Constructs a position at the given time in seconds, of the same kind
+    /// (absolute or musical) as this one. Used when moving things along the
+    /// timeline, so they stay pinned the way they were placed.
+    pub fn moved_to(&self, seconds: f64, tempo_map: &TempoMap) -> Self {
+        match self {
+            TimelinePosition::Seconds(_) => TimelinePosition::Seconds(seconds),
+            TimelinePosition::Musical(_) => TimelinePosition::Musical(MusicalTime::from_beats(
+                tempo_map.seconds_to_beats(seconds),
+                tempo_map,
+            )),
+        }
+    }
+
+    /// Constructs a length of time spanning from `start` to `end` (both in
+    /// seconds), of the same kind as this one. Musical lengths are measured on
+    /// the beat grid from `start` (see `MusicalTime::length_between`).
+    pub fn resized_to(&self, start: f64, end: f64, tempo_map: &TempoMap) -> Self {
+        match self {
+            TimelinePosition::Seconds(_) => TimelinePosition::Seconds(end - start),
+            TimelinePosition::Musical(_) => TimelinePosition::Musical(MusicalTime::length_between(
+                tempo_map.seconds_to_beats(start),
+                tempo_map.seconds_to_beats(end),
+                tempo_map,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    /// 4/4 for two bars, then 3/4, with a ramp from 60 to 120 BPM over the
+    /// first four beats that then holds.
+    fn ramp_and_meter_change() -> TempoMap {
+        TempoMap::new(
+            vec![
+                TempoPoint::new(0.0, 60.0, TempoRamp::Linear),
+                TempoPoint::new(4.0, 120.0, TempoRamp::Constant),
+            ],
+            vec![MeterChange::new(0, 4), MeterChange::new(2, 3)],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn constant_tempo_converts_linearly() {
+        let tempo_map = TempoMap::constant(120.0, 4);
+        assert_close(tempo_map.beats_to_seconds(4.0), 2.0);
+        assert_close(tempo_map.seconds_to_beats(3.0), 6.0);
+        assert_close(tempo_map.beats_to_seconds(-2.0), -1.0);
+        assert_close(
+            TimelinePosition::Musical(MusicalTime::new(1, 2, 480)).to_seconds(&tempo_map),
+            3.25,
+        );
+        assert_close(tempo_map.beat_phase(1.25), 0.5);
+        assert_close(tempo_map.bar_phase(3.0), 0.5);
+    }
+
+    #[test]
+    fn ramps_are_integrated_exactly() {
+        let tempo_map = ramp_and_meter_change();
+        // 60 BPM rising by 15 BPM per beat takes 4 ln(2) seconds to 120 BPM
+        let ramp_seconds = 4.0 * 2f64.ln();
+        assert_close(tempo_map.beats_to_seconds(4.0), ramp_seconds);
+        assert_close(tempo_map.beats_to_seconds(6.0), ramp_seconds + 1.0);
+        assert_close(tempo_map.seconds_to_beats(ramp_seconds + 1.0), 6.0);
+        assert_close(tempo_map.bpm_at_beat(2.0), 90.0);
+        assert_close(tempo_map.bpm_at_beat(10.0), 120.0);
+        // halfway through the ramp in time is less than halfway in beats
+        assert!(tempo_map.seconds_to_beats(ramp_seconds / 2.0) < 2.0);
+    }
+
+    #[test]
+    fn meter_changes_regroup_beats_into_bars() {
+        let tempo_map = ramp_and_meter_change();
+        assert_eq!(tempo_map.beats_per_bar_at(1), 4);
+        assert_eq!(tempo_map.beats_per_bar_at(5), 3);
+        assert_close(tempo_map.bar_to_beats(2), 8.0);
+        assert_close(tempo_map.bar_to_beats(4), 14.0);
+        let (bar, beat_in_bar) = tempo_map.beats_to_bar(12.5);
+        assert_eq!(bar, 3);
+        assert_close(beat_in_bar, 1.5);
+        assert_eq!(
+            MusicalTime::from_beats(12.5, &tempo_map),
+            MusicalTime::new(3, 1, 480)
+        );
+    }
+
+    #[test]
+    fn musical_lengths_count_bars_from_where_they_start() {
+        let tempo_map = ramp_and_meter_change();
+        let bar = TimelinePosition::Musical(MusicalTime::new(1, 0, 0));
+        // a bar of 3/4 at 120 BPM, rather than the 4/4 bar the sequence
+        // starts with
+        let start = tempo_map.beats_to_seconds(9.0);
+        assert_close(bar.length_seconds_from(start, &tempo_map), 1.5);
+        let end = tempo_map.beats_to_seconds(14.0);
+        assert_close(bar.length_seconds_before(end, &tempo_map), 1.5);
+        assert_eq!(
+            MusicalTime::length_between(9.0, 12.0, &tempo_map),
+            MusicalTime::new(1, 0, 0)
+        );
+        // from the middle of a 4/4 bar, onto the same beat of a 3/4 bar
+        assert_eq!(
+            MusicalTime::length_between(6.0, 13.0, &tempo_map),
+            MusicalTime::new(2, 0, 0)
+        );
+        assert_close(
+            MusicalTime::new(2, 1, 0).end_beats_from(6.0, &tempo_map),
+            14.0,
+        );
+    }
+
+    #[test]
+    fn beats_and_seconds_round_trip() {
+        let tempo_map = ramp_and_meter_change();
+        for step in 0..100 {
+            let beats = step as f64 * 0.37;
+            assert_close(
+                tempo_map.seconds_to_beats(tempo_map.beats_to_seconds(beats)),
+                beats,
+            );
+            let seconds = step as f64 * 0.23;
+            assert_close(
+                tempo_map.beats_to_seconds(tempo_map.seconds_to_beats(seconds)),
+                seconds,
+            );
+        }
+        for bar in 0..8 {
+            for beat in 0..tempo_map.beats_per_bar_at(bar) as u32 {
+                let musical_time = MusicalTime::new(bar, beat, 120);
+                let beats = musical_time.to_beats(&tempo_map);
+                assert_eq!(MusicalTime::from_beats(beats, &tempo_map), musical_time);
+                let seconds = TimelinePosition::Musical(musical_time).to_seconds(&tempo_map);
+                assert_eq!(
+                    TimelinePosition::Seconds(seconds).to_musical(&tempo_map),
+                    TimelinePosition::Musical(musical_time)
+                );
+            }
+        }
+    }
+}
@@ -3,15 +3,23 @@ use crate::{
     fixtures::{FixtureRequest, FixtureResponse},
     simple_store::{SimpleHandle, SimpleStore},
     timeline::{
-        effects::{ColorEffectLike, EffectInfo, EffectUpdateCommonInfo, PanTiltEffectLike},
-        keyframes::Keyframes,
+        cues::{CuePlayback, LiveCue},
+        effects::{
+            ColorEffectLike, EffectInfo, EffectUpdateCommonInfo, PanTiltEffectLike, TimeStep,
+        },
+        keyframes::{KeyframeValue, Keyframes},
+        modulators::{ActiveModulation, ModulatorTarget, TargetBase},
         playback::PlaybackInformation,
+        positions::TempoMap,
         sequences::{PrimarySequence, Sequence},
-        tracks::{Clip, ClipsExt, TimeSegment, Track, TrackContents},
+        tracks::{Clip, ClipId, ClipsExt, Track, TrackContents, TrackId, TrackInfo},
     },
     util::blending::BlendingMode,
 };
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPool},
+};
 use derive_more::From;
 
 /// Bevy plugin for the sequence tree.
@@ -21,7 +29,8 @@ impl Plugin for SequenceTreePlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<SequenceTree>()
             .add_systems(FixedUpdate, update_sequence_tree)
-            .add_observer(clear_sequence_tree);
+            .add_observer(clear_sequence_tree)
+            .add_observer(fire_trigger_track);
     }
 }
 
@@ -54,27 +63,68 @@ impl Default for ActiveSequence {
 /// effect and are therefore passed by reference in the effect update functions
 /// to avoid recloning on every modification.
 ///
+/// Remembers the local and global time of its last update, so the next
+/// update can tell the effect whether time moved on or jumped (see
+/// `TimeStep`), and the modulators and audio bindings driving the effect's
+/// parameters as of that update, for fixtures to be given their own phase and
+/// audio levels to be smoothed.
+///
 /// `ActiveSequenceTrack` -> nothing
 #[derive(Debug)]
 pub struct ActiveEffectTrack {
     current_info: EffectInfo,
+    last_update: Option<(f64, f64)>,
+    modulation: ActiveModulation,
 }
 
 /// Represents a single active sequence track, i.e. an indexed child of an
 /// active sequence that is currently being played back and defines a series of
-/// clips which reference other sequences. Holds a reference to the currently
-/// playing clip if the playback head is currently on one, otherwise `None`.
+/// clips which reference other sequences. Holds every clip the playback head
+/// is currently sitting on. Usually this is at most one, but overlapping clips
+/// are kept alive together while they crossfade.
 ///
-/// `ActiveSequenceTrack` -> potentially one `ActiveSequence` node
-#[derive(Debug)]
+/// `active_clips` and `previous_children` are only used during updates, and
+/// are kept around so their buffers can be reused.
+///
+/// `ActiveSequenceTrack` -> potentially several `ActiveSequence` nodes
+#[derive(Debug, Default)]
 pub struct ActiveSequenceTrack {
-    child: Option<(TimeSegment, ActiveSequence)>,
+    children: Vec<ActiveClip>,
+    active_clips: Vec<(usize, f32)>,
+    previous_children: Vec<ActiveClip>,
 }
 
-// ActiveTriggerTrack -> many ActiveSequence nodes
+/// A clip currently being played back within an active sequence track. Holds
+/// the id of the clip (to match it back up with the static clip), its current
+/// weight according to its fade envelope, and the `ActiveSequence`
+/// instantiated from it.
 #[derive(Debug)]
+pub struct ActiveClip {
+    clip_id: ClipId,
+    weight: f32,
+    active_sequence: ActiveSequence,
+}
+
+/// Represents a single active trigger track, i.e. an indexed child of an
+/// active sequence that spawns a new instance of its sequence every time it
+/// is fired. Instances are independent of the parent's playback head and run
+/// on global time, so hits can be layered on top of each other and keep
+/// playing even while the timeline is paused.
+///
+/// `ActiveTriggerTrack` -> many `ActiveSequence` nodes
+#[derive(Debug, Default)]
 pub struct ActiveTriggerTrack {
-    // TODO: implement trigger tracks
+    instances: Vec<PastTrigger>,
+}
+
+/// A single firing of a trigger track. Holds the global time at which the
+/// trigger was fired, the global time at which it expires (fire time plus the
+/// length of the sequence), and the `ActiveSequence` spawned by the trigger.
+#[derive(Debug)]
+pub struct PastTrigger {
+    triggered_at: f64,
+    expires_at: f64,
+    active_sequence: ActiveSequence,
 }
 
 /// Collection of all active track content types to be included as data for
@@ -90,10 +140,20 @@ pub enum ActiveTrackContents {
 /// sequence that is currently being played back. Contains basic track
 /// metadata, as well as any variant-specific contents of the track, such as
 /// the effect information or children, within `contents`.
+///
+/// Remembers the id and revision of the static track it was built from, so
+/// it can be matched back up with that track after edits (see
+/// `SequenceTree::reconcile_tracks`).
+///
+/// `factor` holds the factor at the origin; modulators that spread it across
+/// fixtures are kept in `factor_modulation` (see `ActiveTrack::factor_at`).
 #[derive(Debug)]
 pub struct ActiveTrack {
+    track_id: TrackId,
+    track_revision: u64,
     blending_mode: BlendingMode,
     factor: f32,
+    factor_modulation: ActiveModulation,
     local_time: f64,
     contents: ActiveTrackContents,
 }
@@ -106,37 +166,157 @@ impl From<&Track> for ActiveTrack {
             TrackContents::EffectTrack {
                 effect_init_info, ..
             } => Self {
+                track_id: value.id(),
+                track_revision: value.revision(),
                 blending_mode: value.info.blending_mode,
                 factor: value.info.factor,
+                factor_modulation: ActiveModulation::default(),
                 local_time: 0.0, // will be set later down the line
                 contents: ActiveEffectTrack {
                     // Since an effect can be instantiated several times, data
                     // has to be cloned each time.
                     current_info: effect_init_info.clone(),
+                    last_update: None,
+                    modulation: ActiveModulation::default(),
                 }
                 .into(),
             },
             TrackContents::SequenceTrack { .. } => Self {
+                track_id: value.id(),
+                track_revision: value.revision(),
                 blending_mode: value.info.blending_mode,
                 factor: value.info.factor,
+                factor_modulation: ActiveModulation::default(),
                 local_time: 0.0,
-                contents: ActiveSequenceTrack {
-                    child: None, // will be set later down the line
-                }
-                .into(),
+                // children will be set later down the line
+                contents: ActiveSequenceTrack::default().into(),
             },
             TrackContents::TriggerTrack { .. } => Self {
+                track_id: value.id(),
+                track_revision: value.revision(),
                 blending_mode: value.info.blending_mode,
                 factor: value.info.factor,
+                factor_modulation: ActiveModulation::default(),
                 local_time: 0.0,
-                // TODO: implement trigger tracks
-                contents: ActiveTriggerTrack {}.into(),
+                contents: ActiveTriggerTrack::default().into(),
             },
         }
     }
 }
 
 impl ActiveTrack {
+    /// Whether the active track was built from the given track as it is now,
+    /// i.e. it is the same track, its contents haven't been replaced since,
+    /// and it is still of the same type.
+    fn is_up_to_date(&self, track: &Track) -> bool {
+        self.track_id == track.id()
+            && self.track_revision == track.revision()
+            && self.matches_type(track)
+    }
+
+    fn matches_type(&self, track: &Track) -> bool {
+        matches!(
+            (&self.contents, &track.contents),
+            (
+                ActiveTrackContents::ActiveEffectTrack(_),
+                TrackContents::EffectTrack { .. }
+            ) | (
+                ActiveTrackContents::ActiveSequenceTrack(_),
+                TrackContents::SequenceTrack { .. }
+            ) | (
+                ActiveTrackContents::ActiveTriggerTrack(_),
+                TrackContents::TriggerTrack { .. }
+            )
+        )
+    }
+
+    /// Rebuilds the active track from the current state of its static track,
+    /// keeping whatever still applies. Effects are re-instantiated from the
+    /// new init info but inherit their runtime state, and carry on from their
+    /// last update rather than starting over, while sequence and trigger
+    /// tracks keep their children (which reconcile themselves). Audio bindings
+    /// keep their levels either way.
+    fn rebuild(self, track: &Track) -> Self {
+        let mut rebuilt = ActiveTrack::from(track);
+        rebuilt.factor_modulation = self.factor_modulation;
+        match (&mut rebuilt.contents, self.contents) {
+            (
+                ActiveTrackContents::ActiveEffectTrack(new_effect_track),
+                ActiveTrackContents::ActiveEffectTrack(old_effect_track),
+            ) => {
+                new_effect_track
+                    .current_info
+                    .inherit_state(&old_effect_track.current_info);
+                new_effect_track.last_update = old_effect_track.last_update;
+                new_effect_track.modulation = old_effect_track.modulation;
+            }
+            (
+                ActiveTrackContents::ActiveSequenceTrack(new_sequence_track),
+                ActiveTrackContents::ActiveSequenceTrack(old_sequence_track),
+            ) => *new_sequence_track = old_sequence_track,
+            (
+                ActiveTrackContents::ActiveTriggerTrack(new_trigger_track),
+                ActiveTrackContents::ActiveTriggerTrack(old_trigger_track),
+            ) => *new_trigger_track = old_trigger_track,
+            // changed type, so nothing carries over
+            _ => {}
+        }
+        rebuilt
+    }
+
+    /// Re-evaluates the track-level parameters (factor and blending mode)
+    /// from the static track info and its track keyframes at the given time
+    /// within the parent sequence. Parameters without keyframes fall back to
+    /// the values set on the static track. Modulators and audio bindings
+    /// driving the factor are applied on top.
+    fn update_info(
+        &mut self,
+        track_info: &TrackInfo,
+        current_time: f64,
+        common_info: &EffectUpdateCommonInfo,
+    ) {
+        let keyframes = &track_info.track_keyframes;
+        self.factor = keyframes.get_float_value(
+            "factor",
+            current_time,
+            common_info.tempo_map,
+            &track_info.factor,
+        );
+        let factor = self.factor;
+        self.factor_modulation.update(
+            &track_info.modulators,
+            &track_info.audio_bindings,
+            current_time,
+            common_info,
+            |target| {
+                (*target == ModulatorTarget::Factor).then_some(TargetBase {
+                    value: KeyframeValue::FloatKeyframe(factor),
+                    range: None,
+                    field: None,
+                })
+            },
+        );
+        self.factor = self.factor_at(Vec3::ZERO);
+        self.blending_mode = keyframes.get_blending_mode_value(
+            "blending_mode",
+            current_time,
+            common_info.tempo_map,
+            &track_info.blending_mode,
+        );
+    }
+
+    /// Gets the factor of the track for a fixture at the given position, which
+    /// only differs from `factor` if modulators spread it across fixtures.
+    fn factor_at(&self, position: Vec3) -> f32 {
+        match self
+            .factor_modulation
+            .value_at(&ModulatorTarget::Factor, position)
+        {
+            Some(KeyframeValue::FloatKeyframe(factor)) => factor,
+            _ => self.factor,
+        }
+    }
+
     fn as_active_effect_track(&mut self) -> &mut ActiveEffectTrack {
         match &mut self.contents {
             ActiveTrackContents::ActiveEffectTrack(active_effect_track) => active_effect_track,
@@ -176,16 +356,33 @@ impl ActiveTrack {
     }
 }
 
+/// A cue from a cue list that is currently live, along with the level of its
+/// fades. Cues are roots of their own, next to the primary sequence, and are
+/// layered on top of it.
+///
+/// `ActiveCue` -> `ActiveSequence`
+#[derive(Debug)]
+pub struct ActiveCue {
+    id: u64,
+    weight: f32,
+    active_sequence: ActiveSequence,
+}
+
 /// The almighty sequence tree, which drives how the effects and sub-sequences
 /// of the current active sequence are built out into the actual, concrete
 /// effects being played back and rendered out to the fixtures. If a track,
 /// effect, or sequence of any sort is currently a factor in playback, it will
 /// be included in this tree in the form of its `Active...` variant.
 ///
+/// Besides the primary sequence, every live cue of the cue lists gets a node
+/// of its own (see `ActiveCue`), so several cues can be crossfading at once.
+///
 /// Stored as a global Bevy resource.
 #[derive(Resource, Debug, Default)]
 pub struct SequenceTree {
     primary_node: Option<ActiveSequence>,
+    cue_nodes: Vec<ActiveCue>,
+    pending_triggers: Vec<TrackId>,
 }
 
 impl SequenceTree {
@@ -194,43 +391,105 @@ impl SequenceTree {
         Self::default()
     }
 
-    /// Clears out the sequence tree. By setting the primary node to `None`, a
-    /// full regeneration is forced during the next update cycle. Should be
-    /// called whenever changes are made to the order or major contents of any
-    /// sequence to avoid stale data from causing issues.
+    /// Clears out the sequence tree. By dropping the primary node and every
+    /// cue node, a full regeneration is forced during the next update cycle,
+    /// dropping all effect state. Edits to tracks are picked up without this
+    /// (see `SequenceTree::reconcile_tracks`), so it is only needed when the
+    /// whole show is replaced.
     pub fn clear(&mut self) {
         self.primary_node = None;
+        self.cue_nodes.clear();
+    }
+
+    /// Queues a trigger track to be fired during the next update cycle. Every
+    /// active instance of the track will spawn a new instance of its sequence.
+    /// Tracks are referred to by id, so the trigger still reaches the right
+    /// track if tracks are added, removed, or reordered in the meantime.
+    pub fn fire_trigger(&mut self, track: TrackId) {
+        self.pending_triggers.push(track);
     }
 
     /// Updates the sequence store based on the primary sequence open in the
-    /// program. Creates and removes branches as necessary, as well as updating
-    /// any active effects within the tree. This should be called every upate
-    /// cycle to keep the active tree up to date in playback so it can be
-    /// accurately sampled by any fixtures.
+    /// program and the live cues. Creates and removes branches as necessary,
+    /// as well as updating any active effects within the tree. This should be
+    /// called every upate cycle to keep the active tree up to date in playback
+    /// so it can be accurately sampled by any fixtures. Without a primary
+    /// sequence, only the cues are played.
+    ///
+    /// The primary sequence plays at `common_info.tempo_map`, which is usually
+    /// its own tempo map, unless an external clock sets the tempo (see
+    /// `PlaybackInformation::external_tempo`). Every other sequence plays at
+    /// its own.
     pub fn update_recursive(
         &mut self,
         sequence_store: &SimpleStore<Sequence>,
-        primary_sequence_handle: SimpleHandle<Sequence>,
+        primary_sequence_handle: Option<SimpleHandle<Sequence>>,
         primary_sequence_time: f64,
+        live_cues: &[LiveCue],
         common_info: &EffectUpdateCommonInfo,
     ) {
-        SequenceTree::update_recursive_sequence(
-            sequence_store,
-            primary_sequence_handle,
-            // create the primary node if it does not exist
-            self.primary_node.get_or_insert(ActiveSequence::default()),
-            primary_sequence_time,
-            common_info,
-        )
+        // triggers are only ever consumed once, even if nothing picks them up
+        let fired_triggers = std::mem::take(&mut self.pending_triggers);
+        match primary_sequence_handle {
+            Some(primary_sequence_handle) => SequenceTree::update_recursive_sequence(
+                sequence_store,
+                primary_sequence_handle,
+                // create the primary node if it does not exist
+                self.primary_node.get_or_insert(ActiveSequence::default()),
+                primary_sequence_time,
+                &fired_triggers,
+                common_info,
+            ),
+            None => self.primary_node = None,
+        }
+
+        // cues are matched up with their nodes by id, so that each run of a
+        // cue keeps its effect state
+        let mut previous_cue_nodes = std::mem::take(&mut self.cue_nodes);
+        for live_cue in live_cues {
+            let Some(cue_sequence) = sequence_store.get(live_cue.sequence) else {
+                continue;
+            };
+            let active_sequence = match previous_cue_nodes
+                .iter()
+                .position(|cue_node| cue_node.id == live_cue.id)
+            {
+                Some(cue_node_i) => previous_cue_nodes.swap_remove(cue_node_i).active_sequence,
+                None => ActiveSequence::default(),
+            };
+            let mut cue_node = ActiveCue {
+                id: live_cue.id,
+                weight: live_cue.weight,
+                active_sequence,
+            };
+            SequenceTree::update_recursive_sequence(
+                sequence_store,
+                live_cue.sequence,
+                &mut cue_node.active_sequence,
+                live_cue.time,
+                &fired_triggers,
+                &EffectUpdateCommonInfo {
+                    tempo_map: &cue_sequence.tempo_map,
+                    playback_rate: 1.0,
+                    ..*common_info
+                },
+            );
+            self.cue_nodes.push(cue_node);
+        }
+
+        // any previous cue nodes left over have faded out and are dropped
     }
 
     /// Helper function for `SequenceTree::update_recursive`. Recursively
-    /// updates the sequence subtree and effects within an active sequence.
+    /// updates the sequence subtree and effects within an active sequence,
+    /// which plays at the tempo map and playback rate in `common_info`, so
+    /// everything within the sequence is resolved through them.
     fn update_recursive_sequence(
         sequence_store: &SimpleStore<Sequence>,
         current_sequence_handle: SimpleHandle<Sequence>,
         current_active_sequence: &mut ActiveSequence,
         current_time: f64,
+        fired_triggers: &[TrackId],
         common_info: &EffectUpdateCommonInfo,
     ) {
         let Some(current_sequence) = sequence_store.get(current_sequence_handle) else {
@@ -239,32 +498,31 @@ impl SequenceTree {
 
         current_active_sequence.local_time = current_time;
 
-        let needs_initialization = current_active_sequence.children.is_empty();
-
-        for (track_i, track) in current_sequence.tracks.iter().enumerate() {
-            if needs_initialization {
-                current_active_sequence
-                    .children
-                    .push(ActiveTrack::from(track));
-            }
-
-            let active_child_element = current_active_sequence
-                .children
-                .get_mut(track_i)
-                .expect("sequence and active sequence track counts don't match");
+        SequenceTree::reconcile_tracks(
+            &current_sequence.tracks,
+            &mut current_active_sequence.children,
+        );
 
+        for (track, active_child_element) in current_sequence
+            .tracks
+            .iter()
+            .zip(current_active_sequence.children.iter_mut())
+        {
             active_child_element.local_time = current_time;
-            // TODO: Update factor using keyframes. Probably through a helper within `ActiveTrack`.
+            active_child_element.update_info(&track.info, current_time, common_info);
 
             match &track.contents {
                 TrackContents::EffectTrack {
-                    effect_keyframes, ..
+                    effect_init_info,
+                    effect_keyframes,
                 } => {
                     SequenceTree::update_recursive_effect_track(
                         active_child_element.as_active_effect_track(),
                         current_time,
                         common_info,
+                        effect_init_info,
                         effect_keyframes,
+                        &track.info,
                     );
                 }
                 TrackContents::SequenceTrack { clips } => {
@@ -273,16 +531,67 @@ impl SequenceTree {
                         clips,
                         active_child_element.as_active_sequence_track(),
                         current_time,
+                        fired_triggers,
                         common_info,
                     );
                 }
                 TrackContents::TriggerTrack { sequence_handle } => {
-                    // TODO: recurse on trigger tracks
+                    let was_fired = fired_triggers.contains(&track.id());
+                    SequenceTree::update_recursive_trigger_track(
+                        sequence_store,
+                        *sequence_handle,
+                        active_child_element.as_active_trigger_track(),
+                        was_fired,
+                        fired_triggers,
+                        common_info,
+                    );
                 }
             }
         }
     }
 
+    /// Helper function for `SequenceTree::update_recursive_sequence`. Brings
+    /// the active tracks of an active sequence in line with the tracks of its
+    /// sequence, so that there is exactly one up to date active track per
+    /// track, in the same order.
+    ///
+    /// Active tracks are matched up with tracks by id, so they survive other
+    /// tracks being added, removed, or reordered around them. Only tracks that
+    /// are new or have changed are built again (see `ActiveTrack::rebuild`),
+    /// and active tracks of removed tracks are dropped. If nothing changed,
+    /// this is a single pass comparing ids.
+    fn reconcile_tracks(tracks: &[Track], children: &mut Vec<ActiveTrack>) {
+        let is_up_to_date = tracks.len() == children.len()
+            && tracks
+                .iter()
+                .zip(children.iter())
+                .all(|(track, child)| child.is_up_to_date(track));
+        if is_up_to_date {
+            return;
+        }
+
+        let mut previous_children: Vec<Option<ActiveTrack>> =
+            std::mem::take(children).into_iter().map(Some).collect();
+        for track in tracks {
+            let previous_child = previous_children
+                .iter_mut()
+                .find(|child| {
+                    child
+                        .as_ref()
+                        .is_some_and(|child| child.track_id == track.id())
+                })
+                .and_then(Option::take);
+            children.push(match previous_child {
+                Some(child) if child.is_up_to_date(track) => child,
+                Some(child) => child.rebuild(track),
+                None => ActiveTrack::from(track),
+            });
+        }
+
+        // any previous children left over belong to removed tracks and are
+        // dropped
+    }
+
     /// Helper function for `SequenceTree::update_recursive`. Applies updates
     /// to an active effect track within the sequence tree, as dictated by the
     /// specific effect implementation. Does not recurse.
@@ -290,12 +599,28 @@ impl SequenceTree {
         current_active_track: &mut ActiveEffectTrack,
         current_time: f64,
         common_info: &EffectUpdateCommonInfo,
+        effect_init_info: &EffectInfo,
         effect_keyframes: &Keyframes,
+        track_info: &TrackInfo,
     ) {
+        let step = TimeStep::between(
+            current_active_track.last_update,
+            current_time,
+            common_info.global_time,
+            common_info.playback_rate,
+        );
+        current_active_track.last_update = Some((current_time, common_info.global_time));
+
         // Let the effect implementation itself decide how to update.
-        current_active_track
-            .current_info
-            .update(effect_keyframes, current_time, common_info);
+        current_active_track.current_info.update(
+            effect_init_info,
+            effect_keyframes,
+            track_info,
+            &mut current_active_track.modulation,
+            current_time,
+            step,
+            common_info,
+        );
 
         // No need to recurse!
     }
@@ -308,105 +633,260 @@ impl SequenceTree {
         clips: &Vec<Clip>,
         current_active_track: &mut ActiveSequenceTrack,
         current_time: f64,
+        fired_triggers: &[TrackId],
         common_info: &EffectUpdateCommonInfo,
     ) {
-        let current_clip = clips.find_current(current_time);
-        match current_clip {
-            Some(current_clip) => {
-                match &mut current_active_track.child {
-                    Some((time_segment, active_sequence)) => {
-                        // the clips are already guaranteed to be on the same
-                        // track, so only the time segment needs to be checked
-                        if *time_segment != current_clip.time_segment {
-                            // there is already a clip but it is the wrong one, so swap it out
-                            *active_sequence = ActiveSequence::default();
-                        }
-                    }
-                    None => {
-                        // there was no clip, so make a new one
-                        current_active_track.child =
-                            Some((current_clip.time_segment, ActiveSequence::default()));
-                    }
-                }
+        let ActiveSequenceTrack {
+            children,
+            active_clips,
+            previous_children,
+        } = current_active_track;
+        std::mem::swap(children, previous_children);
 
-                // now that the child has been updated, recurse on it
-                let (time_segment, next_active_sequence) = current_active_track
-                    .child
-                    .as_mut()
-                    .expect("ActiveSequenceTrack child somehow does not exist after creation");
-                SequenceTree::update_recursive_sequence(
-                    sequence_store,
-                    current_clip.sequence_handle,
-                    next_active_sequence,
-                    current_time - time_segment.start_time + time_segment.start_offset,
-                    common_info,
-                );
-            }
-            // no further checks needed
-            None => current_active_track.child = None,
+        clips.find_active(current_time, common_info.tempo_map, active_clips);
+        for &(clip_i, weight) in active_clips.iter() {
+            let current_clip = &clips[clip_i];
+            let active_sequence = match previous_children
+                .iter()
+                .position(|child| child.clip_id == current_clip.id())
+            {
+                Some(child_i) => previous_children.swap_remove(child_i).active_sequence,
+                // there was no such clip, so make a new one
+                None => ActiveSequence::default(),
+            };
+
+            let mut active_clip = ActiveClip {
+                clip_id: current_clip.id(),
+                weight,
+                active_sequence,
+            };
+
+            // now that the child has been updated, recurse on it
+            let Some(next_sequence) = sequence_store.get(current_clip.sequence_handle) else {
+                panic!("encountered sequence that does not exist while updating sequence tree");
+            };
+            SequenceTree::update_recursive_sequence(
+                sequence_store,
+                current_clip.sequence_handle,
+                &mut active_clip.active_sequence,
+                current_clip.sequence_time(current_time, common_info.tempo_map, next_sequence),
+                fired_triggers,
+                &EffectUpdateCommonInfo {
+                    tempo_map: &next_sequence.tempo_map,
+                    playback_rate: common_info.playback_rate
+                        * current_clip.playback_rate(
+                            current_time,
+                            common_info.tempo_map,
+                            next_sequence,
+                        ),
+                    ..*common_info
+                },
+            );
+
+            children.push(active_clip);
+        }
+
+        // any previous children left over are no longer playing and are dropped
+        previous_children.clear();
+    }
+
+    /// Helper function for `SequenceTree::update_recursive`. Drops any expired
+    /// instances of an active trigger track, spawns a new instance if the
+    /// track was fired this cycle, and then recursively updates every
+    /// remaining instance. Instances are timed against global time, not the
+    /// parent's playback head.
+    fn update_recursive_trigger_track(
+        sequence_store: &SimpleStore<Sequence>,
+        sequence_handle: SimpleHandle<Sequence>,
+        current_active_track: &mut ActiveTriggerTrack,
+        was_fired: bool,
+        fired_triggers: &[TrackId],
+        common_info: &EffectUpdateCommonInfo,
+    ) {
+        let global_time = common_info.global_time;
+
+        current_active_track
+            .instances
+            .retain(|instance| instance.expires_at > global_time);
+
+        let sequence = sequence_store.get(sequence_handle);
+        if was_fired {
+            let Some(sequence) = sequence else {
+                panic!("encountered sequence that does not exist while firing trigger track");
+            };
+            current_active_track.instances.push(PastTrigger {
+                triggered_at: global_time,
+                expires_at: global_time + sequence.length,
+                active_sequence: ActiveSequence::default(),
+            });
+        }
+
+        for instance in &mut current_active_track.instances {
+            let Some(sequence) = sequence else {
+                panic!("encountered sequence that does not exist while updating sequence tree");
+            };
+            SequenceTree::update_recursive_sequence(
+                sequence_store,
+                sequence_handle,
+                &mut instance.active_sequence,
+                global_time - instance.triggered_at,
+                fired_triggers,
+                &EffectUpdateCommonInfo {
+                    tempo_map: &sequence.tempo_map,
+                    playback_rate: 1.0,
+                    ..*common_info
+                },
+            );
         }
     }
 
-    /// Recursively retreives a set of values for fixtures. Batches the whole
+    /// Recursively retrieves a set of values for fixtures. Batches the whole
     /// set together for performance improvement. Does not ask for unrelated
     /// information if a fixture does not need it. Assumes that the sequence
     /// tree is up to date (`SequenceTree::update_recursive` has been called).
     /// The number of responses returned will equal the number of requests
     /// passed in.
-    pub fn get_values_recursive(&self, fixtures: &[FixtureRequest]) -> Vec<FixtureResponse> {
-        // can be ignored if there is no set primary node
-        if let Some(primary_node) = &self.primary_node {
-            SequenceTree::get_values_recursive_sequence(primary_node, fixtures)
+    ///
+    /// Every fixture is evaluated independently, so large sets of fixtures are
+    /// split into chunks that are evaluated in parallel on the compute task
+    /// pool. All intermediate values are written into buffers held by the
+    /// `TreeEvaluator`, so once it has warmed up, evaluation does not allocate
+    /// (as long as the tree's shape and the number of fixtures stay the same).
+    pub fn get_values_recursive<'a>(
+        &self,
+        fixtures: &[FixtureRequest],
+        evaluator: &'a mut TreeEvaluator,
+    ) -> &'a [FixtureResponse] {
+        let TreeEvaluator {
+            responses,
+            chunk_scratch,
+            parallel,
+        } = evaluator;
+        responses.clear();
+        responses.extend(fixtures.iter().map(FixtureRequest::default_response));
+
+        // can be ignored if there is nothing playing or nothing to light
+        if (self.primary_node.is_none() && self.cue_nodes.is_empty()) || fixtures.is_empty() {
+            return responses;
+        }
+
+        let chunk_size = if *parallel {
+            PARALLEL_CHUNK_SIZE
         } else {
-            fixtures
-                .iter()
-                .map(FixtureRequest::default_response)
-                .collect()
+            fixtures.len().max(1)
+        };
+        let chunk_count = fixtures.len().div_ceil(chunk_size);
+        if chunk_scratch.len() < chunk_count {
+            chunk_scratch.resize_with(chunk_count, EvaluationScratch::default);
         }
+
+        if chunk_count <= 1 {
+            // not worth spinning up tasks for
+            self.get_values_recursive_roots(fixtures, responses, &mut chunk_scratch[0]);
+        } else {
+            ComputeTaskPool::get_or_init(TaskPool::default).scope(|scope| {
+                for ((fixtures, responses), scratch) in fixtures
+                    .chunks(chunk_size)
+                    .zip(responses.chunks_mut(chunk_size))
+                    .zip(chunk_scratch.iter_mut())
+                {
+                    scope.spawn(async move {
+                        self.get_values_recursive_roots(fixtures, responses, scratch);
+                    });
+                }
+            });
+        }
+
+        responses
+    }
+
+    /// Helper function for `SequenceTree::get_values_recursive` that evaluates
+    /// the roots of the tree for a chunk of fixtures. Live cues are added on
+    /// top of the primary sequence, each according to the level of its fades,
+    /// which crossfades between cues.
+    fn get_values_recursive_roots(
+        &self,
+        fixtures: &[FixtureRequest],
+        output: &mut [FixtureResponse],
+        scratch: &mut EvaluationScratch,
+    ) {
+        if let Some(primary_node) = &self.primary_node {
+            SequenceTree::get_values_recursive_sequence(primary_node, fixtures, output, scratch);
+        }
+        if self.cue_nodes.is_empty() {
+            return;
+        }
+
+        let mut new_values = scratch.take(fixtures.len());
+        for cue_node in &self.cue_nodes {
+            SequenceTree::get_values_recursive_sequence(
+                &cue_node.active_sequence,
+                fixtures,
+                &mut new_values,
+                scratch,
+            );
+            for (existing_val, new_val) in output.iter_mut().zip(new_values.iter()) {
+                existing_val.merge_in_place(new_val, cue_node.weight, BlendingMode::Add);
+            }
+        }
+        scratch.give_back(new_values);
     }
 
     /// Helper function for `SequenceTree::get_values_recursive` that recurses
     /// over all tracks within a sequence, collects the data they provide for
     /// each request, and merges them together according to factor and blend
-    /// mode.
+    /// mode. Results are written to `output`, which must be as long as
+    /// `fixtures`.
     fn get_values_recursive_sequence(
         current_active_sequence: &ActiveSequence,
         fixtures: &[FixtureRequest],
-    ) -> Vec<FixtureResponse> {
-        let mut final_inputs: Vec<FixtureResponse> = fixtures
-            .iter()
-            .map(FixtureRequest::default_response)
-            .collect();
+        output: &mut [FixtureResponse],
+        scratch: &mut EvaluationScratch,
+    ) {
+        for (response, fixture) in output.iter_mut().zip(fixtures) {
+            *response = fixture.default_response();
+        }
 
+        let mut new_values = scratch.take(fixtures.len());
         for active_track in &current_active_sequence.children {
-            let new_values = match &active_track.contents {
+            match &active_track.contents {
                 ActiveTrackContents::ActiveEffectTrack(active_effect_track) => {
-                    SequenceTree::get_values_recursive_effect_track(active_effect_track, fixtures)
+                    SequenceTree::get_values_recursive_effect_track(
+                        active_effect_track,
+                        fixtures,
+                        &mut new_values,
+                        scratch,
+                    )
                 }
                 ActiveTrackContents::ActiveSequenceTrack(active_sequence_track) => {
                     SequenceTree::get_values_recursive_sequence_track(
                         active_sequence_track,
                         fixtures,
+                        &mut new_values,
+                        scratch,
                     )
                 }
                 ActiveTrackContents::ActiveTriggerTrack(active_trigger_track) => {
-                    // TODO: implement trigger tracks
-                    fixtures
-                        .iter()
-                        .map(FixtureRequest::default_response)
-                        .collect()
+                    SequenceTree::get_values_recursive_trigger_track(
+                        active_trigger_track,
+                        active_track.blending_mode,
+                        fixtures,
+                        &mut new_values,
+                        scratch,
+                    )
                 }
             };
-            for (existing_val, new_val) in final_inputs.iter_mut().zip(new_values.iter()) {
+            for ((existing_val, new_val), fixture) in
+                output.iter_mut().zip(new_values.iter()).zip(fixtures)
+            {
                 existing_val.merge_in_place(
-                    &new_val,
-                    active_track.factor,
+                    new_val,
+                    active_track.factor_at(fixture.position),
                     active_track.blending_mode,
                 );
             }
         }
-
-        final_inputs
+        scratch.give_back(new_values);
     }
 
     /// Helper function for `SequenceTree::get_values_recursive` that retrieves
@@ -415,52 +895,194 @@ impl SequenceTree {
     fn get_values_recursive_effect_track(
         current_active_track: &ActiveEffectTrack,
         fixtures: &[FixtureRequest],
-    ) -> Vec<FixtureResponse> {
-        let mut final_inputs: Vec<FixtureResponse> = Vec::new();
+        output: &mut [FixtureResponse],
+        scratch: &mut EvaluationScratch,
+    ) {
+        if current_active_track.modulation.has_spread() {
+            // every fixture has its own phase, so the modulated parameters are
+            // worked out again for each of them, in a copy of the effect
+            let effect_info = scratch.effect(&current_active_track.current_info);
+            for (response, fixture) in output.iter_mut().zip(fixtures) {
+                effect_info.apply_modulation(&current_active_track.modulation, fixture.position);
+                *response = match &*effect_info {
+                    EffectInfo::ColorEffectInfo(color_effect) if fixture.has_color => {
+                        FixtureResponse::color_only(color_effect.get_value(fixture.position))
+                    }
+                    EffectInfo::PanTiltEffectInfo(pan_tilt_effect) if fixture.has_pan_tilt => {
+                        FixtureResponse::pan_tilt_only(pan_tilt_effect.get_value(fixture.position))
+                    }
+                    _ => FixtureResponse::default(),
+                };
+            }
+            return;
+        }
 
         match &current_active_track.current_info {
             EffectInfo::ColorEffectInfo(color_effect) => {
-                for fixture in fixtures {
-                    if fixture.has_color {
-                        let output = color_effect.get_value(fixture.position);
-                        final_inputs.push(FixtureResponse::color_only(output));
+                for (response, fixture) in output.iter_mut().zip(fixtures) {
+                    *response = if fixture.has_color {
+                        FixtureResponse::color_only(color_effect.get_value(fixture.position))
                     } else {
-                        final_inputs.push(FixtureResponse::default())
-                    }
+                        FixtureResponse::default()
+                    };
                 }
             }
             EffectInfo::PanTiltEffectInfo(pan_tilt_effect) => {
-                for fixture in fixtures {
-                    if fixture.has_pan_tilt {
-                        let output = pan_tilt_effect.get_value(fixture.position);
-                        final_inputs.push(FixtureResponse::pan_tilt_only(output));
+                for (response, fixture) in output.iter_mut().zip(fixtures) {
+                    *response = if fixture.has_pan_tilt {
+                        FixtureResponse::pan_tilt_only(pan_tilt_effect.get_value(fixture.position))
                     } else {
-                        final_inputs.push(FixtureResponse::default())
-                    }
+                        FixtureResponse::default()
+                    };
                 }
             }
         }
 
-        final_inputs
         // No need to recurse!
     }
 
     /// Helper function for `SequenceTree::get_values_recursive` that retrieves
-    /// the value from inside a sequence track if there is currently an active
-    /// clip playing inside of it, default otherwise.
+    /// the values from inside a sequence track for every clip currently
+    /// playing inside of it, default otherwise. Clips are summed according to
+    /// their fade weights, which crossfades between overlapping clips.
     fn get_values_recursive_sequence_track(
         current_active_track: &ActiveSequenceTrack,
         fixtures: &[FixtureRequest],
-    ) -> Vec<FixtureResponse> {
-        match &current_active_track.child {
-            Some((_time_segment, active_sequence)) => {
-                SequenceTree::get_values_recursive_sequence(active_sequence, fixtures)
+        output: &mut [FixtureResponse],
+        scratch: &mut EvaluationScratch,
+    ) {
+        for (response, fixture) in output.iter_mut().zip(fixtures) {
+            *response = fixture.default_response();
+        }
+
+        let mut new_values = scratch.take(fixtures.len());
+        for child in &current_active_track.children {
+            SequenceTree::get_values_recursive_sequence(
+                &child.active_sequence,
+                fixtures,
+                &mut new_values,
+                scratch,
+            );
+            for (existing_val, new_val) in output.iter_mut().zip(new_values.iter()) {
+                existing_val.merge_in_place(new_val, child.weight, BlendingMode::Add);
             }
-            None => fixtures
-                .iter()
-                .map(FixtureRequest::default_response)
-                .collect(),
         }
+        scratch.give_back(new_values);
+    }
+
+    /// Helper function for `SequenceTree::get_values_recursive` that retrieves
+    /// the values from every live instance of a trigger track and blends them
+    /// together using the track's blending mode, so overlapping hits layer on
+    /// top of each other. The first instance starts the layering rather than
+    /// being blended into the defaults. Returns defaults if nothing is
+    /// currently playing.
+    fn get_values_recursive_trigger_track(
+        current_active_track: &ActiveTriggerTrack,
+        blending_mode: BlendingMode,
+        fixtures: &[FixtureRequest],
+        output: &mut [FixtureResponse],
+        scratch: &mut EvaluationScratch,
+    ) {
+        let mut instances = current_active_track.instances.iter();
+        let Some(first_instance) = instances.next() else {
+            for (response, fixture) in output.iter_mut().zip(fixtures) {
+                *response = fixture.default_response();
+            }
+            return;
+        };
+        SequenceTree::get_values_recursive_sequence(
+            &first_instance.active_sequence,
+            fixtures,
+            output,
+            scratch,
+        );
+
+        let mut new_values = scratch.take(fixtures.len());
+        for instance in instances {
+            SequenceTree::get_values_recursive_sequence(
+                &instance.active_sequence,
+                fixtures,
+                &mut new_values,
+                scratch,
+            );
+            for (existing_val, new_val) in output.iter_mut().zip(new_values.iter()) {
+                existing_val.merge_in_place(new_val, 1.0, blending_mode);
+            }
+        }
+        scratch.give_back(new_values);
+    }
+}
+
+/// Number of fixtures evaluated per parallel task. Sets of fixtures at most
+/// this large are evaluated on the calling thread.
+const PARALLEL_CHUNK_SIZE: usize = 256;
+
+/// Reusable buffers for evaluating the sequence tree (see
+/// `SequenceTree::get_values_recursive`). Holds the responses, plus scratch
+/// space for every chunk of fixtures evaluated in parallel. Should be kept
+/// around between updates (e.g. in a `Local`) so the buffers can be reused.
+#[derive(Debug)]
+pub struct TreeEvaluator {
+    responses: Vec<FixtureResponse>,
+    chunk_scratch: Vec<EvaluationScratch>,
+    parallel: bool,
+}
+
+impl Default for TreeEvaluator {
+    fn default() -> Self {
+        Self {
+            responses: Vec::new(),
+            chunk_scratch: Vec::new(),
+            parallel: true,
+        }
+    }
+}
+
+impl TreeEvaluator {
+    /// Constructs a new `TreeEvaluator` that evaluates in parallel.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a new `TreeEvaluator` that evaluates every fixture on the
+    /// calling thread.
+    pub fn single_threaded() -> Self {
+        Self {
+            parallel: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// Stack of intermediate value buffers used while recursing through the tree.
+/// Each level of the tree takes a buffer for the values of its children and
+/// gives it back once it has merged them, so the stack only ever grows as deep
+/// as the tree.
+///
+/// Also holds the copy that effects with modulators spread across fixtures
+/// are modulated in for each fixture, so that its buffers are reused.
+#[derive(Debug, Default)]
+struct EvaluationScratch {
+    buffers: Vec<Vec<FixtureResponse>>,
+    effect: Option<EffectInfo>,
+}
+
+impl EvaluationScratch {
+    fn take(&mut self, len: usize) -> Vec<FixtureResponse> {
+        let mut buffer = self.buffers.pop().unwrap_or_default();
+        buffer.resize(len, FixtureResponse::default());
+        buffer
+    }
+
+    fn give_back(&mut self, buffer: Vec<FixtureResponse>) {
+        self.buffers.push(buffer);
+    }
+
+    /// Gets the scratch effect as a copy of `effect_info`.
+    fn effect(&mut self, effect_info: &EffectInfo) -> &mut EffectInfo {
+        let effect = self.effect.get_or_insert_with(|| effect_info.clone());
+        effect.copy_from(effect_info);
+        effect
     }
 }
 
@@ -474,30 +1096,60 @@ fn clear_sequence_tree(_reset: On<ClearSequenceTree>, mut sequence_tree: ResMut<
     sequence_tree.clear();
 }
 
+/// Bevy event that fires a trigger track, spawning a new instance of its
+/// sequence in every active copy of that track during the next update cycle.
+#[derive(Event)]
+pub struct FireTriggerTrack {
+    pub track: TrackId,
+}
+
+/// Bevy observer that listens for `FireTriggerTrack` events and queues the
+/// referenced trigger track to be fired on the sequence tree.
+fn fire_trigger_track(fire: On<FireTriggerTrack>, mut sequence_tree: ResMut<SequenceTree>) {
+    sequence_tree.fire_trigger(fire.track);
+}
+
 /// Bevy system that updates the sequence tree, keeping both structure and
 /// effect values up to date. See `SequenceTree::update_recursive` for more
-/// information; this is basically just a wrapper around that.
+/// information; this is basically just a wrapper around that. While playback
+/// is blacked out, the primary sequence is left out of the tree, so only live
+/// cues are shown.
 fn update_sequence_tree(
     time: Res<Time>,
     sequence_store: Res<SimpleStore<Sequence>>,
     primary_sequence: Res<PrimarySequence>,
     mut sequence_tree: ResMut<SequenceTree>,
     playback_info: Res<PlaybackInformation>,
+    cue_playback: Res<CuePlayback>,
     recent_fft_data: Res<RecentFftData>,
 ) {
-    let Some(primary_sequence_handle) = primary_sequence.0 else {
-        return;
-    };
+    let primary_sequence_handle = primary_sequence
+        .0
+        .filter(|handle| sequence_store.get(*handle).is_some())
+        .filter(|_| !playback_info.blacked_out);
+    // an external clock can set the tempo the primary sequence plays at
+    let default_tempo_map = TempoMap::default();
+    let tempo_map = primary_sequence_handle
+        .and_then(|handle| sequence_store.get(handle))
+        .map_or(&default_tempo_map, |sequence| {
+            playback_info
+                .external_tempo
+                .as_ref()
+                .unwrap_or(&sequence.tempo_map)
+        });
 
     let common_info = EffectUpdateCommonInfo {
         recent_fft_data: &recent_fft_data,
         global_time: time.elapsed_secs_f64(),
+        tempo_map,
+        playback_rate: 1.0,
     };
 
     sequence_tree.update_recursive(
         &sequence_store,
         primary_sequence_handle,
         playback_info.current_time,
+        cue_playback.live_cues(),
         &common_info,
     );
 }
@@ -1,8 +1,18 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt,
+    path::PathBuf,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use crate::{
     simple_store::{SimpleHandle, SimpleStore},
-    timeline::tracks::Track,
+    timeline::{
+        positions::{TempoMap, TimelinePosition},
+        tracks::Track,
+    },
 };
 
 /// Bevy plugin for sequences.
@@ -22,10 +32,351 @@ pub struct PrimarySequence(pub Option<SimpleHandle<Sequence>>);
 
 /// Primary representation of visuals. Along with metadata, contains a list of
 /// `Track`s that allow for effects to be added and for sequences to be nested
-/// within each other.
+/// within each other. The tempo map defines the beat grid of the sequence,
+/// which any musical keyframe and clip times within it are resolved through.
+/// The loop region marks a section to repeat during rehearsal (see
+/// `LoopRegion`), and the audio is the song that plays along (see
+/// `SequenceAudio`).
+///
+/// The `id` refers to the sequence in show files, and stays the same across
+/// saving and loading (see `SequenceId`).
 #[derive(Debug)]
 pub struct Sequence {
+    pub id: SequenceId,
     pub name: String,
     pub length: f64,
+    pub tempo_map: TempoMap,
     pub tracks: Vec<Track>,
+    pub loop_region: Option<LoopRegion>,
+    pub audio: Option<SequenceAudio>,
+}
+
+/// Stable identifier for a sequence within a show file. In-memory
+/// `SimpleHandle`s are only meaningful for the lifetime of the program, so all
+/// references between sequences are remapped to these on save and back to
+/// fresh handles on load. Sequences keep their id from the file they were
+/// loaded from, so ids don't change as sequences are added, removed, or
+/// reordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SequenceId(pub u32);
+
+/// The lowest id that hasn't been handed out or loaded yet.
+static NEXT_SEQUENCE_ID: AtomicU32 = AtomicU32::new(0);
+
+impl SequenceId {
+    /// Constructs a new, never before used `SequenceId`.
+    pub fn fresh() -> Self {
+        Self(NEXT_SEQUENCE_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Marks the id as used, so that new ids won't collide with it. Called for
+    /// ids loaded from show files.
+    pub fn reserve(self) {
+        NEXT_SEQUENCE_ID.fetch_max(self.0.saturating_add(1), Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for SequenceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// An audio file attached to a sequence, usually the song a show is
+/// programmed to. It plays in sync with the playback head while the sequence
+/// is the primary sequence, starting `offset` seconds into the sequence. A
+/// negative offset skips the start of the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SequenceAudio {
+    pub path: PathBuf,
+    pub offset: f64,
+}
+
+impl SequenceAudio {
+    /// Constructs a new `SequenceAudio` that starts along with the sequence.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            offset: 0.0,
+        }
+    }
+}
+
+/// Section of a sequence, marked by a start and an end position, that
+/// playback keeps repeating while looping is enabled (see
+/// `PlaybackInformation::loop_region_enabled`). Used to rehearse one part of
+/// a show over and over. Both positions are resolved through the tempo map of
+/// the sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LoopRegion {
+    pub start: TimelinePosition,
+    pub end: TimelinePosition,
+}
+
+impl LoopRegion {
+    /// Constructs a new `LoopRegion`.
+    pub fn new(start: impl Into<TimelinePosition>, end: impl Into<TimelinePosition>) -> Self {
+        LoopRegion {
+            start: start.into(),
+            end: end.into(),
+        }
+    }
+
+    /// Resolves the start and end of the region into seconds, clamped to the
+    /// length of the sequence. Returns `None` if nothing is left of the region
+    /// after clamping.
+    pub fn seconds(&self, sequence: &Sequence) -> Option<(f64, f64)> {
+        let start = self
+            .start
+            .to_seconds(&sequence.tempo_map)
+            .clamp(0.0, sequence.length);
+        let end = self
+            .end
+            .to_seconds(&sequence.tempo_map)
+            .clamp(0.0, sequence.length);
+        (end > start).then_some((start, end))
+    }
+}
+
+impl Sequence {
+    /// Iterates over the handles of every sequence nested directly within this
+    /// sequence, through any of its tracks.
+    pub fn referenced_sequences(&self) -> impl Iterator<Item = SimpleHandle<Sequence>> + '_ {
+        self.tracks
+            .iter()
+            .flat_map(|track| track.contents.referenced_sequences())
+    }
+}
+
+/// A loop of sequences that nest each other, either through clips or trigger
+/// tracks. Playing any sequence within the loop would instantiate it inside of
+/// itself forever, so edits and show files containing one are refused. The
+/// path starts and ends with the same sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceCycle {
+    pub path: Vec<SimpleHandle<Sequence>>,
+    pub names: Vec<String>,
+}
+
+impl SequenceCycle {
+    fn new(sequence_store: &SimpleStore<Sequence>, path: Vec<SimpleHandle<Sequence>>) -> Self {
+        let names = path
+            .iter()
+            .map(|handle| {
+                sequence_store
+                    .get(*handle)
+                    .map_or_else(|| "<missing>".to_string(), |sequence| sequence.name.clone())
+            })
+            .collect();
+        SequenceCycle { path, names }
+    }
+}
+
+impl fmt::Display for SequenceCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sequences nest each other in a cycle: ")?;
+        for (name_i, name) in self.names.iter().enumerate() {
+            if name_i > 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "\"{}\"", name)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SequenceCycle {}
+
+/// Checks whether nesting `child` within `parent` (by adding a clip or
+/// trigger track referencing it) would create a cycle, i.e. whether `parent`
+/// is `child` or already nested somewhere within it. Returns the cycle that
+/// would be created if so.
+pub fn check_nesting(
+    sequence_store: &SimpleStore<Sequence>,
+    parent: SimpleHandle<Sequence>,
+    child: SimpleHandle<Sequence>,
+) -> Result<(), SequenceCycle> {
+    if child == parent {
+        return Err(SequenceCycle::new(sequence_store, vec![parent, child]));
+    }
+
+    let mut visited = HashSet::from([child]);
+    // depth first search, where the stack always holds the path from the child
+    // to the sequence currently being visited
+    let mut stack = vec![(child, nested_sequences(sequence_store, child))];
+
+    while let Some((_, remaining)) = stack.last_mut() {
+        let Some(next) = remaining.pop() else {
+            stack.pop();
+            continue;
+        };
+        if next == parent {
+            let path = std::iter::once(parent)
+                .chain(stack.iter().map(|(handle, _)| *handle))
+                .chain(std::iter::once(parent))
+                .collect();
+            return Err(SequenceCycle::new(sequence_store, path));
+        }
+        if visited.insert(next) {
+            stack.push((next, nested_sequences(sequence_store, next)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Searches every sequence in the store for a cycle of nested sequences (see
+/// `SequenceCycle`), returning the first one found.
+pub fn find_sequence_cycle(sequence_store: &SimpleStore<Sequence>) -> Result<(), SequenceCycle> {
+    // sequences whose nested sequences have all been searched without
+    // finding a cycle
+    let mut finished = HashSet::new();
+
+    for (root, _) in sequence_store.iter() {
+        if finished.contains(&root) {
+            continue;
+        }
+
+        // depth first search, where the stack always holds the path from the
+        // root to the sequence currently being visited
+        let mut stack = vec![(root, nested_sequences(sequence_store, root))];
+        while let Some((current, remaining)) = stack.last_mut() {
+            let current = *current;
+            let Some(next) = remaining.pop() else {
+                finished.insert(current);
+                stack.pop();
+                continue;
+            };
+            if finished.contains(&next) {
+                continue;
+            }
+            if let Some(start) = stack.iter().position(|(handle, _)| *handle == next) {
+                let path = stack[start..]
+                    .iter()
+                    .map(|(handle, _)| *handle)
+                    .chain(std::iter::once(next))
+                    .collect();
+                return Err(SequenceCycle::new(sequence_store, path));
+            }
+            stack.push((next, nested_sequences(sequence_store, next)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Helper function for the cycle searches. Collects the sequences nested
+/// directly within a sequence, in reverse so they can be popped off in order.
+/// Sequences that don't exist have nothing nested within them.
+fn nested_sequences(
+    sequence_store: &SimpleStore<Sequence>,
+    handle: SimpleHandle<Sequence>,
+) -> Vec<SimpleHandle<Sequence>> {
+    let mut nested: Vec<SimpleHandle<Sequence>> = sequence_store
+        .get(handle)
+        .map(|sequence| sequence.referenced_sequences().collect())
+        .unwrap_or_default();
+    nested.reverse();
+    nested
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        timeline::{
+            keyframes::Keyframes,
+            tracks::{TrackContents, TrackInfo},
+        },
+        util::blending::BlendingMode,
+    };
+
+    fn add_sequence(
+        sequence_store: &mut SimpleStore<Sequence>,
+        name: &str,
+    ) -> SimpleHandle<Sequence> {
+        sequence_store.add(Sequence {
+            id: SequenceId::fresh(),
+            name: name.into(),
+            length: 1.0,
+            tempo_map: TempoMap::default(),
+            tracks: Vec::new(),
+            loop_region: None,
+            audio: None,
+        })
+    }
+
+    /// Nests `child` within `parent` through a trigger track, without checking
+    /// for cycles.
+    fn nest(
+        sequence_store: &mut SimpleStore<Sequence>,
+        parent: SimpleHandle<Sequence>,
+        child: SimpleHandle<Sequence>,
+    ) {
+        let track = Track::new(
+            TrackInfo {
+                blending_mode: BlendingMode::default(),
+                factor: 1.0,
+                track_keyframes: Keyframes::default(),
+                modulators: Vec::new(),
+                audio_bindings: Vec::new(),
+            },
+            TrackContents::TriggerTrack {
+                sequence_handle: child,
+            },
+        );
+        sequence_store.get_mut(parent).unwrap().tracks.push(track);
+    }
+
+    #[test]
+    fn nesting_refuses_direct_and_indirect_cycles() {
+        let mut sequence_store = SimpleStore::default();
+        let a = add_sequence(&mut sequence_store, "A");
+        let b = add_sequence(&mut sequence_store, "B");
+        let c = add_sequence(&mut sequence_store, "C");
+        nest(&mut sequence_store, a, b);
+        nest(&mut sequence_store, b, c);
+
+        let cycle = check_nesting(&sequence_store, a, a).unwrap_err();
+        assert_eq!(cycle.path, vec![a, a]);
+
+        let cycle = check_nesting(&sequence_store, b, a).unwrap_err();
+        assert_eq!(cycle.path, vec![b, a, b]);
+
+        let cycle = check_nesting(&sequence_store, c, a).unwrap_err();
+        assert_eq!(cycle.path, vec![c, a, b, c]);
+        assert_eq!(
+            cycle.to_string(),
+            "sequences nest each other in a cycle: \"C\" -> \"A\" -> \"B\" -> \"C\""
+        );
+
+        // nesting the same sequence twice, or along another path, is fine
+        assert!(check_nesting(&sequence_store, a, c).is_ok());
+        assert!(check_nesting(&sequence_store, a, b).is_ok());
+    }
+
+    #[test]
+    fn finds_direct_and_indirect_cycles_in_the_store() {
+        let mut sequence_store = SimpleStore::default();
+        let a = add_sequence(&mut sequence_store, "A");
+        let b = add_sequence(&mut sequence_store, "B");
+        let c = add_sequence(&mut sequence_store, "C");
+        nest(&mut sequence_store, a, b);
+        nest(&mut sequence_store, b, c);
+        nest(&mut sequence_store, a, c);
+        assert!(find_sequence_cycle(&sequence_store).is_ok());
+
+        nest(&mut sequence_store, c, a);
+        let cycle = find_sequence_cycle(&sequence_store).unwrap_err();
+        assert_eq!(cycle.path, vec![a, b, c, a]);
+
+        let mut sequence_store = SimpleStore::default();
+        let a = add_sequence(&mut sequence_store, "A");
+        let b = add_sequence(&mut sequence_store, "B");
+        nest(&mut sequence_store, a, b);
+        nest(&mut sequence_store, b, b);
+        let cycle = find_sequence_cycle(&sequence_store).unwrap_err();
+        assert_eq!(cycle.path, vec![b, b]);
+    }
 }
@@ -1,8 +1,17 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::{
     simple_store::SimpleHandle,
-    timeline::{effects::EffectInfo, keyframes::Keyframes, sequences::Sequence},
+    timeline::{
+        audio_bindings::AudioBinding,
+        effects::EffectInfo,
+        keyframes::Keyframes,
+        modulators::Modulator,
+        positions::{TempoMap, TimelinePosition},
+        sequences::Sequence,
+    },
     util::blending::BlendingMode,
 };
 
@@ -13,19 +22,100 @@ use crate::{
 ///
 /// Tracks can be one of three different types, depending on the variant of
 /// `contents`. See `TrackContents` docs for more information.
+///
+/// Every track has an `id` that stays the same for as long as it exists, and
+/// a revision that changes whenever its contents are replaced in a way that
+/// `ActiveTrack`s need to be rebuilt for (see `Track::mark_changed`). These
+/// let the sequence tree follow tracks being added, removed, or reordered
+/// without losing the state of the others.
 #[derive(Debug)]
 pub struct Track {
+    id: TrackId,
+    revision: u64,
     pub info: TrackInfo,
     pub contents: TrackContents,
 }
 
+impl Track {
+    /// Constructs a new `Track` with a fresh id.
+    pub fn new(info: TrackInfo, contents: TrackContents) -> Self {
+        Self {
+            id: TrackId::new(),
+            revision: 0,
+            info,
+            contents,
+        }
+    }
+
+    pub fn id(&self) -> TrackId {
+        self.id
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Marks the contents of the track as changed, so that any active
+    /// instances of it are rebuilt during the next update cycle. Should be
+    /// called whenever the effect init info is replaced, since active effect
+    /// tracks work on their own copy of it. Changes to keyframes and clips are
+    /// picked up without this.
+    pub fn mark_changed(&mut self) {
+        self.revision += 1;
+    }
+}
+
+/// Unique identifier of a `Track`, assigned when the track is constructed.
+/// Not saved to show files; tracks simply get new ids when loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TrackId(u64);
+
+impl TrackId {
+    /// Constructs a new, never before used `TrackId`.
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Unique identifier of a `Clip`, assigned when the clip is constructed. Not
+/// saved to show files, just like `TrackId`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClipId(u64);
+
+impl ClipId {
+    /// Constructs a new, never before used `ClipId`.
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 /// Holds generic information about a track. Currently contains blending
 /// mode, factor, and keyframes that modify any supported track values.
+///
+/// `track_keyframes` are evaluated in the parent sequence's time and support
+/// the keys `"factor"` (float) and `"blending_mode"` (blending mode). Values
+/// without keyframes fall back to the fields set here.
+///
+/// `modulators` move the factor or the parameters of the track's effect on top
+/// of their keyframes (see `Modulator`), and `audio_bindings` make them react
+/// to the incoming audio (see `AudioBinding`).
 #[derive(Debug)]
 pub struct TrackInfo {
     pub blending_mode: BlendingMode,
     pub factor: f32,
     pub track_keyframes: Keyframes,
+    pub modulators: Vec<Modulator>,
+    pub audio_bindings: Vec<AudioBinding>,
+}
+
+impl TrackInfo {
+    /// Checks that track keyframes only use the keys `"factor"` (float) and
+    /// `"blending_mode"` (blending mode).
+    pub fn validate_keyframes(keyframes: &Keyframes) -> Result<(), String> {
+        keyframes.validate_keys(&[("factor", "float"), ("blending_mode", "blending mode")])
+    }
 }
 
 /// Tracks can be one of three different types, depending on the variant of
@@ -40,8 +130,10 @@ pub struct TrackInfo {
 /// selected, and the corresponding sequence will be instantiated as an
 /// `ActiveSequence` linked back to its corresponding `ActiveSequenceTrack`.
 ///
-/// - `TriggerTrack`: Unimplemented. Will allow for sequences to be spawned in
-/// on user input.
+/// - `TriggerTrack`: Contains a reference to a sequence that is spawned in as
+/// a new `ActiveSequence` every time the track is fired (see
+/// `FireTriggerTrack`). Instances overlap freely and expire once they have
+/// played for the length of the sequence.
 #[derive(Debug)]
 pub enum TrackContents {
     /// Contains an effect that plays indefinitely. The contents
@@ -56,71 +148,430 @@ pub enum TrackContents {
     /// selected, and the corresponding sequence will be instantiated as an
     /// `ActiveSequence` linked back to its corresponding `ActiveSequenceTrack`.
     SequenceTrack { clips: Vec<Clip> },
-    /// Unimplemented. Will allow for sequences to be spawned in on user input.
+    /// Contains a reference to a sequence that is spawned in as a new
+    /// `ActiveSequence` every time the track is fired. Instances overlap
+    /// freely and expire once they have played for the length of the sequence.
     TriggerTrack {
         sequence_handle: SimpleHandle<Sequence>,
     },
 }
 
+impl TrackContents {
+    /// Iterates over the handles of every sequence nested within the track,
+    /// either through its clips or as the sequence spawned by a trigger.
+    pub fn referenced_sequences(&self) -> impl Iterator<Item = SimpleHandle<Sequence>> + '_ {
+        let (clips, trigger_sequence) = match self {
+            TrackContents::EffectTrack { .. } => (&[][..], None),
+            TrackContents::SequenceTrack { clips } => (&clips[..], None),
+            TrackContents::TriggerTrack { sequence_handle } => (&[][..], Some(*sequence_handle)),
+        };
+        clips
+            .iter()
+            .map(|clip| clip.sequence_handle)
+            .chain(trigger_sequence)
+    }
+}
+
 /// Represents a small window on the timeline of a sequence. The `TimeSegment`
 /// allows for arbitrary start times, start offsets, and durations. If the
 /// playback head falls within a clip's span, it will instantiate the
-/// corresponding `Sequence` as an `ActiveSequence`.
-#[derive(Debug)]
+/// corresponding `Sequence` as an `ActiveSequence`. The `ClipPlayback`
+/// controls how time within the clip maps onto time within the sequence.
+///
+/// `fade_in` and `fade_out` shape the clip's envelope, measured from its start
+/// and end respectively and resolved through the tempo map of the track's
+/// sequence. Where clips overlap, they are crossfaded automatically over the
+/// length of the overlap (or the explicit fade, if that is longer).
+///
+/// Like tracks, every clip has an `id` that stays the same for as long as it
+/// exists (including when it is moved), so the sequence tree can tell clips
+/// apart even if they cover the same time.
+#[derive(Debug, Clone)]
 pub struct Clip {
+    id: ClipId,
     pub sequence_handle: SimpleHandle<Sequence>,
     pub time_segment: TimeSegment,
+    pub playback: ClipPlayback,
+    pub fade_in: TimelinePosition,
+    pub fade_out: TimelinePosition,
+}
+
+impl Clip {
+    /// Constructs a new `Clip` with default (normal speed, play once)
+    /// playback.
+    pub fn new(sequence_handle: SimpleHandle<Sequence>, time_segment: TimeSegment) -> Self {
+        Self {
+            id: ClipId::new(),
+            sequence_handle,
+            time_segment,
+            playback: ClipPlayback::default(),
+            fade_in: TimelinePosition::default(),
+            fade_out: TimelinePosition::default(),
+        }
+    }
+
+    pub fn id(&self) -> ClipId {
+        self.id
+    }
+
+    /// Resolves the explicit fade-in and fade-out lengths of the clip into
+    /// seconds. Musical fades are measured on the beat grid from the start
+    /// (for fade-ins) or back from the end (for fade-outs) of the clip.
+    fn fade_seconds(&self, tempo_map: &TempoMap) -> (f64, f64) {
+        let start = self.time_segment.start_seconds(tempo_map);
+        let end = self.time_segment.end_seconds(tempo_map);
+        let fade_in = self.fade_in.length_seconds_from(start, tempo_map);
+        let fade_out = self.fade_out.length_seconds_before(end, tempo_map);
+        (fade_in.max(0.0), fade_out.max(0.0))
+    }
+
+    /// Splits the clip in two at the given time within the track's sequence.
+    /// The first half keeps the fade-in and the second half the fade-out. The
+    /// first half also keeps the id, while the second half is a new clip.
+    /// Returns `None` if the time doesn't fall strictly within the clip.
+    ///
+    /// Clips that play once (forwards or in reverse) or follow a time remap
+    /// play exactly the same after the split. Looping clips restart their loop
+    /// at the split point.
+    pub fn split_at(
+        &self,
+        time: f64,
+        parent_tempo_map: &TempoMap,
+        sequence: &Sequence,
+    ) -> Option<(Clip, Clip)> {
+        let start = self.time_segment.start_seconds(parent_tempo_map);
+        let end = self.time_segment.end_seconds(parent_tempo_map);
+        if time <= start || time >= end {
+            return None;
+        }
+
+        let mut first = self.clone();
+        let mut second = self.clone();
+        second.id = ClipId::new();
+        let segment = &self.time_segment;
+        first.time_segment.duration = segment.duration.resized_to(start, time, parent_tempo_map);
+        first.fade_out = TimelinePosition::default();
+        second.time_segment.start_time = segment.start_time.moved_to(time, parent_tempo_map);
+        second.time_segment.duration = segment.duration.resized_to(time, end, parent_tempo_map);
+        second.fade_in = TimelinePosition::default();
+
+        match &self.playback.time_remap {
+            Some(time_remap) => {
+                // the curve is measured from the start of the clip, so it
+                // has to be moved back by however much the second half starts
+                // later
+                let keyframes = time_remap
+                    .inner()
+                    .iter()
+                    .cloned()
+                    .map(|mut keyframe| {
+                        let seconds = keyframe.time.to_seconds(&sequence.tempo_map);
+                        keyframe.time = TimelinePosition::Seconds(seconds - (time - start));
+                        keyframe
+                    })
+                    .collect();
+                second.playback.time_remap = Some(Keyframes::new(keyframes));
+            }
+            None if self.playback.loop_mode == ClipLoopMode::Once => {
+                let offset = segment.start_offset_seconds(&sequence.tempo_map);
+                let (first_offset, second_offset) = if self.playback.reverse {
+                    (offset + (end - time) * self.playback.speed, offset)
+                } else {
+                    (offset, offset + (time - start) * self.playback.speed)
+                };
+                first.time_segment.start_offset = segment
+                    .start_offset
+                    .moved_to(first_offset, &sequence.tempo_map);
+                second.time_segment.start_offset = segment
+                    .start_offset
+                    .moved_to(second_offset, &sequence.tempo_map);
+            }
+            None => {}
+        }
+
+        Some((first, second))
+    }
+
+    /// Maps a time within the track's sequence to the corresponding time
+    /// within the clip's sequence, applying the start time and offset as well
+    /// as any speed, time remapping, looping, and reversal set on the clip.
+    ///
+    /// `parent_tempo_map` belongs to the sequence the clip is placed in, and
+    /// `sequence` is the sequence the clip refers to.
+    pub fn sequence_time(
+        &self,
+        parent_time: f64,
+        parent_tempo_map: &TempoMap,
+        sequence: &Sequence,
+    ) -> f64 {
+        let start_offset = self.time_segment.start_offset_seconds(&sequence.tempo_map);
+        let elapsed = self.playback.remap(
+            parent_time - self.time_segment.start_seconds(parent_tempo_map),
+            &sequence.tempo_map,
+        );
+
+        // how much of the sequence the clip travels through before it repeats
+        // (or, if not looping, over its whole duration)
+        let span = match self.playback.loop_mode {
+            ClipLoopMode::Once => self.playback.remap(
+                self.time_segment.end_seconds(parent_tempo_map)
+                    - self.time_segment.start_seconds(parent_tempo_map),
+                &sequence.tempo_map,
+            ),
+            ClipLoopMode::Loop | ClipLoopMode::PingPong => match self.playback.loop_length {
+                Some(loop_length) => {
+                    loop_length.length_seconds_from(start_offset, &sequence.tempo_map)
+                }
+                None => sequence.length - start_offset,
+            },
+        };
+
+        let position = match self.playback.loop_mode {
+            ClipLoopMode::Once => elapsed,
+            ClipLoopMode::Loop if span > 0.0 => elapsed.rem_euclid(span),
+            ClipLoopMode::PingPong if span > 0.0 => {
+                let position = elapsed.rem_euclid(2.0 * span);
+                if position < span {
+                    position
+                } else {
+                    2.0 * span - position
+                }
+            }
+            // a zero-length loop just holds the start
+            ClipLoopMode::Loop | ClipLoopMode::PingPong => 0.0,
+        };
+
+        if self.playback.reverse {
+            start_offset + span - position
+        } else {
+            start_offset + position
+        }
+    }
+
+    /// How fast time within the clip's sequence moves per second of time
+    /// within the track's sequence, at the given time within the latter. This
+    /// is the speed of the clip, or the slope of its time remap curve, and is
+    /// positive whichever way the clip plays. Arguments are the same as for
+    /// `Clip::sequence_time`.
+    pub fn playback_rate(
+        &self,
+        parent_time: f64,
+        parent_tempo_map: &TempoMap,
+        sequence: &Sequence,
+    ) -> f64 {
+        let elapsed = parent_time - self.time_segment.start_seconds(parent_tempo_map);
+        match &self.playback.time_remap {
+            Some(_) => {
+                let before = self
+                    .playback
+                    .remap(elapsed - RATE_SAMPLE_WIDTH / 2.0, &sequence.tempo_map);
+                let after = self
+                    .playback
+                    .remap(elapsed + RATE_SAMPLE_WIDTH / 2.0, &sequence.tempo_map);
+                ((after - before) / RATE_SAMPLE_WIDTH).abs()
+            }
+            None => self.playback.speed.abs(),
+        }
+    }
 }
 
-/// Helper trait to search a list of `Clip`s for the currently playing one.
+/// The stretch of time, in seconds, over which the slope of a time remap
+/// curve is measured (see `Clip::playback_rate`).
+const RATE_SAMPLE_WIDTH: f64 = 0.01;
+
+/// Controls how time flows through a clip. By default, a clip plays its
+/// sequence once at normal speed.
+///
+/// - `speed`: Multiplier on playback speed. Ignored if `time_remap` is set.
+///
+/// - `reverse`: Plays the clip (or each repetition of the loop) backwards.
+///
+/// - `loop_mode`/`loop_length`: Repeats a section of the sequence, starting at
+///   the clip's start offset, for the whole duration of the clip. If no loop
+///   length is set, the rest of the sequence after the start offset is used.
+///   The length is resolved through the tempo map of the clip's sequence,
+///   counting from the start offset.
+///
+/// - `time_remap`: Curve that maps time elapsed within the clip (as keyframe
+///   times) to time within the sequence (in seconds, as float values under
+///   the key `"time"`). Allows for speed ramps and freezes. Musical keyframe
+///   times are resolved through the tempo map of the clip's sequence.
+#[derive(Debug, Clone)]
+pub struct ClipPlayback {
+    pub speed: f64,
+    pub reverse: bool,
+    pub loop_mode: ClipLoopMode,
+    pub loop_length: Option<TimelinePosition>,
+    pub time_remap: Option<Keyframes>,
+}
+
+impl Default for ClipPlayback {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            reverse: false,
+            loop_mode: ClipLoopMode::default(),
+            loop_length: None,
+            time_remap: None,
+        }
+    }
+}
+
+impl ClipPlayback {
+    /// Checks that a time remap curve only uses the key `"time"` (float).
+    pub fn validate_time_remap(time_remap: &Keyframes) -> Result<(), String> {
+        time_remap.validate_keys(&[("time", "float")])
+    }
+
+    /// Maps time elapsed within the clip to time elapsed within the sequence,
+    /// before looping or reversal, using either the time remap curve or the
+    /// playback speed. `tempo_map` belongs to the clip's sequence and resolves
+    /// musical keyframe times on the curve.
+    fn remap(&self, elapsed: f64, tempo_map: &TempoMap) -> f64 {
+        match &self.time_remap {
+            Some(time_remap) => {
+                time_remap.get_float_value("time", elapsed, tempo_map, &(elapsed as f32)) as f64
+            }
+            None => elapsed * self.speed,
+        }
+    }
+}
+
+/// How a clip behaves once it reaches the end of the section it plays.
+/// `Once` simply keeps going, `Loop` jumps back to the start of the section,
+/// and `PingPong` alternates between playing forwards and backwards.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClipLoopMode {
+    #[default]
+    Once,
+    Loop,
+    PingPong,
+}
+
+/// Helper trait to search a list of `Clip`s for the currently playing one(s).
 pub trait ClipsExt {
-    fn find_current(&self, time: f64) -> Option<&Clip>;
+    fn find_current(&self, time: f64, tempo_map: &TempoMap) -> Option<&Clip>;
+
+    fn find_active(&self, time: f64, tempo_map: &TempoMap, active: &mut Vec<(usize, f32)>);
 }
 
 impl ClipsExt for [Clip] {
     /// Helper function to search a list of `Clip`s for the currently playing
     /// one. Simple linear scan; I can't imagine this becomes a significant
     /// bottleneck.
-    fn find_current(&self, time: f64) -> Option<&Clip> {
+    fn find_current(&self, time: f64, tempo_map: &TempoMap) -> Option<&Clip> {
         for clip in self {
-            if clip.time_segment.start_time <= time
-                && (clip.time_segment.start_time + clip.time_segment.duration) > time
+            if clip.time_segment.start_seconds(tempo_map) <= time
+                && clip.time_segment.end_seconds(tempo_map) > time
             {
                 return Some(clip);
             }
         }
         None
     }
+
+    /// Helper function to find every clip that is currently playing, along
+    /// with its weight (from 0 to 1) according to its fade envelope. Clips
+    /// that overlap are crossfaded over the length of the overlap, unless an
+    /// explicit fade is longer. Results are written to `active` as clip
+    /// indices, in track order, so the buffer can be reused between updates.
+    fn find_active(&self, time: f64, tempo_map: &TempoMap, active: &mut Vec<(usize, f32)>) {
+        active.clear();
+        for (clip_i, clip) in self.iter().enumerate() {
+            let start = clip.time_segment.start_seconds(tempo_map);
+            let end = clip.time_segment.end_seconds(tempo_map);
+            if time < start || time >= end {
+                continue;
+            }
+
+            let (mut fade_in, mut fade_out) = clip.fade_seconds(tempo_map);
+            for (other_i, other) in self.iter().enumerate() {
+                if other_i == clip_i {
+                    continue;
+                }
+                let other_start = other.time_segment.start_seconds(tempo_map);
+                let other_end = other.time_segment.end_seconds(tempo_map);
+                // another clip is still playing when this one starts
+                if other_start < start && other_end > start {
+                    fade_in = fade_in.max(other_end.min(end) - start);
+                }
+                // another clip starts while this one is still playing
+                if other_start > start && other_start < end && other_end > end {
+                    fade_out = fade_out.max(end - other_start);
+                }
+            }
+
+            let fade_in_weight = if fade_in > 0.0 {
+                ((time - start) / fade_in).min(1.0)
+            } else {
+                1.0
+            };
+            let fade_out_weight = if fade_out > 0.0 {
+                ((end - time) / fade_out).min(1.0)
+            } else {
+                1.0
+            };
+            active.push((clip_i, (fade_in_weight * fade_out_weight) as f32));
+        }
+    }
 }
 
 /// Represents a window of time that a sequence is played within a
 /// sequence track. `start_time` represents the start time within that track,
 /// and `duration` represents the length of time for which it will play.
 /// `start_offset` represents where in the sequence the clip will start
-/// playback from, in seconds from the start.
-#[derive(Default, Debug, Clone, Copy, PartialEq)]
+/// playback from, measured from the start.
+///
+/// Each value can be either absolute or on the beat grid. `start_time` and
+/// `duration` are resolved through the tempo map of the track's sequence,
+/// while `start_offset` is resolved through the tempo map of the clip's
+/// sequence.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct TimeSegment {
-    pub start_time: f64,
-    pub duration: f64,
-    pub start_offset: f64,
+    pub start_time: TimelinePosition,
+    pub duration: TimelinePosition,
+    pub start_offset: TimelinePosition,
 }
 
 impl TimeSegment {
     /// Constructs a new `TimeSegment`.
-    pub fn new(start_time: f64, duration: f64, start_offset: f64) -> Self {
+    pub fn new(
+        start_time: impl Into<TimelinePosition>,
+        duration: impl Into<TimelinePosition>,
+        start_offset: impl Into<TimelinePosition>,
+    ) -> Self {
         TimeSegment {
-            start_time,
-            duration,
-            start_offset,
+            start_time: start_time.into(),
+            duration: duration.into(),
+            start_offset: start_offset.into(),
         }
     }
+
+    /// Resolves the start time of the segment into seconds.
+    pub fn start_seconds(&self, tempo_map: &TempoMap) -> f64 {
+        self.start_time.to_seconds(tempo_map)
+    }
+
+    /// Resolves the end time of the segment (start time plus duration) into
+    /// seconds. Musical durations are counted on the beat grid from the start
+    /// time, so a clip that lasts a bar always lasts exactly the bar it starts
+    /// in, even across tempo and meter changes.
+    pub fn end_seconds(&self, tempo_map: &TempoMap) -> f64 {
+        let start = self.start_seconds(tempo_map);
+        start + self.duration.length_seconds_from(start, tempo_map)
+    }
+
+    /// Resolves the start offset of the segment into seconds.
+    pub fn start_offset_seconds(&self, tempo_map: &TempoMap) -> f64 {
+        self.start_offset.to_seconds(tempo_map)
+    }
 }
 
 /// Single datastructure to refer to a track. Holds a handle to the `Sequence`
 /// the track resides in, as well as an index to indicate which track within
 /// that sequence it is. Should be invalidated whenever the static sequence
 /// tree changes.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TrackReference {
     pub sequence: SimpleHandle<Sequence>,
     pub index: usize,
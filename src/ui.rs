@@ -3,49 +3,426 @@ use bevy::prelude::*;
 use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
 
 use crate::{
+    audio::song::SongLibrary,
+    editing::{EditHistory, RedoEdit, UndoEdit},
+    midi::clock::MidiClock,
+    show_file::{LoadShow, SaveShow},
     simple_store::SimpleStore,
-    timeline::{playback::*, sequences::*},
+    timecode::{TimecodeChase, TimecodeSettings},
+    timeline::{cues::*, playback::*, sequences::*},
 };
 
+pub mod cues;
+pub mod curves;
+pub mod effects;
+pub mod midi_clock;
+pub mod modulators;
+pub mod song;
+pub mod timecode;
 pub mod timeline;
 
 pub struct UiPlugin;
 
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(EguiPrimaryContextPass, ui_playback_system);
+        app.init_resource::<timeline::TimelineEditor>()
+            .init_resource::<curves::CurveEditor>()
+            .init_resource::<cues::CueEditor>()
+            .init_resource::<song::SongEditor>()
+            .add_systems(EguiPrimaryContextPass, ui_playback_system)
+            .add_systems(EguiPrimaryContextPass, ui_curve_editor_system)
+            .add_systems(EguiPrimaryContextPass, ui_effect_inspector_system)
+            .add_systems(EguiPrimaryContextPass, ui_modulator_system)
+            .add_systems(EguiPrimaryContextPass, ui_cue_system)
+            .add_systems(EguiPrimaryContextPass, ui_song_system)
+            .add_systems(EguiPrimaryContextPass, ui_sync_system)
+            .add_systems(EguiPrimaryContextPass, ui_show_file_system)
+            .add_systems(
+                EguiPrimaryContextPass,
+                ui_undo_redo_shortcuts.run_if(resource_exists::<ButtonInput<KeyCode>>),
+            );
     }
 }
 
 pub fn ui_playback_system(
+    mut commands: Commands,
+    mut editor: ResMut<timeline::TimelineEditor>,
     mut playback: ResMut<PlaybackInformation>,
-    primary_sequence: Res<PrimarySequence>,
-    sequence_store: Res<SimpleStore<Sequence>>,
+    mut primary_sequence: ResMut<PrimarySequence>,
+    mut playlist: ResMut<Playlist>,
+    (sequence_store, mut library): (Res<SimpleStore<Sequence>>, ResMut<SongLibrary>),
     mut contexts: EguiContexts,
 ) {
     match contexts.ctx_mut() {
         Ok(contexts) => {
             egui::Window::new("Playback").show(contexts, |ui| {
-                if playback.is_playing {
-                    if ui.button("Pause").clicked() {
-                        playback.is_playing = false;
+                ui.horizontal(|ui| {
+                    // an external clock decides whether playback is running
+                    let has_control = !playback.external_clock;
+                    if playback.is_playing {
+                        if ui
+                            .add_enabled(has_control, egui::Button::new("Pause"))
+                            .clicked()
+                        {
+                            playback.pause();
+                        }
+                    } else {
+                        if ui
+                            .add_enabled(has_control, egui::Button::new("Play"))
+                            .clicked()
+                        {
+                            playback.play();
+                        }
                     }
-                } else {
-                    if ui.button("Play").clicked() {
-                        playback.is_playing = true;
+
+                    egui::ComboBox::from_id_salt("end_mode")
+                        .selected_text(playback.end_mode.to_string())
+                        .show_ui(ui, |ui| {
+                            for end_mode in PlaybackEndMode::ALL {
+                                ui.selectable_value(
+                                    &mut playback.end_mode,
+                                    end_mode,
+                                    end_mode.to_string(),
+                                );
+                            }
+                        });
+
+                    let has_loop_region = primary_sequence
+                        .0
+                        .and_then(|handle| sequence_store.get(handle))
+                        .is_some_and(|sequence| sequence.loop_region.is_some());
+                    ui.add_enabled(
+                        has_loop_region,
+                        egui::Checkbox::new(&mut playback.loop_region_enabled, "Loop region"),
+                    );
+
+                    if playback.blacked_out {
+                        ui.label("Blacked out");
                     }
-                }
+                });
+
+                ui.collapsing("Playlist", |ui| {
+                    draw_playlist(
+                        ui,
+                        &mut playlist,
+                        &mut primary_sequence,
+                        &mut playback,
+                        &sequence_store,
+                    );
+                });
+
+                timeline::draw_timeline(
+                    ui,
+                    &mut commands,
+                    &mut editor,
+                    &mut playback,
+                    &sequence_store,
+                    primary_sequence.0,
+                    &mut library,
+                );
+            });
+        }
+        Err(error) => println!("Error: Could not get egui context:\n{}", error),
+    }
+}
+
+/// Draws the playlist used by `PlaybackEndMode::FollowOn`. Clicking an entry
+/// opens its sequence from the start.
+fn draw_playlist(
+    ui: &mut egui::Ui,
+    playlist: &mut Playlist,
+    primary_sequence: &mut PrimarySequence,
+    playback: &mut PlaybackInformation,
+    sequence_store: &SimpleStore<Sequence>,
+) {
+    let mut remove = None;
+    let mut swap = None;
+    let entry_count = playlist.0.len();
+    for (entry_i, handle) in playlist.0.iter().enumerate() {
+        ui.horizontal(|ui| {
+            let name = sequence_store
+                .get(*handle)
+                .map_or("<missing>", |sequence| sequence.name.as_str());
+            let is_open = primary_sequence.0 == Some(*handle);
+            if ui
+                .selectable_label(is_open, format!("{}. {}", entry_i + 1, name))
+                .clicked()
+            {
+                primary_sequence.0 = Some(*handle);
+                playback.seek(0.0);
+            }
+            if ui
+                .add_enabled(entry_i > 0, egui::Button::new("Up"))
+                .clicked()
+            {
+                swap = Some((entry_i - 1, entry_i));
+            }
+            if ui
+                .add_enabled(entry_i + 1 < entry_count, egui::Button::new("Down"))
+                .clicked()
+            {
+                swap = Some((entry_i, entry_i + 1));
+            }
+            if ui.button("Remove").clicked() {
+                remove = Some(entry_i);
+            }
+        });
+    }
+    if let Some((a, b)) = swap {
+        playlist.0.swap(a, b);
+    }
+    if let Some(entry_i) = remove {
+        playlist.0.remove(entry_i);
+    }
 
-                let keyframe_times: Vec<f64> = Vec::new();
-                // TODO: Add keyframe times back?
+    if ui
+        .add_enabled(
+            primary_sequence.0.is_some(),
+            egui::Button::new("Add open sequence"),
+        )
+        .clicked()
+        && let Some(handle) = primary_sequence.0
+    {
+        playlist.0.push(handle);
+    }
+}
+
+pub fn ui_curve_editor_system(
+    mut commands: Commands,
+    mut curve_editor: ResMut<curves::CurveEditor>,
+    timeline_editor: Res<timeline::TimelineEditor>,
+    primary_sequence: Res<PrimarySequence>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    mut contexts: EguiContexts,
+) {
+    match contexts.ctx_mut() {
+        Ok(contexts) => {
+            egui::Window::new("Curves").show(contexts, |ui| {
+                curves::draw_curve_editor(
+                    ui,
+                    &mut commands,
+                    &mut curve_editor,
+                    &timeline_editor,
+                    &sequence_store,
+                    primary_sequence.0,
+                );
+            });
+        }
+        Err(error) => println!("Error: Could not get egui context:\n{}", error),
+    }
+}
+
+pub fn ui_effect_inspector_system(
+    mut commands: Commands,
+    timeline_editor: Res<timeline::TimelineEditor>,
+    primary_sequence: Res<PrimarySequence>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    mut contexts: EguiContexts,
+) {
+    match contexts.ctx_mut() {
+        Ok(contexts) => {
+            egui::Window::new("Effect").show(contexts, |ui| {
+                effects::draw_effect_inspector(
+                    ui,
+                    &mut commands,
+                    &timeline_editor,
+                    &sequence_store,
+                    primary_sequence.0,
+                );
+            });
+        }
+        Err(error) => println!("Error: Could not get egui context:\n{}", error),
+    }
+}
+
+pub fn ui_modulator_system(
+    mut commands: Commands,
+    timeline_editor: Res<timeline::TimelineEditor>,
+    primary_sequence: Res<PrimarySequence>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    mut contexts: EguiContexts,
+) {
+    match contexts.ctx_mut() {
+        Ok(contexts) => {
+            egui::Window::new("Modulators").show(contexts, |ui| {
+                modulators::draw_modulators(
+                    ui,
+                    &mut commands,
+                    &timeline_editor,
+                    &sequence_store,
+                    primary_sequence.0,
+                );
+            });
+        }
+        Err(error) => println!("Error: Could not get egui context:\n{}", error),
+    }
+}
 
-                let primary_sequence = primary_sequence
+pub fn ui_cue_system(
+    mut commands: Commands,
+    mut editor: ResMut<cues::CueEditor>,
+    mut cue_lists: ResMut<SimpleStore<CueList>>,
+    cue_playback: Res<CuePlayback>,
+    primary_sequence: Res<PrimarySequence>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    mut contexts: EguiContexts,
+) {
+    match contexts.ctx_mut() {
+        Ok(contexts) => {
+            egui::Window::new("Cues").show(contexts, |ui| {
+                cues::draw_cue_lists(
+                    ui,
+                    &mut commands,
+                    &mut editor,
+                    &mut cue_lists,
+                    &cue_playback,
+                    &sequence_store,
+                    primary_sequence.0,
+                );
+            });
+        }
+        Err(error) => println!("Error: Could not get egui context:\n{}", error),
+    }
+}
+
+pub fn ui_song_system(
+    mut commands: Commands,
+    mut editor: ResMut<song::SongEditor>,
+    mut library: ResMut<SongLibrary>,
+    primary_sequence: Res<PrimarySequence>,
+    sequence_store: Res<SimpleStore<Sequence>>,
+    mut contexts: EguiContexts,
+) {
+    match contexts.ctx_mut() {
+        Ok(contexts) => {
+            egui::Window::new("Song").show(contexts, |ui| {
+                let Some((handle, sequence)) = primary_sequence
                     .0
-                    .and_then(|handle| sequence_store.get(handle));
+                    .and_then(|handle| Some((handle, sequence_store.get(handle)?)))
+                else {
+                    ui.label("No primary sequence");
+                    return;
+                };
+                song::draw_song(
+                    ui,
+                    &mut commands,
+                    &mut editor,
+                    &mut library,
+                    handle,
+                    sequence,
+                );
+            });
+        }
+        Err(error) => println!("Error: Could not get egui context:\n{}", error),
+    }
+}
+
+pub fn ui_sync_system(
+    time: Res<Time>,
+    mut timecode_settings: ResMut<TimecodeSettings>,
+    chase: Res<TimecodeChase>,
+    mut midi_clock: ResMut<MidiClock>,
+    mut contexts: EguiContexts,
+) {
+    match contexts.ctx_mut() {
+        Ok(contexts) => {
+            egui::Window::new("Sync").show(contexts, |ui| {
+                ui.collapsing("Timecode", |ui| {
+                    timecode::draw_timecode(ui, &mut timecode_settings, &chase);
+                });
+                ui.collapsing("MIDI clock", |ui| {
+                    midi_clock::draw_midi_clock(
+                        ui,
+                        &mut midi_clock,
+                        &timecode_settings,
+                        time.elapsed_secs_f64(),
+                    );
+                });
+            });
+        }
+        Err(error) => println!("Error: Could not get egui context:\n{}", error),
+    }
+}
 
-                timeline::draw_timeline(ui, &mut playback, keyframe_times, primary_sequence);
+pub fn ui_show_file_system(
+    mut commands: Commands,
+    mut show_path: Local<String>,
+    history: Res<EditHistory>,
+    mut contexts: EguiContexts,
+) {
+    match contexts.ctx_mut() {
+        Ok(contexts) => {
+            egui::Window::new("Show").show(contexts, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Path");
+                    ui.text_edit_singleline(&mut *show_path);
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        commands.trigger(SaveShow {
+                            path: show_path.as_str().into(),
+                        });
+                    }
+                    if ui.button("Load").clicked() {
+                        commands.trigger(LoadShow {
+                            path: show_path.as_str().into(),
+                        });
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(history.can_undo(), egui::Button::new("Undo"))
+                        .clicked()
+                    {
+                        commands.trigger(UndoEdit {});
+                    }
+                    if ui
+                        .add_enabled(history.can_redo(), egui::Button::new("Redo"))
+                        .clicked()
+                    {
+                        commands.trigger(RedoEdit {});
+                    }
+                    if history.is_dirty() {
+                        ui.label("Unsaved changes");
+                    }
+                });
             });
         }
         Err(error) => println!("Error: Could not get egui context:\n{}", error),
     }
 }
+
+/// Bevy system that maps Ctrl+Z to undo and Ctrl+Shift+Z/Ctrl+Y to redo. The
+/// shortcuts are left to egui while it wants the keyboard, e.g. to undo
+/// typing in a text field.
+pub fn ui_undo_redo_shortcuts(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut contexts: EguiContexts,
+) {
+    match contexts.ctx_mut() {
+        Ok(contexts) => {
+            if contexts.wants_keyboard_input() {
+                return;
+            }
+        }
+        Err(error) => {
+            println!("Error: Could not get egui context:\n{}", error);
+            return;
+        }
+    }
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if !ctrl {
+        return;
+    }
+    if keys.just_pressed(KeyCode::KeyZ) {
+        if shift {
+            commands.trigger(RedoEdit {});
+        } else {
+            commands.trigger(UndoEdit {});
+        }
+    } else if keys.just_pressed(KeyCode::KeyY) {
+        commands.trigger(RedoEdit {});
+    }
+}
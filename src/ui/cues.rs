@@ -0,0 +1,201 @@
+use bevy::prelude::{Commands, Resource};
+use bevy_egui::egui::{self, Ui};
+
+use crate::{
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{cues::*, sequences::Sequence},
+};
+
+/// Bevy resource that holds the state of the cue list editor, i.e. which cue
+/// list is open.
+#[derive(Resource, Default, Debug)]
+pub struct CueEditor {
+    pub selected: Option<SimpleHandle<CueList>>,
+}
+
+/// Draws the cue list editor: GO, BACK and release controls for the open cue
+/// list, followed by a row per cue with its content, timings, follow, and
+/// current level. Clicking a cue's number jumps to it.
+pub fn draw_cue_lists(
+    ui: &mut Ui,
+    commands: &mut Commands,
+    editor: &mut CueEditor,
+    cue_lists: &mut SimpleStore<CueList>,
+    cue_playback: &CuePlayback,
+    sequence_store: &SimpleStore<Sequence>,
+    primary_sequence: Option<SimpleHandle<Sequence>>,
+) {
+    // cue lists may have been replaced (e.g. by loading a show)
+    if editor
+        .selected
+        .is_some_and(|handle| cue_lists.get(handle).is_none())
+    {
+        editor.selected = None;
+    }
+
+    ui.horizontal(|ui| {
+        let selected_name = editor
+            .selected
+            .and_then(|handle| cue_lists.get(handle))
+            .map_or("No cue list", |cue_list| cue_list.name.as_str());
+        egui::ComboBox::from_id_salt("cue_list")
+            .selected_text(selected_name)
+            .show_ui(ui, |ui| {
+                for (handle, cue_list) in cue_lists.iter() {
+                    ui.selectable_value(&mut editor.selected, Some(handle), &cue_list.name);
+                }
+            });
+        if ui.button("New cue list").clicked() {
+            let count = cue_lists.iter().count();
+            editor.selected = Some(cue_lists.add(CueList {
+                name: format!("Cue list {}", count + 1),
+                cues: Vec::new(),
+            }));
+        }
+    });
+
+    let Some(handle) = editor.selected else {
+        return;
+    };
+    let Some(cue_list) = cue_lists.get_mut(handle) else {
+        return;
+    };
+    ui.text_edit_singleline(&mut cue_list.name);
+
+    ui.horizontal(|ui| {
+        if ui.button("GO").clicked() {
+            commands.trigger(CueGo { cue_list: handle });
+        }
+        if ui.button("BACK").clicked() {
+            commands.trigger(CueBack { cue_list: handle });
+        }
+        if ui.button("Release").clicked() {
+            commands.trigger(CueRelease { cue_list: handle });
+        }
+    });
+
+    let current = cue_playback.current_cue(handle);
+    let mut remove = None;
+    egui::Grid::new("cues").striped(true).show(ui, |ui| {
+        for header in [
+            "Cue", "Name", "Sequence", "Look", "Delay", "Fade in", "Fade out", "Follow", "Level",
+            "",
+        ] {
+            ui.label(header);
+        }
+        ui.end_row();
+
+        for (cue_i, cue) in cue_list.cues.iter_mut().enumerate() {
+            if ui
+                .selectable_label(current == Some(cue_i), (cue_i + 1).to_string())
+                .on_hover_text("Jump to cue")
+                .clicked()
+            {
+                commands.trigger(CueJump {
+                    cue_list: handle,
+                    cue: cue_i,
+                });
+            }
+            ui.text_edit_singleline(&mut cue.name);
+
+            let mut sequence = cue.sequence();
+            egui::ComboBox::from_id_salt(("cue_sequence", cue_i))
+                .selected_text(
+                    sequence_store
+                        .get(sequence)
+                        .map_or("<missing>", |sequence| sequence.name.as_str()),
+                )
+                .show_ui(ui, |ui| {
+                    for (sequence_handle, sequence_ref) in sequence_store.iter() {
+                        ui.selectable_value(&mut sequence, sequence_handle, &sequence_ref.name);
+                    }
+                });
+            ui.horizontal(|ui| {
+                let mut look_time = match cue.content {
+                    CueContent::Sequence(_) => None,
+                    CueContent::Look { time, .. } => Some(time),
+                };
+                let mut is_look = look_time.is_some();
+                ui.checkbox(&mut is_look, "");
+                if is_look {
+                    let time = look_time.get_or_insert(0.0);
+                    ui.add(
+                        egui::DragValue::new(time)
+                            .speed(0.01)
+                            .range(0.0..=f64::MAX)
+                            .suffix(" s"),
+                    );
+                } else {
+                    look_time = None;
+                }
+                cue.content = match look_time {
+                    Some(time) => CueContent::Look { sequence, time },
+                    None => CueContent::Sequence(sequence),
+                };
+            });
+
+            for value in [&mut cue.delay, &mut cue.fade_in, &mut cue.fade_out] {
+                ui.add(
+                    egui::DragValue::new(value)
+                        .speed(0.05)
+                        .range(0.0..=f64::MAX)
+                        .suffix(" s"),
+                );
+            }
+
+            ui.horizontal(|ui| {
+                let follow_name = match cue.follow {
+                    CueFollow::Manual => "Manual",
+                    CueFollow::AfterFade => "After fade",
+                    CueFollow::Wait(_) => "Wait",
+                };
+                egui::ComboBox::from_id_salt(("cue_follow", cue_i))
+                    .selected_text(follow_name)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut cue.follow, CueFollow::Manual, "Manual");
+                        ui.selectable_value(&mut cue.follow, CueFollow::AfterFade, "After fade");
+                        if ui
+                            .selectable_label(matches!(cue.follow, CueFollow::Wait(_)), "Wait")
+                            .clicked()
+                            && !matches!(cue.follow, CueFollow::Wait(_))
+                        {
+                            cue.follow = CueFollow::Wait(cue.fade_in);
+                        }
+                    });
+                if let CueFollow::Wait(wait) = &mut cue.follow {
+                    ui.add(
+                        egui::DragValue::new(wait)
+                            .speed(0.05)
+                            .range(0.0..=f64::MAX)
+                            .suffix(" s"),
+                    );
+                }
+            });
+
+            ui.add(
+                egui::ProgressBar::new(cue_playback.cue_level(handle, cue_i)).desired_width(60.0),
+            );
+            if ui.button("Delete").clicked() {
+                remove = Some(cue_i);
+            }
+            ui.end_row();
+        }
+    });
+    if let Some(cue_i) = remove {
+        cue_list.cues.remove(cue_i);
+    }
+
+    if ui
+        .add_enabled(
+            primary_sequence.is_some(),
+            egui::Button::new("Add cue with open sequence"),
+        )
+        .clicked()
+        && let Some(sequence) = primary_sequence
+    {
+        let name = format!("Cue {}", cue_list.cues.len() + 1);
+        cue_list
+            .cues
+            .push(Cue::new(name, CueContent::Sequence(sequence)));
+    }
+}
@@ -0,0 +1,766 @@
+use std::collections::HashSet;
+
+use bevy::prelude::{Color, Commands, Oklaba, Resource};
+use bevy_egui::egui::{
+    self, Align2, Color32, FontId, Pos2, Rect, Response, Sense, Shape, Stroke, Ui, Vec2,
+};
+
+use crate::{
+    editing::{ApplyEdit, EditCommand, KeyframesTarget},
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{
+        keyframes::{InterpolationType, KeyframeValue, Keyframes},
+        positions::TempoMap,
+        sequences::Sequence,
+        tracks::TrackContents,
+    },
+    ui::timeline::TimelineEditor,
+};
+
+const PLOT_HEIGHT: f32 = 240.0;
+const POINT_SIZE: f32 = 4.0;
+const HANDLE_SIZE: f32 = 3.0;
+const CURVE_SAMPLES: usize = 200;
+const CURVE_COLORS: [Color32; 6] = [
+    Color32::from_rgb(230, 90, 90),
+    Color32::from_rgb(90, 200, 90),
+    Color32::from_rgb(90, 140, 240),
+    Color32::from_rgb(220, 200, 80),
+    Color32::from_rgb(200, 100, 220),
+    Color32::from_rgb(80, 210, 210),
+];
+
+/// Bevy resource that holds the state of the curve editor, which plots the
+/// effect keyframes of the track selected in the timeline editor.
+///
+/// Every float parameter is plotted as one curve, every `Vec3` parameter as
+/// one curve per axis, and every color parameter as one curve per Oklab
+/// component (L, a, b), since that is the space colors are interpolated in.
+/// Keyframes are selected per curve, as (keyframe index, component index).
+#[derive(Resource, Default, Debug)]
+pub struct CurveEditor {
+    selection: HashSet<(usize, usize)>,
+    hidden_keys: HashSet<String>,
+    /// Range of values shown, from bottom to top. `None` fits the view to the
+    /// keyframes on the next frame.
+    value_range: Option<(f32, f32)>,
+    /// The sequence and track the editor is showing, so that the view and
+    /// selection can be reset when a different track is selected.
+    shown_track: Option<(SimpleHandle<Sequence>, usize)>,
+    drag: Option<CurveDrag>,
+}
+
+/// A drag in progress in the curve editor. As in the timeline editor, edits
+/// are computed from the keyframes at the start of the drag and merged into a
+/// single undo step.
+#[derive(Debug)]
+struct CurveDrag {
+    kind: CurveDragKind,
+    original: Keyframes,
+    original_selection: HashSet<(usize, usize)>,
+    grab: (f64, f32),
+    edited: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CurveDragKind {
+    /// Moves the selected keyframes in time and value.
+    Move,
+    /// Scales the selected keyframes in time and value around a pivot (the
+    /// center of the selection).
+    Scale { pivot: (f64, f32) },
+    /// Moves one of the tangent handles of the curve leading into a keyframe.
+    Tangent {
+        index: usize,
+        component: usize,
+        outgoing: bool,
+    },
+    /// Selects all keyframes within the box between the grab point and the
+    /// pointer.
+    BoxSelect { additive: bool },
+}
+
+/// Names of the curves a keyframe value is plotted as. Blending modes are
+/// discrete and therefore not plotted at all.
+fn component_names(value: &KeyframeValue) -> &'static [&'static str] {
+    match value {
+        KeyframeValue::FloatKeyframe(_) => &[""],
+        KeyframeValue::Vec3Keyframe(_) => &["x", "y", "z"],
+        KeyframeValue::ColorKeyframe(_) => &["L", "a", "b"],
+        KeyframeValue::BlendingModeKeyframe(_) => &[],
+    }
+}
+
+/// Gets a single plotted component of a keyframe value.
+fn get_component(value: &KeyframeValue, component: usize) -> f32 {
+    match value {
+        KeyframeValue::FloatKeyframe(value) => *value,
+        KeyframeValue::Vec3Keyframe(value) => value[component],
+        KeyframeValue::ColorKeyframe(value) => {
+            let oklab = Oklaba::from(*value);
+            [oklab.lightness, oklab.a, oklab.b][component]
+        }
+        KeyframeValue::BlendingModeKeyframe(_) => 0.0,
+    }
+}
+
+/// Sets a single plotted component of a keyframe value.
+fn set_component(value: &mut KeyframeValue, component: usize, new_value: f32) {
+    match value {
+        KeyframeValue::FloatKeyframe(value) => *value = new_value,
+        KeyframeValue::Vec3Keyframe(value) => value[component] = new_value,
+        KeyframeValue::ColorKeyframe(value) => {
+            let mut oklab = Oklaba::from(*value);
+            match component {
+                0 => oklab.lightness = new_value,
+                1 => oklab.a = new_value,
+                _ => oklab.b = new_value,
+            }
+            *value = Color::from(oklab);
+        }
+        KeyframeValue::BlendingModeKeyframe(_) => {}
+    }
+}
+
+/// Evaluates a plotted component of a parameter at a point in time, the same
+/// way effects read it.
+fn sample_component(
+    keyframes: &Keyframes,
+    key: &str,
+    default: &KeyframeValue,
+    component: usize,
+    time: f64,
+    tempo_map: &TempoMap,
+) -> f32 {
+    let value = match default {
+        KeyframeValue::FloatKeyframe(default) => {
+            KeyframeValue::FloatKeyframe(keyframes.get_float_value(key, time, tempo_map, default))
+        }
+        KeyframeValue::Vec3Keyframe(default) => {
+            KeyframeValue::Vec3Keyframe(keyframes.get_vec3_value(key, time, tempo_map, default))
+        }
+        KeyframeValue::ColorKeyframe(default) => {
+            KeyframeValue::ColorKeyframe(keyframes.get_color_value(key, time, tempo_map, default))
+        }
+        KeyframeValue::BlendingModeKeyframe(_) => return 0.0,
+    };
+    get_component(&value, component)
+}
+
+/// Finds the keyframe before the given one with the same key, which is where
+/// the curve leading into it starts.
+fn previous_with_key(keyframes: &Keyframes, index: usize) -> Option<usize> {
+    let key = &keyframes.inner().get(index)?.key;
+    (0..index)
+        .rev()
+        .find(|&other| keyframes.inner()[other].key == *key)
+}
+
+/// Finds the keyframe after the given one with the same key.
+fn next_with_key(keyframes: &Keyframes, index: usize) -> Option<usize> {
+    let key = &keyframes.inner().get(index)?.key;
+    (index + 1..keyframes.inner().len()).find(|&other| keyframes.inner()[other].key == *key)
+}
+
+/// Draws the curve editor for the effect track selected in the timeline
+/// editor. Keyframes can be selected by clicking or dragging a box around
+/// them (holding shift adds to the selection), moved by dragging, and scaled
+/// around the center of the selection by dragging with ctrl held. Bézier
+/// keyframes show tangent handles while selected. All edits go through the
+/// edit history.
+pub fn draw_curve_editor(
+    ui: &mut Ui,
+    commands: &mut Commands,
+    curve_editor: &mut CurveEditor,
+    timeline_editor: &TimelineEditor,
+    sequence_store: &SimpleStore<Sequence>,
+    primary_sequence: Option<SimpleHandle<Sequence>>,
+) {
+    let selected = primary_sequence.and_then(|primary| {
+        let handle = timeline_editor.path.last().copied().unwrap_or(primary);
+        let track = timeline_editor.selected_track?;
+        let sequence = sequence_store.get(handle)?;
+        match &sequence.tracks.get(track)?.contents {
+            TrackContents::EffectTrack {
+                effect_keyframes, ..
+            } => Some((handle, track, sequence, effect_keyframes)),
+            _ => None,
+        }
+    });
+    let Some((handle, track, sequence, keyframes)) = selected else {
+        ui.label("Select an effect track in the timeline to edit its curves");
+        return;
+    };
+
+    if curve_editor.shown_track != Some((handle, track)) {
+        *curve_editor = CurveEditor {
+            shown_track: Some((handle, track)),
+            hidden_keys: std::mem::take(&mut curve_editor.hidden_keys),
+            ..Default::default()
+        };
+    }
+    // keyframes may have been removed since (e.g. by undoing)
+    curve_editor.selection.retain(|&(index, component)| {
+        keyframes
+            .inner()
+            .get(index)
+            .is_some_and(|keyframe| component < component_names(&keyframe.value).len())
+    });
+
+    let tempo_map = &sequence.tempo_map;
+    let edit = |keyframes: Keyframes, merge: bool| {
+        ApplyEdit::new(
+            EditCommand::SetKeyframes {
+                sequence: handle,
+                track,
+                target: KeyframesTarget::Effect,
+                keyframes,
+            },
+            merge,
+        )
+    };
+
+    // every plottable parameter, in order of appearance, along with a value
+    // of the right type to sample it with
+    let mut parameters: Vec<(&str, &KeyframeValue)> = Vec::new();
+    for keyframe in keyframes.inner() {
+        if !component_names(&keyframe.value).is_empty()
+            && !parameters.iter().any(|(key, _)| *key == keyframe.key)
+        {
+            parameters.push((&keyframe.key, &keyframe.value));
+        }
+    }
+    let curve_color = |key: &str, component: usize| {
+        let parameter_i = parameters
+            .iter()
+            .position(|(other, _)| *other == key)
+            .unwrap_or(0);
+        CURVE_COLORS[(parameter_i * 3 + component) % CURVE_COLORS.len()]
+    };
+    let is_visible =
+        |curve_editor: &CurveEditor, key: &str| !curve_editor.hidden_keys.contains(key);
+
+    // parameter toggles and view controls
+    ui.horizontal_wrapped(|ui| {
+        for (key, _) in &parameters {
+            let mut visible = is_visible(curve_editor, key);
+            let text = egui::RichText::new(*key).color(curve_color(key, 0));
+            if ui.checkbox(&mut visible, text).changed() {
+                if visible {
+                    curve_editor.hidden_keys.remove(*key);
+                } else {
+                    curve_editor.hidden_keys.insert(key.to_string());
+                }
+            }
+        }
+        ui.separator();
+        if ui.button("Fit").clicked() {
+            curve_editor.value_range = None;
+        }
+    });
+
+    // interpolation and numeric editing of the selection
+    let selected_indices: HashSet<usize> = curve_editor
+        .selection
+        .iter()
+        .map(|(index, _)| *index)
+        .collect();
+    ui.horizontal(|ui| {
+        ui.add_enabled_ui(!selected_indices.is_empty(), |ui| {
+            for (label, interpolation) in [
+                ("Constant", InterpolationType::CONSTANT),
+                ("Linear", InterpolationType::LINEAR),
+                ("Bézier", InterpolationType::EASE_IN_OUT),
+            ] {
+                if ui.button(label).clicked() {
+                    let mut changed = keyframes.inner().clone();
+                    for &index in &selected_indices {
+                        let keyframe = &mut changed[index];
+                        // keep existing tangents when already on a curve
+                        let keep_tangents = matches!(
+                            (keyframe.interpolation, interpolation),
+                            (
+                                InterpolationType::BEZIER { .. },
+                                InterpolationType::BEZIER { .. }
+                            )
+                        );
+                        if !keep_tangents {
+                            keyframe.interpolation = interpolation;
+                        }
+                    }
+                    commands.trigger(edit(Keyframes::new(changed), false));
+                }
+            }
+        });
+
+        if let [(index, component)] = curve_editor.selection.iter().copied().collect::<Vec<_>>()[..]
+        {
+            let keyframe = &keyframes.inner()[index];
+            ui.separator();
+            let mut time = keyframe.time.to_seconds(tempo_map);
+            let time_response = ui.add(
+                egui::DragValue::new(&mut time)
+                    .speed(0.01)
+                    .range(0.0..=f64::MAX)
+                    .prefix("time: "),
+            );
+            let mut value = get_component(&keyframe.value, component);
+            let value_response = ui.add(
+                egui::DragValue::new(&mut value)
+                    .speed(0.01)
+                    .prefix("value: "),
+            );
+
+            for (response, is_time) in [(&time_response, true), (&value_response, false)] {
+                if !response.changed() {
+                    continue;
+                }
+                let mut changed = keyframes.clone();
+                let new_selection = {
+                    let mut inner = changed.inner().clone();
+                    if is_time {
+                        inner[index].time = inner[index].time.moved_to(time, tempo_map);
+                    } else {
+                        set_component(&mut inner[index].value, component, value);
+                    }
+                    changed = Keyframes::new(inner);
+                    let new_indices = changed.sort_by_time(tempo_map);
+                    (new_indices[index], component)
+                };
+                curve_editor.selection = HashSet::from([new_selection]);
+                let merge = response.dragged() && !response.drag_started();
+                commands.trigger(edit(changed, merge));
+            }
+        }
+    });
+
+    // view
+    let (lowest, highest) = *curve_editor.value_range.get_or_insert_with(|| {
+        let values = keyframes.inner().iter().flat_map(|keyframe| {
+            (0..component_names(&keyframe.value).len())
+                .map(move |component| get_component(&keyframe.value, component))
+        });
+        let (min, max) = values.fold((f32::MAX, f32::MIN), |(min, max), value| {
+            (min.min(value), max.max(value))
+        });
+        if min > max {
+            (0.0, 1.0)
+        } else if max - min < 1e-3 {
+            (min - 1.0, max + 1.0)
+        } else {
+            let padding = (max - min) * 0.1;
+            (min - padding, max + padding)
+        }
+    });
+
+    let (response, painter) = ui.allocate_painter(
+        Vec2::new(ui.available_width(), PLOT_HEIGHT),
+        Sense::click_and_drag(),
+    );
+    let rect = response.rect;
+    let to_screen = |time: f64, value: f32| {
+        Pos2::new(
+            rect.left() + (time / sequence.length) as f32 * rect.width(),
+            rect.bottom() - (value - lowest) / (highest - lowest) * rect.height(),
+        )
+    };
+    let from_screen = |pos: Pos2| {
+        (
+            ((pos.x - rect.left()) / rect.width()) as f64 * sequence.length,
+            lowest + (rect.bottom() - pos.y) / rect.height() * (highest - lowest),
+        )
+    };
+
+    // background, zero line, and range labels
+    painter.rect_filled(rect, 0.0, Color32::from_black_alpha(128));
+    if lowest < 0.0 && highest > 0.0 {
+        let y = to_screen(0.0, 0.0).y;
+        painter.line_segment(
+            [Pos2::new(rect.left(), y), Pos2::new(rect.right(), y)],
+            Stroke::new(1.0, Color32::from_white_alpha(48)),
+        );
+    }
+    for (value, anchor, y) in [
+        (highest, Align2::LEFT_TOP, rect.top()),
+        (lowest, Align2::LEFT_BOTTOM, rect.bottom()),
+    ] {
+        painter.text(
+            Pos2::new(rect.left() + 2.0, y),
+            anchor,
+            format!("{:.2}", value),
+            FontId::proportional(10.0),
+            Color32::from_white_alpha(128),
+        );
+    }
+
+    // curves
+    let plot_painter = painter.with_clip_rect(rect);
+    for (key, default) in &parameters {
+        if !is_visible(curve_editor, key) {
+            continue;
+        }
+        for component in 0..component_names(default).len() {
+            let points = (0..=CURVE_SAMPLES)
+                .map(|sample| {
+                    let time = sequence.length * sample as f64 / CURVE_SAMPLES as f64;
+                    let value =
+                        sample_component(keyframes, key, default, component, time, tempo_map);
+                    to_screen(time, value)
+                })
+                .collect();
+            plot_painter.add(Shape::line(
+                points,
+                Stroke::new(1.5, curve_color(key, component)),
+            ));
+        }
+    }
+
+    let pointer = ui.ctx().pointer_interact_pos().map(from_screen);
+    let modifiers = ui.input(|input| input.modifiers);
+    let mut drag_started = None;
+    let mut dragging = response.dragged();
+    let mut drag_stopped = response.drag_stopped();
+
+    // tangent handles of selected Bézier keyframes, for each of which the
+    // incoming handle belongs to its own interpolation and the outgoing one to
+    // the interpolation of the next keyframe
+    for &(index, component) in &curve_editor.selection {
+        if !is_visible(curve_editor, &keyframes.inner()[index].key) {
+            continue;
+        }
+        let segments = [
+            (previous_with_key(keyframes, index), Some(index), false),
+            (Some(index), next_with_key(keyframes, index), true),
+        ];
+        for (start, end, outgoing) in segments {
+            let (Some(start), Some(end)) = (start, end) else {
+                continue;
+            };
+            let InterpolationType::BEZIER { x1, y1, x2, y2 } = keyframes.inner()[end].interpolation
+            else {
+                continue;
+            };
+            let point = |keyframe_i: usize| {
+                let keyframe = &keyframes.inner()[keyframe_i];
+                (
+                    keyframe.time.to_seconds(tempo_map),
+                    get_component(&keyframe.value, component),
+                )
+            };
+            let ((t0, v0), (t1, v1)) = (point(start), point(end));
+            let (handle_x, handle_y, anchor) = if outgoing {
+                (x1, y1, (t0, v0))
+            } else {
+                (x2, y2, (t1, v1))
+            };
+            let handle_pos = to_screen(t0 + handle_x as f64 * (t1 - t0), v0 + handle_y * (v1 - v0));
+            plot_painter.line_segment(
+                [to_screen(anchor.0, anchor.1), handle_pos],
+                Stroke::new(1.0, Color32::GRAY),
+            );
+            plot_painter.rect_filled(
+                Rect::from_center_size(handle_pos, Vec2::splat(HANDLE_SIZE * 2.0)),
+                0.0,
+                Color32::LIGHT_GRAY,
+            );
+
+            let handle_response = ui.interact(
+                Rect::from_center_size(handle_pos, Vec2::splat(HANDLE_SIZE * 3.0)),
+                response.id.with(("tangent", index, component, outgoing)),
+                Sense::drag(),
+            );
+            if handle_response.drag_started() {
+                drag_started = Some(CurveDragKind::Tangent {
+                    index: end,
+                    component,
+                    outgoing,
+                });
+            }
+            track_response(&handle_response, &mut dragging, &mut drag_stopped);
+        }
+    }
+
+    // keyframe points
+    let mut clicked_point = None;
+    for (index, keyframe) in keyframes.inner().iter().enumerate() {
+        if !is_visible(curve_editor, &keyframe.key) {
+            continue;
+        }
+        let time = keyframe.time.to_seconds(tempo_map);
+        for component in 0..component_names(&keyframe.value).len() {
+            let center = to_screen(time, get_component(&keyframe.value, component));
+            if !rect.contains(center) {
+                continue;
+            }
+            let is_selected = curve_editor.selection.contains(&(index, component));
+            plot_painter.circle(
+                center,
+                POINT_SIZE,
+                if is_selected {
+                    Color32::WHITE
+                } else {
+                    curve_color(&keyframe.key, component)
+                },
+                Stroke::new(1.0, Color32::BLACK),
+            );
+
+            let point_response = ui
+                .interact(
+                    Rect::from_center_size(center, Vec2::splat(POINT_SIZE * 2.5)),
+                    response.id.with(("point", index, component)),
+                    Sense::click_and_drag(),
+                )
+                .on_hover_text(format!(
+                    "{} {}",
+                    keyframe.key,
+                    component_names(&keyframe.value)[component]
+                ));
+            if point_response.clicked() {
+                clicked_point = Some((index, component));
+            }
+            if point_response.drag_started() {
+                if !is_selected {
+                    if !modifiers.shift {
+                        curve_editor.selection.clear();
+                    }
+                    curve_editor.selection.insert((index, component));
+                }
+                drag_started = Some(CurveDragKind::Move);
+            }
+            track_response(&point_response, &mut dragging, &mut drag_stopped);
+        }
+    }
+
+    // selection by clicking
+    if let Some(point) = clicked_point {
+        if !modifiers.shift {
+            curve_editor.selection.clear();
+            curve_editor.selection.insert(point);
+        } else if !curve_editor.selection.remove(&point) {
+            curve_editor.selection.insert(point);
+        }
+    } else if response.clicked() && !modifiers.shift {
+        curve_editor.selection.clear();
+    }
+
+    // start a drag, turning moves into scales while ctrl is held
+    if response.drag_started() {
+        drag_started = Some(CurveDragKind::BoxSelect {
+            additive: modifiers.shift,
+        });
+    }
+    if let (Some(kind), Some(grab)) = (drag_started, pointer) {
+        let kind = match kind {
+            CurveDragKind::Move if modifiers.ctrl => CurveDragKind::Scale {
+                pivot: selection_center(&curve_editor.selection, keyframes, tempo_map),
+            },
+            kind => kind,
+        };
+        curve_editor.drag = Some(CurveDrag {
+            kind,
+            original: keyframes.clone(),
+            original_selection: curve_editor.selection.clone(),
+            grab,
+            edited: false,
+        });
+    }
+
+    if dragging
+        && let Some(pointer) = pointer
+        && let Some(drag) = curve_editor.drag.as_mut()
+    {
+        match drag.kind {
+            CurveDragKind::BoxSelect { .. } => {
+                plot_painter.rect_stroke(
+                    Rect::from_two_pos(
+                        to_screen(drag.grab.0, drag.grab.1),
+                        to_screen(pointer.0, pointer.1),
+                    ),
+                    0.0,
+                    Stroke::new(1.0, Color32::WHITE),
+                    egui::StrokeKind::Inside,
+                );
+            }
+            kind => {
+                if let Some((changed, new_selection)) =
+                    drag_keyframes(drag, kind, pointer, tempo_map)
+                {
+                    curve_editor.selection = new_selection;
+                    commands.trigger(edit(changed, drag.edited));
+                    drag.edited = true;
+                }
+            }
+        }
+    }
+
+    if drag_stopped
+        && let Some(drag) = curve_editor.drag.take()
+        && let (CurveDragKind::BoxSelect { additive }, Some(pointer)) = (drag.kind, pointer)
+    {
+        let selection_rect = Rect::from_two_pos(
+            to_screen(drag.grab.0, drag.grab.1),
+            to_screen(pointer.0, pointer.1),
+        );
+        if !additive {
+            curve_editor.selection.clear();
+        }
+        for (index, keyframe) in keyframes.inner().iter().enumerate() {
+            if !is_visible(curve_editor, &keyframe.key) {
+                continue;
+            }
+            let time = keyframe.time.to_seconds(tempo_map);
+            for component in 0..component_names(&keyframe.value).len() {
+                let pos = to_screen(time, get_component(&keyframe.value, component));
+                if selection_rect.contains(pos) {
+                    curve_editor.selection.insert((index, component));
+                }
+            }
+        }
+    }
+
+    // deleting the selection
+    if response.contains_pointer()
+        && !curve_editor.selection.is_empty()
+        && ui.input(|input| input.key_pressed(egui::Key::Delete))
+    {
+        let remaining = keyframes
+            .inner()
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !selected_indices.contains(index))
+            .map(|(_, keyframe)| keyframe.clone())
+            .collect();
+        curve_editor.selection.clear();
+        commands.trigger(edit(Keyframes::new(remaining), false));
+    }
+}
+
+/// Records whether an item is being dragged, or has stopped being dragged.
+fn track_response(response: &Response, dragging: &mut bool, drag_stopped: &mut bool) {
+    *dragging |= response.dragged();
+    *drag_stopped |= response.drag_stopped();
+}
+
+/// Finds the center of the bounding box of the selected keyframes.
+fn selection_center(
+    selection: &HashSet<(usize, usize)>,
+    keyframes: &Keyframes,
+    tempo_map: &TempoMap,
+) -> (f64, f32) {
+    let mut time_range = (f64::MAX, f64::MIN);
+    let mut value_range = (f32::MAX, f32::MIN);
+    for &(index, component) in selection {
+        let keyframe = &keyframes.inner()[index];
+        let time = keyframe.time.to_seconds(tempo_map);
+        let value = get_component(&keyframe.value, component);
+        time_range = (time_range.0.min(time), time_range.1.max(time));
+        value_range = (value_range.0.min(value), value_range.1.max(value));
+    }
+    (
+        (time_range.0 + time_range.1) / 2.0,
+        (value_range.0 + value_range.1) / 2.0,
+    )
+}
+
+/// Computes the keyframes for a move, scale, or tangent drag, given where the
+/// pointer is now, along with the selection updated to follow the keyframes
+/// after they have been put back in order.
+fn drag_keyframes(
+    drag: &CurveDrag,
+    kind: CurveDragKind,
+    pointer: (f64, f32),
+    tempo_map: &TempoMap,
+) -> Option<(Keyframes, HashSet<(usize, usize)>)> {
+    let mut changed = drag.original.inner().clone();
+
+    match kind {
+        CurveDragKind::Move | CurveDragKind::Scale { .. } => {
+            // scale factors are how far the pointer now is from the pivot,
+            // relative to where it grabbed
+            let scale = |pointer: f64, grab: f64, pivot: f64| {
+                if (grab - pivot).abs() > 1e-6 {
+                    (pointer - pivot) / (grab - pivot)
+                } else {
+                    1.0
+                }
+            };
+            let time_of = |time: f64| match kind {
+                CurveDragKind::Scale { pivot } => {
+                    pivot.0 + (time - pivot.0) * scale(pointer.0, drag.grab.0, pivot.0)
+                }
+                _ => time + pointer.0 - drag.grab.0,
+            };
+            let value_of = |value: f32| match kind {
+                CurveDragKind::Scale { pivot } => {
+                    let value_scale =
+                        scale(pointer.1 as f64, drag.grab.1 as f64, pivot.1 as f64) as f32;
+                    pivot.1 + (value - pivot.1) * value_scale
+                }
+                _ => value + pointer.1 - drag.grab.1,
+            };
+
+            let moved_indices: HashSet<usize> = drag
+                .original_selection
+                .iter()
+                .map(|(index, _)| *index)
+                .collect();
+            for index in moved_indices {
+                let keyframe = changed.get_mut(index)?;
+                let time = time_of(keyframe.time.to_seconds(tempo_map)).max(0.0);
+                keyframe.time = keyframe.time.moved_to(time, tempo_map);
+            }
+            for &(index, component) in &drag.original_selection {
+                let keyframe = changed.get_mut(index)?;
+                let value = value_of(get_component(&keyframe.value, component));
+                set_component(&mut keyframe.value, component, value);
+            }
+        }
+        CurveDragKind::Tangent {
+            index,
+            component,
+            outgoing,
+        } => {
+            let start = previous_with_key(&drag.original, index)?;
+            let point = |keyframe_i: usize| {
+                let keyframe = &changed[keyframe_i];
+                (
+                    keyframe.time.to_seconds(tempo_map),
+                    get_component(&keyframe.value, component),
+                )
+            };
+            let ((t0, v0), (t1, v1)) = (point(start), point(index));
+            let InterpolationType::BEZIER {
+                mut x1,
+                mut y1,
+                mut x2,
+                mut y2,
+            } = changed[index].interpolation
+            else {
+                return None;
+            };
+            let (handle_x, handle_y) = if outgoing {
+                (&mut x1, &mut y1)
+            } else {
+                (&mut x2, &mut y2)
+            };
+            if t1 > t0 {
+                *handle_x = ((pointer.0 - t0) / (t1 - t0)).clamp(0.0, 1.0) as f32;
+            }
+            // flat segments give no way to tell how far along a value is
+            if (v1 - v0).abs() > 1e-6 {
+                *handle_y = (pointer.1 - v0) / (v1 - v0);
+            }
+            changed[index].interpolation = InterpolationType::BEZIER { x1, y1, x2, y2 };
+        }
+        CurveDragKind::BoxSelect { .. } => return None,
+    }
+
+    let mut keyframes = Keyframes::new(changed);
+    let new_indices = keyframes.sort_by_time(tempo_map);
+    let selection = drag
+        .original_selection
+        .iter()
+        .map(|&(index, component)| (new_indices[index], component))
+        .collect();
+    Some((keyframes, selection))
+}
@@ -0,0 +1,148 @@
+use bevy::prelude::{Color, ColorToComponents, Commands, Srgba, Vec3};
+use bevy_egui::egui::{self, Ui};
+
+use crate::{
+    editing::{ApplyEdit, EditCommand},
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{
+        effects::parameters::ParameterSchema, keyframes::KeyframeValue, sequences::Sequence,
+        tracks::TrackContents,
+    },
+    ui::timeline::TimelineEditor,
+};
+
+/// Draws the inspector for the effect track selected in the timeline editor,
+/// with a widget for every parameter the effect declares. Edits go through the
+/// edit history. Parameters animated by keyframes can't be edited here, since
+/// the keyframes decide their value; they are edited in the curve editor
+/// instead.
+pub fn draw_effect_inspector(
+    ui: &mut Ui,
+    commands: &mut Commands,
+    timeline_editor: &TimelineEditor,
+    sequence_store: &SimpleStore<Sequence>,
+    primary_sequence: Option<SimpleHandle<Sequence>>,
+) {
+    let selected = primary_sequence.and_then(|primary| {
+        let handle = timeline_editor.path.last().copied().unwrap_or(primary);
+        let track = timeline_editor.selected_track?;
+        match &sequence_store.get(handle)?.tracks.get(track)?.contents {
+            TrackContents::EffectTrack {
+                effect_init_info,
+                effect_keyframes,
+            } => Some((handle, track, effect_init_info, effect_keyframes)),
+            _ => None,
+        }
+    });
+    let Some((handle, track, effect_info, keyframes)) = selected else {
+        ui.label("Select an effect track in the timeline to inspect its effect");
+        return;
+    };
+
+    ui.heading(effect_info.name());
+    let schemas = effect_info.parameters();
+    if schemas.is_empty() {
+        ui.label("This effect has no parameters");
+        return;
+    }
+
+    egui::Grid::new("effect_parameters")
+        .num_columns(2)
+        .show(ui, |ui| {
+            for schema in schemas {
+                let Some(mut value) = effect_info.get_parameter(schema.name) else {
+                    continue;
+                };
+                let animated = keyframes
+                    .inner()
+                    .iter()
+                    .any(|keyframe| keyframe.key == schema.name);
+
+                ui.label(schema.name);
+                let (response, reset) = ui
+                    .add_enabled_ui(!animated, |ui| {
+                        let response = ui
+                            .horizontal(|ui| parameter_widget(ui, schema, &mut value))
+                            .inner;
+                        let reset = ui
+                            .button("Reset")
+                            .on_hover_text("Set the parameter back to its default")
+                            .clicked();
+                        (response, reset)
+                    })
+                    .inner;
+                ui.end_row();
+                if animated {
+                    response.on_disabled_hover_text(
+                        "Animated by keyframes; edit it in the curve editor",
+                    );
+                    continue;
+                }
+
+                let (new_value, merge) = if reset {
+                    (schema.default.clone(), false)
+                } else if response.changed() {
+                    (value, response.dragged() && !response.drag_started())
+                } else {
+                    continue;
+                };
+                let mut changed = effect_info.clone();
+                if changed.set_parameter(schema.name, new_value).is_ok() {
+                    commands.trigger(ApplyEdit::new(
+                        EditCommand::SetEffect {
+                            sequence: handle,
+                            track,
+                            effect_info: changed,
+                        },
+                        merge,
+                    ));
+                }
+            }
+        });
+}
+
+/// Draws the widget for a single parameter value, returning the response of
+/// the part that was interacted with.
+fn parameter_widget(
+    ui: &mut Ui,
+    schema: &ParameterSchema,
+    value: &mut KeyframeValue,
+) -> egui::Response {
+    let suffix = schema.unit.suffix();
+    match value {
+        KeyframeValue::FloatKeyframe(value) => {
+            let mut drag_value = egui::DragValue::new(value).speed(0.1).suffix(suffix);
+            if let Some((min, max)) = schema.range {
+                drag_value = drag_value.range(min..=max);
+            }
+            ui.add(drag_value)
+        }
+        KeyframeValue::ColorKeyframe(color) => {
+            let mut rgba = Srgba::from(*color).to_f32_array();
+            let response = ui.color_edit_button_rgba_unmultiplied(&mut rgba);
+            if response.changed() {
+                *color = Color::srgba(rgba[0], rgba[1], rgba[2], rgba[3]);
+            }
+            response
+        }
+        KeyframeValue::Vec3Keyframe(vector) => {
+            let mut components = vector.to_array();
+            let mut response: Option<egui::Response> = None;
+            for (component, prefix) in components.iter_mut().zip(["x: ", "y: ", "z: "]) {
+                let component_response = ui.add(
+                    egui::DragValue::new(component)
+                        .speed(0.1)
+                        .prefix(prefix)
+                        .suffix(suffix),
+                );
+                response = Some(match response {
+                    Some(response) => response | component_response,
+                    None => component_response,
+                });
+            }
+            *vector = Vec3::from_array(components);
+            response.expect("vectors have components")
+        }
+        KeyframeValue::BlendingModeKeyframe(_) => ui.label("-"),
+    }
+}
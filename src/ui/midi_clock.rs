@@ -0,0 +1,33 @@
+use bevy_egui::egui::{self, Ui};
+
+use crate::{midi::clock::MidiClock, timecode::TimecodeSettings};
+
+/// Draws whether playback follows MIDI clock, along with the tempo, transport
+/// state, and song position of the clock.
+pub fn draw_midi_clock(
+    ui: &mut Ui,
+    clock: &mut MidiClock,
+    timecode_settings: &TimecodeSettings,
+    now: f64,
+) {
+    ui.checkbox(&mut clock.follow, "Follow MIDI clock");
+    if clock.follow && !timecode_settings.is_master() {
+        ui.label("Timecode chase takes precedence");
+    }
+
+    let tempo = clock
+        .bpm()
+        .map_or("No clock".to_string(), |bpm| format!("{:.1} BPM", bpm));
+    let transport = if clock.is_running(now) {
+        "running"
+    } else {
+        "stopped"
+    };
+    ui.label(format!(
+        "{}, {}, beat {:.2}",
+        tempo,
+        transport,
+        clock.beats_at(now) + 1.0
+    ));
+    ui.add(egui::ProgressBar::new(clock.beat_phase(now) as f32));
+}
@@ -0,0 +1,449 @@
+use bevy::prelude::{Commands, Vec3, Vec4};
+use bevy_egui::egui::{self, Ui};
+
+use crate::{
+    editing::{ApplyEdit, EditCommand},
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{
+        audio_bindings::{AudioBinding, AudioFeature},
+        effects::parameters::{ParameterKind, ParameterSchema},
+        modulators::{Modulator, ModulatorRate, ModulatorShape, ModulatorTarget},
+        sequences::Sequence,
+        tracks::TrackContents,
+    },
+    ui::timeline::TimelineEditor,
+};
+
+/// Draws the modulators and audio bindings of the track selected in the
+/// timeline editor. Every track can modulate its factor, and effect tracks can
+/// also modulate the parameters of their effect. All edits go through the edit
+/// history.
+pub fn draw_modulators(
+    ui: &mut Ui,
+    commands: &mut Commands,
+    timeline_editor: &TimelineEditor,
+    sequence_store: &SimpleStore<Sequence>,
+    primary_sequence: Option<SimpleHandle<Sequence>>,
+) {
+    let selected = primary_sequence.and_then(|primary| {
+        let handle = timeline_editor.path.last().copied().unwrap_or(primary);
+        let track = timeline_editor.selected_track?;
+        Some((
+            handle,
+            track,
+            sequence_store.get(handle)?.tracks.get(track)?,
+        ))
+    });
+    let Some((handle, track, track_ref)) = selected else {
+        ui.label("Select a track in the timeline to edit its modulators");
+        return;
+    };
+
+    let schemas = match &track_ref.contents {
+        TrackContents::EffectTrack {
+            effect_init_info, ..
+        } => effect_init_info.parameters(),
+        _ => &[],
+    };
+    let modulators = &track_ref.info.modulators;
+    let edit = |modulators: Vec<Modulator>, merge: bool| {
+        ApplyEdit::new(
+            EditCommand::SetModulators {
+                sequence: handle,
+                track,
+                modulators,
+            },
+            merge,
+        )
+    };
+
+    let mut changed: Option<(Vec<Modulator>, bool)> = None;
+    for (modulator_i, modulator) in modulators.iter().enumerate() {
+        let mut new_modulator = modulator.clone();
+        let mut remove = false;
+        let header = format!("{}: {}", modulator_i + 1, target_label(&modulator.target));
+        let response = egui::CollapsingHeader::new(header)
+            .id_salt(("modulator", modulator_i))
+            .default_open(true)
+            .show(ui, |ui| {
+                let response = draw_modulator(ui, modulator_i, &mut new_modulator, schemas);
+                remove = ui.button("Remove").clicked();
+                response
+            })
+            .body_returned;
+
+        if remove {
+            let mut new_modulators = modulators.clone();
+            new_modulators.remove(modulator_i);
+            changed = Some((new_modulators, false));
+        } else if let Some(response) = response
+            && new_modulator != *modulator
+        {
+            let mut new_modulators = modulators.clone();
+            new_modulators[modulator_i] = new_modulator;
+            changed = Some((
+                new_modulators,
+                response.dragged() && !response.drag_started(),
+            ));
+        }
+    }
+
+    if ui.button("Add modulator").clicked() {
+        let mut new_modulators = modulators.clone();
+        new_modulators.push(Modulator::new(ModulatorTarget::Factor));
+        changed = Some((new_modulators, false));
+    }
+
+    if let Some((new_modulators, merge)) = changed {
+        commands.trigger(edit(new_modulators, merge));
+    }
+
+    ui.separator();
+    ui.heading("Audio bindings");
+    let audio_bindings = &track_ref.info.audio_bindings;
+    let mut changed: Option<(Vec<AudioBinding>, bool)> = None;
+    for (binding_i, binding) in audio_bindings.iter().enumerate() {
+        let mut new_binding = binding.clone();
+        let mut remove = false;
+        let header = format!("{}: {}", binding_i + 1, target_label(&binding.target));
+        let response = egui::CollapsingHeader::new(header)
+            .id_salt(("audio_binding", binding_i))
+            .default_open(true)
+            .show(ui, |ui| {
+                let response = draw_audio_binding(ui, binding_i, &mut new_binding, schemas);
+                remove = ui.button("Remove").clicked();
+                response
+            })
+            .body_returned;
+
+        if remove {
+            let mut new_bindings = audio_bindings.clone();
+            new_bindings.remove(binding_i);
+            changed = Some((new_bindings, false));
+        } else if let Some(response) = response
+            && new_binding != *binding
+        {
+            let mut new_bindings = audio_bindings.clone();
+            new_bindings[binding_i] = new_binding;
+            changed = Some((new_bindings, response.dragged() && !response.drag_started()));
+        }
+    }
+
+    if ui.button("Add audio binding").clicked() {
+        let mut new_bindings = audio_bindings.clone();
+        new_bindings.push(AudioBinding::new(ModulatorTarget::Factor));
+        changed = Some((new_bindings, false));
+    }
+
+    if let Some((audio_bindings, merge)) = changed {
+        commands.trigger(ApplyEdit::new(
+            EditCommand::SetAudioBindings {
+                sequence: handle,
+                track,
+                audio_bindings,
+            },
+            merge,
+        ));
+    }
+}
+
+/// Draws the settings of a single modulator, returning the combined response
+/// of its widgets.
+fn draw_modulator(
+    ui: &mut Ui,
+    modulator_i: usize,
+    modulator: &mut Modulator,
+    schemas: &[ParameterSchema],
+) -> egui::Response {
+    egui::Grid::new(("modulator_settings", modulator_i))
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Target");
+            let mut response = egui::ComboBox::from_id_salt(("modulator_target", modulator_i))
+                .selected_text(target_label(&modulator.target))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut modulator.target, ModulatorTarget::Factor, "factor");
+                    for schema in schemas {
+                        let target = ModulatorTarget::Parameter(schema.name.to_string());
+                        ui.selectable_value(&mut modulator.target, target, schema.name);
+                    }
+                })
+                .response;
+            ui.end_row();
+
+            ui.label("Shape");
+            response |= egui::ComboBox::from_id_salt(("modulator_shape", modulator_i))
+                .selected_text(shape_label(modulator.shape))
+                .show_ui(ui, |ui| {
+                    for shape in ModulatorShape::ALL {
+                        ui.selectable_value(&mut modulator.shape, shape, shape_label(shape));
+                    }
+                })
+                .response;
+            ui.end_row();
+
+            ui.label("Rate");
+            ui.horizontal(|ui| {
+                let (mut value, mut synced) = match modulator.rate {
+                    ModulatorRate::Hertz(hertz) => (hertz, false),
+                    ModulatorRate::Beats(beats) => (beats, true),
+                };
+                response |= if synced {
+                    ui.add(
+                        egui::DragValue::new(&mut value)
+                            .speed(0.01)
+                            .range(0.01..=f64::MAX)
+                            .suffix(" beats"),
+                    )
+                } else {
+                    ui.add(egui::DragValue::new(&mut value).speed(0.01).suffix(" Hz"))
+                };
+                response |= ui.checkbox(&mut synced, "Sync to tempo").on_hover_text(
+                    "Count the rate in beats per cycle rather than cycles per second",
+                );
+                modulator.rate = if synced {
+                    ModulatorRate::Beats(value.max(0.01))
+                } else {
+                    ModulatorRate::Hertz(value)
+                };
+            });
+            ui.end_row();
+
+            ui.label("Phase");
+            response |= ui.add(
+                egui::DragValue::new(&mut modulator.phase)
+                    .speed(0.01)
+                    .suffix(" cycles"),
+            );
+            ui.end_row();
+
+            ui.label("Spread")
+                .on_hover_text("Cycles per unit of distance, to run the wave across fixtures");
+            response |= vector_widget(ui, &mut modulator.spread, 0.001);
+            ui.end_row();
+
+            let kind = target_kind(&modulator.target, schemas);
+            ui.label("Depth");
+            response |= components_widget(ui, &mut modulator.depth, kind);
+            ui.end_row();
+
+            ui.label("Offset");
+            response |= components_widget(ui, &mut modulator.offset, kind);
+            ui.end_row();
+
+            if modulator.shape == ModulatorShape::SmoothRandom {
+                ui.label("Seed");
+                response |= ui.add(egui::DragValue::new(&mut modulator.seed));
+                ui.end_row();
+            }
+
+            response
+        })
+        .inner
+}
+
+/// Draws the settings of a single audio binding, returning the combined
+/// response of its widgets.
+fn draw_audio_binding(
+    ui: &mut Ui,
+    binding_i: usize,
+    binding: &mut AudioBinding,
+    schemas: &[ParameterSchema],
+) -> egui::Response {
+    egui::Grid::new(("audio_binding_settings", binding_i))
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Target");
+            let mut response = egui::ComboBox::from_id_salt(("audio_binding_target", binding_i))
+                .selected_text(target_label(&binding.target))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut binding.target, ModulatorTarget::Factor, "factor");
+                    for schema in schemas {
+                        let target = ModulatorTarget::Parameter(schema.name.to_string());
+                        ui.selectable_value(&mut binding.target, target, schema.name);
+                    }
+                })
+                .response;
+            ui.end_row();
+
+            ui.label("Feature");
+            let band = match binding.feature {
+                AudioFeature::BandEnergy { low, high }
+                | AudioFeature::Flux { low, high }
+                | AudioFeature::Onset { low, high } => (low, high),
+                AudioFeature::Rms => (20.0, 150.0),
+            };
+            response |= egui::ComboBox::from_id_salt(("audio_binding_feature", binding_i))
+                .selected_text(feature_label(binding.feature))
+                .show_ui(ui, |ui| {
+                    let (low, high) = band;
+                    for feature in [
+                        AudioFeature::BandEnergy { low, high },
+                        AudioFeature::Rms,
+                        AudioFeature::Flux { low, high },
+                        AudioFeature::Onset { low, high },
+                    ] {
+                        ui.selectable_value(&mut binding.feature, feature, feature_label(feature));
+                    }
+                })
+                .response;
+            ui.end_row();
+
+            if let AudioFeature::BandEnergy { low, high }
+            | AudioFeature::Flux { low, high }
+            | AudioFeature::Onset { low, high } = &mut binding.feature
+            {
+                ui.label("Band");
+                ui.horizontal(|ui| {
+                    response |= ui.add(
+                        egui::DragValue::new(low)
+                            .speed(1.0)
+                            .range(0.0..=20000.0)
+                            .suffix(" Hz"),
+                    );
+                    response |= ui.add(
+                        egui::DragValue::new(high)
+                            .speed(1.0)
+                            .range(0.0..=20000.0)
+                            .prefix("to ")
+                            .suffix(" Hz"),
+                    );
+                });
+                ui.end_row();
+            }
+
+            ui.label("Gain");
+            response |= ui.add(egui::DragValue::new(&mut binding.gain).speed(0.001));
+            ui.end_row();
+
+            ui.label("Threshold")
+                .on_hover_text("Level below which the binding doesn't react");
+            response |= ui.add(
+                egui::DragValue::new(&mut binding.threshold)
+                    .speed(0.01)
+                    .range(0.0..=0.99),
+            );
+            ui.end_row();
+
+            ui.label("Curve")
+                .on_hover_text("Exponent the level is raised to; above 1 favours loud parts");
+            response |= ui.add(
+                egui::DragValue::new(&mut binding.curve)
+                    .speed(0.01)
+                    .range(0.01..=f32::MAX),
+            );
+            ui.end_row();
+
+            ui.label("Attack");
+            response |= ui.add(
+                egui::DragValue::new(&mut binding.attack)
+                    .speed(0.001)
+                    .range(0.0..=f32::MAX)
+                    .suffix(" s"),
+            );
+            ui.end_row();
+
+            ui.label("Release");
+            response |= ui.add(
+                egui::DragValue::new(&mut binding.release)
+                    .speed(0.001)
+                    .range(0.0..=f32::MAX)
+                    .suffix(" s"),
+            );
+            ui.end_row();
+
+            let kind = target_kind(&binding.target, schemas);
+            ui.label("Depth");
+            response |= components_widget(ui, &mut binding.depth, kind);
+            ui.end_row();
+
+            ui.label("Offset");
+            response |= components_widget(ui, &mut binding.offset, kind);
+            ui.end_row();
+
+            response
+        })
+        .inner
+}
+
+/// Draws three drag values for the components of a vector.
+fn vector_widget(ui: &mut Ui, vector: &mut Vec3, speed: f64) -> egui::Response {
+    ui.horizontal(|ui| {
+        let mut components = vector.to_array();
+        let mut response: Option<egui::Response> = None;
+        for (component, prefix) in components.iter_mut().zip(["x: ", "y: ", "z: "]) {
+            let component_response =
+                ui.add(egui::DragValue::new(component).speed(speed).prefix(prefix));
+            response = Some(match response {
+                Some(response) => response | component_response,
+                None => component_response,
+            });
+        }
+        *vector = Vec3::from_array(components);
+        response.expect("vectors have components")
+    })
+    .inner
+}
+
+/// Draws drag values for the components of a depth or offset that apply to a
+/// target of the given kind (see `Modulator`).
+fn components_widget(ui: &mut Ui, components: &mut Vec4, kind: ParameterKind) -> egui::Response {
+    let prefixes: &[&str] = match kind {
+        ParameterKind::Float => &[""],
+        ParameterKind::Vec3 => &["x: ", "y: ", "z: "],
+        ParameterKind::Color => &["L: ", "a: ", "b: ", "alpha: "],
+    };
+    ui.horizontal(|ui| {
+        let mut values = components.to_array();
+        let mut response: Option<egui::Response> = None;
+        for (value, prefix) in values.iter_mut().zip(prefixes) {
+            let component_response =
+                ui.add(egui::DragValue::new(value).speed(0.01).prefix(*prefix));
+            response = Some(match response {
+                Some(response) => response | component_response,
+                None => component_response,
+            });
+        }
+        *components = Vec4::from_array(values);
+        response.expect("targets have at least one component")
+    })
+    .inner
+}
+
+/// Gets the kind of value a target holds. Targets that no longer exist are
+/// treated as floats.
+fn target_kind(target: &ModulatorTarget, schemas: &[ParameterSchema]) -> ParameterKind {
+    match target {
+        ModulatorTarget::Factor => ParameterKind::Float,
+        ModulatorTarget::Parameter(name) => schemas
+            .iter()
+            .find(|schema| schema.name == name)
+            .map_or(ParameterKind::Float, |schema| schema.kind),
+    }
+}
+
+fn target_label(target: &ModulatorTarget) -> &str {
+    match target {
+        ModulatorTarget::Factor => "factor",
+        ModulatorTarget::Parameter(name) => name,
+    }
+}
+
+fn feature_label(feature: AudioFeature) -> &'static str {
+    match feature {
+        AudioFeature::BandEnergy { .. } => "Band energy",
+        AudioFeature::Rms => "RMS",
+        AudioFeature::Flux { .. } => "Flux",
+        AudioFeature::Onset { .. } => "Onset",
+    }
+}
+
+fn shape_label(shape: ModulatorShape) -> &'static str {
+    match shape {
+        ModulatorShape::Sine => "Sine",
+        ModulatorShape::Triangle => "Triangle",
+        ModulatorShape::Saw => "Saw",
+        ModulatorShape::Square => "Square",
+        ModulatorShape::SmoothRandom => "Smooth random",
+    }
+}
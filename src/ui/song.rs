@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Ui};
+use std::path::PathBuf;
+
+use crate::{
+    audio::song::SongLibrary,
+    editing::{ApplyEdit, EditCommand},
+    simple_store::SimpleHandle,
+    timeline::sequences::*,
+};
+
+/// Bevy resource that holds the state of the song window.
+#[derive(Resource, Debug, Default)]
+pub struct SongEditor {
+    /// The path typed in for attaching a song.
+    pub path: String,
+}
+
+/// Draws the audio attached to a sequence: attaching and detaching a song,
+/// its offset, and whether it could be decoded.
+pub fn draw_song(
+    ui: &mut Ui,
+    commands: &mut Commands,
+    editor: &mut SongEditor,
+    library: &mut SongLibrary,
+    handle: SimpleHandle<Sequence>,
+    sequence: &Sequence,
+) {
+    ui.horizontal(|ui| {
+        ui.label("Path");
+        ui.text_edit_singleline(&mut editor.path);
+        if ui.button("Attach").clicked() {
+            let path = PathBuf::from(editor.path.trim());
+            // pick up any changes to a file attached before
+            library.reload(&path);
+            let audio = match &sequence.audio {
+                Some(audio) => SequenceAudio {
+                    path,
+                    ..audio.clone()
+                },
+                None => SequenceAudio::new(path),
+            };
+            commands.trigger(ApplyEdit::new(
+                EditCommand::SetAudio {
+                    sequence: handle,
+                    audio: Some(audio),
+                },
+                false,
+            ));
+        }
+    });
+
+    let Some(audio) = &sequence.audio else {
+        ui.label("No song attached");
+        return;
+    };
+
+    ui.horizontal(|ui| {
+        ui.label(audio.path.display().to_string());
+        if ui.button("Detach").clicked() {
+            commands.trigger(ApplyEdit::new(
+                EditCommand::SetAudio {
+                    sequence: handle,
+                    audio: None,
+                },
+                false,
+            ));
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Offset");
+        let mut offset = audio.offset;
+        let response = ui.add(egui::DragValue::new(&mut offset).speed(0.01).suffix(" s"));
+        if response.changed() {
+            // a whole drag is a single undo step
+            let merge = response.dragged() && !response.drag_started();
+            commands.trigger(ApplyEdit::new(
+                EditCommand::SetAudio {
+                    sequence: handle,
+                    audio: Some(SequenceAudio {
+                        offset,
+                        ..audio.clone()
+                    }),
+                },
+                merge,
+            ));
+        }
+    });
+
+    match library.get(&audio.path) {
+        Ok(Some(song)) => ui.label(format!(
+            "{:.1} s, {} Hz, {} channel(s)",
+            song.duration(),
+            song.sample_rate,
+            song.channels
+        )),
+        Ok(None) => {
+            // keep checking on the decoding
+            ui.ctx().request_repaint();
+            ui.label("Decoding song...")
+        }
+        Err(e) => ui.label(e),
+    };
+}
@@ -0,0 +1,90 @@
+use bevy_egui::egui::{self, Ui};
+
+use crate::timecode::*;
+
+/// Draws the timecode settings: whether to chase an external clock or to
+/// generate timecode as the master, along with frame rate, offset, and the
+/// state of the chase.
+pub fn draw_timecode(ui: &mut Ui, settings: &mut TimecodeSettings, chase: &TimecodeChase) {
+    ui.horizontal(|ui| {
+        ui.label("Clock");
+        let selected_text = settings
+            .chase
+            .map_or("Internal (master)".to_string(), |source| {
+                format!("Chase {}", source)
+            });
+        egui::ComboBox::from_id_salt("timecode_chase")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut settings.chase, None, "Internal (master)");
+                for source in TimecodeSource::ALL {
+                    ui.selectable_value(
+                        &mut settings.chase,
+                        Some(source),
+                        format!("Chase {}", source),
+                    );
+                }
+            });
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Frame rate");
+        egui::ComboBox::from_id_salt("timecode_frame_rate")
+            .selected_text(settings.frame_rate.to_string())
+            .show_ui(ui, |ui| {
+                for frame_rate in FrameRate::ALL {
+                    ui.selectable_value(
+                        &mut settings.frame_rate,
+                        frame_rate,
+                        frame_rate.to_string(),
+                    );
+                }
+            });
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Offset");
+        let offset = &mut settings.offset;
+        let max_frames = settings.frame_rate.nominal() as u8 - 1;
+        ui.add(egui::DragValue::new(&mut offset.hours).range(0..=23));
+        ui.label(":");
+        ui.add(egui::DragValue::new(&mut offset.minutes).range(0..=59));
+        ui.label(":");
+        ui.add(egui::DragValue::new(&mut offset.seconds).range(0..=59));
+        ui.label(":");
+        ui.add(egui::DragValue::new(&mut offset.frames).range(0..=max_frames));
+    });
+
+    match settings.chase {
+        Some(_) => {
+            ui.horizontal(|ui| {
+                ui.label("Freewheel");
+                ui.add(
+                    egui::DragValue::new(&mut settings.freewheel)
+                        .range(0.0..=10.0)
+                        .speed(0.05)
+                        .suffix(" s"),
+                );
+                ui.label("Jump threshold");
+                ui.add(
+                    egui::DragValue::new(&mut settings.jump_threshold)
+                        .range(0.0..=10.0)
+                        .speed(0.01)
+                        .suffix(" s"),
+                );
+            });
+
+            let received = chase.last_received().map_or(String::new(), |received| {
+                format!(" at {} ({})", received.timecode, received.frame_rate)
+            });
+            ui.label(format!("{}{}", chase.status(), received));
+        }
+        None => {
+            ui.horizontal(|ui| {
+                ui.label("Generate");
+                ui.checkbox(&mut settings.generate_art_net, "Art-Net");
+                ui.checkbox(&mut settings.generate_ltc, "LTC");
+            });
+        }
+    }
+}
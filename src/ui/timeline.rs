@@ -1,83 +1,990 @@
-use bevy_egui::egui::{self, Color32, Sense, Stroke, Ui, Vec2};
+use bevy::prelude::{Commands, Resource};
+use bevy_egui::egui::{
+    self, Align2, Color32, ColorImage, CursorIcon, FontId, Painter, Pos2, Rect, Response, Sense,
+    Shape, Stroke, TextureHandle, TextureOptions, Ui, Vec2,
+};
+use std::{fmt, sync::Arc};
 
-use crate::timeline::{playback::*, sequences::*};
+use crate::{
+    audio::{analysis::SongAnalysis, song::SongLibrary},
+    editing::{ApplyEdit, EditCommand, KeyframesTarget},
+    simple_store::{SimpleHandle, SimpleStore},
+    timeline::{
+        keyframes::Keyframes,
+        playback::*,
+        sequences::*,
+        tracks::{TimeSegment, TrackContents},
+    },
+};
 
+const RULER_HEIGHT: f32 = 20.0;
+const LANE_HEIGHT: f32 = 28.0;
+const LABEL_WIDTH: f32 = 100.0;
+const EDGE_GRAB_WIDTH: f32 = 5.0;
+const KEYFRAME_SIZE: f32 = 5.0;
+const WAVEFORM_HEIGHT: f32 = 40.0;
+const SPECTROGRAM_HEIGHT: f32 = 64.0;
+/// Shortest a clip can be resized to, in seconds.
+const MIN_CLIP_DURATION: f64 = 0.01;
+/// Shortest a loop region can be resized to, in seconds.
+const MIN_LOOP_DURATION: f64 = 0.01;
+
+/// Bevy resource that holds the state of the timeline editor: which sequence
+/// is open, which track is selected, and whatever is currently being dragged.
+///
+/// The open sequence is given by `path`, the nested sequences that have been
+/// dived into (by double-clicking their clips) starting from the primary
+/// sequence. An empty path means the primary sequence itself is open.
+#[derive(Resource, Default, Debug)]
+pub struct TimelineEditor {
+    pub path: Vec<SimpleHandle<Sequence>>,
+    /// Track within the open sequence selected by clicking its label, whose
+    /// effect keyframes are shown in the curve editor.
+    pub selected_track: Option<usize>,
+    drag: Option<TimelineDrag>,
+    /// Time (within the open sequence) at which the last context menu was
+    /// opened, used to decide where to split clips.
+    context_time: f64,
+    /// Whether the spectrogram of the song is shown below its waveform.
+    pub show_spectrogram: bool,
+    spectrogram: Option<SpectrogramTexture>,
+}
+
+/// The spectrogram of a song, uploaded as a texture so it can be stretched
+/// to any width without being drawn again.
+struct SpectrogramTexture {
+    analysis: Arc<SongAnalysis>,
+    texture: TextureHandle,
+}
+
+impl fmt::Debug for SpectrogramTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpectrogramTexture")
+            .field("texture", &self.texture.id())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A drag in progress on the timeline. Edits are always computed from the
+/// state at the start of the drag, and every frame after the first is merged
+/// into the same undo step.
+#[derive(Debug)]
+struct TimelineDrag {
+    item: DragItem,
+    grab_time: f64,
+    last_time: f64,
+    edited: bool,
+}
+
+#[derive(Debug)]
+enum DragItem {
+    Clip {
+        track: usize,
+        clip: usize,
+        part: ClipPart,
+        original: TimeSegment,
+    },
+    Keyframe {
+        track: usize,
+        target: KeyframesTarget,
+        index: usize,
+        original: Keyframes,
+    },
+    LoopMarker {
+        end: bool,
+        original: LoopRegion,
+    },
+}
+
+/// Which part of a clip is being dragged. Dragging the body moves the clip,
+/// while dragging either edge resizes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClipPart {
+    Body,
+    Start,
+    End,
+}
+
+/// Draws the timeline editor for the open sequence: a ruler with the bar and
+/// beat grid, the loop region and the playhead, the song attached to the
+/// sequence (if any), followed by one lane per track. Clips can be dragged,
+/// resized, split, and dived into, keyframes can be moved and deleted, and the
+/// loop region markers can be set from the ruler's context menu and dragged.
+/// All edits go through the edit history.
 pub fn draw_timeline(
     ui: &mut Ui,
+    commands: &mut Commands,
+    editor: &mut TimelineEditor,
     playback: &mut PlaybackInformation,
-    keyframe_positions: Vec<f64>,
-    active_sequence: Option<&Sequence>,
-) -> egui::Response {
-    let (response, painter) = ui.allocate_painter(
-        Vec2::new(ui.available_width(), 50.0),
-        Sense::click_and_drag(),
-    );
+    sequence_store: &SimpleStore<Sequence>,
+    primary_sequence: Option<SimpleHandle<Sequence>>,
+    library: &mut SongLibrary,
+) {
+    let Some(primary_handle) = primary_sequence else {
+        ui.label("No sequence open");
+        return;
+    };
 
-    // background
-    painter.rect_filled(response.rect, 0.0, Color32::from_black_alpha(128));
+    // sequences may have been removed or replaced (e.g. by loading a show)
+    if let Some(invalid) = editor
+        .path
+        .iter()
+        .position(|handle| sequence_store.get(*handle).is_none())
+    {
+        editor.path.truncate(invalid);
+        editor.selected_track = None;
+        editor.drag = None;
+    }
 
-    let Some(active_sequence) = active_sequence else {
-        return response;
+    draw_breadcrumbs(ui, editor, sequence_store, primary_handle);
+
+    let handle = editor.path.last().copied().unwrap_or(primary_handle);
+    let Some(sequence) = sequence_store.get(handle) else {
+        return;
     };
+    // the playhead runs on the primary sequence's time
+    let is_primary = editor.path.is_empty();
 
-    if response.clicked() || response.dragged() {
-        let click_pos = response.interact_pointer_pos();
-        if let Some(pos) = click_pos {
-            let local_pos = pos - response.rect.min;
-            let percentage = local_pos.x / response.rect.width();
-            playback.current_time = active_sequence.length * percentage as f64;
-        }
+    let song_height = match (&sequence.audio, editor.show_spectrogram) {
+        (Some(_), true) => WAVEFORM_HEIGHT + SPECTROGRAM_HEIGHT,
+        (Some(_), false) => WAVEFORM_HEIGHT,
+        (None, _) => 0.0,
+    };
+    let tracks_top = RULER_HEIGHT + song_height;
+    let height = tracks_top + LANE_HEIGHT * sequence.tracks.len() as f32;
+    let (response, painter) =
+        ui.allocate_painter(Vec2::new(ui.available_width(), height), Sense::hover());
+    let rect = response.rect;
+
+    // background
+    painter.rect_filled(rect, 0.0, Color32::from_black_alpha(128));
+
+    let area_left = rect.left() + LABEL_WIDTH;
+    let area_width = (rect.width() - LABEL_WIDTH).max(1.0);
+    let time_to_x = |time: f64| area_left + (time / sequence.length) as f32 * area_width;
+    let x_to_time = |x: f32| ((x - area_left) / area_width) as f64 * sequence.length;
+
+    // ruler, which scrubs the playhead
+    let ruler_rect = Rect::from_min_max(
+        Pos2::new(area_left, rect.top()),
+        Pos2::new(rect.right(), rect.top() + RULER_HEIGHT),
+    );
+    let ruler_response = ui.interact(
+        ruler_rect,
+        response.id.with("ruler"),
+        Sense::click_and_drag(),
+    );
+    if is_primary
+        && (ruler_response.clicked() || ruler_response.dragged())
+        && let Some(pos) = ruler_response.interact_pointer_pos()
+    {
+        playback.seek(x_to_time(pos.x).clamp(0.0, sequence.length));
     }
 
-    let playhead_pos = response.rect.min
-        + Vec2::new(
-            (playback.current_time / active_sequence.length) as f32 * response.rect.width(),
+    let mut dragging_time = None;
+    let mut drag_stopped = false;
+    let mut edits = Vec::new();
+    let mut dive_into = None;
+
+    // loop region, which is only highlighted while playback is looping it
+    if let Some(loop_region) = sequence.loop_region
+        && let Some((start, end)) = loop_region.seconds(sequence)
+    {
+        let looping = is_primary && playback.loop_region_enabled;
+        let region_rect = Rect::from_min_max(
+            Pos2::new(time_to_x(start), ruler_rect.top()),
+            Pos2::new(time_to_x(end), ruler_rect.bottom()),
+        );
+        painter.rect_filled(
+            region_rect,
             0.0,
+            Color32::from_rgba_unmultiplied(230, 150, 40, if looping { 96 } else { 40 }),
         );
+        for (end_marker, marker_time) in [(false, start), (true, end)] {
+            let x = time_to_x(marker_time);
+            painter.line_segment(
+                [
+                    Pos2::new(x, ruler_rect.top()),
+                    Pos2::new(x, ruler_rect.bottom()),
+                ],
+                Stroke::new(2.0, Color32::from_rgb(230, 150, 40)),
+            );
+            let marker_response = ui
+                .interact(
+                    Rect::from_center_size(
+                        Pos2::new(x, ruler_rect.center().y),
+                        Vec2::new(EDGE_GRAB_WIDTH * 2.0, RULER_HEIGHT),
+                    ),
+                    response.id.with(("loop_marker", end_marker)),
+                    Sense::drag(),
+                )
+                .on_hover_cursor(CursorIcon::ResizeHorizontal);
+            track_drag(
+                editor,
+                &marker_response,
+                &x_to_time,
+                &mut dragging_time,
+                &mut drag_stopped,
+                || DragItem::LoopMarker {
+                    end: end_marker,
+                    original: loop_region,
+                },
+            );
+        }
+    }
 
-    // bar lines
-    let num_bars = (active_sequence.length * playback.bpm / 60.0).ceil() as usize;
-    for i in 0..num_bars {
-        let x = i as f32 * response.rect.width() / num_bars as f32;
-        let bar_pos = response.rect.min + Vec2::new(x, 0.0);
-        painter.line_segment(
-            [bar_pos, bar_pos + Vec2::Y * response.rect.height()],
-            Stroke::new(1.0, Color32::from_white_alpha(64)),
+    if ruler_response.secondary_clicked()
+        && let Some(pos) = ruler_response.interact_pointer_pos()
+    {
+        editor.context_time = x_to_time(pos.x).clamp(0.0, sequence.length);
+    }
+    ruler_response.context_menu(|ui| {
+        let time = editor.context_time;
+        let current = sequence
+            .loop_region
+            .and_then(|loop_region| loop_region.seconds(sequence));
+        if ui.button("Set loop start here").clicked() {
+            // an end before the new start is pushed out to the end of the
+            // sequence
+            let end = current
+                .map(|(_, end)| end)
+                .filter(|end| *end > time)
+                .unwrap_or(sequence.length);
+            edits.push(EditCommand::SetLoopRegion {
+                sequence: handle,
+                loop_region: Some(LoopRegion::new(time, end)),
+            });
+            ui.close();
+        }
+        if ui.button("Set loop end here").clicked() {
+            let start = current
+                .map(|(start, _)| start)
+                .filter(|start| *start < time)
+                .unwrap_or(0.0);
+            edits.push(EditCommand::SetLoopRegion {
+                sequence: handle,
+                loop_region: Some(LoopRegion::new(start, time)),
+            });
+            ui.close();
+        }
+        if ui
+            .add_enabled(
+                sequence.loop_region.is_some(),
+                egui::Button::new("Clear loop region"),
+            )
+            .clicked()
+        {
+            edits.push(EditCommand::SetLoopRegion {
+                sequence: handle,
+                loop_region: None,
+            });
+            ui.close();
+        }
+    });
+
+    if let Some(audio) = &sequence.audio {
+        let song_rect = Rect::from_min_max(
+            Pos2::new(rect.left(), ruler_rect.bottom()),
+            Pos2::new(rect.right(), ruler_rect.bottom() + song_height),
+        );
+        draw_song_lanes(
+            ui,
+            &painter,
+            editor,
+            library,
+            audio,
+            sequence.length,
+            song_rect,
         );
     }
 
-    // beat lines
-    let num_beats = num_bars * playback.beats_per_bar;
-    for i in 0..num_beats {
-        let x = i as f32 * response.rect.width() / num_beats as f32;
-        let beat_pos = response.rect.min + Vec2::new(x, 0.0);
+    // bar and beat lines, following the sequence's tempo map
+    let tempo_map = &sequence.tempo_map;
+    let mut bar = 0;
+    loop {
+        let bar_start_beats = tempo_map.bar_to_beats(bar);
+        if tempo_map.beats_to_seconds(bar_start_beats) >= sequence.length {
+            break;
+        }
+        for beat in 0..tempo_map.beats_per_bar_at(bar) {
+            let beat_time = tempo_map.beats_to_seconds(bar_start_beats + beat as f64);
+            if beat_time >= sequence.length {
+                break;
+            }
+            let x = time_to_x(beat_time);
+            // the first beat of each bar is drawn brighter as the bar line
+            let alpha = if beat == 0 { 64 } else { 32 };
+            painter.line_segment(
+                [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                Stroke::new(1.0, Color32::from_white_alpha(alpha)),
+            );
+            if beat == 0 {
+                painter.text(
+                    Pos2::new(x + 2.0, rect.top() + 2.0),
+                    Align2::LEFT_TOP,
+                    (bar + 1).to_string(),
+                    FontId::proportional(10.0),
+                    Color32::from_white_alpha(128),
+                );
+            }
+        }
+        bar += 1;
+    }
+
+    for (track_i, track) in sequence.tracks.iter().enumerate() {
+        let lane_top = rect.top() + tracks_top + LANE_HEIGHT * track_i as f32;
+        let lane_rect = Rect::from_min_max(
+            Pos2::new(rect.left(), lane_top),
+            Pos2::new(rect.right(), lane_top + LANE_HEIGHT),
+        );
+        if editor.selected_track == Some(track_i) {
+            painter.rect_filled(
+                lane_rect,
+                0.0,
+                Color32::from_rgba_unmultiplied(80, 80, 160, 48),
+            );
+        } else if track_i % 2 == 0 {
+            painter.rect_filled(lane_rect, 0.0, Color32::from_white_alpha(8));
+        }
+        let label_rect = lane_rect.with_max_x(area_left);
+        let label_response = ui.interact(
+            label_rect,
+            response.id.with(("track", track_i)),
+            Sense::click(),
+        );
+        if label_response.clicked() {
+            editor.selected_track = Some(track_i);
+        }
+        label_response.context_menu(|ui| {
+            let track_count = sequence.tracks.len();
+            for (text, to, enabled) in [
+                ("Move up", track_i.saturating_sub(1), track_i > 0),
+                ("Move down", track_i + 1, track_i + 1 < track_count),
+            ] {
+                if ui.add_enabled(enabled, egui::Button::new(text)).clicked() {
+                    edits.push(EditCommand::MoveTrack {
+                        sequence: handle,
+                        from: track_i,
+                        to,
+                    });
+                    editor.selected_track = Some(to);
+                    ui.close();
+                }
+            }
+            if ui.button("Delete track").clicked() {
+                edits.push(EditCommand::RemoveTrack {
+                    sequence: handle,
+                    index: track_i,
+                });
+                editor.selected_track = None;
+                ui.close();
+            }
+        });
         painter.line_segment(
-            [beat_pos, beat_pos + Vec2::Y * response.rect.height()],
-            Stroke::new(1.0, Color32::from_white_alpha(32)),
+            [lane_rect.left_top(), lane_rect.right_top()],
+            Stroke::new(1.0, Color32::from_white_alpha(24)),
+        );
+
+        let label = match &track.contents {
+            TrackContents::EffectTrack { .. } => "Effect".to_string(),
+            TrackContents::SequenceTrack { .. } => "Sequence".to_string(),
+            TrackContents::TriggerTrack { sequence_handle } => format!(
+                "Trigger: {}",
+                sequence_name(sequence_store, *sequence_handle)
+            ),
+        };
+        painter.with_clip_rect(label_rect).text(
+            Pos2::new(lane_rect.left() + 4.0, lane_rect.center().y),
+            Align2::LEFT_CENTER,
+            format!("{} {}", track_i + 1, label),
+            FontId::proportional(12.0),
+            Color32::LIGHT_GRAY,
         );
+
+        let content_rect = lane_rect.with_min_x(area_left).shrink2(Vec2::new(0.0, 2.0));
+
+        // clips
+        if let TrackContents::SequenceTrack { clips } = &track.contents {
+            for (clip_i, clip) in clips.iter().enumerate() {
+                let start = clip.time_segment.start_seconds(tempo_map);
+                let end = clip.time_segment.end_seconds(tempo_map);
+                let clip_rect = Rect::from_min_max(
+                    Pos2::new(time_to_x(start), content_rect.top()),
+                    Pos2::new(time_to_x(end), content_rect.bottom()),
+                );
+                painter.rect(
+                    clip_rect,
+                    2.0,
+                    Color32::from_rgba_unmultiplied(70, 110, 170, 180),
+                    Stroke::new(1.0, Color32::from_rgb(120, 160, 220)),
+                    egui::StrokeKind::Inside,
+                );
+                painter.with_clip_rect(clip_rect).text(
+                    Pos2::new(clip_rect.left() + 4.0, clip_rect.center().y),
+                    Align2::LEFT_CENTER,
+                    sequence_name(sequence_store, clip.sequence_handle),
+                    FontId::proportional(11.0),
+                    Color32::WHITE,
+                );
+
+                let id = response.id.with(("clip", track_i, clip_i));
+                let body = ui.interact(clip_rect, id, Sense::click_and_drag());
+                let start_edge = ui
+                    .interact(
+                        clip_rect.with_max_x(clip_rect.left() + EDGE_GRAB_WIDTH),
+                        id.with("start"),
+                        Sense::drag(),
+                    )
+                    .on_hover_cursor(CursorIcon::ResizeHorizontal);
+                let end_edge = ui
+                    .interact(
+                        clip_rect.with_min_x(clip_rect.right() - EDGE_GRAB_WIDTH),
+                        id.with("end"),
+                        Sense::drag(),
+                    )
+                    .on_hover_cursor(CursorIcon::ResizeHorizontal);
+
+                for (part, part_response) in [
+                    (ClipPart::Body, &body),
+                    (ClipPart::Start, &start_edge),
+                    (ClipPart::End, &end_edge),
+                ] {
+                    track_drag(
+                        editor,
+                        part_response,
+                        &x_to_time,
+                        &mut dragging_time,
+                        &mut drag_stopped,
+                        || DragItem::Clip {
+                            track: track_i,
+                            clip: clip_i,
+                            part,
+                            original: clip.time_segment,
+                        },
+                    );
+                }
+
+                if body.double_clicked() {
+                    dive_into = Some(clip.sequence_handle);
+                }
+                if body.secondary_clicked()
+                    && let Some(pos) = body.interact_pointer_pos()
+                {
+                    editor.context_time = x_to_time(pos.x);
+                }
+                body.context_menu(|ui| {
+                    if ui.button("Split here").clicked() {
+                        let split = sequence_store
+                            .get(clip.sequence_handle)
+                            .and_then(|child| clip.split_at(editor.context_time, tempo_map, child));
+                        if let Some((first, second)) = split {
+                            edits.push(EditCommand::Batch(vec![
+                                EditCommand::RemoveClip {
+                                    sequence: handle,
+                                    track: track_i,
+                                    index: clip_i,
+                                },
+                                EditCommand::AddClip {
+                                    sequence: handle,
+                                    track: track_i,
+                                    index: clip_i,
+                                    clip: first,
+                                },
+                                EditCommand::AddClip {
+                                    sequence: handle,
+                                    track: track_i,
+                                    index: clip_i + 1,
+                                    clip: second,
+                                },
+                            ]));
+                        }
+                        ui.close();
+                    }
+                    if ui.button("Open sequence").clicked() {
+                        dive_into = Some(clip.sequence_handle);
+                        ui.close();
+                    }
+                    if ui.button("Delete clip").clicked() {
+                        edits.push(EditCommand::RemoveClip {
+                            sequence: handle,
+                            track: track_i,
+                            index: clip_i,
+                        });
+                        ui.close();
+                    }
+                });
+            }
+        }
+
+        // keyframes, with effect keyframes in the middle of the lane and track
+        // keyframes along the bottom
+        let mut keyframe_sets = vec![(
+            KeyframesTarget::Track,
+            &track.info.track_keyframes,
+            content_rect.bottom() - KEYFRAME_SIZE,
+            Color32::from_rgb(0, 170, 170),
+        )];
+        if let TrackContents::EffectTrack {
+            effect_keyframes, ..
+        } = &track.contents
+        {
+            keyframe_sets.push((
+                KeyframesTarget::Effect,
+                effect_keyframes,
+                content_rect.center().y,
+                Color32::from_rgb(200, 200, 0),
+            ));
+        }
+        for (target, keyframes, y, color) in keyframe_sets {
+            for (keyframe_i, keyframe) in keyframes.inner().iter().enumerate() {
+                let center = Pos2::new(time_to_x(keyframe.time.to_seconds(tempo_map)), y);
+                let keyframe_rect =
+                    Rect::from_center_size(center, Vec2::splat(KEYFRAME_SIZE * 2.0));
+                let keyframe_response = ui
+                    .interact(
+                        keyframe_rect,
+                        response
+                            .id
+                            .with(("keyframe", track_i, target as u8, keyframe_i)),
+                        Sense::click_and_drag(),
+                    )
+                    .on_hover_text(&keyframe.key);
+
+                let highlighted = keyframe_response.hovered() || keyframe_response.dragged();
+                painter.add(Shape::convex_polygon(
+                    vec![
+                        center + Vec2::new(0.0, -KEYFRAME_SIZE),
+                        center + Vec2::new(KEYFRAME_SIZE, 0.0),
+                        center + Vec2::new(0.0, KEYFRAME_SIZE),
+                        center + Vec2::new(-KEYFRAME_SIZE, 0.0),
+                    ],
+                    color,
+                    Stroke::new(
+                        1.0,
+                        if highlighted {
+                            Color32::WHITE
+                        } else {
+                            Color32::BLACK
+                        },
+                    ),
+                ));
+
+                track_drag(
+                    editor,
+                    &keyframe_response,
+                    &x_to_time,
+                    &mut dragging_time,
+                    &mut drag_stopped,
+                    || DragItem::Keyframe {
+                        track: track_i,
+                        target,
+                        index: keyframe_i,
+                        original: keyframes.clone(),
+                    },
+                );
+
+                let delete_pressed = keyframe_response.hovered()
+                    && ui.input(|input| input.key_pressed(egui::Key::Delete));
+                let mut delete = delete_pressed;
+                keyframe_response.context_menu(|ui| {
+                    if ui.button("Delete keyframe").clicked() {
+                        delete = true;
+                        ui.close();
+                    }
+                });
+                if delete {
+                    let mut remaining = keyframes.inner().clone();
+                    remaining.remove(keyframe_i);
+                    edits.push(EditCommand::SetKeyframes {
+                        sequence: handle,
+                        track: track_i,
+                        target,
+                        keyframes: Keyframes::new(remaining),
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(time) = dragging_time
+        && let Some(edit) = drag_edit(editor, handle, sequence, sequence_store, time)
+    {
+        commands.trigger(edit);
+    }
+    if drag_stopped {
+        editor.drag = None;
+    }
+    for edit in edits {
+        commands.trigger(ApplyEdit::new(edit, false));
     }
 
-    // keyframes
-    for pos in keyframe_positions {
-        let x = pos as f32 / active_sequence.length as f32 * response.rect.width();
-        let keyframe_pos = response.rect.min + Vec2::new(x, 0.0);
+    // playhead
+    if is_primary {
+        let x = time_to_x(playback.current_time);
         painter.line_segment(
-            [
-                keyframe_pos,
-                keyframe_pos + Vec2::Y * response.rect.height(),
-            ],
-            Stroke::new(2.0, Color32::from_rgb(127, 127, 0)),
+            [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+            Stroke::new(1.0, Color32::WHITE),
         );
     }
 
-    // playhead
+    if let Some(child) = dive_into {
+        editor.path.push(child);
+        editor.selected_track = None;
+        editor.drag = None;
+    }
+}
+
+/// Draws the song attached to a sequence across the timeline: its waveform,
+/// and below that its spectrogram if it is shown. Both are drawn from the
+/// song's analysis, which takes the same time no matter how long the song is
+/// or how wide the timeline is. Nothing but a note is drawn while the
+/// analysis is still running, and nothing at all for an empty sequence.
+fn draw_song_lanes(
+    ui: &mut Ui,
+    painter: &Painter,
+    editor: &mut TimelineEditor,
+    library: &mut SongLibrary,
+    audio: &SequenceAudio,
+    length: f64,
+    rect: Rect,
+) {
+    let area_left = rect.left() + LABEL_WIDTH;
+    let area_width = (rect.width() - LABEL_WIDTH).max(1.0);
+    let time_to_x = |time: f64| area_left + (time / length) as f32 * area_width;
+    let x_to_time = |x: f32| ((x - area_left) / area_width) as f64 * length;
+
+    let waveform_rect = rect.with_max_y(rect.top() + WAVEFORM_HEIGHT);
     painter.line_segment(
-        [
-            playhead_pos,
-            playhead_pos + Vec2::Y * response.rect.height(),
-        ],
-        Stroke::new(1.0, Color32::WHITE),
+        [rect.left_top(), rect.right_top()],
+        Stroke::new(1.0, Color32::from_white_alpha(24)),
+    );
+    let label_rect = waveform_rect.with_max_x(area_left);
+    painter.text(
+        Pos2::new(label_rect.left() + 4.0, label_rect.top() + 4.0),
+        Align2::LEFT_TOP,
+        "Song",
+        FontId::proportional(12.0),
+        Color32::LIGHT_GRAY,
+    );
+    ui.put(
+        label_rect.with_min_y(label_rect.center().y),
+        egui::Checkbox::new(&mut editor.show_spectrogram, "Spectrogram"),
     );
+    // an empty sequence leaves no room to draw the song in
+    if length <= 0.0 {
+        return;
+    }
+
+    let area = waveform_rect.with_min_x(area_left);
+    let analysis = match library.analysis(&audio.path) {
+        Ok(Some(analysis)) => analysis,
+        Ok(None) => {
+            painter.text(
+                Pos2::new(area.left() + 4.0, area.center().y),
+                Align2::LEFT_CENTER,
+                "Analyzing song...",
+                FontId::proportional(11.0),
+                Color32::GRAY,
+            );
+            // keep checking on the analysis
+            ui.ctx().request_repaint();
+            return;
+        }
+        Err(e) => {
+            painter.with_clip_rect(area).text(
+                Pos2::new(area.left() + 4.0, area.center().y),
+                Align2::LEFT_CENTER,
+                e,
+                FontId::proportional(11.0),
+                Color32::from_rgb(220, 90, 90),
+            );
+            return;
+        }
+    };
+
+    // waveform, one line per column of pixels that can be seen
+    let visible = area.intersect(ui.clip_rect());
+    let center_y = area.center().y;
+    let half_height = WAVEFORM_HEIGHT / 2.0 - 2.0;
+    let mut x = visible.left().floor();
+    while x < visible.right() {
+        let start = x_to_time(x) - audio.offset;
+        let end = x_to_time(x + 1.0) - audio.offset;
+        if let Some(peak) = analysis.waveform.peak(start, end) {
+            let top = center_y - peak.max.clamp(-1.0, 1.0) * half_height;
+            let bottom = center_y - peak.min.clamp(-1.0, 1.0) * half_height;
+            painter.line_segment(
+                [
+                    Pos2::new(x + 0.5, top),
+                    Pos2::new(x + 0.5, bottom.max(top + 1.0)),
+                ],
+                Stroke::new(1.0, Color32::from_rgb(90, 170, 120)),
+            );
+        }
+        x += 1.0;
+    }
+
+    if !editor.show_spectrogram {
+        return;
+    }
+    let spectrogram = &analysis.spectrogram;
+    let duration = spectrogram.duration();
+    // the part of the lane that the song covers
+    let start = audio.offset.max(0.0);
+    let end = (audio.offset + duration).min(length);
+    if end <= start {
+        return;
+    }
+    let texture = spectrogram_texture(ui.ctx(), editor, &analysis);
+    let spectrogram_rect = Rect::from_min_max(
+        Pos2::new(time_to_x(start), waveform_rect.bottom()),
+        Pos2::new(time_to_x(end), waveform_rect.bottom() + SPECTROGRAM_HEIGHT),
+    );
+    let uv = Rect::from_min_max(
+        Pos2::new(((start - audio.offset) / duration) as f32, 0.0),
+        Pos2::new(((end - audio.offset) / duration) as f32, 1.0),
+    );
+    painter.image(texture.id(), spectrogram_rect, uv, Color32::WHITE);
+}
+
+/// Gets the texture for the spectrogram of a song, uploading it the first
+/// time it is drawn. Columns are merged if there are more than fit into a
+/// texture.
+fn spectrogram_texture(
+    ctx: &egui::Context,
+    editor: &mut TimelineEditor,
+    analysis: &Arc<SongAnalysis>,
+) -> TextureHandle {
+    if let Some(cached) = &editor.spectrogram
+        && Arc::ptr_eq(&cached.analysis, analysis)
+    {
+        return cached.texture.clone();
+    }
+
+    let spectrogram = &analysis.spectrogram;
+    let column_count = spectrogram.column_count();
+    let bands = spectrogram.bands();
+    let width = column_count
+        .min(ctx.input(|input| input.max_texture_side))
+        .max(1);
+    let mut levels = vec![0.0f32; width * bands];
+    for column in 0..column_count {
+        let x = column * width / column_count;
+        for (band, &level) in spectrogram.column(column).iter().enumerate() {
+            // the lowest band along the bottom
+            let pixel = &mut levels[(bands - 1 - band) * width + x];
+            *pixel = pixel.max(level);
+        }
+    }
+    let pixels = levels.into_iter().map(spectrogram_color).collect();
+
+    let texture = ctx.load_texture(
+        "spectrogram",
+        ColorImage::new([width, bands], pixels),
+        TextureOptions::LINEAR,
+    );
+    editor.spectrogram = Some(SpectrogramTexture {
+        analysis: analysis.clone(),
+        texture: texture.clone(),
+    });
+    texture
+}
+
+/// Colors a level of the spectrogram, from black for silence through blue,
+/// magenta and orange up to pale yellow for full scale.
+fn spectrogram_color(level: f32) -> Color32 {
+    const STOPS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [40.0, 20.0, 110.0],
+        [180.0, 40.0, 120.0],
+        [250.0, 150.0, 40.0],
+        [255.0, 250.0, 200.0],
+    ];
+    let position = level.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let t = position - index as f32;
+    let [r, g, b] = std::array::from_fn(|channel| {
+        (STOPS[index][channel] * (1.0 - t) + STOPS[index + 1][channel] * t) as u8
+    });
+    Color32::from_rgb(r, g, b)
+}
+
+/// Draws the path to the open sequence, with a button for each level to jump
+/// back up to it.
+fn draw_breadcrumbs(
+    ui: &mut Ui,
+    editor: &mut TimelineEditor,
+    sequence_store: &SimpleStore<Sequence>,
+    primary_handle: SimpleHandle<Sequence>,
+) {
+    let mut go_to = None;
+    ui.horizontal(|ui| {
+        if ui
+            .button(sequence_name(sequence_store, primary_handle))
+            .clicked()
+        {
+            go_to = Some(0);
+        }
+        for (depth, handle) in editor.path.iter().enumerate() {
+            ui.label(">");
+            if ui.button(sequence_name(sequence_store, *handle)).clicked() {
+                go_to = Some(depth + 1);
+            }
+        }
+    });
+    if let Some(depth) = go_to {
+        editor.path.truncate(depth);
+        editor.selected_track = None;
+        editor.drag = None;
+    }
+}
+
+/// Starts, continues, or stops a drag according to an item's response.
+fn track_drag(
+    editor: &mut TimelineEditor,
+    response: &Response,
+    x_to_time: &impl Fn(f32) -> f64,
+    dragging_time: &mut Option<f64>,
+    drag_stopped: &mut bool,
+    item: impl FnOnce() -> DragItem,
+) {
+    let Some(pos) = response.interact_pointer_pos() else {
+        *drag_stopped |= response.drag_stopped();
+        return;
+    };
+    let time = x_to_time(pos.x);
+    if response.drag_started() {
+        editor.drag = Some(TimelineDrag {
+            item: item(),
+            grab_time: time,
+            last_time: time,
+            edited: false,
+        });
+    }
+    if response.dragged() {
+        *dragging_time = Some(time);
+    }
+    *drag_stopped |= response.drag_stopped();
+}
+
+/// Computes the edit for the drag in progress, given where the pointer is now.
+fn drag_edit(
+    editor: &mut TimelineEditor,
+    handle: SimpleHandle<Sequence>,
+    sequence: &Sequence,
+    sequence_store: &SimpleStore<Sequence>,
+    time: f64,
+) -> Option<ApplyEdit> {
+    let drag = editor.drag.as_mut()?;
+    if time == drag.last_time {
+        return None;
+    }
+    drag.last_time = time;
+    let delta = time - drag.grab_time;
+    let tempo_map = &sequence.tempo_map;
+
+    let command = match &drag.item {
+        DragItem::Clip {
+            track,
+            clip,
+            part,
+            original,
+        } => {
+            let start = original.start_seconds(tempo_map);
+            let end = original.end_seconds(tempo_map);
+            let mut time_segment = *original;
+            match part {
+                ClipPart::Body => {
+                    let new_start = (start + delta).max(0.0);
+                    time_segment.start_time = original.start_time.moved_to(new_start, tempo_map);
+                }
+                ClipPart::End => {
+                    let new_end = (end + delta).max(start + MIN_CLIP_DURATION);
+                    time_segment.duration = original.duration.resized_to(start, new_end, tempo_map);
+                }
+                ClipPart::Start => {
+                    // trimming the start moves the start offset along with it,
+                    // so the rest of the clip stays where it is
+                    let TrackContents::SequenceTrack { clips } =
+                        &sequence.tracks.get(*track)?.contents
+                    else {
+                        return None;
+                    };
+                    let clip_ref = clips.get(*clip)?;
+                    let child_tempo_map = &sequence_store.get(clip_ref.sequence_handle)?.tempo_map;
+                    let speed = clip_ref.playback.speed;
+                    let offset = original.start_offset_seconds(child_tempo_map);
+                    let earliest = if speed > 0.0 {
+                        start - offset / speed
+                    } else {
+                        0.0
+                    };
+                    let new_start = (start + delta)
+                        .max(earliest)
+                        .max(0.0)
+                        .min(end - MIN_CLIP_DURATION);
+                    time_segment.start_time = original.start_time.moved_to(new_start, tempo_map);
+                    time_segment.duration = original.duration.resized_to(new_start, end, tempo_map);
+                    time_segment.start_offset = original
+                        .start_offset
+                        .moved_to(offset + (new_start - start) * speed, child_tempo_map);
+                }
+            }
+            EditCommand::MoveClip {
+                sequence: handle,
+                track: *track,
+                clip: *clip,
+                time_segment,
+            }
+        }
+        DragItem::Keyframe {
+            track,
+            target,
+            index,
+            original,
+        } => {
+            let mut moved = original.inner().clone();
+            let keyframe = moved.get_mut(*index)?;
+            let new_time = (keyframe.time.to_seconds(tempo_map) + delta).max(0.0);
+            keyframe.time = keyframe.time.moved_to(new_time, tempo_map);
+            let mut keyframes = Keyframes::new(moved);
+            keyframes.sort_by_time(tempo_map);
+            EditCommand::SetKeyframes {
+                sequence: handle,
+                track: *track,
+                target: *target,
+                keyframes,
+            }
+        }
+        DragItem::LoopMarker { end, original } => {
+            let (start_time, end_time) = original.seconds(sequence)?;
+            let mut loop_region = *original;
+            if *end {
+                let new_end = (end_time + delta)
+                    .max(start_time + MIN_LOOP_DURATION)
+                    .min(sequence.length);
+                loop_region.end = original.end.moved_to(new_end, tempo_map);
+            } else {
+                let new_start = (start_time + delta)
+                    .min(end_time - MIN_LOOP_DURATION)
+                    .max(0.0);
+                loop_region.start = original.start.moved_to(new_start, tempo_map);
+            }
+            EditCommand::SetLoopRegion {
+                sequence: handle,
+                loop_region: Some(loop_region),
+            }
+        }
+    };
+
+    let merge = drag.edited;
+    drag.edited = true;
+    Some(ApplyEdit::new(command, merge))
+}
 
-    response
+fn sequence_name(sequence_store: &SimpleStore<Sequence>, handle: SimpleHandle<Sequence>) -> &str {
+    sequence_store
+        .get(handle)
+        .map(|sequence| sequence.name.as_str())
+        .unwrap_or("<missing>")
 }
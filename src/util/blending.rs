@@ -1,7 +1,10 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
 pub mod colors;
 pub mod pan_tilt;
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Reflect)]
 pub enum BlendingMode {
     #[default]
     Add,